@@ -0,0 +1,274 @@
+//! Derive macros for `bevy_stream_dingtalk`
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Expr, Fields, GenericArgument, Lit, LitStr,
+    Meta, PathArguments, Type,
+};
+
+/// Implement `BotCommand` for an enum, one chat command per variant
+///
+/// The command name is the kebab-cased variant name unless set with `#[command(name = "..")]`,
+/// `#[command(alias = "..")]` adds more names. Names match case-insensitively and are listed in
+/// lowercase. Doc comments become the help description.
+/// `#[command(hidden)]` leaves a command out of help, `#[command(admin)]` restricts it to admins.
+///
+/// Fields are positional arguments parsed with `FromStr`, in declaration order:
+/// - `Option<T>` fields are optional
+/// - `#[arg(default = "..")]` is used when the argument is missing
+/// - `#[arg(rest)]` takes the remaining text, spaces included
+/// - `#[arg(name = "..")]` renames the argument in usage and errors
+#[proc_macro_derive(BotCommand, attributes(command, arg))]
+pub fn derive_bot_command(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let Data::Enum(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input.ident,
+            "BotCommand can only be derived for enums",
+        ));
+    };
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut arms = Vec::new();
+    let mut infos = Vec::new();
+
+    for variant in &data.variants {
+        let attrs = CommandAttrs::parse(&variant.attrs)?;
+        // incoming command names are lowercased before matching
+        let name = attrs
+            .name
+            .map(|name| name.to_lowercase())
+            .unwrap_or_else(|| kebab_case(&variant.ident.to_string()));
+        let aliases: Vec<String> = attrs.aliases.iter().map(|a| a.to_lowercase()).collect();
+        let hidden = attrs.hidden;
+        let admin = attrs.admin;
+        let description = doc_string(&variant.attrs);
+        let variant_ident = &variant.ident;
+
+        let mut usage = vec![name.clone()];
+        let construct = match &variant.fields {
+            Fields::Unit => quote!(#ident::#variant_ident),
+            Fields::Named(fields) => {
+                let mut inits = Vec::new();
+                for field in &fields.named {
+                    let field_ident = field.ident.as_ref().unwrap();
+                    let (getter, arg_usage) = argument(field_ident.to_string(), field)?;
+                    usage.push(arg_usage);
+                    inits.push(quote!(#field_ident: #getter));
+                }
+                quote!(#ident::#variant_ident { #(#inits),* })
+            }
+            Fields::Unnamed(fields) => {
+                let mut values = Vec::new();
+                for (i, field) in fields.unnamed.iter().enumerate() {
+                    let (getter, arg_usage) = argument(format!("arg{}", i + 1), field)?;
+                    usage.push(arg_usage);
+                    values.push(getter);
+                }
+                quote!(#ident::#variant_ident(#(#values),*))
+            }
+        };
+
+        let usage = usage.join(" ");
+        let names = std::iter::once(&name).chain(aliases.iter());
+        arms.push(quote! {
+            #(#names)|* => {
+                let value = #construct;
+                args.finish()?;
+                ::std::result::Result::Ok(value)
+            }
+        });
+        infos.push(quote! {
+            ::bevy_stream_dingtalk::command::CommandInfo {
+                name: #name,
+                aliases: &[#(#aliases),*],
+                description: #description,
+                usage: #usage,
+                hidden: #hidden,
+                admin: #admin,
+            }
+        });
+    }
+
+    Ok(quote! {
+        impl #impl_generics ::bevy_stream_dingtalk::command::BotCommand for #ident #ty_generics #where_clause {
+            fn parse(
+                text: &str,
+            ) -> ::std::result::Result<Self, ::bevy_stream_dingtalk::command::CommandParseError> {
+                let mut args = ::bevy_stream_dingtalk::command::CommandArgs::new(text)?;
+                match args.command() {
+                    #(#arms)*
+                    _ => ::std::result::Result::Err(
+                        ::bevy_stream_dingtalk::command::CommandParseError::Unknown(
+                            args.command().to_owned(),
+                        ),
+                    ),
+                }
+            }
+
+            fn commands() -> ::std::vec::Vec<::bevy_stream_dingtalk::command::CommandInfo> {
+                ::std::vec![#(#infos),*]
+            }
+        }
+    })
+}
+
+/// getter expression and usage fragment of one field
+fn argument(default_name: String, field: &syn::Field) -> syn::Result<(TokenStream2, String)> {
+    let attrs = ArgAttrs::parse(&field.attrs)?;
+    let name = attrs.name.unwrap_or(default_name);
+    let ty = &field.ty;
+
+    Ok(match (option_inner(ty), attrs.rest, attrs.default) {
+        (Some(_), _, Some(_)) => {
+            return Err(syn::Error::new_spanned(
+                ty,
+                "`default` can not be used on Option fields",
+            ))
+        }
+        (Some(inner), true, None) => (
+            quote!(args.optional_rest::<#inner>(#name)?),
+            format!("[{name}...]"),
+        ),
+        (Some(inner), false, None) => {
+            (quote!(args.optional::<#inner>(#name)?), format!("[{name}]"))
+        }
+        (None, rest, Some(default)) => {
+            let getter = if rest {
+                quote!(args.optional_rest::<#ty>(#name)?)
+            } else {
+                quote!(args.optional::<#ty>(#name)?)
+            };
+            (
+                quote!(match #getter {
+                    ::std::option::Option::Some(value) => value,
+                    ::std::option::Option::None => args.parse_default::<#ty>(#name, #default)?,
+                }),
+                format!("[{name}={default}]"),
+            )
+        }
+        (None, true, None) => (quote!(args.rest::<#ty>(#name)?), format!("<{name}...>")),
+        (None, false, None) => (quote!(args.required::<#ty>(#name)?), format!("<{name}>")),
+    })
+}
+
+#[derive(Default)]
+struct CommandAttrs {
+    name: Option<String>,
+    aliases: Vec<String>,
+    hidden: bool,
+    admin: bool,
+}
+
+impl CommandAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut result = Self::default();
+        for attr in attrs.iter().filter(|a| a.path().is_ident("command")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    result.name = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("alias") {
+                    result
+                        .aliases
+                        .push(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("hidden") {
+                    result.hidden = true;
+                } else if meta.path.is_ident("admin") {
+                    result.admin = true;
+                } else {
+                    return Err(meta.error("expected `name`, `alias`, `hidden` or `admin`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(result)
+    }
+}
+
+#[derive(Default)]
+struct ArgAttrs {
+    name: Option<String>,
+    default: Option<String>,
+    rest: bool,
+}
+
+impl ArgAttrs {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut result = Self::default();
+        for attr in attrs.iter().filter(|a| a.path().is_ident("arg")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("name") {
+                    result.name = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("default") {
+                    result.default = Some(meta.value()?.parse::<LitStr>()?.value());
+                } else if meta.path.is_ident("rest") {
+                    result.rest = true;
+                } else {
+                    return Err(meta.error("expected `name`, `default` or `rest`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(result)
+    }
+}
+
+/// `T` of an `Option<T>` field
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Option" {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(inner) => Some(inner),
+        _ => None,
+    }
+}
+
+fn doc_string(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(syn::ExprLit {
+                    lit: Lit::Str(s), ..
+                }) => Some(s.value().trim().to_owned()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn kebab_case(name: &str) -> String {
+    let mut result = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 {
+                result.push('-');
+            }
+            result.extend(c.to_lowercase());
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
@@ -0,0 +1,594 @@
+//! Coin rush played from a group chat
+//!
+//! Players `/join`, start a round with `/start` and walk with `/go right 3`. The arena is
+//! simulated by ordinary Bevy systems on a fixed tick; every command is stamped with the tick it
+//! takes effect on, so `/replay 42` rebuilds the board as it was at tick 42 by running the same
+//! systems over the recorded inputs in a scratch world. Boards are rendered to PNG, uploaded and
+//! posted as images, the final standings go out as one bundle.
+//!
+//! Run with `cargo run --example chat_arena -- <client_id> <client_secret>` and mention the robot
+//! in a group.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::ecs::system::SystemParam;
+use bevy::log::{Level, LogPlugin};
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_stream_dingtalk::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const SIZE: i32 = 12;
+const TICKS_PER_SECOND: f64 = 2.0;
+const ROUND_TICKS: u32 = 120;
+const COIN_EVERY: u32 = 6;
+/// pixels per cell of rendered boards
+const CELL: u32 = 24;
+
+#[derive(BotCommand, Debug)]
+enum ArenaCommand {
+    /// enter the next round
+    Join,
+    /// sit out the next rounds
+    Leave,
+    /// start a round with everyone who joined
+    Start,
+    /// walk up, down, left or right
+    #[command(alias = "g")]
+    Go {
+        direction: Direction,
+        #[arg(default = "1")]
+        steps: u8,
+    },
+    /// picture of the arena right now
+    Board,
+    /// picture of the last round at a tick
+    Replay { tick: u32 },
+    /// scores of the current round
+    Score,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "up" | "u" | "w" => Ok(Direction::Up),
+            "down" | "d" | "s" => Ok(Direction::Down),
+            "left" | "l" | "a" => Ok(Direction::Left),
+            "right" | "r" => Ok(Direction::Right),
+            _ => Err("expected up, down, left or right".to_owned()),
+        }
+    }
+}
+
+impl Direction {
+    fn offset(self) -> IVec2 {
+        match self {
+            Direction::Up => IVec2::NEG_Y,
+            Direction::Down => IVec2::Y,
+            Direction::Left => IVec2::NEG_X,
+            Direction::Right => IVec2::X,
+        }
+    }
+}
+
+/// What a player did, applied at the start of a tick
+#[derive(Debug, Clone)]
+enum Input {
+    Spawn {
+        user_id: String,
+        nick: String,
+    },
+    Walk {
+        user_id: String,
+        direction: Direction,
+        steps: u8,
+    },
+}
+
+/// Simulation state shared by the live arena and replays
+#[derive(Resource, Debug, Default)]
+struct Arena {
+    tick: u32,
+    running: bool,
+}
+
+/// Seed and inputs of the round, enough to rebuild any of its ticks
+#[derive(Resource, Debug, Default, Clone)]
+struct RoundLog {
+    seed: u64,
+    inputs: Vec<(u32, Input)>,
+}
+
+#[derive(Resource)]
+struct ArenaRng(StdRng);
+
+#[derive(Component, Debug)]
+struct Player {
+    user_id: String,
+    nick: String,
+    score: u32,
+    path: VecDeque<Direction>,
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+struct Cell(IVec2);
+
+#[derive(Component, Debug)]
+struct Coin;
+
+/// Chat side of the arena, not part of the simulation
+#[derive(Resource, Debug, Default)]
+struct Lobby {
+    conversation_id: Option<String>,
+    players: HashMap<String, String>,
+    /// rendered boards waiting for their media id
+    uploads: HashMap<PathBuf, String>,
+}
+
+fn main() {
+    let client_id = std::env::args().nth(1).unwrap();
+    let client_secret = std::env::args().nth(2).unwrap();
+    App::new()
+        .add_plugins(
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1.0 / 60.0,
+            ))),
+        )
+        .add_plugins(LogPlugin {
+            level: Level::INFO,
+            filter: "bevy_stream_dingtalk=info,chat_arena=debug".to_string(),
+            update_subscriber: None,
+        })
+        .add_plugins(
+            StreamDingTalkPlugin::new(client_id, client_secret)
+                .message_filter(MessageFilter::MentionedOrDirect),
+        )
+        .add_plugins(CommandRouter::<ArenaCommand>::new().cooldown(Cooldown {
+            per_user: Duration::from_millis(500),
+            ..default()
+        }))
+        .insert_resource(Time::<Fixed>::from_hz(TICKS_PER_SECOND))
+        .init_resource::<Arena>()
+        .init_resource::<RoundLog>()
+        .init_resource::<Lobby>()
+        .insert_resource(ArenaRng(StdRng::seed_from_u64(0)))
+        .add_systems(FixedUpdate, simulation())
+        .add_systems(
+            Update,
+            (handle_commands, announce_results, post_uploaded_boards),
+        )
+        .run();
+}
+
+/// One tick of the arena, used by the app and by [`replay`]
+fn simulation() -> impl IntoSystemConfigs<()> {
+    (apply_inputs, walk, collect_coins, spawn_coins, advance_tick)
+        .chain()
+        .run_if(|arena: Res<Arena>| arena.running)
+}
+
+fn apply_inputs(
+    mut commands: Commands,
+    arena: Res<Arena>,
+    log: Res<RoundLog>,
+    mut rng: ResMut<ArenaRng>,
+    mut players: Query<&mut Player>,
+) {
+    for (_, input) in log.inputs.iter().filter(|(tick, _)| *tick == arena.tick) {
+        match input {
+            Input::Spawn { user_id, nick } => {
+                let cell = IVec2::new(rng.0.gen_range(0..SIZE), rng.0.gen_range(0..SIZE));
+                commands.spawn((
+                    Player {
+                        user_id: user_id.clone(),
+                        nick: nick.clone(),
+                        score: 0,
+                        path: VecDeque::new(),
+                    },
+                    Cell(cell),
+                ));
+            }
+            Input::Walk {
+                user_id,
+                direction,
+                steps,
+            } => {
+                if let Some(mut player) = players.iter_mut().find(|p| &p.user_id == user_id) {
+                    player.path.clear();
+                    player
+                        .path
+                        .extend(std::iter::repeat_n(*direction, *steps as usize));
+                }
+            }
+        }
+    }
+}
+
+fn walk(mut players: Query<(&mut Player, &mut Cell)>) {
+    for (mut player, mut cell) in &mut players {
+        if let Some(direction) = player.path.pop_front() {
+            cell.0 = (cell.0 + direction.offset()).clamp(IVec2::ZERO, IVec2::splat(SIZE - 1));
+        }
+    }
+}
+
+fn collect_coins(
+    mut commands: Commands,
+    coins: Query<(Entity, &Cell), With<Coin>>,
+    mut players: Query<(&mut Player, &Cell)>,
+) {
+    for (coin, coin_cell) in &coins {
+        // ties go to the smallest user id, so replays agree with the live round
+        let mut here: Vec<_> = players
+            .iter_mut()
+            .filter(|(_, cell)| *cell == coin_cell)
+            .collect();
+        here.sort_by_key(|(player, _)| player.user_id.clone());
+        if let Some((player, _)) = here.first_mut() {
+            player.score += 1;
+            commands.entity(coin).despawn();
+        }
+    }
+}
+
+fn spawn_coins(mut commands: Commands, arena: Res<Arena>, mut rng: ResMut<ArenaRng>) {
+    if arena.tick.is_multiple_of(COIN_EVERY) {
+        let cell = IVec2::new(rng.0.gen_range(0..SIZE), rng.0.gen_range(0..SIZE));
+        commands.spawn((Coin, Cell(cell)));
+    }
+}
+
+fn advance_tick(mut arena: ResMut<Arena>) {
+    arena.tick += 1;
+    if arena.tick >= ROUND_TICKS {
+        arena.running = false;
+    }
+}
+
+fn handle_commands(
+    mut commands: Commands,
+    mut events: EventReader<CommandReceived<ArenaCommand>>,
+    mut arena: ResMut<Arena>,
+    mut log: ResMut<RoundLog>,
+    mut lobby: ResMut<Lobby>,
+    board: Board,
+    dingtalk: DingTalk,
+) {
+    for CommandReceived { command, message } in events.read() {
+        if !message.is_group() {
+            dingtalk.reply(message, text("the arena only runs in group chats"));
+            continue;
+        }
+        lobby.conversation_id = Some(message.conversation_id.clone());
+        // inputs land on the next tick, the one the simulation has not run yet
+        let next_tick = arena.tick;
+
+        match command {
+            ArenaCommand::Join => {
+                let known = lobby
+                    .players
+                    .insert(message.sender_id.clone(), message.sender_nick.clone())
+                    .is_some();
+                if arena.running && !known {
+                    log.inputs.push((
+                        next_tick,
+                        Input::Spawn {
+                            user_id: message.sender_id.clone(),
+                            nick: message.sender_nick.clone(),
+                        },
+                    ));
+                }
+                dingtalk.reply(
+                    message,
+                    text(format!(
+                        "{} is in, {} players",
+                        message.sender_nick,
+                        lobby.players.len()
+                    )),
+                );
+            }
+            ArenaCommand::Leave => {
+                lobby.players.remove(&message.sender_id);
+                dingtalk.reply(message, text(format!("bye {}", message.sender_nick)));
+            }
+            ArenaCommand::Start if arena.running => {
+                dingtalk.reply(
+                    message,
+                    text(format!("a round is running, tick {}", arena.tick)),
+                );
+            }
+            ArenaCommand::Start if lobby.players.is_empty() => {
+                dingtalk.reply(message, text("nobody joined yet, send /join"));
+            }
+            ArenaCommand::Start => {
+                board.clear(&mut commands);
+                *log = RoundLog {
+                    seed: rand::random(),
+                    inputs: spawn_inputs(&lobby.players),
+                };
+                commands.insert_resource(ArenaRng(StdRng::seed_from_u64(log.seed)));
+                *arena = Arena {
+                    tick: 0,
+                    running: true,
+                };
+                dingtalk.send_markdown(
+                    &message.conversation_id,
+                    "Coin rush",
+                    format!(
+                        "#### Coin rush started\n\n{} players, {} seconds, collect the coins!",
+                        lobby.players.len(),
+                        ROUND_TICKS as f64 / TICKS_PER_SECOND
+                    ),
+                );
+            }
+            ArenaCommand::Go { direction, steps } => {
+                if !arena.running {
+                    dingtalk.reply(message, text("no round running, send /start"));
+                    continue;
+                }
+                log.inputs.push((
+                    next_tick,
+                    Input::Walk {
+                        user_id: message.sender_id.clone(),
+                        direction: *direction,
+                        steps: *steps,
+                    },
+                ));
+            }
+            ArenaCommand::Board => {
+                let image = board.render();
+                upload_board(
+                    &mut lobby,
+                    &dingtalk,
+                    image,
+                    arena.tick,
+                    &message.conversation_id,
+                );
+            }
+            ArenaCommand::Replay { tick } => {
+                if log.inputs.is_empty() {
+                    dingtalk.reply(message, text("nothing to replay yet"));
+                    continue;
+                }
+                let tick = (*tick).min(arena.tick);
+                let image = replay(&log, tick);
+                upload_board(&mut lobby, &dingtalk, image, tick, &message.conversation_id);
+            }
+            ArenaCommand::Score => {
+                dingtalk.send_markdown(&message.conversation_id, "Scores", board.standings());
+            }
+        }
+    }
+}
+
+/// post standings and the final board once the round ran out
+fn announce_results(
+    arena: Res<Arena>,
+    mut announced: Local<bool>,
+    lobby: Res<Lobby>,
+    board: Board,
+    dingtalk: DingTalk,
+) {
+    if arena.running || arena.tick < ROUND_TICKS {
+        *announced = false;
+        return;
+    }
+    if *announced {
+        return;
+    }
+    *announced = true;
+    let Some(conversation_id) = &lobby.conversation_id else {
+        return;
+    };
+
+    let path = board_path(arena.tick);
+    if let Err(e) = save(board.render(), &path) {
+        error!("render final board error: {:?}", e);
+        return;
+    }
+    dingtalk.send_bundle(
+        conversation_id,
+        MessageBundle::new()
+            .markdown("Coin rush results", board.standings())
+            .image(path)
+            .text("send /replay <tick> to see any moment of the round"),
+    );
+}
+
+/// send boards whose upload finished
+fn post_uploaded_boards(
+    mut events: EventReader<MediaUploaded>,
+    mut lobby: ResMut<Lobby>,
+    dingtalk: DingTalk,
+) {
+    for event in events.read() {
+        let Some(conversation_id) = lobby.uploads.remove(&event.path) else {
+            continue;
+        };
+        match &event.result {
+            Ok(media_id) => dingtalk.send(
+                conversation_id,
+                MessageTemplate::SampleImageMsg {
+                    photo_url: media_id.clone(),
+                },
+            ),
+            Err(e) => dingtalk.send_text(conversation_id, format!("board upload failed: {e}")),
+        }
+    }
+}
+
+/// rebuild the arena at `tick` by running the simulation over the recorded inputs
+fn replay(log: &RoundLog, tick: u32) -> Image {
+    let mut world = World::new();
+    world.insert_resource(Arena {
+        tick: 0,
+        running: true,
+    });
+    world.insert_resource(log.clone());
+    world.insert_resource(ArenaRng(StdRng::seed_from_u64(log.seed)));
+
+    let mut schedule = Schedule::default();
+    schedule.add_systems(simulation());
+    while world.resource::<Arena>().tick < tick && world.resource::<Arena>().running {
+        schedule.run(&mut world);
+    }
+
+    let players = world
+        .query_filtered::<&Cell, With<Player>>()
+        .iter(&world)
+        .copied()
+        .collect::<Vec<_>>();
+    let coins = world
+        .query_filtered::<&Cell, With<Coin>>()
+        .iter(&world)
+        .copied()
+        .collect::<Vec<_>>();
+    render(players, coins)
+}
+
+/// players already in the lobby enter on the first tick, sorted so the rng draws are stable
+fn spawn_inputs(players: &HashMap<String, String>) -> Vec<(u32, Input)> {
+    let mut players: Vec<_> = players.iter().collect();
+    players.sort();
+    players
+        .into_iter()
+        .map(|(user_id, nick)| {
+            (
+                0,
+                Input::Spawn {
+                    user_id: user_id.clone(),
+                    nick: nick.clone(),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Live players and coins
+#[derive(SystemParam)]
+struct Board<'w, 's> {
+    players: Query<'w, 's, (Entity, &'static Player, &'static Cell)>,
+    coins: Query<'w, 's, (Entity, &'static Cell), With<Coin>>,
+}
+
+impl Board<'_, '_> {
+    fn clear(&self, commands: &mut Commands) {
+        for entity in self
+            .players
+            .iter()
+            .map(|p| p.0)
+            .chain(self.coins.iter().map(|c| c.0))
+        {
+            commands.entity(entity).despawn();
+        }
+    }
+
+    fn render(&self) -> Image {
+        render(
+            self.players.iter().map(|(_, _, cell)| *cell),
+            self.coins.iter().map(|(_, cell)| *cell),
+        )
+    }
+
+    fn standings(&self) -> String {
+        let mut ranked: Vec<_> = self.players.iter().map(|(_, p, _)| p).collect();
+        ranked.sort_by(|a, b| b.score.cmp(&a.score).then(a.nick.cmp(&b.nick)));
+
+        let mut text = String::from("#### Scores\n\n");
+        for (rank, player) in ranked.iter().enumerate() {
+            let _ = writeln!(text, "{}. {} - {}", rank + 1, player.nick, player.score);
+        }
+        if ranked.is_empty() {
+            text.push_str("nobody played");
+        }
+        text
+    }
+}
+
+/// grid of the arena, players white and coins yellow
+fn render(players: impl IntoIterator<Item = Cell>, coins: impl IntoIterator<Item = Cell>) -> Image {
+    let side = SIZE as u32 * CELL;
+    let mut data = Vec::with_capacity((side * side * 4) as usize);
+    for _ in 0..side * side {
+        data.extend_from_slice(&[40, 44, 52, 255]);
+    }
+
+    let mut fill = |cell: Cell, color: [u8; 4], inset: u32| {
+        let origin = cell.0.as_uvec2() * CELL;
+        for y in inset..CELL - inset {
+            for x in inset..CELL - inset {
+                let i = (((origin.y + y) * side + origin.x + x) * 4) as usize;
+                data[i..i + 4].copy_from_slice(&color);
+            }
+        }
+    };
+    for cell in coins {
+        fill(cell, [240, 200, 40, 255], 7);
+    }
+    for cell in players {
+        fill(cell, [230, 230, 230, 255], 2);
+    }
+
+    Image::new(
+        Extent3d {
+            width: side,
+            height: side,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    )
+}
+
+fn upload_board(
+    lobby: &mut Lobby,
+    dingtalk: &DingTalk,
+    image: Image,
+    tick: u32,
+    conversation_id: &str,
+) {
+    let path = board_path(tick);
+    match save(image, &path) {
+        Ok(()) => {
+            lobby
+                .uploads
+                .insert(path.clone(), conversation_id.to_owned());
+            dingtalk.upload(path, UploadType::Image);
+        }
+        Err(e) => error!("render board error: {:?}", e),
+    }
+}
+
+fn board_path(tick: u32) -> PathBuf {
+    std::env::temp_dir().join(format!("chat_arena_{tick}.png"))
+}
+
+fn save(image: Image, path: &Path) -> anyhow::Result<()> {
+    image.try_into_dynamic()?.save(path)?;
+    Ok(())
+}
+
+fn text(content: impl Into<String>) -> MessageTemplate {
+    MessageTemplate::SampleText {
+        content: content.into(),
+    }
+}
@@ -3,7 +3,7 @@ use std::time::Duration;
 use bevy::app::ScheduleRunnerPlugin;
 use bevy::log::{Level, LogPlugin};
 use bevy::prelude::*;
-use bevy_stream_dingtalk::prelude::StreamDingTalkPlugin;
+use bevy_stream_dingtalk::prelude::*;
 
 fn main() {
     let client_id = std::env::args().nth(1).unwrap();
@@ -16,12 +16,12 @@ fn main() {
         )
         .add_plugins(LogPlugin {
             level: Level::INFO,
-            filter: "bevy_stream_dingtalk=debug".to_string(),
+            filter: "bevy_stream_dingtalk=debug,bevy_stream_dingtalk::ws=info".to_string(),
             update_subscriber: None,
         })
-        .add_plugins(StreamDingTalkPlugin {
-            client_id,
-            client_secret,
-        })
+        .add_plugins(
+            StreamDingTalkPlugin::new(client_id, client_secret)
+                .message_filter(MessageFilter::MentionedOrDirect),
+        )
         .run();
 }
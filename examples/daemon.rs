@@ -0,0 +1,254 @@
+//! Headless bot meant to run as a service
+//!
+//! Configured from the environment:
+//! - `DINGTALK_CLIENT_ID` and `DINGTALK_CLIENT_SECRET`, required
+//! - `HEALTH_ADDR`, address of the health endpoint, default `127.0.0.1:8080`
+//! - `SHUTDOWN_GRACE_SECS`, time left to outgoing messages after a stop request, default `5`
+//! - `RUST_LOG`, log filter, default `info`
+//!
+//! `GET /health` answers `200` while the credentials and gateway check out and `503` once they
+//! fail or the service is stopping. SIGTERM or SIGINT disconnects the stream right away, so no
+//! new messages arrive, then exits after the grace period. A systemd unit can be as simple as
+//!
+//! ```ini
+//! [Service]
+//! ExecStart=/usr/local/bin/daemon
+//! EnvironmentFile=/etc/dingtalk-bot.env
+//! Restart=on-failure
+//! TimeoutStopSec=15
+//! ```
+
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bevy::app::{AppExit, ScheduleRunnerPlugin};
+use bevy::log::{Level, LogPlugin};
+use bevy::prelude::*;
+use bevy_stream_dingtalk::client::{AsyncRuntime, Client, DingTalkClient};
+use bevy_stream_dingtalk::prelude::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// health results are reused for this long, every check asks DingTalk for a token and a ticket
+const HEALTH_CACHE: Duration = Duration::from_secs(30);
+
+#[derive(Resource, Debug, Clone)]
+struct DaemonConfig {
+    client_id: String,
+    client_secret: String,
+    health_addr: SocketAddr,
+    shutdown_grace: Duration,
+}
+
+impl DaemonConfig {
+    fn from_env() -> Result<Self, String> {
+        let var = |name: &str| std::env::var(name).map_err(|_| format!("{name} is not set"));
+        Ok(Self {
+            client_id: var("DINGTALK_CLIENT_ID")?,
+            client_secret: var("DINGTALK_CLIENT_SECRET")?,
+            health_addr: std::env::var("HEALTH_ADDR")
+                .unwrap_or_else(|_| "127.0.0.1:8080".to_owned())
+                .parse()
+                .map_err(|e| format!("HEALTH_ADDR: {e}"))?,
+            shutdown_grace: Duration::from_secs(
+                std::env::var("SHUTDOWN_GRACE_SECS")
+                    .unwrap_or_else(|_| "5".to_owned())
+                    .parse()
+                    .map_err(|e| format!("SHUTDOWN_GRACE_SECS: {e}"))?,
+            ),
+        })
+    }
+}
+
+/// State shared with the signal and health tasks on the plugin's runtime
+#[derive(Resource, Debug, Clone, Default)]
+struct Lifecycle(Arc<Mutex<LifecycleState>>);
+
+#[derive(Debug, Default)]
+struct LifecycleState {
+    /// set by the signal task
+    stop_requested: bool,
+    /// when the app started shutting down
+    stopping_since: Option<Instant>,
+    last_check: Option<(Instant, HealthReport)>,
+}
+
+fn main() {
+    let config = match DaemonConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+
+    App::new()
+        .add_plugins(
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1.0 / 30.0,
+            ))),
+        )
+        .add_plugins(LogPlugin {
+            level: Level::INFO,
+            filter: std::env::var("RUST_LOG").unwrap_or_else(|_| "info".to_owned()),
+            update_subscriber: None,
+        })
+        .add_plugins(
+            StreamDingTalkPlugin::new(config.client_id.clone(), config.client_secret.clone())
+                .message_filter(MessageFilter::MentionedOrDirect),
+        )
+        .insert_resource(config)
+        .init_resource::<Lifecycle>()
+        .add_systems(Startup, (listen_for_signals, serve_health))
+        .add_systems(Update, (echo, shut_down))
+        .run();
+
+    info!("stopped");
+}
+
+/// the bot itself, replace with real work
+fn echo(mut events: EventReader<RobotMessageReceived>, dingtalk: DingTalk) {
+    for event in events.read() {
+        dingtalk.reply(
+            &event.message,
+            MessageTemplate::SampleText {
+                content: event.message.content.summary(),
+            },
+        );
+    }
+}
+
+fn listen_for_signals(rt: Res<AsyncRuntime>, lifecycle: Res<Lifecycle>) {
+    let lifecycle = lifecycle.0.clone();
+    rt.spawn(async move {
+        if wait_for_stop().await {
+            lifecycle.lock().unwrap().stop_requested = true;
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_stop() -> bool {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (Ok(mut term), Ok(mut int)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) else {
+        error!("cannot install signal handlers");
+        return false;
+    };
+    tokio::select! {
+        _ = term.recv() => info!("SIGTERM received"),
+        _ = int.recv() => info!("SIGINT received"),
+    }
+    true
+}
+
+/// only Ctrl+C outside unix
+#[cfg(not(unix))]
+async fn wait_for_stop() -> bool {
+    tokio::signal::ctrl_c().await.is_ok()
+}
+
+/// disconnect on a stop request, exit once the grace period is over
+fn shut_down(
+    lifecycle: Res<Lifecycle>,
+    config: Res<DaemonConfig>,
+    mut keep: ResMut<KeepConnected>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let mut state = lifecycle.0.lock().unwrap();
+    if !state.stop_requested {
+        return;
+    }
+    match state.stopping_since {
+        None => {
+            info!("disconnecting, exit in {:?}", config.shutdown_grace);
+            // no more incoming messages, queued sends keep going
+            keep.0 = false;
+            state.stopping_since = Some(Instant::now());
+        }
+        Some(since) if since.elapsed() >= config.shutdown_grace => {
+            exit.send(AppExit);
+        }
+        Some(_) => {}
+    }
+}
+
+/// minimal HTTP/1.1 health endpoint, everything but `GET /health` is a 404
+fn serve_health(
+    rt: Res<AsyncRuntime>,
+    client: Res<DingTalkClient>,
+    config: Res<DaemonConfig>,
+    lifecycle: Res<Lifecycle>,
+) {
+    let client = client.clone();
+    let addr = config.health_addr;
+    let lifecycle = lifecycle.0.clone();
+    rt.spawn(async move {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(listener) => listener,
+            Err(e) => {
+                error!("health endpoint on {} error: {:?}", addr, e);
+                return;
+            }
+        };
+        info!("health endpoint on http://{}/health", addr);
+
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let client = client.clone();
+            let lifecycle = lifecycle.clone();
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let n = stream.read(&mut request).await.unwrap_or_default();
+                let (status, body) = if request[..n].starts_with(b"GET /health ") {
+                    health(&client, &lifecycle).await
+                } else {
+                    ("404 Not Found", "not found".to_owned())
+                };
+                let response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+}
+
+async fn health(client: &Arc<Client>, lifecycle: &Mutex<LifecycleState>) -> (&'static str, String) {
+    let cached = {
+        let state = lifecycle.lock().unwrap();
+        if state.stop_requested {
+            return ("503 Service Unavailable", "stopping".to_owned());
+        }
+        state
+            .last_check
+            .as_ref()
+            .filter(|(at, _)| at.elapsed() < HEALTH_CACHE)
+            .map(|(_, report)| report.clone())
+    };
+    let report = match cached {
+        Some(report) => report,
+        None => {
+            let report = client.health_check().await;
+            lifecycle.lock().unwrap().last_check = Some((Instant::now(), report.clone()));
+            report
+        }
+    };
+
+    if report.is_healthy() {
+        return ("200 OK", "ok".to_owned());
+    }
+    let failures = report
+        .failures()
+        .map(|(step, e)| format!("{step}: {e}"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    ("503 Service Unavailable", failures)
+}
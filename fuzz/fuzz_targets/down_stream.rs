@@ -0,0 +1,15 @@
+//! Arbitrary frames off the stream connection, the first thing parsed from the network
+
+#![no_main]
+
+use bevy_stream_dingtalk::protocol::{ClientDownStream, RobotRecvMessage};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(frame) = serde_json::from_slice::<ClientDownStream>(data) else {
+        return;
+    };
+    if let Ok(msg) = RobotRecvMessage::from_json(&frame.data) {
+        let _ = msg.content.summary();
+    }
+});
@@ -0,0 +1,17 @@
+//! Message templates serialize back to what they were parsed from
+
+#![no_main]
+
+use bevy_stream_dingtalk::protocol::MessageTemplate;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(template) = serde_json::from_slice::<MessageTemplate>(data) else {
+        return;
+    };
+    let value = serde_json::to_value(&template).unwrap();
+    let again: MessageTemplate = serde_json::from_value(value.clone()).unwrap();
+    assert_eq!(value, serde_json::to_value(&again).unwrap());
+
+    let _: Result<String, _> = template.try_into();
+});
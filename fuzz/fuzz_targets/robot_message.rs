@@ -0,0 +1,48 @@
+//! Robot message data, checks the content variant follows msgtype and unknown content survives
+//!
+//! Real payloads to start from are in `fixtures/robot_message` of the crate root:
+//! `cargo fuzz run robot_message fixtures/robot_message`
+
+#![no_main]
+
+use bevy_stream_dingtalk::protocol::{MsgContent, RobotRecvMessage};
+use libfuzzer_sys::fuzz_target;
+use serde_json::Value;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(data) = std::str::from_utf8(data) else {
+        return;
+    };
+    let Ok(msg) = RobotRecvMessage::from_json(data) else {
+        return;
+    };
+    let _ = msg.content.summary();
+    if let Some(msgtype) = variant_msgtype(&msg.content) {
+        assert_eq!(msgtype, msg.msgtype, "content decoded as the wrong variant");
+    }
+
+    if let MsgContent::UnknownMsgType { raw, .. } = &msg.content {
+        let value: Value = serde_json::from_str(data).unwrap();
+        let sent = value
+            .get("content")
+            .or_else(|| value.get("text"))
+            .cloned()
+            .unwrap_or_default();
+        assert_eq!(raw, &sent, "lenient parsing lost the content of {}", msg.msgtype);
+    }
+});
+
+fn variant_msgtype(content: &MsgContent) -> Option<&'static str> {
+    Some(match content {
+        MsgContent::Text { .. } => "text",
+        MsgContent::File { .. } => "file",
+        MsgContent::Picture { .. } => "picture",
+        MsgContent::RichText { .. } => "richText",
+        MsgContent::Audio { .. } => "audio",
+        MsgContent::Video { .. } => "video",
+        MsgContent::Location { .. } => "location",
+        MsgContent::Sticker(_) => "sticker",
+        MsgContent::Emotion(_) => "emotion",
+        MsgContent::UnknownMsgType { .. } | MsgContent::Custom { .. } => return None,
+    })
+}
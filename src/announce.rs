@@ -0,0 +1,144 @@
+//! Optional "online" and "shutting down" messages in an ops conversation
+//!
+//! Enabled with [`StreamDingTalkPlugin::announce`](crate::prelude::StreamDingTalkPlugin::announce).
+//! The online message goes out once the first stream connection is up, reconnects stay quiet.
+//! The shutdown message is sent when the app exits through [`AppExit`], holding the exit back
+//! for at most [`Announcement::exit_timeout`].
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use bevy::app::AppExit;
+use bevy::prelude::*;
+
+use crate::client::up::{MessageTemplate, RobotSendMessage};
+use crate::client::{AsyncRuntime, Client, DingTalkClient};
+
+/// Messages posted when the bot comes online and when it shuts down cleanly
+///
+/// `{version}` and `{commit}` in the texts are replaced, the commit reads `unknown` when not set.
+#[derive(Debug, Clone)]
+pub struct Announcement {
+    /// conversation receiving the messages, the plugin's ops conversation when `None`
+    pub conversation_id: Option<String>,
+    pub version: String,
+    pub commit: Option<String>,
+    /// default `online, version {version}, commit {commit}`
+    pub online: String,
+    /// default `shutting down`, nothing is sent on exit when `None`
+    pub offline: Option<String>,
+    /// longest wait for the shutdown message, default 3s
+    pub exit_timeout: Duration,
+}
+
+impl Announcement {
+    /// announce `version`, usually `env!("CARGO_PKG_VERSION")`
+    pub fn new(version: impl Into<String>) -> Self {
+        Self {
+            conversation_id: None,
+            version: version.into(),
+            commit: None,
+            online: "online, version {version}, commit {commit}".to_owned(),
+            offline: Some("shutting down".to_owned()),
+            exit_timeout: Duration::from_secs(3),
+        }
+    }
+
+    /// commit of the build, e.g. `option_env!("GIT_COMMIT")` exported by a build script
+    pub fn commit(mut self, commit: impl Into<String>) -> Self {
+        self.commit = Some(commit.into());
+        self
+    }
+
+    /// post to `conversation_id` instead of the ops conversation
+    pub fn conversation(mut self, conversation_id: impl Into<String>) -> Self {
+        self.conversation_id = Some(conversation_id.into());
+        self
+    }
+
+    pub fn online(mut self, template: impl Into<String>) -> Self {
+        self.online = template.into();
+        self
+    }
+
+    /// `None` sends nothing on exit
+    pub fn offline(mut self, template: Option<String>) -> Self {
+        self.offline = template;
+        self
+    }
+
+    pub fn exit_timeout(mut self, timeout: Duration) -> Self {
+        self.exit_timeout = timeout;
+        self
+    }
+
+    pub fn render(&self, template: &str) -> String {
+        template
+            .replace("{version}", &self.version)
+            .replace("{commit}", self.commit.as_deref().unwrap_or("unknown"))
+    }
+}
+
+/// [`Announcement`] with its conversation resolved
+#[derive(Debug, Resource)]
+pub(crate) struct Announcing {
+    pub conversation_id: String,
+    pub announcement: Announcement,
+}
+
+impl Announcing {
+    /// post the online message on the first connection
+    pub fn register(&self, client: &Arc<Client>) {
+        let conversation_id = self.conversation_id.clone();
+        let content = self.announcement.render(&self.announcement.online);
+        let announced = AtomicBool::new(false);
+        client.clone().on_connected(move |client, _| {
+            let first = !announced.swap(true, Ordering::SeqCst);
+            let conversation_id = conversation_id.clone();
+            let content = content.clone();
+            async move {
+                if first {
+                    post(&client, &conversation_id, content).await?;
+                }
+                Ok(())
+            }
+        });
+    }
+}
+
+async fn post(client: &Arc<Client>, conversation_id: &str, content: String) -> Result<()> {
+    RobotSendMessage::group(
+        client.clone(),
+        conversation_id,
+        MessageTemplate::SampleText { content },
+    )?
+    .send()
+    .await?;
+    Ok(())
+}
+
+/// send the shutdown message before the app loop ends
+pub(crate) fn announce_exit(
+    mut exits: EventReader<AppExit>,
+    announcing: Res<Announcing>,
+    client: Res<DingTalkClient>,
+    rt: Res<AsyncRuntime>,
+    mut sent: Local<bool>,
+) {
+    if exits.read().count() == 0 || std::mem::replace(&mut *sent, true) {
+        return;
+    }
+    let announcement = &announcing.announcement;
+    let Some(template) = &announcement.offline else {
+        return;
+    };
+    let content = announcement.render(template);
+    let sending = post(&client, &announcing.conversation_id, content);
+    match rt.block_on(tokio::time::timeout(announcement.exit_timeout, sending)) {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("send shutdown announcement error: {:?}", e),
+        Err(_) => warn!("shutdown announcement timed out"),
+    }
+}
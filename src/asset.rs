@@ -0,0 +1,97 @@
+//! Message templates loaded from `.dtmsg` asset files
+
+use anyhow::Result;
+use bevy::asset::io::Reader;
+use bevy::asset::{AssetLoader, AsyncReadExt, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use serde_json::Value;
+
+use crate::client::up::MessageTemplate;
+
+/// Registers [`MessageTemplateAsset`] and its `.dtmsg` loader
+///
+/// Add after `AssetPlugin` (part of `DefaultPlugins`). With bevy's `file_watcher` feature enabled,
+/// edited files are reloaded while the app runs.
+pub struct MessageTemplatePlugin;
+
+impl Plugin for MessageTemplatePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<MessageTemplateAsset>()
+            .init_asset_loader::<MessageTemplateLoader>();
+    }
+}
+
+/// A message template loaded from a `.dtmsg` file
+///
+/// The file is the json form of [`MessageTemplate`], string fields may contain `{name}`
+/// placeholders filled in by [`MessageTemplateAsset::render`]:
+///
+/// ```json
+/// { "msgKey": "sampleMarkdown", "msgParam": { "title": "Welcome", "text": "Hello **{name}**" } }
+/// ```
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct MessageTemplateAsset {
+    pub template: MessageTemplate,
+}
+
+impl MessageTemplateAsset {
+    /// copy of the template with every `{key}` replaced by its value
+    pub fn render<K, V>(&self, vars: impl IntoIterator<Item = (K, V)>) -> Result<MessageTemplate>
+    where
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let vars: Vec<_> = vars
+            .into_iter()
+            .map(|(k, v)| (format!("{{{}}}", k.as_ref()), v.as_ref().to_owned()))
+            .collect();
+        let mut value = serde_json::to_value(&self.template)?;
+        if let Some(param) = value.get_mut("msgParam") {
+            substitute(param, &vars);
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+fn substitute(value: &mut Value, vars: &[(String, String)]) {
+    match value {
+        Value::String(s) => {
+            for (key, v) in vars {
+                if s.contains(key.as_str()) {
+                    *s = s.replace(key.as_str(), v);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|v| substitute(v, vars)),
+        Value::Object(map) => map.values_mut().for_each(|v| substitute(v, vars)),
+        _ => {}
+    }
+}
+
+#[derive(Default)]
+pub struct MessageTemplateLoader;
+
+impl AssetLoader for MessageTemplateLoader {
+    type Asset = MessageTemplateAsset;
+    type Settings = ();
+    type Error = anyhow::Error;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let template = serde_json::from_slice(&bytes)?;
+            Ok(MessageTemplateAsset { template })
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["dtmsg"]
+    }
+}
@@ -0,0 +1,46 @@
+//! Channel that carries work from the async runtime back into the Bevy world
+
+use bevy::prelude::{Event, Resource, World};
+use log::trace;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::targets::BRIDGE;
+
+pub(crate) type WorldCommand = Box<dyn FnOnce(&mut World) + Send>;
+
+/// Sending half owned by [`Client`](crate::client::Client)
+///
+/// Stays detached until a Bevy app takes the receiver, so the plain async client never buffers
+/// events nobody is going to read.
+#[derive(Debug, Default)]
+pub(crate) struct Bridge(std::sync::Mutex<Option<UnboundedSender<WorldCommand>>>);
+
+impl Bridge {
+    pub fn attach(&self) -> BridgeReceiver {
+        let (tx, rx) = unbounded_channel();
+        *self.0.lock().unwrap() = Some(tx);
+        BridgeReceiver(rx)
+    }
+
+    pub fn run(&self, command: impl FnOnce(&mut World) + Send + 'static) {
+        if let Some(tx) = self.0.lock().unwrap().as_ref() {
+            let _ = tx.send(Box::new(command));
+        }
+    }
+
+    pub fn send_event<E: Event>(&self, event: E) {
+        trace!(target: BRIDGE, "send {}", std::any::type_name::<E>());
+        self.run(move |world| {
+            world.send_event(event);
+        });
+    }
+}
+
+#[derive(Resource)]
+pub(crate) struct BridgeReceiver(UnboundedReceiver<WorldCommand>);
+
+impl BridgeReceiver {
+    pub fn try_recv(&mut self) -> Option<WorldCommand> {
+        self.0.try_recv().ok()
+    }
+}
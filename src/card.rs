@@ -0,0 +1,104 @@
+//! Typed handling of interactive card clicks
+//!
+//! Add a [`CardActionRouter`] for a type the action parameters deserialize into, every click
+//! that decodes then arrives as a [`CardActionReceived`] event. An enum tagged by one of the
+//! card's parameters gives one variant per button:
+//!
+//! ```ignore
+//! #[derive(Deserialize, Debug)]
+//! #[serde(tag = "action", rename_all = "snake_case")]
+//! enum Rsvp {
+//!     Accept,
+//!     Decline { reason: String },
+//! }
+//!
+//! app.add_plugins(CardActionRouter::<Rsvp>::new());
+//! ```
+
+use std::marker::PhantomData;
+
+use bevy::prelude::*;
+use serde::de::DeserializeOwned;
+
+use crate::event::CardActionEvent;
+use crate::subscriptions::DingTalkSubscriptions;
+use crate::system::handle_network_events;
+
+/// A card click whose parameters decoded as `A`
+#[derive(Event, Debug)]
+pub struct CardActionReceived<A: Send + Sync + 'static> {
+    pub action: A,
+    pub event: CardActionEvent,
+}
+
+/// Decodes [`CardActionEvent`]s into [`CardActionReceived<A>`] events
+///
+/// Enables card callbacks in [`DingTalkSubscriptions`], add it after
+/// [`StreamDingTalkPlugin`](crate::plugin::StreamDingTalkPlugin). Clicks that do not decode are
+/// left to other routers.
+pub struct CardActionRouter<A> {
+    action_ids: Vec<String>,
+    marker: PhantomData<fn() -> A>,
+}
+
+impl<A> Default for CardActionRouter<A> {
+    fn default() -> Self {
+        Self {
+            action_ids: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<A: DeserializeOwned + Send + Sync + 'static> CardActionRouter<A> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// only route clicks of `action_id`, may be given several times, default is every action
+    pub fn action(mut self, action_id: impl Into<String>) -> Self {
+        self.action_ids.push(action_id.into());
+        self
+    }
+}
+
+#[derive(Resource)]
+struct CardRoutes<A> {
+    action_ids: Vec<String>,
+    marker: PhantomData<fn() -> A>,
+}
+
+impl<A: DeserializeOwned + Send + Sync + 'static> Plugin for CardActionRouter<A> {
+    fn build(&self, app: &mut App) {
+        if let Some(mut subscriptions) = app.world.get_resource_mut::<DingTalkSubscriptions>() {
+            subscriptions.set_card_callbacks(true);
+        }
+        app.add_event::<CardActionReceived<A>>()
+            .insert_resource(CardRoutes::<A> {
+                action_ids: self.action_ids.clone(),
+                marker: PhantomData,
+            })
+            .add_systems(Update, route_card_actions::<A>.after(handle_network_events));
+    }
+}
+
+fn route_card_actions<A: DeserializeOwned + Send + Sync + 'static>(
+    routes: Res<CardRoutes<A>>,
+    mut clicks: EventReader<CardActionEvent>,
+    mut actions: EventWriter<CardActionReceived<A>>,
+) {
+    for event in clicks.read() {
+        if !routes.action_ids.is_empty() && !routes.action_ids.contains(&event.action_id) {
+            continue;
+        }
+        match event.params::<A>() {
+            Ok(action) => {
+                actions.send(CardActionReceived {
+                    action,
+                    event: event.clone(),
+                });
+            }
+            Err(e) => trace!("card action {} not routed: {:?}", event.action_id, e),
+        }
+    }
+}
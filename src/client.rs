@@ -1,4 +1,7 @@
-use std::ops::Deref;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+};
 use anyhow::{bail, Result};
 use bevy::prelude::{debug, Deref, DerefMut, FromWorld, Resource, States, World};
 use chrono::{DateTime, Duration, Local};
@@ -9,27 +12,76 @@ use serde::{Deserialize, Serialize};
 use async_broadcast::{Receiver, Sender};
 
 use bevy::log::{error, info, trace, warn};
-use down::{ClientDownStream, EventData, RobotRecvMessage};
+use ack::{AckOrder, AckTracker, RecentIds};
+use contact::UserCache;
+use down::{
+    ClientDownStream, DownstreamEnvelope, EventData, MessageFilter, MsgTypeRegistry,
+    RobotRecvMessage,
+};
 use futures::{stream::SplitStream, Future, StreamExt};
 use native_tls::TlsConnector;
-use reqwest::{header::ACCEPT, ClientBuilder};
+use reqwest::{header::ACCEPT, ClientBuilder, Method};
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Arc, Mutex, RwLock,
 };
 use tokio::{net::TcpStream, runtime, sync::Notify, time::sleep};
 use tokio_tungstenite::{
-    connect_async_tls_with_config,
-    tungstenite::{Error, Message},
+    client_async_tls_with_config,
+    tungstenite::{
+        protocol::{CloseFrame, WebSocketConfig},
+        Error, Message,
+    },
     Connector, MaybeTlsStream, WebSocketStream,
 };
-use up::{EventAckData, Sink};
+use drive::DriveFallback;
+use hooks::LinkHooks;
+use mock::MockTransport;
+use net::{connect_tcp, CloseAction, ClosePolicy, ConnectOptions, HttpPoolOptions};
+use prompt::PendingPrompts;
+use quiet::{HeldSends, QuietHours};
+use stats::{LinkCounters, MessageStats};
+use tenant::TokenManager;
+use throttle::Throttle;
+use up::{EventAckData, SendTapped, Sink};
+use zone::Zone;
 
-use crate::constant::{GATEWAY_URL, GET_TOKEN_URL};
+use crate::bridge::Bridge;
+use crate::clock::{Clock, SystemClock};
+use crate::constant::{API_BASE_URL, GATEWAY_URL, GET_TOKEN_URL, OAPI_BASE_URL, UPLOAD_URL};
+use crate::targets::{TOKEN, WS};
+use crate::error::{DingTalkError, ErrorContext, GatewayError, GatewayErrorKind};
+use crate::storage::{MemoryStorage, Storage, TOKENS};
+use crate::event::{
+    AuthFailedEvent, DingTalkErrorEvent, FatalCloseEvent, FrameErrorEvent, GatewayErrorEvent,
+};
 
+pub mod ack;
+pub mod assistant;
+pub mod auth;
+pub mod bundle;
+pub mod card;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod contact;
 pub mod down;
+pub mod drive;
 pub mod group;
+pub mod health;
+pub mod hooks;
+pub mod jsapi;
+pub mod media;
+pub mod mock;
+pub mod net;
+pub mod prompt;
+pub mod quiet;
+pub mod stats;
+pub mod suspend;
+pub mod tap;
+pub mod tenant;
+pub mod throttle;
 pub mod up;
+pub mod zone;
 
 #[derive(Debug, Resource, Deref, DerefMut)]
 pub struct AsyncRuntime(pub tokio::runtime::Runtime);
@@ -40,6 +92,22 @@ pub enum ConnectionState {
     Connecting,
     #[default]
     Disconnected,
+    /// Reconnecting gave up after rejected credentials or a fatal close code, turn
+    /// [`KeepConnected`] off and on again to retry
+    Stopped,
+}
+
+/// Whether the plugin keeps the stream connected, default `true`
+///
+/// Set it directly or map app states to it with
+/// [`StreamDingTalkPlugin::connect_in_states`](crate::plugin::StreamDingTalkPlugin::connect_in_states).
+#[derive(Debug, Resource, Clone, Copy, PartialEq, Eq)]
+pub struct KeepConnected(pub bool);
+
+impl Default for KeepConnected {
+    fn default() -> Self {
+        Self(true)
+    }
 }
 
 #[derive(Resource)]
@@ -53,6 +121,33 @@ impl DingTalkClient {
             client: Client::new(client_id, client_secret)?
         })
     }
+
+    pub fn new_with_http(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        http: reqwest::Client,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: Client::new_with_http(client_id, client_secret, http)?
+        })
+    }
+
+    pub fn new_with_pool(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        pool: HttpPoolOptions,
+    ) -> Result<Self> {
+        Ok(Self {
+            client: Client::new_with_pool(client_id, client_secret, pool)?
+        })
+    }
+
+    /// second resource for the same client, e.g. for the main world of a sub app
+    pub(crate) fn shared(&self) -> Self {
+        Self {
+            client: self.client.clone(),
+        }
+    }
 }
 
 impl Deref for DingTalkClient {
@@ -74,12 +169,38 @@ pub struct Client {
     rx: Receiver<Arc<ClientDownStream>>,
     tx: Sender<Arc<ClientDownStream>>,
     on_event_callback: EventCallback,
-    sink: tokio::sync::Mutex<Option<Sink>>,
-    alive: AtomicBool,
+    sinks: tokio::sync::Mutex<HashMap<usize, Sink>>,
     user_exit: AtomicBool,
+    auth_failed: AtomicBool,
     aborting: Arc<Notify>,
+    pub(crate) bridge: Bridge,
+    pub(crate) users: UserCache,
+    stats: Mutex<MessageStats>,
+    links: LinkCounters,
+    frames_received: AtomicU64,
+    frame_errors: AtomicU64,
+    tenant_tokens: TokenManager,
+    acks: AckTracker,
+    seen: Mutex<RecentIds>,
+    restarts: AtomicU64,
+    storage: StorageSlot,
+    clock: RwLock<Arc<dyn Clock>>,
+    marks: AtomicU64,
+    held: HeldSends,
+    /// unanswered skill invocations and the connection they arrived on
+    pending_skills: Mutex<HashMap<String, usize>>,
+    pub(crate) msg_types: MsgTypeRegistry,
+    prompts: PendingPrompts,
+    hooks: LinkHooks,
+    mock: MockTransport,
+    throttle: Throttle,
+    #[cfg(feature = "chaos")]
+    chaos: chaos::Chaos,
+    tap: tap::Tap,
 }
 
+type FrameStream = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+
 struct EventCallback(RwLock<Box<dyn Fn(EventData) -> EventAckData + Send + Sync>>);
 
 impl std::fmt::Debug for EventCallback {
@@ -88,11 +209,48 @@ impl std::fmt::Debug for EventCallback {
     }
 }
 
+struct StorageSlot(RwLock<Arc<dyn Storage>>);
+
+impl std::fmt::Debug for StorageSlot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("StorageSlot").finish()
+    }
+}
+
+/// access token as kept in [`Storage`]
+#[derive(Serialize, Deserialize)]
+struct StoredToken {
+    access_token: String,
+    /// unix timestamp in seconds
+    expires_at: i64,
+}
+
 impl Client {
     /// Create new client, need to specific the id and secret they provided when creating the robot
     pub fn new(
         client_id: impl Into<String>,
         client_secret: impl Into<String>,
+    ) -> Result<Arc<Self>> {
+        Self::new_with_pool(client_id, client_secret, HttpPoolOptions::default())
+    }
+
+    /// Create new client whose https client keeps its connections as `pool` says
+    pub fn new_with_pool(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        pool: HttpPoolOptions,
+    ) -> Result<Arc<Self>> {
+        Self::new_with_http_builder(client_id, client_secret, |builder| pool.apply(builder))
+    }
+
+    /// Create new client sending https requests through `http`
+    ///
+    /// For connection pools, local bind addresses, DNS overrides or proxies the crate should not
+    /// decide on. The websocket connection is not affected.
+    pub fn new_with_http(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        http: reqwest::Client,
     ) -> Result<Arc<Self>> {
         let client_id = client_id.into();
         let client_secret = client_secret.into();
@@ -103,29 +261,220 @@ impl Client {
                 client_secret,
                 ..Default::default()
             })),
-            client: ClientBuilder::new()
-                .no_proxy()
-                .danger_accept_invalid_certs(true)
-                .build()?,
+            client: http,
             tx,
             rx,
-            sink: tokio::sync::Mutex::new(None),
+            sinks: tokio::sync::Mutex::new(HashMap::new()),
             on_event_callback: EventCallback(RwLock::new(Box::new(|p| {
                 info!("default event callback, event received: {:?}", p);
                 EventAckData::default()
             }))),
-            alive: AtomicBool::new(false),
             user_exit: AtomicBool::new(false),
+            auth_failed: AtomicBool::new(false),
             aborting: Arc::new(Notify::new()),
+            bridge: Bridge::default(),
+            users: UserCache::default(),
+            stats: Default::default(),
+            links: LinkCounters::default(),
+            frames_received: AtomicU64::new(0),
+            frame_errors: AtomicU64::new(0),
+            tenant_tokens: TokenManager::default(),
+            acks: AckTracker::default(),
+            seen: Mutex::new(RecentIds::new(SEEN_CAPACITY)),
+            restarts: AtomicU64::new(0),
+            storage: StorageSlot(RwLock::new(MemoryStorage::new())),
+            clock: RwLock::new(Arc::new(SystemClock)),
+            marks: AtomicU64::new(0),
+            held: HeldSends::default(),
+            pending_skills: Mutex::new(HashMap::new()),
+            msg_types: MsgTypeRegistry::default(),
+            prompts: PendingPrompts::default(),
+            hooks: LinkHooks::default(),
+            mock: MockTransport::default(),
+            throttle: Throttle::default(),
+            #[cfg(feature = "chaos")]
+            chaos: Default::default(),
+            tap: Default::default(),
         }))
     }
 
+    /// Create new client whose https client is the default one adjusted by `customize`
+    pub fn new_with_http_builder(
+        client_id: impl Into<String>,
+        client_secret: impl Into<String>,
+        customize: impl FnOnce(ClientBuilder) -> ClientBuilder,
+    ) -> Result<Arc<Self>> {
+        let builder = ClientBuilder::new()
+            .no_proxy()
+            .danger_accept_invalid_certs(true);
+        Self::new_with_http(client_id, client_secret, customize(builder).build()?)
+    }
+
+    /// Persist tokens and dedupe state in `storage` instead of memory
+    pub fn storage(self: Arc<Self>, storage: Arc<dyn Storage>) -> Arc<Self> {
+        *self.storage.0.write().unwrap() = storage;
+        self
+    }
+
+    /// the [`Storage`] in use, embedders may keep their own namespaces in it
+    pub fn store(&self) -> Arc<dyn Storage> {
+        self.storage.0.read().unwrap().clone()
+    }
+
+    /// Read the time from `clock` for expiry checks, schedules and cooldowns, e.g. a
+    /// [`ManualClock`](crate::clock::ManualClock) in tests
+    pub fn clock(self: Arc<Self>, clock: Arc<dyn Clock>) -> Arc<Self> {
+        *self.clock.write().unwrap() = clock;
+        self
+    }
+
+    /// the [`Clock`] in use
+    pub fn current_clock(&self) -> Arc<dyn Clock> {
+        self.clock.read().unwrap().clone()
+    }
+
+    /// wall clock time of the [`Clock`] in use
+    pub fn now(&self) -> DateTime<Local> {
+        self.clock.read().unwrap().local()
+    }
+
+    /// cached token under `key`, `None` when missing, expired or unreadable
+    pub(crate) fn load_token(&self, key: &str) -> Option<(String, DateTime<Local>)> {
+        match self.store().get_json::<StoredToken>(TOKENS, key) {
+            Ok(Some(token)) => DateTime::from_timestamp(token.expires_at, 0)
+                .map(|at| at.with_timezone(&Local))
+                .filter(|at| self.now() < *at)
+                .map(|at| (token.access_token, at)),
+            Ok(None) => None,
+            Err(e) => {
+                warn!(target: TOKEN, "load token {} error: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn save_token(&self, key: &str, access_token: &str, expires_at: DateTime<Local>) {
+        let token = StoredToken {
+            access_token: access_token.to_owned(),
+            expires_at: expires_at.timestamp(),
+        };
+        if let Err(e) = self.store().put_json(TOKENS, key, &token) {
+            warn!(target: TOKEN, "save token {} error: {:?}", key, e);
+        }
+    }
+
+    /// true when `key` was stored by [`Client::remember`], errors count as unknown
+    pub(crate) fn remembered(&self, namespace: &str, key: &str) -> bool {
+        match self.store().get(namespace, key) {
+            Ok(found) => found.is_some(),
+            Err(e) => {
+                warn!("read {} store error: {:?}", namespace, e);
+                false
+            }
+        }
+    }
+
+    /// persist `key` for about `ttl_secs`, expired keys are swept every [`MARK_SWEEP`] calls
+    pub(crate) fn remember(&self, namespace: &str, key: &str, ttl_secs: i64) {
+        let storage = self.store();
+        let now = self.current_clock().now();
+        if let Err(e) = storage.mark(namespace, key, now) {
+            warn!("write {} store error: {:?}", namespace, e);
+        }
+        if self.marks.fetch_add(1, Ordering::Relaxed) % MARK_SWEEP == MARK_SWEEP - 1 {
+            if let Err(e) = storage.remove_expired(namespace, ttl_secs, now) {
+                warn!("sweep {} store error: {:?}", namespace, e);
+            }
+        }
+    }
+
     /// Change the User-Agent
     pub fn ua(self: Arc<Self>, value: impl Into<String>) -> Arc<Self> {
         self.config.lock().unwrap().ua = value.into();
         self
     }
 
+    /// Log outgoing sends instead of performing them, the stream keeps receiving as usual.
+    /// Can also be toggled at runtime through [`Client::config`].
+    pub fn dry_run(self: Arc<Self>, value: bool) -> Arc<Self> {
+        self.config.lock().unwrap().dry_run = value;
+        self
+    }
+
+    /// true when the send described by `what`, a `method` request to `url`, must be skipped
+    /// because of dry-run mode
+    pub(crate) fn skip_in_dry_run(
+        &self,
+        what: &str,
+        method: Method,
+        url: &str,
+        body: impl std::fmt::Display,
+    ) -> bool {
+        let dry_run = self.config.lock().unwrap().dry_run;
+        if dry_run {
+            info!("[dry-run] {}: {}", what, body);
+            if self.tapping() {
+                self.tap.record_skipped(method, url, &body.to_string());
+            }
+        }
+        dry_run
+    }
+
+    /// Robot code used for sends and downloads, when the app has more than one robot.
+    /// Defaults to the client id.
+    pub fn robot_code(self: Arc<Self>, value: impl Into<String>) -> Arc<Self> {
+        self.config.lock().unwrap().robot_code = Some(value.into());
+        self
+    }
+
+    /// configured robot code, falling back to the client id
+    pub(crate) fn current_robot_code(&self) -> String {
+        let config = self.config.lock().unwrap();
+        config
+            .robot_code
+            .clone()
+            .unwrap_or_else(|| config.client_id.clone())
+    }
+
+    /// Change how the websocket connection is established, e.g. prefer IPv4 on networks with
+    /// broken IPv6, see [`ConnectOptions`]
+    pub fn connect_options(self: Arc<Self>, value: ConnectOptions) -> Arc<Self> {
+        self.config.lock().unwrap().connect = value;
+        self
+    }
+
+    /// Change websocket size limits, see [`WebSocketLimits`]
+    pub fn websocket_limits(self: Arc<Self>, value: WebSocketLimits) -> Arc<Self> {
+        self.config.lock().unwrap().websocket = value;
+        self
+    }
+
+    /// Point the client at a private-cloud or regional deployment
+    pub fn endpoints(self: Arc<Self>, value: Endpoints) -> Arc<Self> {
+        self.config.lock().unwrap().endpoints = value;
+        self
+    }
+
+    /// full url of an `api.dingtalk.com` path on the configured deployment
+    pub(crate) fn api_url(&self, path: &str) -> String {
+        format!("{}{}", self.config.lock().unwrap().endpoints.api, path)
+    }
+
+    /// full url of an `oapi.dingtalk.com` path on the configured deployment
+    pub(crate) fn oapi_url(&self, path: &str) -> String {
+        format!("{}{}", self.config.lock().unwrap().endpoints.oapi, path)
+    }
+
+    /// Open `value` stream connections at once, default is 1
+    ///
+    /// Every connection gets its own ticket and DingTalk balances frames across them, so dropping
+    /// one of them does not interrupt receiving. Frames delivered more than once are dispatched
+    /// only the first time.
+    pub fn connections(self: Arc<Self>, value: usize) -> Arc<Self> {
+        self.config.lock().unwrap().connections = value.max(1);
+        self
+    }
+
     /// Control client side keep alive heartbeat interval(ms), default is 8000.
     /// When set to 0, means disable keep alive heartbeat.
     pub fn keep_alive(self: Arc<Self>, value: i64) -> Arc<Self> {
@@ -140,8 +489,105 @@ impl Client {
         self
     }
 
+    /// Choose how a connection reconnects after the server closed it, by close code.
+    /// The default reconnects right away on a normal closure, stops on a policy violation and
+    /// backs off otherwise.
+    pub fn close_policy(self: Arc<Self>, value: ClosePolicy) -> Arc<Self> {
+        self.config.lock().unwrap().close_policy = value;
+        self
+    }
+
+    /// Control how received frames are logged, default is [`FrameLogging::Full`].
+    /// Can also be changed at runtime through [`Client::config`].
+    pub fn frame_logging(self: Arc<Self>, value: FrameLogging) -> Arc<Self> {
+        self.config.lock().unwrap().frame_logging = value;
+        self
+    }
+
+    /// Number of frames that could not be parsed or handled since the client was created
+    pub fn frame_errors(&self) -> u64 {
+        self.frame_errors.load(Ordering::Relaxed)
+    }
+
+    /// a single bad frame is logged and reported, the connection stays up
+    fn on_frame_error(&self, message_id: String, error: anyhow::Error) {
+        self.frame_errors.fetch_add(1, Ordering::Relaxed);
+        warn!("handle frame {} error: {:?}", message_id, error);
+        self.error_event(ErrorContext::Parse, format!("handle frame {message_id}"), &error);
+        self.bridge.send_event(FrameErrorEvent {
+            message_id,
+            error: error.to_string(),
+        });
+    }
+
+    /// log `error` and report it as a [`DingTalkErrorEvent`]
+    pub(crate) fn report_error(
+        &self,
+        context: ErrorContext,
+        what: impl Into<String>,
+        error: &anyhow::Error,
+    ) {
+        let what = what.into();
+        error!("{} error: {:?}", what, error);
+        self.error_event(context, what, error);
+    }
+
+    /// report a failure already logged as a [`DingTalkErrorEvent`]
+    pub(crate) fn error_event(
+        &self,
+        context: ErrorContext,
+        what: impl Into<String>,
+        error: impl std::fmt::Display,
+    ) {
+        self.bridge.send_event(DingTalkErrorEvent {
+            context,
+            what: what.into(),
+            error: format!("{:#}", error),
+        });
+    }
+
+    /// text of a binary frame, gunzipped first with the `gzip` feature
+    fn decode_binary(&self, data: Vec<u8>) -> Option<String> {
+        debug!(target: WS, "recv websocket binary, {} bytes", data.len());
+        let text = if data.starts_with(&GZIP_MAGIC) {
+            gunzip(&data)
+        } else {
+            String::from_utf8(data).map_err(Into::into)
+        };
+        self.record_binary(text.is_ok());
+        text.map_err(|e| warn!(target: WS, "drop websocket binary: {:?}", e)).ok()
+    }
+
+    fn log_frame(&self, text: &str) {
+        let policy = self.config.lock().unwrap().frame_logging;
+        match policy {
+            FrameLogging::Off => {}
+            FrameLogging::Full => debug!(target: WS, "recv websocket text: {text}"),
+            FrameLogging::Sampled(n) => {
+                let seq = self.frames_received.fetch_add(1, Ordering::Relaxed);
+                if n <= 1 || seq.is_multiple_of(n as u64) {
+                    debug!(target: WS, "recv websocket text (1 in {n}): {text}");
+                }
+            }
+            FrameLogging::Truncated(max) => {
+                if text.len() <= max {
+                    debug!(target: WS, "recv websocket text: {text}");
+                } else {
+                    debug!(
+                        target: WS,
+                        "recv websocket text: {}... ({} bytes)",
+                        truncate_on_char(text, max),
+                        text.len()
+                    );
+                }
+            }
+        }
+    }
+
     /// Add listener to watch all event.
     /// Calling this interface multiple times will replace the old listener with a new one.
+    /// The returned [`EventAckData`] is sent as the event's ACK, response `data` included.
+    /// Fields of payloads without a type here are read with [`EventData::extract`].
     pub fn register_all_event_listener<P>(self: Arc<Self>, on_event_received: P) -> Arc<Self>
     where
         P: Fn(EventData) -> EventAckData + Send + Sync + 'static,
@@ -160,34 +606,115 @@ impl Client {
         P: Fn(Arc<Self>, RobotRecvMessage) -> F + Send + 'static,
         F: Future<Output = Result<()>> + Send,
     {
-        let event_id = event_id.as_ref();
-        {
-            let mut config = self.config.lock().unwrap();
-            if !config
-                .subscriptions
-                .iter()
-                .any(|s| s.topic == event_id && s.r#type == "CALLBACK")
-            {
-                config.subscriptions.push(Subscription {
-                    topic: event_id.to_owned(),
-                    r#type: "CALLBACK".to_owned(),
-                });
+        self.register_filtered_callback_listener(event_id, MessageFilter::All, callback)
+    }
+
+    /// Add listener to watch specifc event id, only messages accepted by `filter` are delivered
+    ///
+    /// Use [`MessageFilter::MentionedOrDirect`] to skip group chatter that does not @ the robot
+    pub fn register_filtered_callback_listener<P, F>(
+        self: Arc<Self>,
+        event_id: impl AsRef<str>,
+        filter: MessageFilter,
+        callback: P,
+    ) -> Arc<Self>
+    where
+        P: Fn(Arc<Self>, RobotRecvMessage) -> F + Send + 'static,
+        F: Future<Output = Result<()>> + Send,
+    {
+        self.register_enveloped_listener(event_id, filter, move |s, msg, _| callback(s, msg))
+    }
+
+    /// Add listener receiving every CALLBACK frame on `topic` unparsed, for topics this crate
+    /// has no types for
+    pub fn register_raw_listener<P, F>(
+        self: Arc<Self>,
+        topic: impl AsRef<str>,
+        callback: P,
+    ) -> Arc<Self>
+    where
+        P: Fn(Arc<Self>, DownstreamEnvelope) -> F + Send + 'static,
+        F: Future<Output = Result<()>> + Send,
+    {
+        let topic = topic.as_ref().to_owned();
+        self.subscribe_callback(&topic);
+
+        tokio::spawn({
+            let mut rx = self.rx.clone();
+            let s = self.clone();
+            async move {
+                while let Ok(frame) = rx.recv().await {
+                    if frame.headers.topic != topic {
+                        continue;
+                    }
+                    if let Err(e) = callback(s.clone(), DownstreamEnvelope::from(&*frame)).await {
+                        let what = format!("raw listener on {topic}");
+                        s.report_error(ErrorContext::Callback, what, &e);
+                    }
+                }
             }
+        });
+
+        self
+    }
+
+    fn subscribe_callback(&self, topic: &str) {
+        let mut config = self.config.lock().unwrap();
+        if !config
+            .subscriptions
+            .iter()
+            .any(|s| s.topic == topic && s.r#type == "CALLBACK")
+        {
+            config.subscriptions.push(Subscription {
+                topic: topic.to_owned(),
+                r#type: "CALLBACK".to_owned(),
+            });
         }
+    }
+
+    /// robot message listener also handed the frame it arrived in
+    pub(crate) fn register_enveloped_listener<P, F>(
+        self: Arc<Self>,
+        event_id: impl AsRef<str>,
+        filter: MessageFilter,
+        callback: P,
+    ) -> Arc<Self>
+    where
+        P: Fn(Arc<Self>, RobotRecvMessage, DownstreamEnvelope) -> F + Send + 'static,
+        F: Future<Output = Result<()>> + Send,
+    {
+        let event_id = event_id.as_ref();
+        self.subscribe_callback(event_id);
 
         tokio::spawn({
             let mut rx = self.rx.clone();
             let s = self.clone();
+            let topic = event_id.to_owned();
             async move {
                 while let Ok(msg) = rx.recv().await {
-                    match serde_json::from_str(&msg.data) {
-                        Ok(msg) => {
-                            if let Err(e) = callback(s.clone(), msg).await {
-                                error!("callback error: {:?}", e);
+                    if msg.headers.topic != topic {
+                        continue;
+                    }
+                    match s.parse_robot_message(&msg.data) {
+                        Ok(recv) => {
+                            if !filter.matches(&recv) {
+                                trace!("message {} skipped by {:?}", recv.msg_id, filter);
+                                if let Some(ack) = s.frame_ack_token(&msg.headers.message_id) {
+                                    ack.success();
+                                }
+                                continue;
+                            }
+                            let envelope = DownstreamEnvelope::from(&*msg);
+                            if let Err(e) = callback(s.clone(), recv, envelope).await {
+                                s.report_error(ErrorContext::Callback, "callback", &e);
                             }
                         }
                         Err(e) => {
-                            error!("can not parse data: {:?}", e);
+                            s.report_error(ErrorContext::Parse, "parse robot message", &e);
+                            // a redelivery would not parse either
+                            if let Some(ack) = s.frame_ack_token(&msg.headers.message_id) {
+                                ack.success();
+                            }
                         }
                     }
                 }
@@ -198,13 +725,31 @@ impl Client {
     }
 
     pub(crate) async fn token(&self) -> Result<String> {
-        let (access_token, token_expires_in) = {
+        #[cfg(feature = "chaos")]
+        if self.chaos.take_token_expiry() {
+            debug!(target: TOKEN, "chaos: token expired");
+            return self.get_token().await;
+        }
+        let (mut access_token, mut token_expires_in, client_id) = {
             let config = self.config.lock().unwrap();
-            (config.access_token.clone(), config.token_expires_in)
+            (
+                config.access_token.clone(),
+                config.token_expires_in,
+                config.client_id.clone(),
+            )
         };
+        if access_token.is_empty() {
+            if let Some((token, expires)) = self.load_token(&client_id) {
+                debug!(target: TOKEN, "reuse stored token");
+                let mut config = self.config.lock().unwrap();
+                config.access_token = token.clone();
+                config.token_expires_in = expires;
+                (access_token, token_expires_in) = (token, expires);
+            }
+        }
 
-        Ok(if Local::now() > token_expires_in {
-            debug!("token expired, get token again");
+        Ok(if self.now() > token_expires_in {
+            debug!(target: TOKEN, "token expired, get token again");
             self.get_token().await?
         } else {
             access_token
@@ -214,13 +759,13 @@ impl Client {
     async fn get_token(&self) -> Result<String> {
         let url = {
             let config = self.config.lock().unwrap();
-            debug!("get connect endpoint by config {:#?}", *config);
+            debug!(target: TOKEN, "get connect endpoint by config {:#?}", *config);
             format!(
-                "{GET_TOKEN_URL}?appkey={}&appsecret={}",
-                config.client_id, config.client_secret
+                "{}?appkey={}&appsecret={}",
+                config.endpoints.token, config.client_id, config.client_secret
             )
         };
-        let response = self.client.get(url).send().await?;
+        let response = self.client.get(url).send_tapped(self).await?;
         if !response.status().is_success() {
             bail!(
                 "get token http error: {} - {}",
@@ -230,6 +775,9 @@ impl Client {
         }
 
         let token: TokenResponse = response.json().await?;
+        if INVALID_CREDENTIAL_CODES.contains(&token.errcode) {
+            self.on_auth_failed(format!("get token: {} - {}", token.errcode, token.errmsg));
+        }
         if token.errcode != 0 {
             bail!(
                 "get token content error: {} - {}",
@@ -238,41 +786,57 @@ impl Client {
             );
         }
 
-        debug!("get token: {:?}", token);
+        debug!(target: TOKEN, "get token: {:?}", token);
         let access_token = token.access_token;
-        let mut config = self.config.lock().unwrap();
-        config.access_token = access_token.clone();
-        config.token_expires_in = Local::now() + Duration::seconds(token.expires_in as i64);
+        let expires = self.now() + Duration::seconds(token.expires_in as i64);
+        let client_id = {
+            let mut config = self.config.lock().unwrap();
+            config.access_token = access_token.clone();
+            config.token_expires_in = expires;
+            config.client_id.clone()
+        };
+        self.save_token(&client_id, &access_token, expires);
         Ok(access_token)
     }
 
     async fn get_endpoint(&self) -> Result<String> {
         let token = self.get_token().await?;
+        let gateway = self.config.lock().unwrap().endpoints.gateway.clone();
 
         let response = self
             .client
-            .post(GATEWAY_URL)
+            .post(gateway)
             .json(&*self.config)
             .header(ACCEPT, "application/json")
             .header("access-token", token)
-            .send()
+            .send_tapped(self)
             .await?;
         if !response.status().is_success() {
-            bail!(
-                "get endpoint http error: {} - {}",
-                response.status(),
-                response.text().await?
-            );
+            let status = response.status().as_u16();
+            let error = GatewayError::from_body(status, &response.text().await?);
+            error!(target: WS, "get endpoint failed: {}", error);
+            self.log_subscription_diagnostics(&error);
+            self.error_event(ErrorContext::Connection, "get endpoint", &error);
+            if error.kind() == GatewayErrorKind::InvalidCredentials {
+                self.on_auth_failed(error.to_string());
+            }
+            self.bridge.send_event(GatewayErrorEvent(error.clone()));
+            return Err(error.into());
         }
 
         let endpoint: EndpointResponse = response.json().await?;
-        debug!("get endpoint: {:?}", endpoint);
+        debug!(target: WS, "get endpoint: {:?}", endpoint);
         let EndpointResponse { endpoint, ticket } = endpoint;
 
         Ok(format!("{endpoint}?ticket={ticket}"))
     }
 
-    async fn serve(self: &Arc<Self>, url: String) -> Result<()> {
+    /// run one connection until it drops, with the close frame if the server sent one
+    async fn serve(
+        self: &Arc<Self>,
+        link: usize,
+        url: String,
+    ) -> Result<Option<CloseFrame<'static>>> {
         let tls_connect = Connector::NativeTls({
             TlsConnector::builder()
                 .danger_accept_invalid_certs(true)
@@ -280,12 +844,16 @@ impl Client {
                 .build()?
         });
 
+        let (ws_config, connect_options) = {
+            let config = self.config.lock().unwrap();
+            (config.websocket.to_config(), config.connect)
+        };
+        let tcp = connect_tcp(&url, &connect_options).await?;
         let (stream, _) =
-            match connect_async_tls_with_config(&url, None, false, Some(tls_connect)).await {
-                Ok(x) => {
-                    self.alive.store(true, Ordering::SeqCst);
-                    x
-                }
+            match client_async_tls_with_config(&url, tcp, Some(ws_config), Some(tls_connect))
+                .await
+            {
+                Ok(x) => x,
                 Err(e) => {
                     if let Error::Http(ref h) = e {
                         bail!(
@@ -300,22 +868,26 @@ impl Client {
             };
 
         let (sink, stream) = stream.split();
-        *self.sink.lock().await = Some(sink);
+        self.sinks.lock().await.insert(link, sink);
+        self.run_connected_hooks(link);
+        let alive = Arc::new(AtomicBool::new(true));
+        let dropped = Arc::new(Notify::new());
         let heartbeat_interval = self.config.lock().unwrap().heartbeat_interval;
         if heartbeat_interval > 0 {
             tokio::spawn({
                 let s = self.clone();
-                let aborting = self.aborting.clone();
+                let alive = alive.clone();
+                let dropped = dropped.clone();
                 async move {
                     loop {
-                        if !s.alive.load(Ordering::SeqCst) {
-                            aborting.notify_one();
+                        if !alive.load(Ordering::SeqCst) {
+                            dropped.notify_one();
                             break;
                         }
 
-                        trace!("websocket ping");
-                        s.alive.store(false, Ordering::SeqCst);
-                        let _ = s.ping().await;
+                        trace!(target: WS, "websocket ping");
+                        alive.store(false, Ordering::SeqCst);
+                        let _ = s.ping(link).await;
                         // heartbeat_interval is always larger than zero, to_std() never failed. unwrap is safe here
                         sleep(Duration::milliseconds(heartbeat_interval).to_std().unwrap()).await;
                     }
@@ -323,78 +895,208 @@ impl Client {
             });
         }
 
+        let mut closed = None;
         tokio::select! {
-            _ = self.aborting.notified() => { warn!("server aborting"); }
-            _ = self.process(stream) => { warn!("server error or closed"); }
+            _ = self.aborting.notified() => { warn!(target: WS, "server aborting"); }
+            _ = dropped.notified() => { warn!(target: WS, "connection {} heartbeat lost", link); }
+            result = self.process(link, &alive, stream) => {
+                warn!(target: WS, "server error or closed");
+                closed = result.unwrap_or_default();
+            }
         }
 
-        self.alive.store(false, Ordering::SeqCst);
-        Ok(())
+        alive.store(false, Ordering::SeqCst);
+        self.sinks.lock().await.remove(&link);
+        self.run_disconnected_hooks(link);
+        Ok(closed)
     }
 
     async fn process(
-        &self,
-        mut stream: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-    ) -> Result<()> {
-        while let Some(message) = stream.next().await {
+        self: &Arc<Self>,
+        link: usize,
+        alive: &AtomicBool,
+        mut stream: FrameStream,
+    ) -> Result<Option<CloseFrame<'static>>> {
+        while let Some(message) = self.next_frame(&mut stream).await {
             let message = match message {
                 Ok(m) => m,
+                Err(Error::Capacity(e)) => {
+                    error!(
+                        target: WS,
+                        "recv websocket message exceeds limits: {}, raise ClientConfig::websocket",
+                        e
+                    );
+                    break;
+                }
                 Err(e) => {
-                    error!("recv websocket message error: {:?}", e);
+                    error!(target: WS, "recv websocket message error: {:?}", e);
+                    self.error_event(ErrorContext::Connection, "recv websocket message", &e);
                     break;
                 }
             };
 
+            let message = match message {
+                Message::Binary(data) => match self.decode_binary(data) {
+                    Some(t) => Message::Text(t),
+                    None => continue,
+                },
+                message => message,
+            };
+
             match message {
                 Message::Text(t) => {
-                    debug!("recv websocket text: {t}");
+                    #[cfg(feature = "chaos")]
+                    let Some(t) = self.chaos.on_frame(t).await else {
+                        break;
+                    };
+                    self.log_frame(&t);
                     match serde_json::from_str::<ClientDownStream>(&t) {
-                        Ok(p) => self.on_down_stream(p).await?,
+                        Ok(mut p) => {
+                            p.link = link;
+                            let message_id = p.headers.message_id.clone();
+                            if let Err(e) = self.on_down_stream(p).await {
+                                // a broken sink means the connection is gone, reconnect
+                                if e.downcast_ref::<Error>().is_some() {
+                                    return Err(e);
+                                }
+                                self.on_frame_error(message_id, e);
+                            }
+                        }
                         Err(e) => {
-                            warn!("parse websocket text error: {:?}", e)
+                            warn!(target: WS, "parse websocket text error: {:?}", e);
+                            self.on_frame_error(String::new(), e.into());
                         }
                     }
                 }
-                Message::Pong(_) => {
-                    trace!("websocket pong");
-                    self.alive.store(true, Ordering::SeqCst)
+                Message::Pong(payload) => {
+                    trace!(target: WS, "websocket pong");
+                    self.record_pong(&payload);
+                    alive.store(true, Ordering::SeqCst)
                 }
                 Message::Close(c) => {
                     warn!(
+                        target: WS,
                         "Websocket closed: {}",
-                        if let Some(c) = c {
+                        if let Some(c) = &c {
                             c.to_string()
                         } else {
                             "Unknown reason".to_owned()
                         }
                     );
 
-                    break;
+                    return Ok(c);
                 }
 
                 _ => {
-                    warn!("Unhandled websocket message: {:?}", message)
+                    warn!(target: WS, "Unhandled websocket message: {:?}", message)
                 }
             }
         }
 
-        Ok(())
+        Ok(None)
+    }
+
+    #[cfg(not(feature = "chaos"))]
+    async fn next_frame(&self, stream: &mut FrameStream) -> Option<Result<Message, Error>> {
+        stream.next().await
+    }
+
+    /// next frame, `None` when [`chaos::Chaos::disconnect_now`] drops the connection
+    #[cfg(feature = "chaos")]
+    async fn next_frame(&self, stream: &mut FrameStream) -> Option<Result<Message, Error>> {
+        tokio::select! {
+            message = stream.next() => message,
+            _ = self.chaos.disconnected() => None,
+        }
     }
 
     /// Connect to api gateway, and begin the websocket stream process
+    ///
+    /// With [`Client::connections`] above one, every connection reconnects on its own and this
+    /// returns once all of them stopped.
     pub async fn connect(self: Arc<Self>) -> Result<()> {
+        if self.is_mock() {
+            debug!(target: WS, "mock mode, not connecting");
+            return Ok(());
+        }
+        self.auth_failed.store(false, Ordering::SeqCst);
+        self.user_exit.store(false, Ordering::SeqCst);
+        let connections = self.config.lock().unwrap().connections;
+        let links = async {
+            if connections <= 1 {
+                return self.clone().run_link(0).await;
+            }
+
+            let results =
+                futures::future::join_all((0..connections).map(|link| self.clone().run_link(link)))
+                    .await;
+            results.into_iter().collect()
+        };
+
+        tokio::select! {
+            result = links => result,
+            _ = self.watch_suspend() => Ok(()),
+        }
+    }
+
+    async fn run_link(self: Arc<Self>, link: usize) -> Result<()> {
+        let mut reconnecting = false;
+        let mut backoffs = 0;
         loop {
+            if self.auth_failed.load(Ordering::SeqCst) {
+                return Err(DingTalkError::ReconnectStopped {
+                    reason: "credentials rejected".to_owned(),
+                }
+                .into());
+            }
+            if std::mem::replace(&mut reconnecting, true) {
+                self.record_reconnect();
+            }
+
             let c = self.clone();
             let reconnect_interval = c.config.lock().unwrap().reconnect_interval;
+            // loaded before the endpoint request, which registers the subscriptions, so a restart
+            // while it is in flight is not missed
+            let generation = self.restarts.load(Ordering::SeqCst);
             let url = c.get_endpoint().await?;
-            c.serve(url).await?;
+            if self.restarts.load(Ordering::SeqCst) != generation {
+                info!(target: WS, "Restarting connection {}", link);
+                continue;
+            }
+            let closed = c.serve(link, url).await?;
 
-            if reconnect_interval > 0 && !self.user_exit.load(Ordering::SeqCst) {
-                info!("Reconnecting in {} seconds...", reconnect_interval / 1000);
+            if self.restarts.load(Ordering::SeqCst) != generation {
+                info!(target: WS, "Restarting connection {}", link);
+                continue;
+            }
 
+            let policy = self.config.lock().unwrap().close_policy.clone();
+            let close = closed.map(|frame| (u16::from(frame.code), frame.reason.into_owned()));
+            let action = close.as_ref().map(|(code, _)| policy.action(*code));
+            if let (Some(CloseAction::Stop), Some((code, reason))) = (action, close) {
+                self.on_fatal_close(link, code, reason);
+                return Err(DingTalkError::ReconnectStopped {
+                    reason: format!("connection {link} closed with code {code}"),
+                }
+                .into());
+            }
+
+            if reconnect_interval > 0 && !self.user_exit.load(Ordering::SeqCst) {
                 // reconnect_interval is always larger than zero, to_std() never failed. unwrap is safe here
-                sleep(Duration::milliseconds(reconnect_interval).to_std().unwrap()).await;
-                debug!("initial reconnecting...");
+                let interval = Duration::milliseconds(reconnect_interval).to_std().unwrap();
+                let wait = match action {
+                    Some(CloseAction::Immediate) => std::time::Duration::ZERO,
+                    Some(CloseAction::Backoff) => policy.backoff(interval, backoffs),
+                    _ => interval,
+                };
+                backoffs = match action {
+                    Some(CloseAction::Backoff) => backoffs + 1,
+                    _ => 0,
+                };
+                info!(target: WS, "Reconnecting connection {} in {:?}...", link, wait);
+
+                sleep(wait).await;
+                debug!(target: WS, "initial reconnecting...");
             } else {
                 break;
             }
@@ -403,12 +1105,106 @@ impl Client {
         Ok(())
     }
 
+    /// name the subscriptions a permission or subscription rejection concerns
+    fn log_subscription_diagnostics(&self, error: &GatewayError) {
+        if !matches!(
+            error.kind(),
+            GatewayErrorKind::PermissionDenied | GatewayErrorKind::SubscriptionRejected
+        ) {
+            return;
+        }
+        let subscriptions = self
+            .config
+            .lock()
+            .unwrap()
+            .subscriptions
+            .iter()
+            .map(|s| format!("{} {}", s.r#type, s.topic))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match error.missing_scopes() {
+            [] => error!(
+                target: WS,
+                "subscriptions [{}] refused, check the app's permissions and that stream mode is enabled",
+                subscriptions
+            ),
+            scopes => error!(
+                target: WS,
+                "subscriptions [{}] need the permissions [{}], grant them in the developer console",
+                subscriptions,
+                scopes.join(", ")
+            ),
+        }
+    }
+
+    /// Credentials are no longer accepted, stop the connection instead of retrying forever
+    pub(crate) fn on_auth_failed(&self, reason: String) {
+        error!(target: TOKEN, "authentication failed: {}", reason);
+        self.error_event(ErrorContext::ReconnectStopped, "authenticate", &reason);
+        self.auth_failed.store(true, Ordering::SeqCst);
+        self.aborting.notify_waiters();
+        self.bridge.send_event(AuthFailedEvent { reason });
+    }
+
+    /// The server closed a connection with a code that stops reconnecting
+    fn on_fatal_close(&self, link: usize, code: u16, reason: String) {
+        error!(target: WS, "connection {} closed by server with code {}: {}", link, code, reason);
+        self.error_event(
+            ErrorContext::ReconnectStopped,
+            format!("connection {link} closed with code {code}"),
+            &reason,
+        );
+        self.bridge.send_event(FatalCloseEvent { link, code, reason });
+    }
+
+    /// Drop the current connections and open new ones right away, picking up changed
+    /// subscriptions
+    pub fn restart(&self) {
+        self.restarts.fetch_add(1, Ordering::SeqCst);
+        self.aborting.notify_waiters();
+    }
+
     pub fn exit(&self) {
         self.user_exit.store(true, Ordering::SeqCst);
         self.aborting.notify_waiters();
     }
 }
 
+/// writes to persisted key sets between sweeps of expired keys
+const MARK_SWEEP: u64 = 1024;
+
+/// frame ids remembered to drop deliveries repeated on another connection
+const SEEN_CAPACITY: usize = 4096;
+
+/// gettoken errcodes meaning the appkey/appsecret pair itself is wrong
+const INVALID_CREDENTIAL_CODES: [u32; 3] = [40089, 40096, 40013];
+
+/// first bytes of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[cfg(feature = "gzip")]
+fn gunzip(data: &[u8]) -> Result<String> {
+    use std::io::Read;
+
+    let mut text = String::new();
+    flate2::read::GzDecoder::new(data).read_to_string(&mut text)?;
+    Ok(text)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn gunzip(_data: &[u8]) -> Result<String> {
+    bail!("gzipped frame needs the `gzip` feature")
+}
+
+/// the longest start of `text` of at most `max` bytes that ends on a char boundary
+fn truncate_on_char(text: &str, max: usize) -> &str {
+    let mut end = max.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
 #[derive(Deserialize, Debug)]
 struct TokenResponse {
     errcode: u32,
@@ -427,6 +1223,9 @@ pub struct ClientConfig {
     pub client_secret: String,
     /// User-Agent sent to server
     pub ua: String,
+    /// Robot code used as `robotCode` in sends, `None` means the client id
+    #[serde(skip_serializing)]
+    pub robot_code: Option<String>,
     /// Subscriptions defines the types of event that you are concerned about
     pub subscriptions: Vec<Subscription>,
     #[serde(skip_serializing)]
@@ -437,6 +1236,159 @@ pub struct ClientConfig {
     reconnect_interval: i64,
     #[serde(skip_serializing)]
     heartbeat_interval: i64,
+    /// How received frames are written to the debug log
+    #[serde(skip_serializing)]
+    pub frame_logging: FrameLogging,
+    /// Server addresses, only needs changing for private deployments
+    #[serde(skip_serializing)]
+    pub endpoints: Endpoints,
+    /// When set, sends and uploads are only logged and reported as successful
+    #[serde(skip_serializing)]
+    pub dry_run: bool,
+    /// Use per corp tokens for replies, see [`Client::multi_tenant`]
+    #[serde(skip_serializing)]
+    pub multi_tenant: bool,
+    /// Corp tokens unused this long are evicted, see [`Client::tenant_idle_timeout`]
+    #[serde(skip_serializing)]
+    pub tenant_idle: std::time::Duration,
+    /// Websocket size limits, applied on the next connection
+    #[serde(skip_serializing)]
+    pub websocket: WebSocketLimits,
+    /// Address selection and timeouts of the websocket connection
+    #[serde(skip_serializing)]
+    pub connect: ConnectOptions,
+    /// Number of parallel stream connections, see [`Client::connections`]
+    #[serde(skip_serializing)]
+    pub connections: usize,
+    /// Conversation that receives the canary of [`Client::health_check`]
+    #[serde(skip_serializing)]
+    pub ops_conversation: Option<String>,
+    /// Drive used for files above the media upload limit, see [`Client::drive_fallback`]
+    #[serde(skip_serializing)]
+    pub drive_fallback: Option<DriveFallback>,
+    /// Quiet hours by conversation, see [`Client::quiet_hours`]
+    #[serde(skip_serializing)]
+    pub quiet_hours: HashMap<String, QuietHours>,
+    /// Callback topics acknowledged after their handler, see [`Client::ack_order`]
+    #[serde(skip_serializing)]
+    pub ack_orders: HashMap<String, AckOrder>,
+    /// Zone of conversations and users without their own
+    #[serde(skip_serializing)]
+    pub timezone: Zone,
+    /// Zones by conversation or user, see [`Client::timezone`]
+    #[serde(skip_serializing)]
+    pub timezones: HashMap<String, Zone>,
+    /// Callback topics answered with [`Client::send_stream_response`] instead of an automatic ACK
+    #[serde(skip_serializing)]
+    pub manual_response_topics: HashSet<String>,
+    /// Reconnect strategy by close code, see [`Client::close_policy`]
+    #[serde(skip_serializing)]
+    pub close_policy: ClosePolicy,
+    /// Clock jump taken for a system sleep, see [`Client::suspend_threshold`]
+    #[serde(skip_serializing)]
+    pub suspend_threshold: Option<std::time::Duration>,
+}
+
+/// Size limits of the websocket connection
+///
+/// Large rich text or card payloads may exceed the defaults, which closes the connection with a
+/// capacity error.
+#[derive(Debug, Clone, Copy)]
+pub struct WebSocketLimits {
+    /// largest accepted message in bytes, `None` for unlimited, default 64 MiB
+    pub max_message_size: Option<usize>,
+    /// largest accepted frame in bytes, `None` for unlimited, default 16 MiB
+    pub max_frame_size: Option<usize>,
+    /// bytes buffered before writing to the socket, default 128 KiB
+    pub write_buffer_size: usize,
+    /// upper bound of the write buffer, must be larger than `write_buffer_size`
+    pub max_write_buffer_size: usize,
+}
+
+impl Default for WebSocketLimits {
+    fn default() -> Self {
+        let config = WebSocketConfig::default();
+        Self {
+            max_message_size: config.max_message_size,
+            max_frame_size: config.max_frame_size,
+            write_buffer_size: config.write_buffer_size,
+            max_write_buffer_size: config.max_write_buffer_size,
+        }
+    }
+}
+
+impl WebSocketLimits {
+    fn to_config(self) -> WebSocketConfig {
+        WebSocketConfig {
+            max_message_size: self.max_message_size,
+            max_frame_size: self.max_frame_size,
+            write_buffer_size: self.write_buffer_size,
+            max_write_buffer_size: self.max_write_buffer_size,
+            ..Default::default()
+        }
+    }
+}
+
+/// Base urls of the DingTalk services used by the client
+///
+/// Defaults to the public cloud. Private-cloud and specialized (e.g. government) deployments expose
+/// the same APIs on their own domains.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    /// stream gateway used to open connections
+    pub gateway: String,
+    /// url returning the app access token
+    pub token: String,
+    /// base of the new style `api.dingtalk.com` APIs, without trailing slash
+    pub api: String,
+    /// base of the legacy `oapi.dingtalk.com` APIs, without trailing slash
+    pub oapi: String,
+    /// media upload url
+    pub upload: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            gateway: GATEWAY_URL.to_owned(),
+            token: GET_TOKEN_URL.to_owned(),
+            api: API_BASE_URL.to_owned(),
+            oapi: OAPI_BASE_URL.to_owned(),
+            upload: UPLOAD_URL.to_owned(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// every url derived from two base urls, e.g. `https://api.example.gov.cn`
+    pub fn from_bases(api: impl Into<String>, oapi: impl Into<String>) -> Self {
+        let api = api.into().trim_end_matches('/').to_owned();
+        let oapi = oapi.into().trim_end_matches('/').to_owned();
+        Self {
+            gateway: format!("{api}/v1.0/gateway/connections/open"),
+            token: format!("{oapi}/gettoken"),
+            upload: format!("{oapi}/media/upload"),
+            api,
+            oapi,
+        }
+    }
+}
+
+/// Logging policy for raw frames received from the websocket
+///
+/// Frames carry message contents, so [`FrameLogging::Truncated`] or [`FrameLogging::Off`] are
+/// recommended outside of development.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FrameLogging {
+    /// never log frames
+    Off,
+    /// log one of every N frames
+    Sampled(u32),
+    /// log at most N bytes of each frame
+    Truncated(usize),
+    /// log every frame in full
+    #[default]
+    Full,
 }
 
 impl Default for ClientConfig {
@@ -445,6 +1397,7 @@ impl Default for ClientConfig {
             client_id: Default::default(),
             client_secret: Default::default(),
             ua: Default::default(),
+            robot_code: None,
             subscriptions: vec![
                 Subscription {
                     r#type: "EVENT".to_owned(),
@@ -459,12 +1412,29 @@ impl Default for ClientConfig {
             token_expires_in: Local::now(),
             reconnect_interval: 1000,
             heartbeat_interval: 8000,
+            frame_logging: FrameLogging::default(),
+            endpoints: Endpoints::default(),
+            dry_run: false,
+            multi_tenant: false,
+            tenant_idle: std::time::Duration::from_secs(24 * 60 * 60),
+            websocket: WebSocketLimits::default(),
+            connect: ConnectOptions::default(),
+            connections: 1,
+            ops_conversation: None,
+            drive_fallback: None,
+            quiet_hours: HashMap::new(),
+            ack_orders: HashMap::new(),
+            timezone: Zone::default(),
+            timezones: HashMap::new(),
+            manual_response_topics: HashSet::new(),
+            close_policy: ClosePolicy::default(),
+            suspend_threshold: Some(std::time::Duration::from_secs(10)),
         }
     }
 }
 
 /// Definition of subscription types registered with the DingTalk server
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Subscription {
     /// Type
     /// - EVENT
@@ -482,3 +1452,25 @@ struct EndpointResponse {
     endpoint: String,
     ticket: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_on_char_keeps_whole_chars() {
+        let text = "比赛开始了";
+        assert_eq!(truncate_on_char(text, 3), "比");
+        assert_eq!(truncate_on_char(text, 4), "比");
+        assert_eq!(truncate_on_char(text, 8), "比赛");
+        assert_eq!(truncate_on_char(text, 2), "");
+        assert_eq!(truncate_on_char(text, 100), text);
+    }
+
+    #[test]
+    fn truncate_on_char_mixed_content() {
+        assert_eq!(truncate_on_char("ok 好的", 4), "ok ");
+        assert_eq!(truncate_on_char("ok 好的", 6), "ok 好");
+        assert_eq!(truncate_on_char("plain", 3), "pla");
+    }
+}
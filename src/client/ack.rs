@@ -0,0 +1,558 @@
+//! Bookkeeping of stream ACKs, so missing ones can be explained
+//!
+//! DingTalk pushes CALLBACK and EVENT frames again when no ACK arrives in time. Failed ACKs are
+//! reported, and frames that come back after such a failure are flagged as redeliveries.
+//!
+//! CALLBACK frames are acknowledged on arrival, before any handler ran. Topics set to
+//! [`AckOrder::AfterHandler`] with [`Client::ack_order`] are acknowledged by their handler
+//! instead, with the frame's [`AckToken`]. [At-least-once](Client::at_least_once) mode does so
+//! for robot messages.
+//!
+//! Messages and events answered with LATER are remembered by their id, so the redelivered copy
+//! carries how often it was put off, see [`Client::redelivery_count`].
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use log::{debug, info, warn};
+use serde_json::json;
+use tokio::runtime::Handle;
+
+use crate::client::down::RobotRecvMessage;
+use crate::client::up::ClientUpStream;
+use crate::client::Client;
+use crate::error::ErrorContext;
+use crate::event::{AckFailedEvent, RedeliveryDetected};
+use crate::storage::DEDUPE;
+use crate::topics::Topic;
+
+/// failed message ids remembered for redelivery detection
+const FAILED_CAPACITY: usize = 1024;
+
+/// frames whose connection is remembered for [`Client::send_stream_response`]
+const LINK_CAPACITY: usize = 1024;
+
+/// ids left for redelivery remembered
+const LATER_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+pub(crate) struct AckTracker {
+    /// frames waiting for their ACK, by stream message id
+    outstanding: Mutex<HashMap<String, Outstanding>>,
+    /// connection of recent frames, by stream message id
+    links: Mutex<(RecentIds, HashMap<String, usize>)>,
+    failed: Mutex<RecentIds>,
+    /// frames left to their handler, by stream message id
+    deferred: Mutex<HashMap<String, Deferred>>,
+    /// messages and events answered with LATER, by message or event id
+    later: Mutex<(RecentIds, HashMap<String, Later>)>,
+}
+
+#[derive(Debug)]
+struct Outstanding {
+    topic: String,
+    /// id the frame is deduplicated by
+    frame_id: String,
+    received_at: Instant,
+}
+
+#[derive(Debug)]
+struct Later {
+    /// times the id was answered with LATER
+    count: u32,
+    /// when the last LATER was sent, `None` once the copy arrived
+    left_at: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Deferred {
+    /// connection the frame arrived on
+    link: usize,
+    /// id the message is deduplicated by
+    msg_id: String,
+    /// runtime the ACK is sent from, tokens are mostly decided in Bevy systems
+    runtime: Handle,
+}
+
+impl Default for AckTracker {
+    fn default() -> Self {
+        Self {
+            outstanding: Default::default(),
+            links: Mutex::new((RecentIds::new(LINK_CAPACITY), HashMap::new())),
+            failed: Mutex::new(RecentIds::new(FAILED_CAPACITY)),
+            deferred: Default::default(),
+            later: Mutex::new((RecentIds::new(LATER_CAPACITY), HashMap::new())),
+        }
+    }
+}
+
+/// Bounded set of ids, the oldest are forgotten first
+#[derive(Debug)]
+pub(crate) struct RecentIds {
+    capacity: usize,
+    order: VecDeque<String>,
+    ids: HashSet<String>,
+}
+
+impl RecentIds {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            ids: HashSet::with_capacity(capacity),
+        }
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// returns false when the id was already known
+    pub fn insert(&mut self, id: &str) -> bool {
+        self.insert_evicting(id).0
+    }
+
+    /// like [`RecentIds::insert`], also returns the id forgotten to make room
+    pub fn insert_evicting(&mut self, id: &str) -> (bool, Option<String>) {
+        if !self.ids.insert(id.to_owned()) {
+            return (false, None);
+        }
+        let mut evicted = None;
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+                evicted = Some(oldest);
+            }
+        }
+        self.order.push_back(id.to_owned());
+        (true, evicted)
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if self.ids.remove(id) {
+            self.order.retain(|known| known != id);
+        }
+    }
+}
+
+/// Decides the ACK of a frame on a topic acknowledged [after its handler](AckOrder::AfterHandler)
+///
+/// Clones share the decision, the first call wins. A token dropped undecided sends nothing and
+/// DingTalk delivers the message again after its ACK timeout.
+#[derive(Clone)]
+pub struct AckToken {
+    client: Arc<Client>,
+    message_id: String,
+}
+
+impl std::fmt::Debug for AckToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AckToken")
+            .field("message_id", &self.message_id)
+            .field("decided", &self.is_decided())
+            .finish()
+    }
+}
+
+impl AckToken {
+    /// stream message id of the frame
+    pub fn message_id(&self) -> &str {
+        &self.message_id
+    }
+
+    /// whether `success` or `retry_later` was already called on this token or a clone
+    pub fn is_decided(&self) -> bool {
+        !self
+            .client
+            .acks
+            .deferred
+            .lock()
+            .unwrap()
+            .contains_key(&self.message_id)
+    }
+
+    /// the frame was handled, send its ACK
+    pub fn success(&self) {
+        let Some(deferred) = self.take() else {
+            return;
+        };
+        self.client.settle_redelivery(&deferred.msg_id);
+        let client = self.client.clone();
+        let msg = ClientUpStream::new(json!({ "response": {} }).to_string(), &self.message_id);
+        deferred.runtime.spawn(async move {
+            let _ = client.send_ack(deferred.link, msg).await;
+        });
+    }
+
+    /// the frame could not be handled now, let DingTalk deliver it again
+    ///
+    /// No ACK is sent and the frame is no longer considered seen, so the redelivery is not
+    /// dropped as duplicate. The redelivered copy counts this call in its
+    /// [`redelivery_count`](Client::redelivery_count).
+    pub fn retry_later(&self) {
+        let Some(deferred) = self.take() else {
+            return;
+        };
+        debug!("leave {} for redelivery", self.message_id);
+        self.client
+            .acks
+            .outstanding
+            .lock()
+            .unwrap()
+            .remove(&self.message_id);
+        self.client.leave_for_redelivery(&deferred.msg_id);
+    }
+
+    fn take(&self) -> Option<Deferred> {
+        self.client
+            .acks
+            .deferred
+            .lock()
+            .unwrap()
+            .remove(&self.message_id)
+    }
+}
+
+/// When CALLBACK frames of a topic are acknowledged, see [`Client::ack_order`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AckOrder {
+    /// on arrival, a frame lost by a crashing handler is not delivered again
+    #[default]
+    BeforeHandler,
+    /// once the handler decided with the frame's [`AckToken`], undecided frames are delivered
+    /// again after DingTalk's ACK timeout
+    AfterHandler,
+}
+
+/// A message or event answered with LATER whose copy has not arrived yet
+#[derive(Debug, Clone)]
+pub struct PendingRedelivery {
+    /// robot message id or event id
+    pub id: String,
+    /// times it was answered with LATER
+    pub count: u32,
+    /// time since the last LATER
+    pub waiting: Duration,
+}
+
+/// A received frame whose ACK has not been sent yet
+#[derive(Debug, Clone)]
+pub struct OutstandingAck {
+    pub message_id: String,
+    pub topic: String,
+    /// time since the frame was received
+    pub age: Duration,
+}
+
+impl Client {
+    /// remember a frame that needs an ACK, returns true if an earlier ACK of it failed
+    ///
+    /// `frame_id` is the id the frame is deduplicated by, it is forgotten when the ACK fails
+    pub(crate) fn track_ack(
+        &self,
+        message_id: &str,
+        frame_id: &str,
+        topic: &str,
+        link: usize,
+    ) -> bool {
+        self.acks.outstanding.lock().unwrap().insert(
+            message_id.to_owned(),
+            Outstanding {
+                topic: topic.to_owned(),
+                frame_id: frame_id.to_owned(),
+                received_at: self.current_clock().instant(),
+            },
+        );
+        let mut links = self.acks.links.lock().unwrap();
+        let (order, by_id) = &mut *links;
+        if let (_, Some(evicted)) = order.insert_evicting(message_id) {
+            by_id.remove(&evicted);
+        }
+        by_id.insert(message_id.to_owned(), link);
+        drop(links);
+
+        let redelivered = self.acks.failed.lock().unwrap().contains(message_id);
+        if redelivered {
+            info!(
+                "frame {} on {} redelivered after a failed ack",
+                message_id, topic
+            );
+            self.bridge.send_event(RedeliveryDetected {
+                message_id: message_id.to_owned(),
+                topic: topic.to_owned(),
+            });
+        }
+        redelivered
+    }
+
+    /// Leave the ACK of robot messages to their handler, see [`AckToken`]
+    ///
+    /// Handlers must call [`AckToken::success`] or [`AckToken::retry_later`] on the token of
+    /// each message, see [`Client::ack_token`]. Messages skipped by a listener's filter are
+    /// acknowledged right away. Same as [`AckOrder::AfterHandler`] for
+    /// [`Topic::ROBOT_MESSAGES`].
+    pub fn at_least_once(self: Arc<Self>, value: bool) -> Arc<Self> {
+        let order = if value {
+            AckOrder::AfterHandler
+        } else {
+            AckOrder::BeforeHandler
+        };
+        self.ack_order(Topic::ROBOT_MESSAGES, order)
+    }
+
+    /// Acknowledge CALLBACK frames on `topic` before or after their handler, by default before
+    ///
+    /// [`AckOrder::BeforeHandler`] keeps DingTalk from waiting on slow handlers, at the cost of
+    /// frames whose handler failed or never ran. With [`AckOrder::AfterHandler`] handlers get an
+    /// [`AckToken`]: robot messages through [`Client::ack_token`], card clicks in
+    /// [`CardActionEvent::ack`](crate::event::CardActionEvent::ack) and other topics through
+    /// [`Client::frame_ack_token`]. Topics answered [manually](Client::respond_manually) and AI
+    /// assistant requests are not affected, neither are EVENT frames, which are acknowledged
+    /// with the answer of the event listener.
+    pub fn ack_order(self: Arc<Self>, topic: impl Into<String>, order: AckOrder) -> Arc<Self> {
+        self.config
+            .lock()
+            .unwrap()
+            .ack_orders
+            .insert(topic.into(), order);
+        self
+    }
+
+    /// when CALLBACK frames on `topic` are acknowledged
+    pub fn ack_order_of(&self, topic: &str) -> AckOrder {
+        self.config
+            .lock()
+            .unwrap()
+            .ack_orders
+            .get(topic)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// hold the ACK of a frame until its token decides, must run on the runtime
+    ///
+    /// `msg_id` is the id the frame is deduplicated and redelivered by
+    pub(crate) fn defer_ack(&self, message_id: &str, link: usize, msg_id: &str) {
+        self.acks.deferred.lock().unwrap().insert(
+            message_id.to_owned(),
+            Deferred {
+                link,
+                msg_id: msg_id.to_owned(),
+                runtime: Handle::current(),
+            },
+        );
+    }
+
+    /// Token deciding the ACK of `message`, `None` when it was already decided or robot
+    /// messages are acknowledged [before their handler](AckOrder::BeforeHandler)
+    pub fn ack_token(self: &Arc<Self>, message: &RobotRecvMessage) -> Option<AckToken> {
+        let deferred = self.acks.deferred.lock().unwrap();
+        let message_id = deferred
+            .iter()
+            .find(|(_, d)| d.msg_id == message.msg_id)
+            .map(|(id, _)| id.clone())?;
+        Some(AckToken {
+            client: self.clone(),
+            message_id,
+        })
+    }
+
+    /// Token deciding the ACK of the frame `message_id`, e.g. of a
+    /// [`DownstreamEnvelope`](crate::client::down::DownstreamEnvelope) on a raw topic
+    ///
+    /// `None` when it was already decided or its topic is acknowledged
+    /// [before the handler](AckOrder::BeforeHandler).
+    pub fn frame_ack_token(self: &Arc<Self>, message_id: &str) -> Option<AckToken> {
+        self.acks
+            .deferred
+            .lock()
+            .unwrap()
+            .contains_key(message_id)
+            .then(|| AckToken {
+                client: self.clone(),
+                message_id: message_id.to_owned(),
+            })
+    }
+
+    /// count a LATER answer for the message or event `id` and let its redelivery through
+    /// deduplication
+    pub(crate) fn leave_for_redelivery(&self, id: &str) {
+        if id.is_empty() {
+            return;
+        }
+        let mut later = self.acks.later.lock().unwrap();
+        let (order, by_id) = &mut *later;
+        if let (_, Some(evicted)) = order.insert_evicting(id) {
+            by_id.remove(&evicted);
+        }
+        let entry = by_id.entry(id.to_owned()).or_insert(Later {
+            count: 0,
+            left_at: None,
+        });
+        entry.count += 1;
+        entry.left_at = Some(self.current_clock().instant());
+        debug!("{} left for redelivery {} times", id, entry.count);
+        drop(later);
+        self.forget_seen(id);
+    }
+
+    /// note the arrival of a copy of `id` that was answered with LATER
+    pub(crate) fn track_redelivery(&self, id: &str) {
+        let mut later = self.acks.later.lock().unwrap();
+        if let Some(entry) = later.1.get_mut(id) {
+            if let Some(left_at) = entry.left_at.take() {
+                info!(
+                    "{} redelivered {:?} after LATER, {} times so far",
+                    id,
+                    self.current_clock()
+                        .instant()
+                        .saturating_duration_since(left_at),
+                    entry.count
+                );
+            }
+        }
+    }
+
+    /// forget the LATER answers of `id` once it was handled
+    pub(crate) fn settle_redelivery(&self, id: &str) {
+        let mut later = self.acks.later.lock().unwrap();
+        if later.1.remove(id).is_some() {
+            later.0.remove(id);
+        }
+    }
+
+    /// How often the robot message or event `id` was answered with LATER before, 0 for a first
+    /// delivery
+    ///
+    /// Handlers can give up on a message after a few tries, robot messages carry the count as
+    /// [`RobotMessageReceived::redelivery_count`](crate::event::RobotMessageReceived::redelivery_count).
+    /// Only the last 1024 ids are remembered.
+    pub fn redelivery_count(&self, id: &str) -> u32 {
+        self.acks
+            .later
+            .lock()
+            .unwrap()
+            .1
+            .get(id)
+            .map_or(0, |later| later.count)
+    }
+
+    /// messages and events answered with LATER whose copy has not arrived yet, longest waiting
+    /// first
+    pub fn awaiting_redelivery(&self) -> Vec<PendingRedelivery> {
+        let now = self.current_clock().instant();
+        let mut pending: Vec<_> = self
+            .acks
+            .later
+            .lock()
+            .unwrap()
+            .1
+            .iter()
+            .filter_map(|(id, later)| {
+                Some(PendingRedelivery {
+                    id: id.clone(),
+                    count: later.count,
+                    waiting: now.saturating_duration_since(later.left_at?),
+                })
+            })
+            .collect();
+        pending.sort_by_key(|p| std::cmp::Reverse(p.waiting));
+        pending
+    }
+
+    /// let a redelivery of `id` through deduplication
+    fn forget_seen(&self, id: &str) {
+        self.seen.lock().unwrap().remove(id);
+        if let Err(e) = self.store().remove(DEDUPE, id) {
+            warn!("forget {} error: {:?}", id, e);
+        }
+    }
+
+    /// Answer the frame `message_id` with a raw up-stream frame, for callback topics the client
+    /// does not know
+    ///
+    /// Register the topic with [`Client::respond_manually`] first, otherwise the client already
+    /// acknowledged the frame on arrival. `data` is sent as is, usually a JSON string like
+    /// `{"response":{}}`. The frame goes out on the connection it arrived on, frames older than
+    /// the last 1024 are unknown.
+    pub async fn send_stream_response(
+        &self,
+        message_id: &str,
+        code: u32,
+        data: impl Into<String>,
+    ) -> Result<()> {
+        let Some(link) = self.acks.links.lock().unwrap().1.get(message_id).copied() else {
+            bail!("frame {} unknown or too old to answer", message_id);
+        };
+        let mut msg = ClientUpStream::new(data, message_id);
+        msg.code = code;
+        if code != 200 {
+            msg.message = String::new();
+        }
+        self.send_ack(link, msg).await
+    }
+
+    /// Leave the answer to CALLBACK frames on `topic` to [`Client::send_stream_response`]
+    pub fn respond_manually(self: Arc<Self>, topic: impl Into<String>) -> Arc<Self> {
+        self.config
+            .lock()
+            .unwrap()
+            .manual_response_topics
+            .insert(topic.into());
+        self
+    }
+
+    /// send an ACK, failures are recorded and reported before the error is returned
+    ///
+    /// The frame of a failed ACK is no longer considered seen, so DingTalk's redelivery is
+    /// handled instead of dropped as duplicate.
+    pub(crate) async fn send_ack(&self, link: usize, msg: ClientUpStream) -> Result<()> {
+        let message_id = msg.headers.message_id.clone();
+        let tracked = self.acks.outstanding.lock().unwrap().remove(&message_id);
+        let result = self.send_frame(link, msg, true).await;
+        if let Err(e) = &result {
+            let topic = match tracked {
+                Some(tracked) => {
+                    self.forget_seen(&tracked.frame_id);
+                    tracked.topic
+                }
+                None => String::new(),
+            };
+            warn!(
+                "ack {} on {} not sent, DingTalk will redeliver it: {:?}",
+                message_id, topic, e
+            );
+            self.acks.failed.lock().unwrap().insert(&message_id);
+            self.error_event(ErrorContext::Ack, format!("ack {message_id}"), e);
+            self.bridge.send_event(AckFailedEvent {
+                message_id,
+                topic,
+                error: e.to_string(),
+            });
+        }
+        result
+    }
+
+    /// frames received but not acknowledged yet, oldest first
+    pub fn outstanding_acks(&self) -> Vec<OutstandingAck> {
+        let now = self.current_clock().instant();
+        let mut acks: Vec<_> = self
+            .acks
+            .outstanding
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, tracked)| OutstandingAck {
+                message_id: id.clone(),
+                topic: tracked.topic.clone(),
+                age: now.saturating_duration_since(tracked.received_at),
+            })
+            .collect();
+        acks.sort_by_key(|a| std::cmp::Reverse(a.age));
+        acks
+    }
+}
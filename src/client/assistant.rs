@@ -0,0 +1,118 @@
+//! AI assistant skills served over the stream
+//!
+//! An assistant invokes a skill through the graph API topic. The request arrives as an HTTP-like
+//! [`GraphRequest`] and the skill's answer is sent back as the ACK of that frame, so the response
+//! has to be given before DingTalk's ACK timeout.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use log::{debug, warn};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
+
+use crate::client::down::ClientDownStream;
+use crate::client::up::ClientUpStream;
+use crate::client::Client;
+use crate::event::AssistantSkillInvoked;
+
+/// Skill invocation of an AI assistant
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+#[serde(rename_all = "camelCase")]
+pub struct GraphRequest {
+    pub request_line: RequestLine,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+#[serde(rename_all = "camelCase")]
+pub struct RequestLine {
+    pub method: String,
+    /// path of the skill action, e.g. `/v1/actions/example/weather/get`
+    pub uri: String,
+}
+
+impl GraphRequest {
+    /// the body parsed as json, skill parameters are sent this way
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_str(&self.body)?)
+    }
+}
+
+/// Answer of a skill, see [`DingTalk::respond_skill`](crate::param::DingTalk::respond_skill)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GraphResponse {
+    pub status_line: StatusLine,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusLine {
+    pub code: u16,
+    pub reason_phrase: String,
+}
+
+impl GraphResponse {
+    /// 200 response with `body` serialized as json
+    pub fn json<T: Serialize>(body: &T) -> Result<Self> {
+        Ok(Self {
+            status_line: StatusLine {
+                code: 200,
+                reason_phrase: "OK".to_owned(),
+            },
+            headers: HashMap::from([("Content-Type".to_owned(), "application/json".to_owned())]),
+            body: serde_json::to_string(body)?,
+        })
+    }
+
+    /// failed invocation, `reason` is shown to the assistant
+    pub fn error(code: u16, reason: impl Into<String>) -> Self {
+        Self {
+            status_line: StatusLine {
+                code,
+                reason_phrase: reason.into(),
+            },
+            headers: HashMap::new(),
+            body: String::new(),
+        }
+    }
+}
+
+impl Client {
+    /// forward a skill invocation to Bevy, the frame stays unacknowledged until it is answered
+    pub(crate) fn on_graph_request(&self, p: ClientDownStream) -> Result<()> {
+        let request: GraphRequest = serde_json::from_str(&p.data)?;
+        debug!(
+            "assistant skill invoked: {} {}",
+            request.request_line.method, request.request_line.uri
+        );
+        self.pending_skills
+            .lock()
+            .unwrap()
+            .insert(p.headers.message_id.clone(), p.link);
+        self.bridge.send_event(AssistantSkillInvoked {
+            request_id: p.headers.message_id,
+            request,
+        });
+        Ok(())
+    }
+
+    /// answer the skill invocation `request_id`
+    pub async fn respond_skill(&self, request_id: &str, response: GraphResponse) -> Result<()> {
+        let Some(link) = self.pending_skills.lock().unwrap().remove(request_id) else {
+            warn!("skill request {} already answered or unknown", request_id);
+            return Ok(());
+        };
+        let data = serde_json::to_string(&json!({ "response": response }))?;
+        self.send_ack(link, ClientUpStream::new(data, request_id))
+            .await
+    }
+}
@@ -0,0 +1,208 @@
+//! Tokens beyond the app's own access token
+//!
+//! ISV apps get corp tokens through the suite ticket flow, web and H5 logins exchange OAuth2 codes
+//! for user tokens and admin consoles use SSO tokens. None of them are cached, callers decide how
+//! long to keep them.
+
+use anyhow::{bail, Result};
+use log::debug;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::client::up::SendTapped;
+use crate::client::Client;
+
+const LOGIN_URL: &str = "https://login.dingtalk.com/oauth2/auth";
+const CORP_ACCESS_TOKEN_PATH: &str = "/v1.0/oauth2/corpAccessToken";
+const USER_ACCESS_TOKEN_PATH: &str = "/v1.0/oauth2/userAccessToken";
+const SSO_ACCESS_TOKEN_PATH: &str = "/v1.0/oauth2/ssoAccessToken";
+const CURRENT_USER_PATH: &str = "/v1.0/contact/users/me";
+
+/// Key and secret of a third-party enterprise app suite
+#[derive(Debug, Clone)]
+pub struct SuiteCredentials {
+    pub suite_key: String,
+    pub suite_secret: String,
+}
+
+/// Token for one corp or an SSO session
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessToken {
+    pub access_token: String,
+    /// lifetime in seconds
+    #[serde(alias = "expireIn")]
+    pub expires_in: i64,
+}
+
+/// Token acting on behalf of a user who logged in through OAuth2
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+#[serde(rename_all = "camelCase")]
+pub struct UserAccessToken {
+    pub access_token: String,
+    pub refresh_token: String,
+    /// lifetime in seconds
+    #[serde(alias = "expireIn")]
+    pub expires_in: i64,
+    /// corp the user logged in with, empty for personal accounts
+    #[serde(default)]
+    pub corp_id: String,
+}
+
+/// DingTalk account of a user who logged in through OAuth2
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/dingtalk-retrieve-user-information) for the definition of each field
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+#[serde(rename_all = "camelCase")]
+pub struct DingTalkUser {
+    /// stable across all apps of the same developer, use it to bind game accounts
+    #[serde(default)]
+    pub union_id: String,
+    /// only stable inside this app
+    #[serde(default)]
+    pub open_id: String,
+    #[serde(default)]
+    pub nick: String,
+    #[serde(default)]
+    pub avatar_url: String,
+    /// only present with the `Contact.User.mobile` permission
+    #[serde(default)]
+    pub mobile: String,
+    #[serde(default)]
+    pub email: String,
+}
+
+impl Client {
+    /// Url of the DingTalk login page, e.g. for the button of a "link your account" card
+    ///
+    /// After login the browser is sent to `redirect_uri` with `code` and `state` query parameters,
+    /// pass them to [`Client::authenticate_user`]. `state` should identify the game account.
+    pub fn login_url(&self, redirect_uri: &str, state: &str) -> String {
+        let client_id = self.config.lock().unwrap().client_id.clone();
+        let mut url = url::Url::parse(LOGIN_URL).unwrap();
+        url.query_pairs_mut()
+            .append_pair("redirect_uri", redirect_uri)
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &client_id)
+            .append_pair("scope", "openid")
+            .append_pair("state", state)
+            .append_pair("prompt", "consent");
+        url.into()
+    }
+
+    /// Exchange the login `code` and fetch the account it belongs to
+    pub async fn authenticate_user(&self, code: &str) -> Result<(DingTalkUser, UserAccessToken)> {
+        let token = self.user_access_token(code).await?;
+        let user = self.current_user(&token.access_token).await?;
+        Ok((user, token))
+    }
+
+    /// Account of the owner of a user access token
+    pub async fn current_user(&self, user_access_token: &str) -> Result<DingTalkUser> {
+        let response = self
+            .client
+            .get(self.api_url(CURRENT_USER_PATH))
+            .header("x-acs-dingtalk-access-token", user_access_token)
+            .send_tapped(self)
+            .await?;
+        if !response.status().is_success() {
+            bail!(
+                "get current user error: {} - {}",
+                response.status(),
+                response.text().await?
+            );
+        }
+        Ok(response.json().await?)
+    }
+
+    /// Corp token of an ISV suite installed in `auth_corp_id`
+    ///
+    /// `suite_ticket` is the latest ticket DingTalk pushed to the suite.
+    pub async fn suite_corp_access_token(
+        &self,
+        suite: &SuiteCredentials,
+        auth_corp_id: &str,
+        suite_ticket: &str,
+    ) -> Result<AccessToken> {
+        self.post_unauthenticated(
+            CORP_ACCESS_TOKEN_PATH,
+            json!({
+                "suiteKey": suite.suite_key,
+                "suiteSecret": suite.suite_secret,
+                "authCorpId": auth_corp_id,
+                "suiteTicket": suite_ticket,
+            }),
+        )
+        .await
+    }
+
+    /// Exchange the authorization `code` of an OAuth2 login for a user token
+    pub async fn user_access_token(&self, code: &str) -> Result<UserAccessToken> {
+        let (client_id, client_secret) = self.credentials();
+        self.post_unauthenticated(
+            USER_ACCESS_TOKEN_PATH,
+            json!({
+                "clientId": client_id,
+                "clientSecret": client_secret,
+                "code": code,
+                "grantType": "authorization_code",
+            }),
+        )
+        .await
+    }
+
+    /// New user token from the `refresh_token` of an earlier one
+    pub async fn refresh_user_access_token(&self, refresh_token: &str) -> Result<UserAccessToken> {
+        let (client_id, client_secret) = self.credentials();
+        self.post_unauthenticated(
+            USER_ACCESS_TOKEN_PATH,
+            json!({
+                "clientId": client_id,
+                "clientSecret": client_secret,
+                "refreshToken": refresh_token,
+                "grantType": "refresh_token",
+            }),
+        )
+        .await
+    }
+
+    /// Token for the admin SSO APIs of `corp_id`
+    pub async fn sso_access_token(&self, corp_id: &str, sso_secret: &str) -> Result<AccessToken> {
+        self.post_unauthenticated(
+            SSO_ACCESS_TOKEN_PATH,
+            json!({ "corpid": corp_id, "ssoSecret": sso_secret }),
+        )
+        .await
+    }
+
+    fn credentials(&self) -> (String, String) {
+        let config = self.config.lock().unwrap();
+        (config.client_id.clone(), config.client_secret.clone())
+    }
+
+    /// post to an api path that authenticates through its body instead of a token header
+    async fn post_unauthenticated<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        data: Value,
+    ) -> Result<T> {
+        let response = self
+            .client
+            .post(self.api_url(path))
+            .json(&data)
+            .send_tapped(self)
+            .await?;
+        if !response.status().is_success() {
+            bail!(
+                "{} http error: {} - {}",
+                path,
+                response.status(),
+                response.text().await?
+            );
+        }
+        debug!("{} succeeded", path);
+        Ok(response.json().await?)
+    }
+}
@@ -0,0 +1,181 @@
+//! Several messages sent as one, e.g. a report with its chart and spreadsheet
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::{debug, warn};
+
+use crate::client::quiet::{Held, HeldBundle};
+use crate::client::up::{MessageTemplate, RobotSendMessage, SendResult, UploadType};
+use crate::client::Client;
+
+/// Part of a [`MessageBundle`]
+#[derive(Debug, Clone)]
+pub enum BundlePart {
+    Message(Box<MessageTemplate>),
+    /// picture uploaded and sent as [`MessageTemplate::SampleImageMsg`]
+    Image(PathBuf),
+    /// file uploaded and sent as [`MessageTemplate::SampleFile`]
+    File(PathBuf),
+}
+
+/// Messages sent in order to one conversation
+///
+/// Every upload happens before the first send, so a missing or oversized file sends nothing.
+/// When a send fails the parts already sent are recalled, unless disabled with
+/// [`MessageBundle::rollback`]. During [quiet hours](Client::quiet_hours) the parts are held as
+/// one, unless the bundle is [urgent](MessageBundle::urgent), and released the same way.
+#[derive(Debug, Clone)]
+pub struct MessageBundle {
+    pub parts: Vec<BundlePart>,
+    pub rollback: bool,
+    pub urgent: bool,
+}
+
+impl Default for MessageBundle {
+    fn default() -> Self {
+        Self {
+            parts: vec![],
+            rollback: true,
+            urgent: false,
+        }
+    }
+}
+
+impl MessageBundle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn message(mut self, message: MessageTemplate) -> Self {
+        self.parts.push(BundlePart::Message(Box::new(message)));
+        self
+    }
+
+    pub fn text(self, content: impl Into<String>) -> Self {
+        self.message(MessageTemplate::SampleText {
+            content: content.into(),
+        })
+    }
+
+    pub fn markdown(self, title: impl Into<String>, text: impl Into<String>) -> Self {
+        self.message(MessageTemplate::SampleMarkdown {
+            title: title.into(),
+            text: text.into(),
+        })
+    }
+
+    pub fn image(mut self, path: impl Into<PathBuf>) -> Self {
+        self.parts.push(BundlePart::Image(path.into()));
+        self
+    }
+
+    pub fn file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.parts.push(BundlePart::File(path.into()));
+        self
+    }
+
+    /// recall sent parts when a later one fails, default is `true`
+    pub fn rollback(mut self, rollback: bool) -> Self {
+        self.rollback = rollback;
+        self
+    }
+
+    /// send during quiet hours instead of holding the parts until their end
+    pub fn urgent(mut self, urgent: bool) -> Self {
+        self.urgent = urgent;
+        self
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+impl Client {
+    /// Upload the media of `bundle`, then send its parts in order to group `conversation_id`
+    pub async fn send_bundle(
+        self: &Arc<Self>,
+        conversation_id: impl Into<String>,
+        bundle: MessageBundle,
+    ) -> Result<Vec<SendResult>> {
+        let conversation_id = conversation_id.into();
+        let mut messages = Vec::with_capacity(bundle.parts.len());
+        for part in bundle.parts {
+            messages.push(match part {
+                BundlePart::Message(message) => *message,
+                BundlePart::Image(path) => MessageTemplate::SampleImageMsg {
+                    photo_url: self.upload(&path, UploadType::Image).await?,
+                },
+                BundlePart::File(path) => MessageTemplate::SampleFile {
+                    media_id: self.upload(&path, UploadType::File).await?,
+                    file_name: path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    file_type: extension(&path),
+                },
+            });
+        }
+
+        let sends = messages
+            .into_iter()
+            .map(|message| {
+                RobotSendMessage::group(self.clone(), conversation_id.clone(), message)
+                    .map(|send| send.urgent(true))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !bundle.urgent && self.quiet_for(&conversation_id).is_some() {
+            debug!("hold bundle of {} parts until quiet hours end", sends.len());
+            let parts = sends
+                .iter()
+                .map(RobotSendMessage::to_held)
+                .collect::<Result<_>>()?;
+            self.hold(&Held::Bundle(HeldBundle {
+                conversation_id,
+                rollback: bundle.rollback,
+                parts,
+            }))?;
+            return Ok(vec![SendResult::held(); sends.len()]);
+        }
+        self.send_in_order(&conversation_id, sends, bundle.rollback)
+            .await
+    }
+
+    /// send `sends` to group `conversation_id` one after another, recalling the sent ones when
+    /// one fails and `rollback` is set
+    pub(crate) async fn send_in_order(
+        self: &Arc<Self>,
+        conversation_id: &str,
+        sends: Vec<RobotSendMessage>,
+        rollback: bool,
+    ) -> Result<Vec<SendResult>> {
+        let count = sends.len();
+        let mut results: Vec<SendResult> = Vec::with_capacity(count);
+        for (i, send) in sends.iter().enumerate() {
+            match send.send().await {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    let keys: Vec<String> = results
+                        .iter()
+                        .map(|r| r.process_query_key.clone())
+                        .filter(|key| !key.is_empty())
+                        .collect();
+                    if rollback && !keys.is_empty() {
+                        debug!("bundle part {} failed, recall {} parts", i + 1, keys.len());
+                        match self.recall_group_messages(conversation_id, &keys).await {
+                            // recalled parts go out again when the bundle is retried
+                            Ok(()) => sends[..i].iter().for_each(RobotSendMessage::forget_sent),
+                            Err(e) => warn!("recall bundle parts error: {:?}", e),
+                        }
+                    }
+                    return Err(e.context(format!("send bundle part {} of {}", i + 1, count)));
+                }
+            }
+        }
+        Ok(results)
+    }
+}
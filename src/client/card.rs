@@ -0,0 +1,570 @@
+//! Interactive cards and their callbacks
+//!
+//! Cards are instances of a template made in the card builder of the developer console, filled
+//! with `cardParamMap` values. Clicking a button of an interactive card pushes a CALLBACK frame on [`Topic::CARD_CALLBACKS`]. Its
+//! `content` is a json string holding the ids of the clicked actions and their parameters.
+//!
+//! [`Topic::CARD_CALLBACKS`]: crate::topics::Topic::CARD_CALLBACKS
+
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use futures::{stream, StreamExt};
+use log::debug;
+use reqwest::Method;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::client::tenant::TenantId;
+use crate::client::Client;
+use crate::event::CardActionEvent;
+
+const CREATE_AND_DELIVER_PATH: &str = "/v1.0/card/instances/createAndDeliver";
+const CARD_INSTANCES_PATH: &str = "/v1.0/card/instances";
+/// requests in flight during [`Client::update_cards_bulk`]
+const BULK_UPDATE_CONCURRENCY: usize = 8;
+
+/// Instance of a card template, ready to be sent
+#[derive(Debug, Clone)]
+pub struct InteractiveCard {
+    pub template_id: String,
+    /// id of this instance, used to update it and given back in callbacks
+    pub out_track_id: String,
+    pub params: Map<String, Value>,
+}
+
+impl InteractiveCard {
+    /// card of `template_id` with a random out track id
+    pub fn new(template_id: impl Into<String>) -> Self {
+        Self {
+            template_id: template_id.into(),
+            out_track_id: format!("{:032x}", rand::random::<u128>()),
+            params: Map::new(),
+        }
+    }
+
+    pub fn out_track_id(mut self, out_track_id: impl Into<String>) -> Self {
+        self.out_track_id = out_track_id.into();
+        self
+    }
+
+    /// set a template variable, values which are not strings are sent json encoded
+    pub fn param(mut self, name: impl Into<String>, value: impl Serialize) -> Self {
+        self.params.insert(
+            name.into(),
+            serde_json::to_value(value).unwrap_or(Value::Null),
+        );
+        self
+    }
+}
+
+/// `cardParamMap` only takes strings
+fn card_param_map(params: &Map<String, Value>) -> Map<String, Value> {
+    params
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            (name.clone(), Value::String(value))
+        })
+        .collect()
+}
+
+/// Kind of a [`FormField`], rendered by the card template
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FormFieldKind {
+    Text,
+    Number,
+    Select,
+    MultiSelect,
+    Date,
+    Checkbox,
+}
+
+/// Input of a [`CardForm`]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormField {
+    /// key of the submitted value
+    pub name: String,
+    pub label: String,
+    #[serde(rename = "type")]
+    pub kind: FormFieldKind,
+    pub required: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<FormOption>,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    pub default_value: Value,
+}
+
+/// Choice of a select field
+#[derive(Debug, Clone, Serialize)]
+pub struct FormOption {
+    pub value: String,
+    pub text: String,
+}
+
+impl FormField {
+    fn new(kind: FormFieldKind, name: impl Into<String>, label: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            label: label.into(),
+            kind,
+            required: false,
+            options: vec![],
+            default_value: Value::Null,
+        }
+    }
+
+    pub fn text(name: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::new(FormFieldKind::Text, name, label)
+    }
+
+    pub fn number(name: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::new(FormFieldKind::Number, name, label)
+    }
+
+    pub fn date(name: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::new(FormFieldKind::Date, name, label)
+    }
+
+    pub fn checkbox(name: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::new(FormFieldKind::Checkbox, name, label)
+    }
+
+    /// single choice of `(value, text)` options
+    pub fn select<V: Into<String>, T: Into<String>>(
+        name: impl Into<String>,
+        label: impl Into<String>,
+        options: impl IntoIterator<Item = (V, T)>,
+    ) -> Self {
+        Self::new(FormFieldKind::Select, name, label).options(options)
+    }
+
+    /// multiple choices of `(value, text)` options
+    pub fn multi_select<V: Into<String>, T: Into<String>>(
+        name: impl Into<String>,
+        label: impl Into<String>,
+        options: impl IntoIterator<Item = (V, T)>,
+    ) -> Self {
+        Self::new(FormFieldKind::MultiSelect, name, label).options(options)
+    }
+
+    fn options<V: Into<String>, T: Into<String>>(
+        mut self,
+        options: impl IntoIterator<Item = (V, T)>,
+    ) -> Self {
+        self.options = options
+            .into_iter()
+            .map(|(value, text)| FormOption {
+                value: value.into(),
+                text: text.into(),
+            })
+            .collect();
+        self
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn default_value(mut self, value: impl Serialize) -> Self {
+        self.default_value = serde_json::to_value(value).unwrap_or(Value::Null);
+        self
+    }
+}
+
+/// Card with input fields and a submit button
+///
+/// The template is expected to render the `form` variable, a json list of [`FormField`], and
+/// to submit the values keyed by field name as action parameters. Read them back with
+/// [`CardActionEvent::form`].
+#[derive(Debug, Clone, Default)]
+pub struct CardForm {
+    pub title: String,
+    pub fields: Vec<FormField>,
+    pub submit_text: String,
+}
+
+impl CardForm {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            fields: vec![],
+            submit_text: "提交".to_owned(),
+        }
+    }
+
+    pub fn field(mut self, field: FormField) -> Self {
+        self.fields.push(field);
+        self
+    }
+
+    pub fn submit_text(mut self, text: impl Into<String>) -> Self {
+        self.submit_text = text.into();
+        self
+    }
+
+    /// card of `template_id` rendering this form
+    pub fn into_card(self, template_id: impl Into<String>) -> InteractiveCard {
+        InteractiveCard::new(template_id)
+            .param("title", &self.title)
+            .param("submitText", &self.submit_text)
+            .param("form", &self.fields)
+    }
+
+    /// names of required fields left empty in `values`
+    pub fn missing<'a>(&'a self, values: &FormValues) -> Vec<&'a str> {
+        self.fields
+            .iter()
+            .filter(|f| f.required && values.is_empty(&f.name))
+            .map(|f| f.name.as_str())
+            .collect()
+    }
+}
+
+/// Values submitted by a form card, keyed by field name
+///
+/// Cards submit every value as string, lists and objects json encoded, these are decoded back.
+#[derive(Debug, Clone, Default)]
+pub struct FormValues(pub Map<String, Value>);
+
+impl FormValues {
+    pub fn new(params: &Value) -> Self {
+        let values = params
+            .as_object()
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|(name, value)| (name.clone(), decode_form_value(value)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self(values)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.get(name).and_then(Value::as_str)
+    }
+
+    pub fn number(&self, name: &str) -> Option<f64> {
+        match self.get(name)? {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// whether a checkbox is ticked, false when absent
+    pub fn checked(&self, name: &str) -> bool {
+        match self.get(name) {
+            Some(Value::Bool(b)) => *b,
+            Some(Value::String(s)) => s == "true",
+            _ => false,
+        }
+    }
+
+    /// values of the chosen options of a select or multi select field
+    pub fn selected(&self, name: &str) -> Vec<String> {
+        fn option_value(v: &Value) -> Option<String> {
+            match v {
+                Value::String(s) => Some(s.clone()),
+                Value::Object(o) => o.get("value").and_then(option_value),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            }
+        }
+        match self.get(name) {
+            Some(Value::Array(items)) => items.iter().filter_map(option_value).collect(),
+            Some(Value::String(s)) if s.is_empty() => vec![],
+            Some(v) => option_value(v).into_iter().collect(),
+            None => vec![],
+        }
+    }
+
+    /// whether `name` was left empty
+    pub fn is_empty(&self, name: &str) -> bool {
+        match self.get(name) {
+            None | Some(Value::Null) => true,
+            Some(Value::String(s)) => s.trim().is_empty(),
+            Some(Value::Array(a)) => a.is_empty(),
+            Some(_) => false,
+        }
+    }
+
+    /// all values as `T`, e.g. a struct with a field for each input
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(T::deserialize(&Value::Object(self.0.clone()))?)
+    }
+}
+
+fn decode_form_value(value: &Value) -> Value {
+    match value {
+        Value::String(s) if s.starts_with('[') || s.starts_with('{') => {
+            serde_json::from_str(s).unwrap_or_else(|_| value.clone())
+        }
+        other => other.clone(),
+    }
+}
+
+/// CALLBACK frame data of a card action
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/interactive-card-callback) for the definition of each field
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+#[cfg_attr(feature = "strict-protocol", serde(deny_unknown_fields))]
+pub struct CardCallback {
+    /// id given when the card was sent
+    pub out_track_id: String,
+    pub corp_id: String,
+    /// staff id of the user who clicked
+    pub user_id: String,
+    /// `IM` for cards in chats
+    pub space_type: String,
+    pub space_id: String,
+    /// json string of [`CardPrivateData`]
+    pub content: String,
+    pub r#type: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct CallbackContent {
+    card_private_data: CardPrivateData,
+}
+
+/// Actions of one click, as sent by the card
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CardPrivateData {
+    pub action_ids: Vec<String>,
+    pub params: Map<String, Value>,
+}
+
+/// User who clicked a card button
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct CardUser {
+    pub user_id: String,
+    pub corp_id: String,
+}
+
+impl CardCallback {
+    /// the clicked actions, empty when `content` is not valid
+    pub fn private_data(&self) -> CardPrivateData {
+        serde_json::from_str::<CallbackContent>(&self.content)
+            .map(|c| c.card_private_data)
+            .unwrap_or_default()
+    }
+}
+
+impl CardActionEvent {
+    pub(crate) fn from_callback(callback: CardCallback) -> Self {
+        let data = callback.private_data();
+        Self {
+            tenant: TenantId::new(&callback.corp_id),
+            out_track_id: callback.out_track_id,
+            action_id: data.action_ids.into_iter().next().unwrap_or_default(),
+            params: Value::Object(data.params),
+            user: CardUser {
+                user_id: callback.user_id,
+                corp_id: callback.corp_id,
+            },
+            space_id: callback.space_id,
+            ack: None,
+        }
+    }
+
+    /// the action parameters as `T`, e.g. a struct or an enum tagged by a parameter
+    pub fn params<T: DeserializeOwned>(&self) -> Result<T> {
+        Ok(T::deserialize(&self.params)?)
+    }
+
+    /// a single parameter as string
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).and_then(Value::as_str)
+    }
+
+    /// the submitted values of a [`CardForm`]
+    pub fn form(&self) -> FormValues {
+        FormValues::new(&self.params)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CardResponse {
+    #[serde(default)]
+    success: bool,
+    #[serde(default)]
+    result: Value,
+}
+
+/// Outcome of [`Client::update_cards_bulk`], every update is tried even when others fail
+#[derive(Debug, Default)]
+pub struct BulkCardUpdate {
+    /// out track ids updated, in completion order
+    pub updated: Vec<String>,
+    /// out track ids that could not be updated and why
+    pub failed: Vec<(String, anyhow::Error)>,
+}
+
+impl BulkCardUpdate {
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct DeliverResult {
+    space_id: String,
+    success: bool,
+    error_msg: String,
+}
+
+impl Client {
+    /// send `card` to a group chat, returns its out track id
+    pub async fn send_card(
+        &self,
+        conversation_id: impl AsRef<str>,
+        card: &InteractiveCard,
+    ) -> Result<String> {
+        let space = json!({
+            "openSpaceId": format!("dtv1.card//IM_GROUP.{}", conversation_id.as_ref()),
+            "imGroupOpenSpaceModel": { "supportForward": true },
+            "imGroupOpenDeliverModel": { "robotCode": self.current_robot_code() },
+        });
+        self.create_and_deliver(card, space).await
+    }
+
+    /// send `card` to a user in a direct chat with the robot, returns its out track id
+    pub async fn send_card_to_user(
+        &self,
+        user_id: impl AsRef<str>,
+        card: &InteractiveCard,
+    ) -> Result<String> {
+        let space = json!({
+            "openSpaceId": format!("dtv1.card//IM_ROBOT.{}", user_id.as_ref()),
+            "imRobotOpenSpaceModel": { "supportForward": false },
+            "imRobotOpenDeliverModel": {
+                "spaceType": "IM_ROBOT",
+                "robotCode": self.current_robot_code(),
+            },
+        });
+        self.create_and_deliver(card, space).await
+    }
+
+    /// replace the given variables of a sent card, the others are kept
+    pub async fn update_card(
+        &self,
+        out_track_id: impl AsRef<str>,
+        params: &Map<String, Value>,
+    ) -> Result<()> {
+        let body = json!({
+            "outTrackId": out_track_id.as_ref(),
+            "cardData": { "cardParamMap": card_param_map(params) },
+            "cardUpdateOptions": { "updateCardDataByKey": true },
+        });
+        let url = self.api_url(CARD_INSTANCES_PATH);
+        if self.skip_in_dry_run("card update", Method::PUT, &url, &body) {
+            return Ok(());
+        }
+        let res: CardResponse = self.put(url, body).await?;
+        if !res.success {
+            bail!(
+                "update card {} failed: {}",
+                out_track_id.as_ref(),
+                res.result
+            );
+        }
+        Ok(())
+    }
+
+    /// [`update_card`](Self::update_card) for many cards, e.g. a board pinned in many groups,
+    /// with a few requests in flight at a time
+    pub async fn update_cards_bulk(
+        &self,
+        updates: Vec<(String, Map<String, Value>)>,
+    ) -> BulkCardUpdate {
+        let results: Vec<_> = stream::iter(updates)
+            .map(|(out_track_id, params)| async move {
+                let result = self.update_card(&out_track_id, &params).await;
+                (out_track_id, result)
+            })
+            .buffer_unordered(BULK_UPDATE_CONCURRENCY)
+            .collect()
+            .await;
+
+        let mut outcome = BulkCardUpdate::default();
+        for (out_track_id, result) in results {
+            match result {
+                Ok(()) => outcome.updated.push(out_track_id),
+                Err(e) => outcome.failed.push((out_track_id, e)),
+            }
+        }
+        debug!(
+            "bulk card update: {} updated, {} failed",
+            outcome.updated.len(),
+            outcome.failed.len()
+        );
+        outcome
+    }
+
+    async fn create_and_deliver(&self, card: &InteractiveCard, space: Value) -> Result<String> {
+        let mut body = json!({
+            "cardTemplateId": card.template_id,
+            "outTrackId": card.out_track_id,
+            "callbackType": "STREAM",
+            "cardData": { "cardParamMap": card_param_map(&card.params) },
+        });
+        if let (Some(body), Value::Object(space)) = (body.as_object_mut(), space) {
+            body.extend(space);
+        }
+        let url = self.api_url(CREATE_AND_DELIVER_PATH);
+        if self.skip_in_dry_run("card", Method::POST, &url, &body) {
+            return Ok(card.out_track_id.clone());
+        }
+
+        let res: CardResponse = self.post(url, body).await?;
+        let deliveries: Vec<DeliverResult> = res
+            .result
+            .get("deliverResults")
+            .and_then(|v| Vec::deserialize(v).ok())
+            .unwrap_or_default();
+        if let Some(failed) = deliveries.iter().find(|d| !d.success) {
+            bail!(
+                "deliver card {} to {} failed: {}",
+                card.out_track_id,
+                failed.space_id,
+                failed.error_msg
+            );
+        }
+        if !res.success {
+            bail!("create card {} failed: {}", card.out_track_id, res.result);
+        }
+        Ok(card.out_track_id.clone())
+    }
+
+    /// forward a card action to Bevy, the frame is already acknowledged
+    pub(crate) fn on_card_callback(self: &Arc<Self>, message_id: &str, data: &str) -> Result<()> {
+        let callback: CardCallback = serde_json::from_str(data)?;
+        debug!(
+            "card {} action by {}: {}",
+            callback.out_track_id, callback.user_id, callback.content
+        );
+        let mut event = CardActionEvent::from_callback(callback);
+        event.ack = self.frame_ack_token(message_id);
+        self.prompts.answer(&event);
+        self.bridge.send_event(event);
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+//! Fault injection for resilience tests, behind the `chaos` feature
+//!
+//! Every fault is off until set through [`Client::chaos`]:
+//!
+//! ```ignore
+//! client.chaos().set_corrupt_probability(0.1);
+//! client.chaos().set_max_frame_delay(Duration::from_millis(500));
+//! client.chaos().disconnect_now();
+//! ```
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+use std::time::Duration;
+
+use log::warn;
+use rand::Rng;
+use tokio::sync::Notify;
+
+use crate::client::Client;
+use crate::targets::WS;
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Faults {
+    disconnect_probability: f64,
+    corrupt_probability: f64,
+    max_frame_delay: Duration,
+}
+
+/// Faults injected into the stream connection and token handling
+#[derive(Debug, Default)]
+pub struct Chaos {
+    faults: Mutex<Faults>,
+    expire_token: AtomicBool,
+    disconnect: Notify,
+}
+
+impl Chaos {
+    /// chance of dropping the connection when a frame arrives, between 0 and 1
+    pub fn set_disconnect_probability(&self, probability: f64) {
+        self.faults.lock().unwrap().disconnect_probability = probability;
+    }
+
+    /// chance of truncating a frame into invalid json, between 0 and 1
+    pub fn set_corrupt_probability(&self, probability: f64) {
+        self.faults.lock().unwrap().corrupt_probability = probability;
+    }
+
+    /// hold every frame back for a random time up to `delay`
+    pub fn set_max_frame_delay(&self, delay: Duration) {
+        self.faults.lock().unwrap().max_frame_delay = delay;
+    }
+
+    /// drop every open connection, they reconnect as after a network failure
+    pub fn disconnect_now(&self) {
+        warn!(target: WS, "chaos: disconnect");
+        self.disconnect.notify_waiters();
+    }
+
+    /// treat the access token as expired on its next use
+    pub fn expire_token(&self) {
+        self.expire_token.store(true, Ordering::SeqCst);
+    }
+
+    /// turn every fault off
+    pub fn reset(&self) {
+        *self.faults.lock().unwrap() = Faults::default();
+        self.expire_token.store(false, Ordering::SeqCst);
+    }
+
+    pub(crate) async fn disconnected(&self) {
+        self.disconnect.notified().await
+    }
+
+    pub(crate) fn take_token_expiry(&self) -> bool {
+        self.expire_token.swap(false, Ordering::SeqCst)
+    }
+
+    /// apply the faults to a received frame, `None` when the connection is to be dropped
+    pub(crate) async fn on_frame(&self, mut text: String) -> Option<String> {
+        let faults = *self.faults.lock().unwrap();
+        let (disconnect, corrupt, delay) = {
+            let mut rng = rand::thread_rng();
+            (
+                rng.gen_bool(faults.disconnect_probability.clamp(0.0, 1.0)),
+                rng.gen_bool(faults.corrupt_probability.clamp(0.0, 1.0)),
+                faults.max_frame_delay.mul_f64(rng.gen::<f64>()),
+            )
+        };
+        if disconnect {
+            warn!(target: WS, "chaos: drop connection on frame");
+            return None;
+        }
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        if corrupt {
+            let mut at = rand::thread_rng().gen_range(0..text.len().max(1));
+            while !text.is_char_boundary(at) {
+                at -= 1;
+            }
+            warn!(target: WS, "chaos: corrupt frame at byte {}", at);
+            text.truncate(at);
+        }
+        Some(text)
+    }
+}
+
+impl Client {
+    /// fault injection controls of this client
+    pub fn chaos(&self) -> &Chaos {
+        &self.chaos
+    }
+}
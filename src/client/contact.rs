@@ -0,0 +1,197 @@
+//! Types and methods for the contacts API and the cached user lookups built on it
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use futures::{stream, StreamExt};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::client::zone::Zone;
+use crate::client::Client;
+
+const USER_GET_PATH: &str = "/topapi/v2/user/get";
+/// concurrent requests used by [`Client::resolve_users`]
+const RESOLVE_CONCURRENCY: usize = 8;
+
+/// User profile returned by the contacts API
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/query-user-details) for the definition of each field
+#[derive(Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct UserProfile {
+    /// staff id, same as `sender_staff_id` of received messages
+    #[serde(rename = "userid")]
+    pub user_id: String,
+    #[serde(default)]
+    pub unionid: String,
+    #[serde(default)]
+    pub name: String,
+    /// avatar url, may be empty
+    #[serde(default)]
+    pub avatar: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub dept_id_list: Vec<i64>,
+    /// custom address book fields as json object
+    #[serde(default)]
+    pub extension: String,
+}
+
+impl UserProfile {
+    /// zone from a `timezone` or `时区` custom field, e.g. `Europe/Berlin` or `+01:00`
+    pub fn timezone(&self) -> Option<Zone> {
+        let fields: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&self.extension).ok()?;
+        ["timezone", "timeZone", "时区"]
+            .iter()
+            .filter_map(|key| fields.get(*key)?.as_str())
+            .find_map(|value| value.parse().ok())
+    }
+}
+
+/// staff_id → profile cache with a TTL, owned by [`Client`]
+#[derive(Debug)]
+pub(crate) struct UserCache {
+    ttl: Mutex<Duration>,
+    entries: Mutex<HashMap<String, (UserProfile, Instant)>>,
+}
+
+impl Default for UserCache {
+    fn default() -> Self {
+        Self {
+            ttl: Mutex::new(Duration::from_secs(3600)),
+            entries: Default::default(),
+        }
+    }
+}
+
+impl UserCache {
+    /// profile of `user_id` if it was cached less than the TTL before `now`
+    pub fn get(&self, user_id: &str, now: Instant) -> Option<UserProfile> {
+        let ttl = *self.ttl.lock().unwrap();
+        self.entries
+            .lock()
+            .unwrap()
+            .get(user_id)
+            .filter(|(_, at)| now.saturating_duration_since(*at) < ttl)
+            .map(|(p, _)| p.clone())
+    }
+
+    fn insert(&self, profile: UserProfile, now: Instant) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(profile.user_id.clone(), (profile, now));
+    }
+
+    pub fn set_ttl(&self, ttl: Duration) {
+        *self.ttl.lock().unwrap() = ttl;
+    }
+
+    pub fn invalidate(&self, user_id: &str) {
+        self.entries.lock().unwrap().remove(user_id);
+    }
+
+    pub fn invalidate_all(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Payload of `user_add_org` / `user_modify_org` / `user_leave_org` events
+#[derive(Deserialize, Debug, Default)]
+struct UserOrgChanged {
+    #[serde(rename = "UserId", alias = "userId", default)]
+    user_ids: Vec<String>,
+}
+
+impl Client {
+    /// fetch a user profile by staff id, always hits the API
+    pub async fn get_user(&self, user_id: impl AsRef<str>) -> Result<UserProfile> {
+        let profile: UserProfile = self
+            .post_oapi(USER_GET_PATH, json!({ "userid": user_id.as_ref() }))
+            .await?;
+        self.users
+            .insert(profile.clone(), self.current_clock().instant());
+        Ok(profile)
+    }
+
+    /// resolve many staff ids at once, served from cache when fresh
+    ///
+    /// Ids that fail to resolve are logged and left out of the result.
+    pub async fn resolve_users(
+        &self,
+        user_ids: impl IntoIterator<Item = impl Into<String>>,
+    ) -> HashMap<String, UserProfile> {
+        let mut resolved = HashMap::new();
+        let mut missing = Vec::new();
+        let now = self.current_clock().instant();
+        for id in user_ids {
+            let id = id.into();
+            if resolved.contains_key(&id) || missing.contains(&id) {
+                continue;
+            }
+            match self.users.get(&id, now) {
+                Some(p) => {
+                    resolved.insert(id, p);
+                }
+                None => missing.push(id),
+            }
+        }
+
+        debug!(
+            "resolve users: {} cached, {} to fetch",
+            resolved.len(),
+            missing.len()
+        );
+        let fetched: Vec<_> = stream::iter(missing)
+            .map(|id| async move { (self.get_user(&id).await, id) })
+            .buffer_unordered(RESOLVE_CONCURRENCY)
+            .collect()
+            .await;
+        for (result, id) in fetched {
+            match result {
+                Ok(p) => {
+                    resolved.insert(id, p);
+                }
+                Err(e) => warn!("resolve user {} error: {:?}", id, e),
+            }
+        }
+
+        resolved
+    }
+
+    /// cached profile if still within TTL
+    pub fn cached_user(&self, user_id: impl AsRef<str>) -> Option<UserProfile> {
+        self.users
+            .get(user_id.as_ref(), self.current_clock().instant())
+    }
+
+    /// Change how long resolved profiles are kept, default is one hour
+    pub fn user_cache_ttl(&self, ttl: Duration) {
+        self.users.set_ttl(ttl);
+    }
+
+    /// drop cached profiles touched by org change events
+    pub(crate) fn dispatch_contact_event(&self, event_type: &str, data: &str) {
+        match event_type {
+            "user_add_org" | "user_modify_org" | "user_leave_org" => {
+                match serde_json::from_str::<UserOrgChanged>(data) {
+                    Ok(p) => p.user_ids.iter().for_each(|id| self.users.invalidate(id)),
+                    Err(e) => {
+                        warn!("parse {} event error: {:?}", event_type, e);
+                        self.users.invalidate_all();
+                    }
+                }
+            }
+            "org_change" => self.users.invalidate_all(),
+            _ => {}
+        }
+    }
+}
@@ -1,31 +1,90 @@
 //! Types and methods that handles down from DingTalk server
 
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
 use log::{debug, error, warn};
-use serde::Deserialize;
-use serde_json::json;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
 use std::{
+    collections::HashMap,
     io::{Error, ErrorKind},
-    sync::Arc,
+    sync::{Arc, RwLock},
 };
 use tokio::io::{copy, AsyncWrite};
 use tokio_util::io::StreamReader;
+use crate::client::ack::AckOrder;
+use crate::client::card::CardCallback;
+use crate::client::up::SendTapped;
 use crate::client::Client;
-use crate::client::up::ClientUpStream;
+use crate::client::stats::ConversationRef;
+use crate::storage::DEDUPE;
+
+pub use crate::protocol::down::{
+    ClientDownStream, CustomContent, DownstreamEnvelope, EmotionContent, EventData, MsgContent,
+    RichText, RobotRecvMessage, StickerContent, StreamDownHeaders, User,
+};
+pub use crate::protocol::up::{AckStatus, ClientUpStream, EventAckData};
+use crate::targets::WS;
+use crate::topics::Topic;
+
+/// persisted frame ids older than this are forgotten
+const DEDUPE_TTL_SECS: i64 = 24 * 60 * 60;
 
 impl Client {
-    pub(crate) async fn on_down_stream(&self, p: ClientDownStream) -> Result<()> {
+    pub(crate) async fn on_down_stream(self: &Arc<Self>, p: ClientDownStream) -> Result<()> {
+        self.record_server_time(&p.headers.time);
+        if p.r#type != "SYSTEM" {
+            let id = frame_id(&p);
+            self.track_ack(&p.headers.message_id, &id, &p.headers.topic, p.link);
+            self.track_redelivery(&id);
+            if self.is_duplicate(&p) {
+                debug!("drop duplicated frame {}", p.headers.message_id);
+                let data = match p.r#type.as_str() {
+                    "EVENT" => serde_json::to_string(&EventAckData::default())?,
+                    _ => serde_json::to_string(&json!({"response" : {}}))?,
+                };
+                let msg = ClientUpStream::new(data, p.headers.message_id);
+                return self.send_ack(p.link, msg).await;
+            }
+        }
+
         match p.r#type.as_str() {
             "SYSTEM" => self.on_system(p).await?,
-            "EVENT" => self.on_event(p.headers.message_id, p.headers.event).await?,
+            "EVENT" => {
+                self.on_event(p.link, p.headers.message_id, p.headers.event, p.data)
+                    .await?
+            }
+            "CALLBACK" if Topic::AI_ASSISTANT == p.headers.topic => self.on_graph_request(p)?,
             "CALLBACK" => {
-                let msg = ClientUpStream::new(
-                    serde_json::to_string(&json!({"response" : {}}))?,
-                    p.headers.message_id.clone(),
-                );
-                self.send(msg).await?;
+                let manual = self
+                    .config
+                    .lock()
+                    .unwrap()
+                    .manual_response_topics
+                    .contains(&p.headers.topic);
+                if manual {
+                    // answered through Client::send_stream_response
+                } else if self.ack_order_of(&p.headers.topic) == AckOrder::AfterHandler {
+                    self.defer_ack(&p.headers.message_id, p.link, &frame_id(&p));
+                } else {
+                    let msg = ClientUpStream::new(
+                        serde_json::to_string(&json!({"response" : {}}))?,
+                        p.headers.message_id.clone(),
+                    );
+                    self.send_ack(p.link, msg).await?;
+                }
+                if Topic::ROBOT_MESSAGES == p.headers.topic {
+                    if let Ok(c) = serde_json::from_str::<ConversationRef>(&p.data) {
+                        self.record_received(&c.conversation_id);
+                    }
+                }
+                if Topic::CARD_CALLBACKS == p.headers.topic {
+                    if let Err(e) = self.on_card_callback(&p.headers.message_id, &p.data) {
+                        self.on_frame_error(p.headers.message_id.clone(), e);
+                    }
+                }
                 self.tx.broadcast(Arc::new(p)).await?;
             }
             _ => error!("unknown message type: {}", p.r#type),
@@ -34,25 +93,64 @@ impl Client {
         Ok(())
     }
 
-    async fn on_event(&self, message_id: impl Into<String>, p: EventData) -> Result<()> {
+    /// with several connections the same message may arrive on more than one of them
+    fn is_duplicate(&self, p: &ClientDownStream) -> bool {
+        if self.config.lock().unwrap().connections <= 1 {
+            return false;
+        }
+
+        let id = frame_id(p);
+        if !self.seen.lock().unwrap().insert(&id) {
+            return true;
+        }
+        self.seen_before_restart(&id)
+    }
+
+    /// checks and records `id` in the [`DEDUPE`] namespace, which outlives the process
+    fn seen_before_restart(&self, id: &str) -> bool {
+        if self.remembered(DEDUPE, id) {
+            return true;
+        }
+        self.remember(DEDUPE, id, DEDUPE_TTL_SECS);
+        false
+    }
+
+    async fn on_event(
+        &self,
+        link: usize,
+        message_id: impl Into<String>,
+        mut p: EventData,
+        data: String,
+    ) -> Result<()> {
         debug!("event received: {:?}", p);
+        p.data.clone_from(&data);
+        let event_type = p.event_type.clone();
+        let corp_id = p.event_corp_id.clone();
+        let event_id = p.event_id.clone();
         let ack = self.on_event_callback.0.read().unwrap()(p);
+        if ack.status == AckStatus::Later {
+            self.leave_for_redelivery(&event_id);
+        } else {
+            self.settle_redelivery(&event_id);
+        }
+        self.dispatch_group_event(&event_type, &corp_id, &data);
+        self.dispatch_contact_event(&event_type, &data);
         let msg = ClientUpStream::new(serde_json::to_string(&ack)?, message_id);
-        self.send(msg).await?;
+        self.send_ack(link, msg).await?;
 
         Ok(())
     }
 
     async fn on_system(&self, p: ClientDownStream) -> Result<()> {
         match p.headers.topic.as_str() {
-            "CONNECTED" => debug!("[SYSTEM]: connected"),
-            "REGISTERED" => debug!("[SYSTEM]: registered"),
-            "disconnect" => debug!("[SYSTEM]: disconnect"),
-            "KEEPALIVE" => debug!("[SYSTEM]: keepalive"),
+            "CONNECTED" => debug!(target: WS, "[SYSTEM]: connected"),
+            "REGISTERED" => debug!(target: WS, "[SYSTEM]: registered"),
+            "disconnect" => debug!(target: WS, "[SYSTEM]: disconnect"),
+            "KEEPALIVE" => debug!(target: WS, "[SYSTEM]: keepalive"),
             "ping" => {
-                debug!("[SYSTEM]: ping");
+                debug!(target: WS, "[SYSTEM]: ping");
                 let msg = ClientUpStream::new(p.data, p.headers.message_id);
-                self.send(msg).await?;
+                self.send(p.link, msg).await?;
             }
             _ => warn!("unknown system message: {}", p.headers.topic),
         }
@@ -62,16 +160,38 @@ impl Client {
 
     /// get download url instead of download it
     pub async fn download_url(&self, download_code: impl AsRef<str>) -> Result<String> {
-        let client_id = self.config.lock().unwrap().client_id.clone();
+        let robot_code = self.current_robot_code();
         let response: DownloadUrl = self
             .post(
-                DOWNLOAD_URL,
-                json!({ "downloadCode": download_code.as_ref(), "robotCode": client_id}),
+                self.api_url(DOWNLOAD_PATH),
+                json!({ "downloadCode": download_code.as_ref(), "robotCode": robot_code}),
             )
             .await?;
         Ok(response.download_url)
     }
 
+    /// fetch a plain url into memory, returns the body and its content type
+    pub(crate) async fn get_bytes(
+        &self,
+        url: impl AsRef<str>,
+    ) -> Result<(Vec<u8>, Option<String>)> {
+        let response = self.client.get(url.as_ref()).send_tapped(self).await?;
+        if !response.status().is_success() {
+            bail!(
+                "get error: {} - {}",
+                response.status(),
+                response.text().await?
+            );
+        }
+
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(ToOwned::to_owned);
+        Ok((response.bytes().await?.to_vec(), content_type))
+    }
+
     /// download file from download_code
     pub async fn download(
         &self,
@@ -79,7 +199,7 @@ impl Client {
         mut writer: impl AsyncWrite + Unpin,
     ) -> Result<()> {
         let download_url = self.download_url(download_code).await?;
-        let response = self.client.get(download_url).send().await?;
+        let response = self.client.get(download_url).send_tapped(self).await?;
         if !response.status().is_success() {
             bail!(
                 "download error: {} - {}",
@@ -97,160 +217,206 @@ impl Client {
 
         Ok(())
     }
+
+    /// Decode the content of robot messages with `msgtype` as `T`, delivered as
+    /// [`MsgContent::Custom`]
+    ///
+    /// Lets new message types be handled before the crate knows about them, registering a
+    /// built-in msgtype replaces its variant.
+    pub fn register_msg_type<T>(self: Arc<Self>, msgtype: impl Into<String>) -> Arc<Self>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        self.msg_types.0.write().unwrap().insert(
+            msgtype.into(),
+            Box::new(|raw| Ok(CustomContent::new(T::deserialize(raw)?))),
+        );
+        self
+    }
+
+    /// parse a robot message, applying the registered msgtype decoders
+    pub(crate) fn parse_robot_message(&self, data: &str) -> Result<RobotRecvMessage> {
+        Ok(RobotRecvMessage::from_json_with(data, |msgtype, raw| {
+            match self.msg_types.decode(msgtype, raw)? {
+                Ok(content) => Some(MsgContent::Custom {
+                    msgtype: msgtype.to_owned(),
+                    content,
+                }),
+                Err(e) => {
+                    warn!("decode msgtype {} error: {:?}", msgtype, e);
+                    None
+                }
+            }
+        })?)
+    }
 }
 
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct DownloadUrl {
-    download_url: String,
+impl StreamDownHeaders {
+    /// when the server sent the frame, `None` when `time` is not a millisecond timestamp
+    pub fn sent_at(&self) -> Option<DateTime<Utc>> {
+        parse_millis(&self.time)
+    }
 }
-const DOWNLOAD_URL: &str = "https://api.dingtalk.com/v1.0/robot/messageFiles/download";
 
-#[derive(Debug, Default, Deserialize)]
-#[serde(rename_all = "camelCase")]
-#[allow(dead_code)]
-pub(crate) struct ClientDownStream {
-    pub spec_version: String,
-    pub r#type: String,
-    pub headers: StreamDownHeaders,
-    pub data: String,
+impl DownstreamEnvelope {
+    /// when the server sent the frame
+    pub fn sent_at(&self) -> Option<DateTime<Utc>> {
+        parse_millis(&self.time)
+    }
 }
 
-#[derive(Debug, Default, Deserialize)]
-#[serde(rename_all = "camelCase")]
-#[allow(dead_code)]
-pub(crate) struct StreamDownHeaders {
-    #[serde(default)]
-    pub app_id: String,
-    #[serde(default)]
-    pub connection_id: String,
-    pub content_type: String,
-    pub message_id: String,
-    pub time: String,
-    pub topic: String,
-    #[serde(flatten)]
-    pub event: EventData,
+impl EventData {
+    /// when the event happened, `None` for frames without `eventBornTime`
+    pub fn born_at(&self) -> Option<DateTime<Utc>> {
+        parse_millis(&self.event_born_time)
+    }
 }
 
-/// Event type pushed by DingTalk server
-///
-/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/org-event-overview) for the definition of each field
-#[derive(Debug, Default, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct EventData {
-    #[serde(default)]
-    pub event_type: String,
-    #[serde(default)]
-    pub event_born_time: String,
-    #[serde(default)]
-    pub event_id: String,
-    #[serde(default)]
-    pub event_corp_id: String,
-    #[serde(default)]
-    pub event_unified_app_id: String,
+impl RobotRecvMessage {
+    /// when the message was sent, see [`Client::local_time`](crate::client::Client::local_time)
+    /// for the sender's wall clock
+    pub fn created_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.create_at as i64).unwrap_or_default()
+    }
+
+    /// when [`session_webhook`](Self::session_webhook) stops working
+    pub fn webhook_expires_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.session_webhook_expired_time as i64)
+            .unwrap_or_default()
+    }
 }
 
-/// Message type pushed by DingTalk server
-///
-/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/receive-message) for the definition of each field
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct RobotRecvMessage {
-    pub msg_id: String,
-    pub msgtype: String,
-    #[serde(alias = "text")]
-    pub content: MsgContent,
-
-    pub conversation_id: String,
-    /// 1 - single chat
-    /// 2 - group chat
-    pub conversation_type: String,
-    #[serde(default)]
-    pub conversation_title: String,
+/// millisecond timestamp as sent in headers
+fn parse_millis(millis: &str) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(millis.parse().ok()?)
+}
 
-    #[serde(default)]
-    pub at_users: Vec<User>,
-    #[serde(default)]
-    pub is_in_at_list: bool,
+type MsgTypeDecoder = Box<dyn Fn(&Value) -> Result<CustomContent> + Send + Sync>;
 
-    #[serde(default)]
-    pub chatbot_corp_id: String,
-    pub chatbot_user_id: String,
+/// decoders added through [`Client::register_msg_type`]
+#[derive(Default)]
+pub(crate) struct MsgTypeRegistry(RwLock<HashMap<String, MsgTypeDecoder>>);
 
-    pub sender_id: String,
-    pub sender_nick: String,
-    #[serde(default)]
-    pub sender_corp_id: String,
-    #[serde(default)]
-    pub sender_staff_id: String,
+impl MsgTypeRegistry {
+    fn decode(&self, msgtype: &str, raw: &Value) -> Option<Result<CustomContent>> {
+        self.0
+            .read()
+            .unwrap()
+            .get(msgtype)
+            .map(|decoder| decoder(raw))
+    }
+}
+
+impl std::fmt::Debug for MsgTypeRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set()
+            .entries(self.0.read().unwrap().keys())
+            .finish()
+    }
+}
 
-    pub session_webhook_expired_time: u64,
-    pub session_webhook: String,
 
-    #[serde(default)]
-    pub is_admin: bool,
-    pub create_at: u64,
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DownloadUrl {
+    download_url: String,
 }
+const DOWNLOAD_PATH: &str = "/v1.0/robot/messageFiles/download";
 
-/// At(@) User type
-///
-/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/receive-message) for the definition of each field
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
-pub struct User {
-    pub dingtalk_id: String,
+pub(crate) struct MessageId {
     #[serde(default)]
-    pub staff_id: String,
+    pub msg_id: String,
 }
 
-/// Enumeration types for all received messages
-///
-/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/receive-message) for the definition of each field
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase", untagged)]
-pub enum MsgContent {
-    #[serde(rename_all = "camelCase")]
-    Text { content: String },
-    #[serde(rename_all = "camelCase")]
-    File {
-        download_code: String,
-        file_name: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    Picture {
-        download_code: String,
-        #[serde(default)]
-        picture_download_code: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    RichText { rich_text: Vec<RichText> },
-    #[serde(rename_all = "camelCase")]
-    Audio {
-        duration: u32,
-        download_code: String,
-        recognition: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    Video {
-        duration: u32,
-        download_code: String,
-        video_type: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    UnknownMsgType { unknown_msg_type: String },
+/// id a frame is deduplicated and redelivered by: the robot message id, the event id, or the
+/// stream message id for everything else
+fn frame_id(p: &ClientDownStream) -> String {
+    let id = if Topic::ROBOT_MESSAGES == p.headers.topic {
+        serde_json::from_str::<MessageId>(&p.data)
+            .map(|m| m.msg_id)
+            .unwrap_or_default()
+    } else {
+        p.headers.event.event_id.clone()
+    };
+    if id.is_empty() {
+        p.headers.message_id.clone()
+    } else {
+        id
+    }
 }
 
-/// Enumeration types for rich text
+/// Parse a recorded frame and its data the way the client does, e.g. to check captured traffic
+/// in CI
 ///
-/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/receive-message) for the definition of each field
-#[derive(Deserialize, Debug)]
-#[serde(rename_all = "camelCase", untagged)]
-pub enum RichText {
-    #[serde(rename_all = "camelCase")]
-    Text { text: String },
-    #[serde(rename_all = "camelCase")]
-    Picture {
-        download_code: String,
-        r#type: String,
-    },
+/// Robot messages and card callbacks are parsed into their types, other data only has to be
+/// JSON. With the `strict-protocol` feature unknown fields fail, the error names the field.
+pub fn check_frame(text: &str) -> Result<ClientDownStream> {
+    let frame: ClientDownStream = serde_json::from_str(text).context("parse frame")?;
+    let topic = frame.headers.topic.as_str();
+    if Topic::ROBOT_MESSAGES == *topic {
+        RobotRecvMessage::from_json(&frame.data).context("parse robot message")?;
+    } else if Topic::CARD_CALLBACKS == *topic {
+        serde_json::from_str::<CardCallback>(&frame.data).context("parse card callback")?;
+    } else if !frame.data.is_empty() {
+        serde_json::from_str::<Value>(&frame.data)
+            .with_context(|| format!("parse data of {topic}"))?;
+    }
+    Ok(frame)
+}
+
+
+
+/// Decides which robot messages are delivered to a listener
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFilter {
+    /// every message, including group chatter not addressed to the robot
+    #[default]
+    All,
+    /// group messages that @ the robot, and any 1:1 message
+    MentionedOrDirect,
+    /// only group messages that @ the robot
+    Mentioned,
+    /// only 1:1 messages
+    Direct,
+}
+
+impl MessageFilter {
+    pub fn matches(&self, msg: &RobotRecvMessage) -> bool {
+        match self {
+            MessageFilter::All => true,
+            MessageFilter::MentionedOrDirect => msg.is_direct() || msg.is_in_at_list,
+            MessageFilter::Mentioned => msg.is_group() && msg.is_in_at_list,
+            MessageFilter::Direct => msg.is_direct(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn robot_message_frame() -> ClientDownStream {
+        serde_json::from_str(include_str!("../../fixtures/frame/robot_message.json")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_ack_lets_the_redelivery_through() {
+        let client = Client::new("id", "secret").unwrap().connections(2);
+        let frame = robot_message_frame();
+
+        // not connected, so the ACK fails
+        assert!(client.on_down_stream(robot_message_frame()).await.is_err());
+        assert!(client.outstanding_acks().is_empty());
+        assert!(!client.is_duplicate(&frame));
+    }
+
+    #[tokio::test]
+    async fn copies_are_duplicates() {
+        let client = Client::new("id", "secret").unwrap().connections(2);
+        let frame = robot_message_frame();
+        assert!(!client.is_duplicate(&frame));
+        assert!(client.is_duplicate(&frame));
+    }
 }
@@ -0,0 +1,223 @@
+//! Large files through the DingTalk drive (storage) API, for sizes the media upload refuses
+//!
+//! Please refer to the [official document](https://open.dingtalk.com/document/orgapp/multipart-upload-files) for the upload flow
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use log::debug;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{fs::File, io::AsyncReadExt};
+
+use crate::client::up::SendTapped;
+use crate::client::Client;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Where [`Client::upload_to_drive`] stores files
+#[derive(Debug, Clone)]
+pub struct DriveFallback {
+    /// drive space receiving the files
+    pub space_id: String,
+    /// union id of the user the uploads are made as, needs access to the space
+    pub union_id: String,
+    /// folder inside the space, `"0"` is its root
+    pub parent_id: String,
+    /// bytes per uploaded part, default 8 MiB
+    pub part_size: u64,
+}
+
+impl DriveFallback {
+    pub fn new(space_id: impl Into<String>, union_id: impl Into<String>) -> Self {
+        Self {
+            space_id: space_id.into(),
+            union_id: union_id.into(),
+            parent_id: "0".to_owned(),
+            part_size: 8 * MIB,
+        }
+    }
+
+    /// store files in the folder `parent_id`
+    pub fn parent_id(mut self, parent_id: impl Into<String>) -> Self {
+        self.parent_id = parent_id.into();
+        self
+    }
+
+    /// upload in parts of `part_size` bytes
+    pub fn part_size(mut self, part_size: u64) -> Self {
+        self.part_size = part_size.max(MIB);
+        self
+    }
+}
+
+/// A file stored in a drive space
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DriveFile {
+    pub id: String,
+    pub space_id: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub size: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadInit {
+    upload_key: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PartInfos {
+    multipart_header_signature_infos: Vec<PartInfo>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PartInfo {
+    part_number: u64,
+    header_signature_info: SignedResource,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SignedResource {
+    resource_urls: Vec<String>,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct Committed {
+    dentry: DriveFile,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DownloadInfo {
+    header_signature_info: SignedResource,
+}
+
+impl Client {
+    /// Send files above the media upload limit through the drive, see [`Client::send_file`]
+    pub fn drive_fallback(self: Arc<Self>, value: DriveFallback) -> Arc<Self> {
+        self.config.lock().unwrap().drive_fallback = Some(value);
+        self
+    }
+
+    /// Upload `path` to the configured [drive](Client::drive_fallback) in parts
+    pub async fn upload_to_drive(&self, path: impl AsRef<Path>) -> Result<DriveFile> {
+        let Some(drive) = self.config.lock().unwrap().drive_fallback.clone() else {
+            bail!("no drive fallback configured");
+        };
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .context("upload path without file name")?
+            .to_string_lossy()
+            .to_string();
+        let mut file = File::open(path).await?;
+        let size = file.metadata().await?.len();
+        let parts = size.div_ceil(drive.part_size).max(1);
+
+        let init: UploadInit = self
+            .post_as(
+                None,
+                self.drive_url(&drive, "/files/multiPartUploadInfos/init"),
+                json!({ "option": { "preCheckParam": { "name": name, "size": size } } }),
+            )
+            .await?;
+        let infos: PartInfos = self
+            .post_as(
+                None,
+                self.drive_url(&drive, "/files/multiPartUploadInfos/query"),
+                json!({
+                    "uploadKey": init.upload_key,
+                    "partNumbers": (1..=parts).collect::<Vec<_>>(),
+                }),
+            )
+            .await?;
+
+        let mut infos = infos.multipart_header_signature_infos;
+        infos.sort_by_key(|p| p.part_number);
+        for info in infos {
+            let mut chunk = Vec::with_capacity(drive.part_size as usize);
+            (&mut file)
+                .take(drive.part_size)
+                .read_to_end(&mut chunk)
+                .await?;
+            self.put_part(&info.header_signature_info, chunk)
+                .await
+                .with_context(|| format!("upload part {} of {}", info.part_number, name))?;
+            debug!("uploaded part {}/{} of {}", info.part_number, parts, name);
+        }
+
+        let committed: Committed = self
+            .post_as(
+                None,
+                self.drive_url(&drive, "/files/commit"),
+                json!({
+                    "uploadKey": init.upload_key,
+                    "name": name,
+                    "parentId": drive.parent_id,
+                    "option": { "size": size, "conflictStrategy": "AUTO_RENAME" },
+                }),
+            )
+            .await?;
+        Ok(committed.dentry)
+    }
+
+    /// Signed, expiring download url of `file`
+    pub async fn drive_download_url(&self, file: &DriveFile) -> Result<String> {
+        let Some(drive) = self.config.lock().unwrap().drive_fallback.clone() else {
+            bail!("no drive fallback configured");
+        };
+        let info: DownloadInfo = self
+            .post_as(
+                None,
+                self.drive_url(
+                    &drive,
+                    &format!("/dentries/{}/downloadInfos/query", file.id),
+                ),
+                json!({}),
+            )
+            .await?;
+        info.header_signature_info
+            .resource_urls
+            .into_iter()
+            .next()
+            .context("download info without url")
+    }
+
+    fn drive_url(&self, drive: &DriveFallback, path: &str) -> String {
+        self.api_url(&format!(
+            "/v1.0/storage/spaces/{}{}?unionId={}",
+            drive.space_id, path, drive.union_id
+        ))
+    }
+
+    async fn put_part(&self, resource: &SignedResource, chunk: Vec<u8>) -> Result<()> {
+        let url = resource
+            .resource_urls
+            .first()
+            .context("part without upload url")?;
+        let mut request = self.client.put(url).body(chunk);
+        for (name, value) in &resource.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send_tapped(self).await?;
+        if !response.status().is_success() {
+            bail!(
+                "put part error: {} - {}",
+                response.status(),
+                response.text().await?
+            );
+        }
+        Ok(())
+    }
+}
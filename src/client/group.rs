@@ -1 +1,250 @@
+//! Types and methods for group (chat) related events and APIs
 
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::warn;
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::client::tenant::TenantId;
+use crate::client::up::{MessageTemplate, RobotSendMessage, SendResult};
+use crate::client::Client;
+use crate::event::{GroupMemberJoined, GroupMemberLeft, GroupTitleUpdated};
+use crate::markdown;
+
+/// Payload of `chat_add_member` / `chat_remove_member` events
+///
+/// The field casing differs between the legacy HTTP callback and stream pushes, both are accepted.
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/group-session-event) for the definition of each field
+#[derive(Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct GroupMembersChanged {
+    /// corp the event happened in
+    #[serde(skip)]
+    pub tenant: TenantId,
+    #[serde(rename = "ChatId", alias = "chatId", default)]
+    pub chat_id: String,
+    #[serde(rename = "OpenConversationId", alias = "openConversationId", default)]
+    pub open_conversation_id: String,
+    /// user id of whoever added or removed the members
+    #[serde(rename = "Operator", alias = "operator", default)]
+    pub operator: String,
+    #[serde(rename = "UserId", alias = "userId", default)]
+    pub user_ids: Vec<String>,
+}
+
+/// Payload of the `chat_update_title` event
+#[derive(Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct GroupTitleChanged {
+    /// corp the event happened in
+    #[serde(skip)]
+    pub tenant: TenantId,
+    #[serde(rename = "ChatId", alias = "chatId", default)]
+    pub chat_id: String,
+    #[serde(rename = "OpenConversationId", alias = "openConversationId", default)]
+    pub open_conversation_id: String,
+    #[serde(rename = "Operator", alias = "operator", default)]
+    pub operator: String,
+    #[serde(rename = "Title", alias = "title", default)]
+    pub title: String,
+}
+
+pub const EVENT_CHAT_ADD_MEMBER: &str = "chat_add_member";
+pub const EVENT_CHAT_REMOVE_MEMBER: &str = "chat_remove_member";
+pub const EVENT_CHAT_UPDATE_TITLE: &str = "chat_update_title";
+
+const SCENE_GROUP_CREATE_PATH: &str = "/topapi/im/chat/scenegroup/create";
+const SCENE_GROUP_UPDATE_PATH: &str = "/topapi/im/chat/scenegroup/update";
+
+#[derive(Deserialize)]
+struct SceneGroupResult {
+    open_conversation_id: String,
+}
+
+/// Who may use @all in a group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MentionAll {
+    Everyone,
+    OwnerOnly,
+}
+
+/// Changes to a scene group, fields left `None` keep their value
+///
+/// Only groups created from a template of this app can be changed, see
+/// [`Client::create_scene_group`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GroupSettings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// user id of the new owner
+    #[serde(rename = "owner_user_id", skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(
+        rename = "mention_all_authority",
+        serialize_with = "mention_all",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub mention_all: Option<MentionAll>,
+    /// only the owner and admins may speak
+    #[serde(
+        rename = "chat_banned_type",
+        serialize_with = "flag",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub mute_all: Option<bool>,
+    /// new members see the messages sent before they joined
+    #[serde(
+        rename = "show_history_type",
+        serialize_with = "flag",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub show_history: Option<bool>,
+    #[serde(serialize_with = "flag", skip_serializing_if = "Option::is_none")]
+    pub searchable: Option<bool>,
+}
+
+impl GroupSettings {
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn owner(mut self, user_id: impl Into<String>) -> Self {
+        self.owner = Some(user_id.into());
+        self
+    }
+
+    pub fn mention_all(mut self, who: MentionAll) -> Self {
+        self.mention_all = Some(who);
+        self
+    }
+
+    pub fn mute_all(mut self, muted: bool) -> Self {
+        self.mute_all = Some(muted);
+        self
+    }
+
+    pub fn show_history(mut self, show: bool) -> Self {
+        self.show_history = Some(show);
+        self
+    }
+
+    pub fn searchable(mut self, searchable: bool) -> Self {
+        self.searchable = Some(searchable);
+        self
+    }
+}
+
+fn flag<S: serde::Serializer>(value: &Option<bool>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u8(value.map_or(0, u8::from))
+}
+
+fn mention_all<S: serde::Serializer>(
+    value: &Option<MentionAll>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u8(match value {
+        Some(MentionAll::OwnerOnly) => 1,
+        _ => 0,
+    })
+}
+
+impl Client {
+    /// Create a scene group from the group template `template_id` with the robot in it, returns
+    /// its open conversation id
+    ///
+    /// `owner` and `members` are user ids, the owner joins whether listed in `members` or not.
+    /// The template has to be set up in the developer console with this app's robot.
+    pub async fn create_scene_group(
+        &self,
+        name: &str,
+        owner: &str,
+        members: &[String],
+        template_id: &str,
+    ) -> Result<String> {
+        let mut user_ids = members.to_vec();
+        if !user_ids.iter().any(|m| m == owner) {
+            user_ids.insert(0, owner.to_owned());
+        }
+        let body = json!({
+            "title": name,
+            "template_id": template_id,
+            "owner_user_id": owner,
+            "user_ids": user_ids.join(","),
+        });
+        let url = self.oapi_url(SCENE_GROUP_CREATE_PATH);
+        if self.skip_in_dry_run("scene group", Method::POST, &url, &body) {
+            return Ok(String::new());
+        }
+
+        let created: SceneGroupResult = self.post_oapi(SCENE_GROUP_CREATE_PATH, body).await?;
+        Ok(created.open_conversation_id)
+    }
+
+    /// Apply `settings` to the scene group `open_conversation_id`, e.g. mute it until a
+    /// tournament starts
+    pub async fn update_scene_group(
+        &self,
+        open_conversation_id: &str,
+        settings: &GroupSettings,
+    ) -> Result<()> {
+        let mut body = serde_json::to_value(settings)?;
+        body["open_conversation_id"] = json!(open_conversation_id);
+        let url = self.oapi_url(SCENE_GROUP_UPDATE_PATH);
+        if self.skip_in_dry_run("scene group update", Method::POST, &url, &body) {
+            return Ok(());
+        }
+
+        let _: SceneGroupResult = self.post_oapi(SCENE_GROUP_UPDATE_PATH, body).await?;
+        Ok(())
+    }
+
+    /// Post an announcement to a group as a markdown message
+    ///
+    /// The open API has no way for robots to write the announcement board of a group, so this
+    /// is an ordinary message headed by `title`.
+    pub async fn announce_in_group(
+        self: &Arc<Self>,
+        open_conversation_id: &str,
+        title: &str,
+        text: &str,
+    ) -> Result<SendResult> {
+        RobotSendMessage::group(
+            self.clone(),
+            open_conversation_id,
+            MessageTemplate::SampleMarkdown {
+                title: title.to_owned(),
+                text: format!("### {}\n\n{}", markdown::escape(title), text),
+            },
+        )?
+        .send()
+        .await
+    }
+
+    /// turn group events into typed Bevy events, other event types are ignored
+    pub(crate) fn dispatch_group_event(&self, event_type: &str, corp_id: &str, data: &str) {
+        let tenant = TenantId::new(corp_id);
+        let result = match event_type {
+            EVENT_CHAT_ADD_MEMBER => serde_json::from_str(data).map(|p| {
+                self.bridge
+                    .send_event(GroupMemberJoined(GroupMembersChanged { tenant, ..p }))
+            }),
+            EVENT_CHAT_REMOVE_MEMBER => serde_json::from_str(data).map(|p| {
+                self.bridge
+                    .send_event(GroupMemberLeft(GroupMembersChanged { tenant, ..p }))
+            }),
+            EVENT_CHAT_UPDATE_TITLE => serde_json::from_str(data).map(|p| {
+                self.bridge
+                    .send_event(GroupTitleUpdated(GroupTitleChanged { tenant, ..p }))
+            }),
+            _ => return,
+        };
+
+        if let Err(e) = result {
+            warn!("parse {} event error: {:?}", event_type, e);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+//! Startup self-test of credentials, gateway and sending
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::{error, info};
+
+use crate::client::up::{MessageTemplate, RobotSendMessage};
+use crate::client::Client;
+use crate::error::GatewayError;
+
+/// Outcome of [`Client::health_check`], every step runs even when an earlier one failed
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct HealthReport {
+    /// error acquiring an access token
+    pub token_error: Option<String>,
+    /// error opening a connection ticket at the stream gateway
+    pub gateway_error: Option<String>,
+    /// permission scopes the gateway reported missing, see [`GatewayError::missing_scopes`]
+    pub missing_scopes: Vec<String>,
+    /// error sending the canary message
+    pub canary_error: Option<String>,
+    /// conversation the canary was sent to, `None` when no ops conversation is configured
+    pub canary_conversation: Option<String>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// failed steps and their errors
+    pub fn failures(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("token", &self.token_error),
+            ("gateway", &self.gateway_error),
+            ("canary", &self.canary_error),
+        ]
+        .into_iter()
+        .filter_map(|(step, error)| error.as_deref().map(|e| (step, e)))
+    }
+}
+
+impl Client {
+    /// Send a canary to `conversation_id` during [`Client::health_check`]
+    pub fn ops_conversation(self: Arc<Self>, conversation_id: impl Into<String>) -> Arc<Self> {
+        self.config.lock().unwrap().ops_conversation = Some(conversation_id.into());
+        self
+    }
+
+    /// Verify the credentials and gateway, and send a canary message when an
+    /// [ops conversation](Client::ops_conversation) is configured
+    pub async fn health_check(self: &Arc<Self>) -> HealthReport {
+        let token_error = self.token().await.err().map(|e| e.to_string());
+        let gateway = self.get_endpoint().await.err();
+        let mut report = HealthReport {
+            token_error,
+            missing_scopes: gateway
+                .as_ref()
+                .and_then(|e| e.downcast_ref::<GatewayError>())
+                .map(|e| e.missing_scopes().to_vec())
+                .unwrap_or_default(),
+            gateway_error: gateway.map(|e| e.to_string()),
+            ..Default::default()
+        };
+
+        let ops = self.config.lock().unwrap().ops_conversation.clone();
+        if let Some(conversation_id) = ops {
+            report.canary_error = self
+                .send_canary(&conversation_id)
+                .await
+                .err()
+                .map(|e| e.to_string());
+            report.canary_conversation = Some(conversation_id);
+        }
+
+        if report.is_healthy() {
+            info!("health check passed");
+        } else {
+            report
+                .failures()
+                .for_each(|(step, e)| error!("health check {} failed: {}", step, e));
+        }
+        report
+    }
+
+    async fn send_canary(self: &Arc<Self>, conversation_id: &str) -> Result<()> {
+        let message = MessageTemplate::SampleText {
+            content: format!(
+                "health check from {}",
+                self.config.lock().unwrap().client_id
+            ),
+        };
+        RobotSendMessage::group(self.clone(), conversation_id, message)?
+            .send()
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! Async hooks run when a stream connection opens or drops
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use log::error;
+
+use crate::client::Client;
+use crate::error::ErrorContext;
+use crate::targets::WS;
+
+type LinkHook = Box<
+    dyn Fn(Arc<Client>, usize) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync,
+>;
+
+/// hooks added through [`Client::on_connected`] and [`Client::on_disconnected`]
+#[derive(Default)]
+pub(crate) struct LinkHooks {
+    connected: RwLock<Vec<LinkHook>>,
+    disconnected: RwLock<Vec<LinkHook>>,
+}
+
+impl std::fmt::Debug for LinkHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LinkHooks")
+            .field("connected", &self.connected.read().unwrap().len())
+            .field("disconnected", &self.disconnected.read().unwrap().len())
+            .finish()
+    }
+}
+
+fn boxed<P, F>(hook: P) -> LinkHook
+where
+    P: Fn(Arc<Client>, usize) -> F + Send + Sync + 'static,
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    Box::new(move |client, link| Box::pin(hook(client, link)))
+}
+
+impl Client {
+    /// Run `hook` with the connection number every time a stream connection is established,
+    /// reconnects included
+    ///
+    /// Hooks run on their own task and do not hold up frames, e.g. to announce the bot in an ops
+    /// group. With [`Client::connections`] above one they run once per connection.
+    pub fn on_connected<P, F>(self: Arc<Self>, hook: P) -> Arc<Self>
+    where
+        P: Fn(Arc<Self>, usize) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.hooks.connected.write().unwrap().push(boxed(hook));
+        self
+    }
+
+    /// Run `hook` with the connection number every time a stream connection is gone, whether the
+    /// server closed it, the heartbeat was lost or the client exits
+    pub fn on_disconnected<P, F>(self: Arc<Self>, hook: P) -> Arc<Self>
+    where
+        P: Fn(Arc<Self>, usize) -> F + Send + Sync + 'static,
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.hooks.disconnected.write().unwrap().push(boxed(hook));
+        self
+    }
+
+    pub(crate) fn run_connected_hooks(self: &Arc<Self>, link: usize) {
+        self.run_hooks(&self.hooks.connected, "connected", link);
+    }
+
+    pub(crate) fn run_disconnected_hooks(self: &Arc<Self>, link: usize) {
+        self.run_hooks(&self.hooks.disconnected, "disconnected", link);
+    }
+
+    fn run_hooks(self: &Arc<Self>, hooks: &RwLock<Vec<LinkHook>>, name: &str, link: usize) {
+        for hook in hooks.read().unwrap().iter() {
+            let future = hook(self.clone(), link);
+            let name = name.to_owned();
+            let client = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = future.await {
+                    error!(target: WS, "{} hook of connection {} error: {:?}", name, link, e);
+                    client.error_event(
+                        ErrorContext::Callback,
+                        format!("{name} hook of connection {link}"),
+                        &e,
+                    );
+                }
+            });
+        }
+    }
+}
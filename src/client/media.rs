@@ -0,0 +1,237 @@
+//! Helpers that upload media and send it in one step
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use log::debug;
+
+use crate::client::up::{MessageTemplate, RobotSendMessage, SendResult, UploadType};
+use crate::client::Client;
+
+/// Duration and poster frame of a video, probed with `ffmpeg` or given by the caller
+#[derive(Debug, Clone)]
+pub struct VideoMetadata {
+    /// length in seconds
+    pub duration: u32,
+    /// picture shown before the video is played
+    pub poster: PathBuf,
+}
+
+impl Client {
+    /// Upload the video at `path` and its poster, then send them as
+    /// [`MessageTemplate::SampleVideo`] to group `conversation_id`
+    pub async fn send_video_file_with(
+        self: &Arc<Self>,
+        conversation_id: impl Into<String>,
+        path: impl AsRef<Path>,
+        metadata: VideoMetadata,
+    ) -> Result<SendResult> {
+        let path = path.as_ref();
+        let video_type = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_else(|| "mp4".to_owned());
+        let video_media_id = self.upload(path, UploadType::Video).await?;
+        let pic_media_id = self.upload(&metadata.poster, UploadType::Image).await?;
+        debug!(
+            "uploaded video {} with poster {}",
+            video_media_id, pic_media_id
+        );
+
+        let message = MessageTemplate::SampleVideo {
+            duration: metadata.duration.to_string(),
+            video_media_id,
+            video_type,
+            pic_media_id,
+        };
+        RobotSendMessage::group(self.clone(), conversation_id, message)?
+            .send()
+            .await
+    }
+
+    /// Upload the file at `path` and send it as [`MessageTemplate::SampleFile`] to group
+    /// `conversation_id`
+    ///
+    /// Files above the media upload limit go through the [drive](Client::drive_fallback) when
+    /// configured and are sent as a download link instead.
+    pub async fn send_file(
+        self: &Arc<Self>,
+        conversation_id: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> Result<SendResult> {
+        let path = path.as_ref();
+        let size = tokio::fs::metadata(path).await?.len();
+        let has_drive = self.config.lock().unwrap().drive_fallback.is_some();
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let message = if size > UploadType::File.max_size() && has_drive {
+            let file = self.upload_to_drive(path).await?;
+            MessageTemplate::SampleLink {
+                text: format!("{} ({:.1} MiB)", file.name, size as f64 / 1024.0 / 1024.0),
+                title: file.name.clone(),
+                pic_url: String::new(),
+                message_url: self.drive_download_url(&file).await?,
+            }
+        } else {
+            MessageTemplate::SampleFile {
+                media_id: self.upload(path, UploadType::File).await?,
+                file_type: path
+                    .extension()
+                    .map(|e| e.to_string_lossy().to_lowercase())
+                    .unwrap_or_default(),
+                file_name,
+            }
+        };
+        RobotSendMessage::group(self.clone(), conversation_id, message)?
+            .send()
+            .await
+    }
+
+    /// Like [`Client::send_video_file_with`], probing duration and poster with `ffmpeg`
+    #[cfg(feature = "ffmpeg")]
+    pub async fn send_video_file(
+        self: &Arc<Self>,
+        conversation_id: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> Result<SendResult> {
+        let path = path.as_ref();
+        let metadata = probe_video(path).await?;
+        let result = self
+            .send_video_file_with(conversation_id, path, metadata.clone())
+            .await;
+        let _ = tokio::fs::remove_file(&metadata.poster).await;
+        result
+    }
+}
+
+/// Duration and first frame of the video at `path`, using the `ffprobe` and `ffmpeg` binaries
+///
+/// The poster is written to the temp dir, callers remove it when done.
+#[cfg(feature = "ffmpeg")]
+pub async fn probe_video(path: impl AsRef<Path>) -> Result<VideoMetadata> {
+    use anyhow::{bail, Context};
+    use tokio::process::Command;
+
+    let path = path.as_ref();
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format=duration"])
+        .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .output()
+        .await
+        .context("run ffprobe")?;
+    if !output.status.success() {
+        bail!(
+            "ffprobe {} error: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let duration: f64 = String::from_utf8_lossy(&output.stdout).trim().parse()?;
+
+    let poster = std::env::temp_dir().join(format!(
+        "dingtalk-poster-{:016x}.jpg",
+        rand::random::<u64>()
+    ));
+    let output = Command::new("ffmpeg")
+        .args(["-y", "-v", "error", "-i"])
+        .arg(path)
+        .args(["-frames:v", "1"])
+        .arg(&poster)
+        .output()
+        .await
+        .context("run ffmpeg")?;
+    if !output.status.success() {
+        bail!(
+            "ffmpeg poster of {} error: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(VideoMetadata {
+        duration: duration.ceil() as u32,
+        poster,
+    })
+}
+
+#[cfg(feature = "audio")]
+impl Client {
+    /// Convert wav/ogg/mp3 `audio` to AMR, upload it and send it as
+    /// [`MessageTemplate::SampleAudio`] to group `conversation_id`
+    pub async fn send_voice(
+        self: &Arc<Self>,
+        conversation_id: impl Into<String>,
+        audio: impl Into<Vec<u8>>,
+    ) -> Result<SendResult> {
+        let amr = transcode_to_amr(audio).await?;
+        let duration = amr_duration_ms(&amr);
+        let media_id = self
+            .upload_bytes(amr, "voice.amr", UploadType::Voice)
+            .await?;
+
+        let message = MessageTemplate::SampleAudio {
+            media_id,
+            duration: duration.to_string(),
+        };
+        RobotSendMessage::group(self.clone(), conversation_id, message)?
+            .send()
+            .await
+    }
+}
+
+/// AMR-NB bytes of `audio`, DingTalk only plays voice messages in this format
+///
+/// Pipes through the `ffmpeg` binary, which needs to be built with `libopencore_amrnb`.
+#[cfg(feature = "audio")]
+pub async fn transcode_to_amr(audio: impl Into<Vec<u8>>) -> Result<Vec<u8>> {
+    use anyhow::{bail, Context};
+    use std::process::Stdio;
+    use tokio::io::AsyncWriteExt;
+    use tokio::process::Command;
+
+    let audio = audio.into();
+    let mut child = Command::new("ffmpeg")
+        .args(["-v", "error", "-i", "pipe:0"])
+        .args(["-ar", "8000", "-ac", "1", "-c:a", "libopencore_amrnb"])
+        .args(["-b:a", "12.2k", "-f", "amr", "pipe:1"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("run ffmpeg")?;
+
+    // feed input concurrently, ffmpeg starts writing before it has read everything
+    let mut stdin = child.stdin.take().context("ffmpeg stdin")?;
+    let feed = tokio::spawn(async move { stdin.write_all(&audio).await });
+    let output = child.wait_with_output().await?;
+    feed.await??;
+    if !output.status.success() {
+        bail!(
+            "ffmpeg amr transcode error: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+/// play time of AMR-NB `amr` in milliseconds, counted from its 20 ms frames
+#[cfg(feature = "audio")]
+pub fn amr_duration_ms(amr: &[u8]) -> u64 {
+    /// frame size without the header byte, by frame type
+    const FRAME_SIZES: [usize; 16] = [12, 13, 15, 17, 19, 20, 26, 31, 5, 0, 0, 0, 0, 0, 0, 0];
+
+    let mut data = amr.strip_prefix(b"#!AMR\n").unwrap_or(amr);
+    let mut frames = 0;
+    while let Some((header, rest)) = data.split_first() {
+        let size = FRAME_SIZES[((header >> 3) & 0x0f) as usize];
+        data = rest.get(size..).unwrap_or_default();
+        frames += 1;
+    }
+    frames * 20
+}
@@ -0,0 +1,84 @@
+//! Mock mode, a client that never connects and records what it would have sent
+//!
+//! Frames are fed in with [`Client::inject_frame`] instead of arriving over the websocket.
+//! Mock mode implies [dry run](Client::dry_run), so no request reaches the open API either.
+//! What the client would have sent is kept by its [`Tap`](crate::client::tap::Tap).
+//!
+//! ```
+//! # use bevy_stream_dingtalk::client::{mock::Outgoing, Client};
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> anyhow::Result<()> {
+//! let client = Client::new("id", "secret")?.mock(true);
+//! let ping = r#"{"specVersion":"1.0","type":"SYSTEM","headers":{"contentType":"application/json",
+//!     "messageId":"0b1c2d3e","time":"1718083800123","topic":"ping"},"data":"{\"opaque\":\"5a8e\"}"}"#;
+//! client.inject_frame(serde_json::from_str(ping)?).await?;
+//! let [Outgoing::Frame(pong)] = &client.take_outgoing()[..] else {
+//!     panic!("one frame answers a ping");
+//! };
+//! assert!(pong.contains("5a8e"));
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use crate::client::tap::TappedRequest;
+use crate::client::Client;
+use crate::protocol::ClientDownStream;
+
+/// Something the client sent, or would have sent in mock mode
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outgoing {
+    /// ACK of a received frame
+    Ack(String),
+    /// other text frame on the stream connection, e.g. a ping answer
+    Frame(String),
+    /// open API request, sent or skipped by dry run
+    Request(TappedRequest),
+}
+
+impl Outgoing {
+    /// the frame or request body as json, `None` when it is not json
+    pub fn json(&self) -> Option<serde_json::Value> {
+        match self {
+            Outgoing::Ack(text) | Outgoing::Frame(text) => serde_json::from_str(text).ok(),
+            Outgoing::Request(request) => request.body.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct MockTransport {
+    enabled: AtomicBool,
+}
+
+impl MockTransport {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::SeqCst)
+    }
+}
+
+impl Client {
+    /// Never connect and record outgoing frames and requests instead of sending them, for tests
+    pub fn mock(self: Arc<Self>, value: bool) -> Arc<Self> {
+        self.mock.enabled.store(value, Ordering::SeqCst);
+        self.dry_run(value)
+    }
+
+    pub fn is_mock(&self) -> bool {
+        self.mock.is_enabled()
+    }
+
+    /// everything [recorded](Client::tap) since the last call, oldest first
+    pub fn take_outgoing(&self) -> Vec<Outgoing> {
+        self.tap.take_new()
+    }
+
+    /// handle `frame` as if it arrived on connection 0, e.g. a frame of the `fixtures` module
+    pub async fn inject_frame(self: &Arc<Self>, frame: ClientDownStream) -> Result<()> {
+        self.on_down_stream(frame).await
+    }
+}
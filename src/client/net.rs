@@ -0,0 +1,220 @@
+//! Establishing the TCP connection of the websocket
+//!
+//! Some networks have broken IPv6 routes to the gateway, where a plain connect hangs until the
+//! OS gives up. Addresses are tried in the configured order, staggered and with a timeout each,
+//! and the first one that connects wins.
+//!
+//! API requests go through reqwest's connection pool instead, tuned with [`HttpPoolOptions`].
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use futures::{stream::FuturesUnordered, StreamExt};
+use log::debug;
+use reqwest::ClientBuilder;
+use tokio::{
+    net::{lookup_host, TcpStream},
+    time::{sleep, timeout},
+};
+use url::Url;
+
+use crate::targets::WS;
+
+/// Which resolved addresses of the gateway are used, and in which order
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AddressFamily {
+    /// both, alternating and starting with IPv6
+    #[default]
+    Any,
+    /// both, all IPv4 addresses first
+    PreferIpv4,
+    /// both, all IPv6 addresses first
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+/// How the websocket connection is established
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectOptions {
+    pub address_family: AddressFamily,
+    /// give up on a single address after this long, default 5s
+    pub connect_timeout: Duration,
+    /// start the next address when the previous has not connected after this long, default 250ms
+    pub attempt_delay: Duration,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            address_family: AddressFamily::default(),
+            connect_timeout: Duration::from_secs(5),
+            attempt_delay: Duration::from_millis(250),
+        }
+    }
+}
+
+/// Connection pool of the https client used for API requests, see
+/// [`Client::new_with_pool`](crate::client::Client::new_with_pool)
+///
+/// Bursts of sends reuse idle connections instead of opening new ones, which costs a TLS
+/// handshake each and can run out of ephemeral ports under load.
+#[derive(Debug, Clone, Copy)]
+pub struct HttpPoolOptions {
+    /// idle connections kept open per host, default 32
+    pub max_idle_per_host: usize,
+    /// close connections idle for this long, default 90s, never when `None`
+    pub idle_timeout: Option<Duration>,
+    /// interval of TCP keepalive probes, default 60s, off when `None`
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl Default for HttpPoolOptions {
+    fn default() -> Self {
+        Self {
+            max_idle_per_host: 32,
+            idle_timeout: Some(Duration::from_secs(90)),
+            tcp_keepalive: Some(Duration::from_secs(60)),
+        }
+    }
+}
+
+impl HttpPoolOptions {
+    pub(crate) fn apply(&self, builder: ClientBuilder) -> ClientBuilder {
+        builder
+            .pool_max_idle_per_host(self.max_idle_per_host)
+            .pool_idle_timeout(self.idle_timeout)
+            .tcp_keepalive(self.tcp_keepalive)
+    }
+}
+
+/// What a connection does after the server closed it with a close frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseAction {
+    /// reconnect right away
+    Immediate,
+    /// reconnect after the reconnect interval, doubled for every backoff in a row up to
+    /// [`ClosePolicy::max_backoff`]
+    Backoff,
+    /// stop reconnecting and send a [`FatalCloseEvent`](crate::event::FatalCloseEvent)
+    Stop,
+}
+
+/// Reconnect strategy by websocket close code, see
+/// [`Client::close_policy`](crate::client::Client::close_policy)
+///
+/// Connections that drop without a close frame always wait the reconnect interval.
+#[derive(Debug, Clone)]
+pub struct ClosePolicy {
+    /// action of single close codes
+    pub codes: HashMap<u16, CloseAction>,
+    /// action of the codes missing in `codes`, default [`CloseAction::Backoff`]
+    pub default: CloseAction,
+    /// longest wait between backoff reconnects, default 60s
+    pub max_backoff: Duration,
+}
+
+impl Default for ClosePolicy {
+    /// normal closure and going away reconnect right away, a policy violation stops, service
+    /// restart, try again later and everything else back off
+    fn default() -> Self {
+        Self {
+            codes: HashMap::from([
+                (1000, CloseAction::Immediate),
+                (1001, CloseAction::Immediate),
+                (1008, CloseAction::Stop),
+                (1012, CloseAction::Backoff),
+                (1013, CloseAction::Backoff),
+            ]),
+            default: CloseAction::Backoff,
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+}
+
+impl ClosePolicy {
+    /// use `action` for close `code`
+    pub fn on(mut self, code: u16, action: CloseAction) -> Self {
+        self.codes.insert(code, action);
+        self
+    }
+
+    pub fn action(&self, code: u16) -> CloseAction {
+        self.codes.get(&code).copied().unwrap_or(self.default)
+    }
+
+    /// wait before the reconnect after `attempt` backoffs in a row
+    pub(crate) fn backoff(&self, interval: Duration, attempt: u32) -> Duration {
+        interval
+            .saturating_mul(1 << attempt.min(16))
+            .min(self.max_backoff)
+    }
+}
+
+impl AddressFamily {
+    fn order(&self, addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|a| a.is_ipv6());
+        match self {
+            AddressFamily::Any => {
+                let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+                let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+                loop {
+                    match (v6.next(), v4.next()) {
+                        (None, None) => break,
+                        (a, b) => ordered.extend(a.into_iter().chain(b)),
+                    }
+                }
+                ordered
+            }
+            AddressFamily::PreferIpv4 => v4.into_iter().chain(v6).collect(),
+            AddressFamily::PreferIpv6 => v6.into_iter().chain(v4).collect(),
+            AddressFamily::Ipv4Only => v4,
+            AddressFamily::Ipv6Only => v6,
+        }
+    }
+}
+
+/// connect to the host of the websocket `url`, racing its addresses
+pub(crate) async fn connect_tcp(url: &str, options: &ConnectOptions) -> Result<TcpStream> {
+    let url = Url::parse(url)?;
+    let host = url.host_str().context("websocket url without host")?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs = options
+        .address_family
+        .order(lookup_host((host, port)).await?.collect());
+    if addrs.is_empty() {
+        return Err(anyhow!(
+            "no {:?} address for {}",
+            options.address_family,
+            host
+        ));
+    }
+
+    let mut attempts: FuturesUnordered<_> = addrs
+        .into_iter()
+        .enumerate()
+        .map(|(i, addr)| async move {
+            sleep(options.attempt_delay * i as u32).await;
+            debug!(target: WS, "connecting to {}", addr);
+            match timeout(options.connect_timeout, TcpStream::connect(addr)).await {
+                Ok(Ok(stream)) => Ok(stream),
+                Ok(Err(e)) => Err(anyhow!("connect {} error: {}", addr, e)),
+                Err(_) => Err(anyhow!("connect {} timed out", addr)),
+            }
+        })
+        .collect();
+
+    let mut last_error = None;
+    while let Some(result) = attempts.next().await {
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(e) => {
+                debug!(target: WS, "{}", e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("no address to connect to")))
+}
@@ -0,0 +1,121 @@
+//! Interactive cards waiting a limited time for an answer
+//!
+//! A [`Prompt`] is sent like any card, the first click within its timeout answers it. Once the
+//! timeout passes the card is updated with the prompt's expired variables so its template can
+//! grey out the buttons.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Result;
+use log::{debug, warn};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use tokio::sync::oneshot;
+
+use crate::client::card::InteractiveCard;
+use crate::client::Client;
+use crate::event::CardActionEvent;
+
+/// A card sent to a group chat, answered by the first click before `timeout`
+#[derive(Debug, Clone)]
+pub struct Prompt {
+    pub conversation_id: String,
+    pub card: InteractiveCard,
+    pub timeout: Duration,
+    /// variables set on the card when nobody answered in time, default is `expired = "true"`
+    pub expired: Map<String, Value>,
+}
+
+/// How a [`Prompt`] ended
+#[derive(Debug, Clone)]
+pub enum PromptOutcome {
+    Answered(Box<CardActionEvent>),
+    Expired,
+}
+
+impl Prompt {
+    pub fn ask(
+        conversation_id: impl Into<String>,
+        card: InteractiveCard,
+        timeout: Duration,
+    ) -> Self {
+        let mut expired = Map::new();
+        expired.insert("expired".to_owned(), Value::String("true".to_owned()));
+        Self {
+            conversation_id: conversation_id.into(),
+            card,
+            timeout,
+            expired,
+        }
+    }
+
+    /// set a card variable once the prompt expired, e.g. a status text
+    pub fn expired_param(mut self, name: impl Into<String>, value: impl Serialize) -> Self {
+        self.expired.insert(
+            name.into(),
+            serde_json::to_value(value).unwrap_or(Value::Null),
+        );
+        self
+    }
+
+    pub fn out_track_id(&self) -> &str {
+        &self.card.out_track_id
+    }
+}
+
+/// Prompts waiting for their first click, by out track id
+#[derive(Debug, Default)]
+pub(crate) struct PendingPrompts(Mutex<HashMap<String, oneshot::Sender<CardActionEvent>>>);
+
+impl PendingPrompts {
+    /// hand a click to the prompt of its card, if one is still waiting
+    pub fn answer(&self, event: &CardActionEvent) {
+        if let Some(tx) = self.0.lock().unwrap().remove(&event.out_track_id) {
+            let _ = tx.send(event.clone());
+        }
+    }
+}
+
+impl Client {
+    /// send `prompt` and wait for its answer, the card is marked expired after the timeout
+    pub async fn ask(&self, prompt: Prompt) -> Result<PromptOutcome> {
+        let answer = self.open_prompt(&prompt).await?;
+        Ok(self.wait_prompt(prompt, answer).await)
+    }
+
+    pub(crate) async fn open_prompt(
+        &self,
+        prompt: &Prompt,
+    ) -> Result<oneshot::Receiver<CardActionEvent>> {
+        let (tx, rx) = oneshot::channel();
+        let id = prompt.out_track_id().to_owned();
+        self.prompts.0.lock().unwrap().insert(id.clone(), tx);
+        if let Err(e) = self.send_card(&prompt.conversation_id, &prompt.card).await {
+            self.prompts.0.lock().unwrap().remove(&id);
+            return Err(e);
+        }
+        Ok(rx)
+    }
+
+    pub(crate) async fn wait_prompt(
+        &self,
+        prompt: Prompt,
+        answer: oneshot::Receiver<CardActionEvent>,
+    ) -> PromptOutcome {
+        let id = prompt.out_track_id();
+        if let Ok(Ok(event)) = tokio::time::timeout(prompt.timeout, answer).await {
+            debug!("prompt {} answered by {}", id, event.user.user_id);
+            return PromptOutcome::Answered(Box::new(event));
+        }
+
+        // a click racing the timeout is dropped with the sender
+        self.prompts.0.lock().unwrap().remove(id);
+        debug!("prompt {} expired", id);
+        if let Err(e) = self.update_card(id, &prompt.expired).await {
+            warn!("mark prompt {} expired error: {:?}", id, e);
+        }
+        PromptOutcome::Expired
+    }
+}
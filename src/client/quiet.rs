@@ -0,0 +1,320 @@
+//! Quiet hours holding back non-urgent sends
+//!
+//! Held sends and cards are kept in the client's [`Storage`](crate::storage::Storage) under
+//! [`HELD`], so they survive a restart, and go out with [`Client::release_held`] once the quiet
+//! hours end. Failed releases are retried with a growing delay, sends failing
+//! [`MAX_RELEASE_ATTEMPTS`] times move to [`HELD_FAILED`].
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, NaiveTime, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::client::card::InteractiveCard;
+use crate::client::up::RobotSendMessage;
+use crate::client::zone::Zone;
+use crate::client::Client;
+use crate::error::ErrorContext;
+use crate::storage::{HELD, HELD_FAILED};
+
+/// Daily window in which a conversation gets no non-urgent messages
+///
+/// The window may span midnight, e.g. 22:00 to 07:00. Times are read in `zone`, the
+/// [zone of the conversation](Client::zone_of) when `None`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub zone: Option<Zone>,
+}
+
+impl QuietHours {
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        Self {
+            start,
+            end,
+            zone: None,
+        }
+    }
+
+    /// read the window in `zone` instead of the conversation's
+    pub fn zone(mut self, zone: Zone) -> Self {
+        self.zone = Some(zone);
+        self
+    }
+
+    /// time left at `now` until the window ends in `zone`, `None` outside of it
+    pub fn remaining_at(&self, now: DateTime<Utc>, zone: Zone) -> Option<Duration> {
+        let zone = self.zone.unwrap_or(zone);
+        let time = zone.local_time(now).time();
+        let inside = if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        };
+        if !inside {
+            return None;
+        }
+        (zone.next_at(self.end, now) - now).to_std().ok()
+    }
+}
+
+impl Client {
+    /// Hold non-urgent sends to a group chat back during `hours`
+    ///
+    /// Held sends go out once the window ends, see [`RobotSendMessage::urgent`] and
+    /// [`Client::release_held`].
+    pub fn quiet_hours(
+        self: Arc<Self>,
+        conversation_id: impl Into<String>,
+        hours: QuietHours,
+    ) -> Arc<Self> {
+        self.config
+            .lock()
+            .unwrap()
+            .quiet_hours
+            .insert(conversation_id.into(), hours);
+        self
+    }
+
+    /// time until non-urgent sends to `conversation_id` may go out, `None` when they may now
+    pub fn quiet_for(&self, conversation_id: &str) -> Option<Duration> {
+        let hours = *self
+            .config
+            .lock()
+            .unwrap()
+            .quiet_hours
+            .get(conversation_id)?;
+        hours.remaining_at(self.current_clock().now(), self.zone_of(conversation_id))
+    }
+}
+
+/// releases of a held send before it moves to [`HELD_FAILED`]
+pub const MAX_RELEASE_ATTEMPTS: u32 = 8;
+
+/// wait after the first failed release, doubled after every further one
+const RELEASE_RETRY: Duration = Duration::from_secs(30);
+
+/// A group send held back by quiet hours
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct HeldSend {
+    pub idempotency_key: String,
+    pub tenant: Option<String>,
+    pub robot_code: String,
+    pub conversation_id: String,
+    pub msg_key: String,
+    pub msg_param: String,
+}
+
+/// A group card held back by quiet hours
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct HeldCard {
+    pub conversation_id: String,
+    pub template_id: String,
+    pub out_track_id: String,
+    pub params: Map<String, Value>,
+}
+
+/// The group sends of a [`MessageBundle`](crate::client::bundle::MessageBundle) held back by
+/// quiet hours, released together
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct HeldBundle {
+    pub conversation_id: String,
+    pub rollback: bool,
+    pub parts: Vec<HeldSend>,
+}
+
+/// What [`Client::hold`] keeps
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum Held {
+    Message(HeldSend),
+    Card(HeldCard),
+    Bundle(HeldBundle),
+}
+
+impl Held {
+    fn conversation_id(&self) -> &str {
+        match self {
+            Held::Message(send) => &send.conversation_id,
+            Held::Card(card) => &card.conversation_id,
+            Held::Bundle(bundle) => &bundle.conversation_id,
+        }
+    }
+}
+
+/// A stored [`Held`] with its failed releases
+#[derive(Debug, Serialize, Deserialize)]
+struct HeldEntry {
+    held: Held,
+    #[serde(default)]
+    attempts: u32,
+    /// unix time in milliseconds of the next release after a failed one
+    #[serde(default)]
+    retry_at: i64,
+}
+
+/// state of the held sends, owned by [`Client`]
+#[derive(Debug, Default)]
+pub(crate) struct HeldSends {
+    /// a [`Client::release_held`] is running
+    releasing: AtomicBool,
+    /// orders sends held in the same millisecond
+    seq: AtomicU64,
+}
+
+impl Client {
+    /// keep `held` until the quiet hours of its conversation end
+    ///
+    /// Stored under the time it was held, so held sends go out in the order they were made.
+    pub(crate) fn hold(&self, held: &Held) -> Result<()> {
+        let key = format!(
+            "{:013}-{:010}",
+            self.current_clock().now().timestamp_millis().max(0),
+            self.held.seq.fetch_add(1, Ordering::Relaxed)
+        );
+        let entry = HeldEntry {
+            held: held.clone(),
+            attempts: 0,
+            retry_at: 0,
+        };
+        self.store().put_json(HELD, &key, &entry)
+    }
+
+    /// keep `card` for `conversation_id` until its quiet hours end
+    pub(crate) fn hold_card(&self, conversation_id: &str, card: &InteractiveCard) -> Result<()> {
+        debug!("hold card {} until quiet hours end", card.out_track_id);
+        self.hold(&Held::Card(HeldCard {
+            conversation_id: conversation_id.to_owned(),
+            template_id: card.template_id.clone(),
+            out_track_id: card.out_track_id.clone(),
+            params: card.params.clone(),
+        }))
+    }
+
+    /// send a held message, card or bundle now
+    async fn release(self: &Arc<Self>, held: &Held) -> Result<()> {
+        match held.clone() {
+            Held::Message(send) => RobotSendMessage::from_held(self.clone(), send)
+                .send()
+                .await
+                .map(drop),
+            Held::Card(held) => {
+                let card = InteractiveCard {
+                    template_id: held.template_id,
+                    out_track_id: held.out_track_id,
+                    params: held.params,
+                };
+                self.send_card(&held.conversation_id, &card).await.map(drop)
+            }
+            Held::Bundle(bundle) => {
+                let sends = bundle
+                    .parts
+                    .into_iter()
+                    .map(|part| RobotSendMessage::from_held(self.clone(), part))
+                    .collect();
+                self.send_in_order(&bundle.conversation_id, sends, bundle.rollback)
+                    .await
+                    .map(drop)
+            }
+        }
+    }
+
+    /// number of sends and cards held back by quiet hours
+    pub fn held_sends(&self) -> usize {
+        self.store().scan(HELD, "").map_or(0, |held| held.len())
+    }
+
+    /// number of held sends and cards given up after [`MAX_RELEASE_ATTEMPTS`] failed releases,
+    /// kept in the [`HELD_FAILED`] namespace
+    pub fn failed_held_sends(&self) -> usize {
+        self.store()
+            .scan(HELD_FAILED, "")
+            .map_or(0, |failed| failed.len())
+    }
+
+    /// Send the held messages and cards whose quiet hours ended by the
+    /// [`Clock`](crate::clock::Clock), returns how many were sent
+    ///
+    /// [`StreamDingTalkPlugin`](crate::plugin::StreamDingTalkPlugin) calls this every second,
+    /// clients used without it call it themselves. A failed send is reported and kept, it is
+    /// retried after a delay doubling from 30 seconds and later sends to its conversation wait
+    /// for it. After [`MAX_RELEASE_ATTEMPTS`] failures it moves to [`HELD_FAILED`].
+    pub async fn release_held(self: &Arc<Self>) -> usize {
+        if self.held.releasing.swap(true, Ordering::SeqCst) {
+            return 0;
+        }
+        let store = self.store();
+        let held = match store.scan(HELD, "") {
+            Ok(held) => held,
+            Err(e) => {
+                warn!("read {} store error: {:?}", HELD, e);
+                vec![]
+            }
+        };
+        let now = self.current_clock().now().timestamp_millis();
+        // conversations whose earlier sends are still held
+        let mut waiting = HashSet::new();
+        let mut released = 0;
+        for (key, value) in held {
+            let mut entry = match serde_json::from_slice::<HeldEntry>(&value) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("drop unreadable held send {}: {:?}", key, e);
+                    if let Err(e) = store.remove(HELD, &key) {
+                        warn!("remove held send {} error: {:?}", key, e);
+                    }
+                    continue;
+                }
+            };
+            let conversation_id = entry.held.conversation_id().to_owned();
+            if waiting.contains(&conversation_id)
+                || entry.retry_at > now
+                || self.quiet_for(&conversation_id).is_some()
+            {
+                waiting.insert(conversation_id);
+                continue;
+            }
+            debug!("release held send {}", key);
+            let stored = match self.release(&entry.held).await {
+                Ok(()) => {
+                    released += 1;
+                    store.remove(HELD, &key)
+                }
+                Err(e) => {
+                    entry.attempts += 1;
+                    self.report_error(
+                        ErrorContext::Send,
+                        format!("send held message, attempt {}", entry.attempts),
+                        &e,
+                    );
+                    if entry.attempts >= MAX_RELEASE_ATTEMPTS {
+                        warn!(
+                            "give up held send {} after {} attempts",
+                            key, entry.attempts
+                        );
+                        store
+                            .put_json(HELD_FAILED, &key, &entry)
+                            .and_then(|()| store.remove(HELD, &key))
+                    } else {
+                        let delay = RELEASE_RETRY * 2u32.pow(entry.attempts - 1);
+                        entry.retry_at = now + delay.as_millis() as i64;
+                        waiting.insert(conversation_id);
+                        store.put_json(HELD, &key, &entry)
+                    }
+                }
+            };
+            if let Err(e) = stored {
+                warn!("update held send {} error: {:?}", key, e);
+            }
+        }
+        self.held.releasing.store(false, Ordering::SeqCst);
+        released
+    }
+}
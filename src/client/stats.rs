@@ -0,0 +1,260 @@
+//! Local message counters per conversation and day, and connection counters
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+
+use crate::client::Client;
+
+/// days of history kept by [`MessageStats`]
+const RETAIN_DAYS: i64 = 31;
+
+/// Counters for one conversation (or all of them) on one day
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct MessageCounts {
+    pub received: u64,
+    pub sent: u64,
+    pub failed: u64,
+}
+
+impl std::ops::AddAssign for MessageCounts {
+    fn add_assign(&mut self, rhs: Self) {
+        self.received += rhs.received;
+        self.sent += rhs.sent;
+        self.failed += rhs.failed;
+    }
+}
+
+/// Snapshot of the counters returned by [`Client::message_stats`]
+///
+/// Keys are open conversation ids for group chats, and user ids for 1:1 sends.
+/// Counts are local to this process and reset on restart.
+#[derive(Debug, Default, Clone)]
+pub struct MessageStats {
+    days: HashMap<(NaiveDate, String), MessageCounts>,
+    /// local date by the client's [`Clock`](crate::clock::Clock) when the snapshot was taken
+    today: NaiveDate,
+}
+
+impl MessageStats {
+    /// counters of one conversation on `date`
+    pub fn conversation(&self, conversation: impl AsRef<str>, date: NaiveDate) -> MessageCounts {
+        self.days
+            .get(&(date, conversation.as_ref().to_owned()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// counters of all conversations on `date`
+    pub fn day(&self, date: NaiveDate) -> MessageCounts {
+        let mut total = MessageCounts::default();
+        for counts in self
+            .days
+            .iter()
+            .filter(|((d, _), _)| *d == date)
+            .map(|(_, c)| c)
+        {
+            total += *counts;
+        }
+        total
+    }
+
+    /// counters of all conversations today
+    pub fn today(&self) -> MessageCounts {
+        self.day(self.today)
+    }
+
+    /// every (date, conversation) entry that has counts
+    pub fn iter(&self) -> impl Iterator<Item = (NaiveDate, &str, MessageCounts)> {
+        self.days.iter().map(|((d, c), n)| (*d, c.as_str(), *n))
+    }
+
+    fn entry(&mut self, conversation: &str, today: NaiveDate) -> &mut MessageCounts {
+        if !self.days.contains_key(&(today, conversation.to_owned())) {
+            self.days
+                .retain(|(d, _), _| (today - *d).num_days() < RETAIN_DAYS);
+        }
+        self.days
+            .entry((today, conversation.to_owned()))
+            .or_default()
+    }
+}
+
+/// Process-wide connection counters returned by [`Client::connection_stats`]
+///
+/// Displayed as `in=123 out=45 reconnects=0 rtt=82ms drift=-3ms`, followed by
+/// `binary=received/dropped` once a binary frame arrived.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct ConnectionStats {
+    /// frames received over the stream connections
+    pub frames_received: u64,
+    /// robot messages sent successfully
+    pub messages_sent: u64,
+    /// connections opened again after a drop or restart
+    pub reconnects: u64,
+    /// round trip of the latest answered websocket ping
+    pub rtt: Option<Duration>,
+    /// milliseconds the server clock is ahead of the local one, negative when behind,
+    /// estimated from the `time` header of the latest frame
+    pub clock_drift_ms: Option<i64>,
+    /// binary websocket frames, DingTalk only sends text so far
+    pub binary_frames: u64,
+    /// binary frames dropped because they were not UTF-8 text, gzipped or not
+    pub binary_dropped: u64,
+}
+
+impl std::fmt::Display for ConnectionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "in={} out={} reconnects={}",
+            self.frames_received, self.messages_sent, self.reconnects
+        )?;
+        match self.rtt {
+            Some(rtt) => write!(f, " rtt={}ms", rtt.as_millis())?,
+            None => write!(f, " rtt=-")?,
+        }
+        match self.clock_drift_ms {
+            Some(drift) => write!(f, " drift={drift:+}ms")?,
+            None => write!(f, " drift=-")?,
+        }
+        if self.binary_frames > 0 {
+            write!(f, " binary={}/{}", self.binary_frames, self.binary_dropped)?;
+        }
+        Ok(())
+    }
+}
+
+/// counters behind [`ConnectionStats`], frames are counted by the client itself
+#[derive(Debug)]
+pub(crate) struct LinkCounters {
+    messages_sent: AtomicU64,
+    reconnects: AtomicU64,
+    /// latest round trip in microseconds, zero until a pong arrived
+    rtt_micros: AtomicU64,
+    /// `i64::MIN` until a frame with a `time` header arrived
+    drift_ms: AtomicI64,
+    binary_frames: AtomicU64,
+    binary_dropped: AtomicU64,
+    /// ping payloads are microseconds since this instant
+    started: Instant,
+}
+
+impl Default for LinkCounters {
+    fn default() -> Self {
+        Self {
+            messages_sent: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+            rtt_micros: AtomicU64::new(0),
+            drift_ms: AtomicI64::new(i64::MIN),
+            binary_frames: AtomicU64::new(0),
+            binary_dropped: AtomicU64::new(0),
+            started: Instant::now(),
+        }
+    }
+}
+
+/// length of the ping payload, a big endian `u64`
+const PING_PAYLOAD_LEN: usize = 8;
+
+/// just enough of a robot message to count it
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConversationRef {
+    #[serde(default)]
+    pub conversation_id: String,
+}
+
+impl Client {
+    /// local counters of received and sent robot messages
+    pub fn message_stats(&self) -> MessageStats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        stats.today = self.now().date_naive();
+        stats
+    }
+
+    pub(crate) fn record_received(&self, conversation: &str) {
+        let today = self.now().date_naive();
+        self.stats
+            .lock()
+            .unwrap()
+            .entry(conversation, today)
+            .received += 1;
+    }
+
+    /// counters of the stream connections since the client was created
+    pub fn connection_stats(&self) -> ConnectionStats {
+        let rtt_micros = self.links.rtt_micros.load(Ordering::Relaxed);
+        let drift_ms = self.links.drift_ms.load(Ordering::Relaxed);
+        ConnectionStats {
+            frames_received: self.frames_received.load(Ordering::Relaxed),
+            messages_sent: self.links.messages_sent.load(Ordering::Relaxed),
+            reconnects: self.links.reconnects.load(Ordering::Relaxed),
+            rtt: (rtt_micros > 0).then(|| Duration::from_micros(rtt_micros)),
+            clock_drift_ms: (drift_ms != i64::MIN).then_some(drift_ms),
+            binary_frames: self.links.binary_frames.load(Ordering::Relaxed),
+            binary_dropped: self.links.binary_dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn record_reconnect(&self) {
+        self.links.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// payload of the next websocket ping, the pong echoes it back
+    pub(crate) fn ping_payload(&self) -> Vec<u8> {
+        let micros = self.links.started.elapsed().as_micros() as u64;
+        micros.to_be_bytes().to_vec()
+    }
+
+    /// round trip from the timestamp echoed in a pong, other payloads are ignored
+    pub(crate) fn record_pong(&self, payload: &[u8]) {
+        let Ok(sent) = <[u8; PING_PAYLOAD_LEN]>::try_from(payload) else {
+            return;
+        };
+        let sent = Duration::from_micros(u64::from_be_bytes(sent));
+        let Some(rtt) = self.links.started.elapsed().checked_sub(sent) else {
+            return;
+        };
+        let micros = rtt.as_micros().max(1) as u64;
+        self.links.rtt_micros.store(micros, Ordering::Relaxed);
+    }
+
+    /// compare the `time` header of a frame, milliseconds since the epoch, with the local clock
+    ///
+    /// The frame spent about half a round trip on the way, so that is added to the server time.
+    pub(crate) fn record_server_time(&self, time: &str) {
+        let Ok(server_ms) = time.parse::<i64>() else {
+            return;
+        };
+        let half_rtt_ms = (self.links.rtt_micros.load(Ordering::Relaxed) / 2000) as i64;
+        let drift = server_ms + half_rtt_ms - Utc::now().timestamp_millis();
+        self.links.drift_ms.store(drift, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_binary(&self, decoded: bool) {
+        self.links.binary_frames.fetch_add(1, Ordering::Relaxed);
+        if !decoded {
+            self.links.binary_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_sent(&self, conversation: &str, ok: bool) {
+        if ok {
+            self.links.messages_sent.fetch_add(1, Ordering::Relaxed);
+        }
+        let today = self.now().date_naive();
+        let mut stats = self.stats.lock().unwrap();
+        let counts = stats.entry(conversation, today);
+        if ok {
+            counts.sent += 1;
+        } else {
+            counts.failed += 1;
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Noticing a system sleep
+//!
+//! After a laptop wakes up the sockets are dead, but the heartbeat only finds out once the next
+//! ping goes unanswered. A watchdog ticking every second compares the time that actually passed
+//! with the tick; the monotonic clock stops during suspend on some systems, so the wall clock is
+//! checked as well.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::log::{debug, warn};
+use tokio::time::sleep;
+
+use crate::client::Client;
+use crate::event::ResumedFromSuspend;
+use crate::targets::WS;
+
+const TICK: Duration = Duration::from_secs(1);
+
+impl Client {
+    /// Reopen the connections right away when a tick of the watchdog takes `threshold` longer
+    /// than it should, default 10s. `None` leaves it to the heartbeat.
+    pub fn suspend_threshold(self: Arc<Self>, value: Option<Duration>) -> Arc<Self> {
+        self.config.lock().unwrap().suspend_threshold = value;
+        self
+    }
+
+    /// runs while [`Client::connect`] does
+    pub(crate) async fn watch_suspend(&self) {
+        loop {
+            let clock = self.current_clock();
+            let (wall, monotonic) = (clock.now(), clock.instant());
+            sleep(TICK).await;
+            let Some(threshold) = self.config.lock().unwrap().suspend_threshold else {
+                continue;
+            };
+
+            let clock = self.current_clock();
+            let wall_elapsed = (clock.now() - wall).to_std().unwrap_or_else(|_| {
+                debug!(target: WS, "wall clock was set back, going by the monotonic clock");
+                Duration::ZERO
+            });
+            let elapsed = clock
+                .instant()
+                .saturating_duration_since(monotonic)
+                .max(wall_elapsed);
+            let slept = elapsed.saturating_sub(TICK);
+            if slept >= threshold {
+                warn!(target: WS, "resumed after about {:?}, reconnecting", slept);
+                self.bridge.send_event(ResumedFromSuspend { slept });
+                self.restart();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use chrono::Utc;
+
+    use super::*;
+    use crate::clock::ManualClock;
+
+    #[tokio::test]
+    async fn clock_jump_restarts_the_connections() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let client = Client::new("id", "secret").unwrap().clock(clock.clone());
+        let watch = client.clone();
+        let watching = tokio::spawn(async move { watch.watch_suspend().await });
+
+        sleep(TICK * 2).await;
+        assert_eq!(client.restarts.load(Ordering::SeqCst), 0);
+
+        clock.advance(Duration::from_secs(60));
+        sleep(TICK * 2).await;
+        assert_eq!(client.restarts.load(Ordering::SeqCst), 1);
+        watching.abort();
+    }
+}
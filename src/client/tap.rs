@@ -0,0 +1,163 @@
+//! Recording of outgoing frames and requests for tests
+//!
+//! The client's [`Tap`] keeps every text frame written to the stream and every open API request
+//! in [mock mode](Client::mock), whether sent or skipped by [dry run](Client::dry_run). With the
+//! `test-util` feature it records outside of mock mode too:
+//!
+//! ```
+//! # use bevy_stream_dingtalk::client::up::{MessageTemplate, RobotSendMessage};
+//! # use bevy_stream_dingtalk::client::Client;
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> anyhow::Result<()> {
+//! let client = Client::new("id", "secret")?.mock(true);
+//! let hello = MessageTemplate::SampleText {
+//!     content: "hello".to_owned(),
+//! };
+//! RobotSendMessage::group(client.clone(), "cid", hello)?.send().await?;
+//! let sends = client.tap().sent_http("groupMessages/send");
+//! assert_eq!(sends[0].body.as_ref().unwrap()["msgKey"], "sampleText");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use reqwest::Method;
+use serde_json::Value;
+
+use crate::client::mock::Outgoing;
+use crate::client::Client;
+use crate::protocol::ClientUpStream;
+
+/// An open API request as the client made it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TappedRequest {
+    pub method: String,
+    /// full url, query included
+    pub url: String,
+    /// json body, `None` for requests without one or with a multipart body
+    pub body: Option<Value>,
+    /// skipped by dry run instead of sent
+    pub dry_run: bool,
+}
+
+impl TappedRequest {
+    /// url without the query
+    pub fn path(&self) -> &str {
+        self.url.split('?').next().unwrap_or_default()
+    }
+}
+
+/// Frames and requests recorded since the client was made or last [cleared](Tap::clear)
+#[derive(Debug, Default)]
+pub struct Tap {
+    log: Mutex<Vec<Outgoing>>,
+    /// entries already returned by [`Client::take_outgoing`]
+    taken: AtomicUsize,
+}
+
+impl Tap {
+    /// everything recorded, oldest first
+    pub fn outgoing(&self) -> Vec<Outgoing> {
+        self.log.lock().unwrap().clone()
+    }
+
+    /// ACKs of received frames, oldest first
+    pub fn sent_acks(&self) -> Vec<ClientUpStream> {
+        self.log
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|outgoing| match outgoing {
+                Outgoing::Ack(text) => serde_json::from_str(text).ok(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// every request, oldest first
+    pub fn sent_requests(&self) -> Vec<TappedRequest> {
+        self.requests(|_| true)
+    }
+
+    /// requests whose url path contains `path`, e.g. `groupMessages/send`, oldest first
+    pub fn sent_http(&self, path: &str) -> Vec<TappedRequest> {
+        self.requests(|request| request.path().contains(path))
+    }
+
+    /// forget everything recorded so far
+    pub fn clear(&self) {
+        let mut log = self.log.lock().unwrap();
+        log.clear();
+        self.taken.store(0, Ordering::SeqCst);
+    }
+
+    fn requests(&self, filter: impl Fn(&TappedRequest) -> bool) -> Vec<TappedRequest> {
+        self.log
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|outgoing| match outgoing {
+                Outgoing::Request(request) if filter(request) => Some(request.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// entries recorded since the last call
+    pub(crate) fn take_new(&self) -> Vec<Outgoing> {
+        let log = self.log.lock().unwrap();
+        let from = self.taken.swap(log.len(), Ordering::SeqCst);
+        log[from.min(log.len())..].to_vec()
+    }
+
+    /// a text frame, `ack` when it answers a received frame
+    pub(crate) fn record_frame(&self, text: &str, ack: bool) {
+        let outgoing = if ack {
+            Outgoing::Ack(text.to_owned())
+        } else {
+            Outgoing::Frame(text.to_owned())
+        };
+        self.log.lock().unwrap().push(outgoing);
+    }
+
+    pub(crate) fn record_request(&self, request: &reqwest::Request) {
+        let body = request
+            .body()
+            .and_then(|body| body.as_bytes())
+            .and_then(|bytes| serde_json::from_slice(bytes).ok());
+        self.push_request(TappedRequest {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            body,
+            dry_run: false,
+        });
+    }
+
+    /// a `method` request of `body` to `url` skipped by dry run
+    pub(crate) fn record_skipped(&self, method: Method, url: &str, body: &str) {
+        self.push_request(TappedRequest {
+            method: method.to_string(),
+            url: url.to_owned(),
+            body: serde_json::from_str(body).ok(),
+            dry_run: true,
+        });
+    }
+
+    fn push_request(&self, request: TappedRequest) {
+        self.log.lock().unwrap().push(Outgoing::Request(request));
+    }
+}
+
+impl Client {
+    /// outgoing frames and requests of this client
+    pub fn tap(&self) -> &Tap {
+        &self.tap
+    }
+
+    /// true when outgoing traffic is recorded, in mock mode or with the `test-util` feature
+    pub(crate) fn tapping(&self) -> bool {
+        cfg!(feature = "test-util") || self.is_mock()
+    }
+}
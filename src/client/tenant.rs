@@ -0,0 +1,438 @@
+//! Multi-tenant support for apps installed in several corps
+//!
+//! Incoming messages and events carry the corp id they belong to, which is exposed as a
+//! [`TenantId`] on Bevy events. Sends tagged with a tenant use that corp's access token, kept by
+//! the client's [`TokenManager`].
+
+use std::time::Instant;
+use std::{collections::HashMap, fmt, sync::Arc, sync::Mutex};
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Duration, Local};
+use log::debug;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::client::up::SendTapped;
+use crate::client::Client;
+use crate::targets::TOKEN;
+
+/// Corp id identifying the org a message or event belongs to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct TenantId(pub String);
+
+impl TenantId {
+    pub fn new(corp_id: impl Into<String>) -> Self {
+        Self(corp_id.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// no corp id was present, i.e. the app's own corp
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for TenantId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// App and corp an access token belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TokenKey {
+    /// client id of the app, also known as AppKey
+    pub app_key: String,
+    pub corp_id: TenantId,
+}
+
+/// `{app_key}/{corp_id}`, the key tokens are stored under
+impl fmt::Display for TokenKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.app_key, self.corp_id)
+    }
+}
+
+/// An access token and when it has to be replaced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedToken {
+    pub access_token: String,
+    /// a minute before the real expiry, so in-flight requests don't race it
+    pub expires_at: DateTime<Local>,
+}
+
+#[derive(Debug)]
+struct TokenSlot {
+    token: Option<CachedToken>,
+    last_used: Instant,
+    /// held while the token is fetched, so each key has at most one refresh in flight
+    refresh: Arc<tokio::sync::Mutex<()>>,
+}
+
+/// Access tokens of the app in other corps, returned by [`Client::tenant_tokens`]
+///
+/// Every [`TokenKey`] has its own cache entry and refresh, a slow or failing corp does not hold
+/// up the others. Keys unused for [`Client::tenant_idle_timeout`] are evicted, their tokens
+/// stay in [`Storage`](crate::storage::Storage) until they expire.
+#[derive(Debug, Default)]
+pub struct TokenManager {
+    slots: Mutex<HashMap<TokenKey, TokenSlot>>,
+}
+
+impl TokenManager {
+    /// cached token of `key`, expired or not
+    pub fn get(&self, key: &TokenKey) -> Option<CachedToken> {
+        self.slots.lock().unwrap().get(key)?.token.clone()
+    }
+
+    /// keys with a cache entry
+    pub fn keys(&self) -> Vec<TokenKey> {
+        let mut keys: Vec<_> = self.slots.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        keys
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// drop the cached token of `key`, the next request fetches a new one
+    pub fn invalidate(&self, key: &TokenKey) {
+        if let Some(slot) = self.slots.lock().unwrap().get_mut(key) {
+            slot.token = None;
+        }
+    }
+
+    /// token of `key` still valid at `now`, counts as a use at `at`
+    fn fresh(&self, key: &TokenKey, now: DateTime<Local>, at: Instant) -> Option<CachedToken> {
+        let mut slots = self.slots.lock().unwrap();
+        let slot = slots.get_mut(key)?;
+        slot.last_used = at;
+        slot.token.clone().filter(|token| now < token.expires_at)
+    }
+
+    fn insert(&self, key: &TokenKey, token: CachedToken, at: Instant) {
+        self.slots
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .and_modify(|slot| slot.token = Some(token.clone()))
+            .or_insert_with(|| TokenSlot {
+                token: Some(token),
+                last_used: at,
+                refresh: Default::default(),
+            });
+    }
+
+    fn refresh_lock(&self, key: &TokenKey, at: Instant) -> Arc<tokio::sync::Mutex<()>> {
+        self.slots
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| TokenSlot {
+                token: None,
+                last_used: at,
+                refresh: Default::default(),
+            })
+            .refresh
+            .clone()
+    }
+
+    /// drop keys unused since `idle` before `at`, returns them
+    ///
+    /// Keys whose refresh is running or awaited are kept, a new slot would start a second
+    /// refresh next to it.
+    fn evict_idle(&self, at: Instant, idle: std::time::Duration) -> Vec<TokenKey> {
+        let mut evicted = Vec::new();
+        self.slots.lock().unwrap().retain(|key, slot| {
+            let keep = at.saturating_duration_since(slot.last_used) < idle
+                || Arc::strong_count(&slot.refresh) > 1;
+            if !keep {
+                evicted.push(key.clone());
+            }
+            keep
+        });
+        evicted
+    }
+}
+
+#[derive(Deserialize)]
+struct CorpTokenResponse {
+    #[serde(alias = "accessToken")]
+    access_token: String,
+    #[serde(alias = "expireIn")]
+    expires_in: i64,
+}
+
+const CORP_TOKEN_PATH: &str = "/v1.0/oauth2/{corpId}/token";
+
+impl Client {
+    /// Route replies through the corp a message came from, using a per corp access token.
+    /// Needed by apps that are installed in more than one corp, default is off.
+    pub fn multi_tenant(self: Arc<Self>, value: bool) -> Arc<Self> {
+        self.config.lock().unwrap().multi_tenant = value;
+        self
+    }
+
+    pub(crate) fn is_multi_tenant(&self) -> bool {
+        self.config.lock().unwrap().multi_tenant
+    }
+
+    /// Forget the token of a corp that made no request for `idle`, default 24 hours
+    pub fn tenant_idle_timeout(self: Arc<Self>, idle: std::time::Duration) -> Arc<Self> {
+        self.config.lock().unwrap().tenant_idle = idle;
+        self
+    }
+
+    /// the per corp access tokens
+    pub fn tenant_tokens(&self) -> &TokenManager {
+        &self.tenant_tokens
+    }
+
+    /// key of the app's token in `tenant`
+    pub fn token_key(&self, tenant: &TenantId) -> TokenKey {
+        TokenKey {
+            app_key: self.config.lock().unwrap().client_id.clone(),
+            corp_id: tenant.clone(),
+        }
+    }
+
+    /// Access token for `tenant`, the app's own token when `None` or empty
+    pub(crate) async fn token_for(
+        &self,
+        tenant: Option<&TenantId>,
+        refresh: bool,
+    ) -> Result<String> {
+        match tenant {
+            Some(tenant) if !tenant.is_empty() => self.corp_token(tenant, refresh).await,
+            _ if refresh => self.get_token().await,
+            _ => self.token().await,
+        }
+    }
+
+    async fn corp_token(&self, tenant: &TenantId, refresh: bool) -> Result<String> {
+        let key = self.token_key(tenant);
+        let at = self.current_clock().instant();
+        let idle = self.config.lock().unwrap().tenant_idle;
+        for evicted in self.tenant_tokens.evict_idle(at, idle) {
+            debug!(target: TOKEN, "forget idle corp token {}", evicted);
+        }
+
+        let seen = self.tenant_tokens.fresh(&key, self.now(), at);
+        if !refresh {
+            if let Some(token) = seen {
+                return Ok(token.access_token);
+            }
+            if let Some((access_token, expires_at)) = self.load_token(&key.to_string()) {
+                let token = CachedToken {
+                    access_token: access_token.clone(),
+                    expires_at,
+                };
+                self.tenant_tokens.insert(&key, token, at);
+                return Ok(access_token);
+            }
+        }
+
+        let refreshing = self.tenant_tokens.refresh_lock(&key, at);
+        let _refreshing = refreshing.lock().await;
+        // another request may have fetched it while this one waited
+        if let Some(token) = self.tenant_tokens.fresh(&key, self.now(), at) {
+            if !refresh || Some(&token) != seen.as_ref() {
+                return Ok(token.access_token);
+            }
+        }
+        self.get_corp_token(&key).await
+    }
+
+    /// access token of the app inside another corp, cached until it expires
+    pub async fn corp_access_token(&self, tenant: &TenantId) -> Result<String> {
+        self.token_for(Some(tenant), false).await
+    }
+
+    async fn get_corp_token(&self, key: &TokenKey) -> Result<String> {
+        let tenant = &key.corp_id;
+        let (url, client_id, client_secret) = {
+            let config = self.config.lock().unwrap();
+            (
+                format!(
+                    "{}{}",
+                    config.endpoints.api,
+                    CORP_TOKEN_PATH.replace("{corpId}", tenant.as_str())
+                ),
+                config.client_id.clone(),
+                config.client_secret.clone(),
+            )
+        };
+        let response = self
+            .client
+            .post(url)
+            .json(&json!({
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "grant_type": "client_credentials",
+            }))
+            .send_tapped(self)
+            .await?;
+        if !response.status().is_success() {
+            bail!(
+                "get corp token http error: {} - {}",
+                response.status(),
+                response.text().await?
+            );
+        }
+
+        let token: CorpTokenResponse = response.json().await?;
+        debug!(target: TOKEN, "get corp token for {}", tenant);
+        // refresh a minute early so in-flight requests don't race the expiry
+        let expires = self.now() + Duration::seconds(token.expires_in - 60);
+        let cached = CachedToken {
+            access_token: token.access_token.clone(),
+            expires_at: expires,
+        };
+        let at = self.current_clock().instant();
+        self.tenant_tokens.insert(key, cached, at);
+        self.save_token(&key.to_string(), &token.access_token, expires);
+        Ok(token.access_token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    fn key() -> TokenKey {
+        TokenKey {
+            app_key: "app".to_owned(),
+            corp_id: TenantId::new("corp"),
+        }
+    }
+
+    /// token endpoint answering after `delay`, returns its base url and request count
+    async fn token_server(delay: Duration) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !request_complete(&request) {
+                        match stream.read(&mut buf).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    tokio::time::sleep(delay).await;
+                    let body = r#"{"accessToken":"corp-token","expireIn":7200}"#;
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                         content-length: {}\r\nconnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                    let _ = stream.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        (url, requests)
+    }
+
+    /// headers and a body of the announced length were read
+    fn request_complete(request: &[u8]) -> bool {
+        let text = String::from_utf8_lossy(request);
+        let Some((head, body)) = text.split_once("\r\n\r\n") else {
+            return false;
+        };
+        let length = head
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            })
+            .unwrap_or(0);
+        body.len() >= length
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_share_one_refresh() {
+        let (url, requests) = token_server(Duration::from_millis(50)).await;
+        let client = Client::new("app", "secret").unwrap();
+        client.config.lock().unwrap().endpoints.api = url;
+
+        let tenant = TenantId::new("corp");
+        let tokens =
+            futures::future::join_all((0..8).map(|_| client.corp_access_token(&tenant))).await;
+
+        for token in tokens {
+            assert_eq!(token.unwrap(), "corp-token");
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            client.tenant_tokens().keys(),
+            vec![client.token_key(&tenant)]
+        );
+    }
+
+    #[test]
+    fn idle_keys_are_evicted() {
+        let tokens = TokenManager::default();
+        let start = Instant::now();
+        let idle = Duration::from_secs(3600);
+        let token = CachedToken {
+            access_token: "token".to_owned(),
+            expires_at: Local::now() + Duration::from_secs(7200),
+        };
+        tokens.insert(&key(), token, start);
+
+        // a use renews the slot
+        assert!(tokens
+            .fresh(&key(), Local::now(), start + Duration::from_secs(1800))
+            .is_some());
+        assert!(tokens
+            .evict_idle(start + Duration::from_secs(4800), idle)
+            .is_empty());
+        assert_eq!(
+            tokens.evict_idle(start + Duration::from_secs(6000), idle),
+            vec![key()]
+        );
+        assert!(tokens.is_empty());
+    }
+
+    #[test]
+    fn slots_being_refreshed_are_not_evicted() {
+        let tokens = TokenManager::default();
+        let start = Instant::now();
+        let idle = Duration::from_secs(60);
+        let later = start + Duration::from_secs(600);
+
+        let refresh = tokens.refresh_lock(&key(), start);
+        let running = refresh.try_lock().unwrap();
+        assert!(tokens.evict_idle(later, idle).is_empty());
+
+        // still awaited by a request that took the lock
+        drop(running);
+        assert!(tokens.evict_idle(later, idle).is_empty());
+
+        drop(refresh);
+        assert_eq!(tokens.evict_idle(later, idle), vec![key()]);
+    }
+}
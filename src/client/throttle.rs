@@ -0,0 +1,67 @@
+//! Pacing of API requests after DingTalk's flow control refused one
+//!
+//! A [`DingTalkError::RateLimited`] answer holds back later requests to the same API, or to every
+//! API when the limit counts the whole app or corp, until its `retry_after` has passed. Requests
+//! are delayed instead of being sent only to be refused again.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use crate::client::Client;
+use crate::error::{DingTalkError, RateLimitScope};
+use crate::targets::HTTP;
+
+/// key of limits that hold back every API
+const ALL_APIS: &str = "";
+
+/// end of the current flow control limits, by API path
+#[derive(Debug, Default)]
+pub(crate) struct Throttle(Mutex<HashMap<String, Instant>>);
+
+/// `url` without its query, access tokens of legacy paths included
+fn api_path(url: &str) -> &str {
+    url.split('?').next().unwrap_or(url)
+}
+
+impl Client {
+    /// wait until requests to `url` are no longer held back by a flow control limit
+    pub(crate) async fn pace(&self, url: &str) {
+        let wait = self.rate_limited_for(url);
+        if !wait.is_zero() {
+            debug!(target: HTTP, "{} rate limited, waiting {:?}", api_path(url), wait);
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Time until requests to `url` may go out again after DingTalk's flow control refused one,
+    /// zero when they are not held back
+    pub fn rate_limited_for(&self, url: &str) -> Duration {
+        let now = self.current_clock().instant();
+        let limits = self.throttle.0.lock().unwrap();
+        [api_path(url), ALL_APIS]
+            .iter()
+            .filter_map(|key| limits.get(*key))
+            .map(|until| until.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default()
+    }
+
+    /// hold back requests after `error` answered a request to `url`
+    pub(crate) fn on_rate_limited(&self, url: &str, error: &DingTalkError) {
+        let DingTalkError::RateLimited { scope, retry_after } = error else {
+            return;
+        };
+        warn!(target: HTTP, "{} {}", api_path(url), error);
+        let key = match scope {
+            RateLimitScope::Api => api_path(url),
+            RateLimitScope::App | RateLimitScope::Corp => ALL_APIS,
+        };
+        let until = self.current_clock().instant() + *retry_after;
+        let mut limits = self.throttle.0.lock().unwrap();
+        let entry = limits.entry(key.to_owned()).or_insert(until);
+        *entry = (*entry).max(until);
+    }
+}
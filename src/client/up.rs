@@ -1,36 +1,98 @@
 //! Types and methods that handle up to DingTalk server
 
-
-
+use crate::client::quiet::{Held, HeldSend};
+use crate::client::tenant::TenantId;
 use crate::client::Client;
+use crate::error::DingTalkError;
+use crate::markdown;
+use crate::storage::SENT;
+use crate::targets::{HTTP, TOKEN};
+
+pub use crate::protocol::down::RobotRecvMessage;
+pub use crate::protocol::up::{
+    AckStatus, ClientUpStream, EventAckData, MessageTemplate, StreamUpHeader,
+};
 use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
 use futures::{stream::SplitSink, SinkExt};
-use log::debug;
+use log::{debug, warn};
 use reqwest::{
     multipart::{Form, Part},
-    Response,
+    Method, RequestBuilder, Response, StatusCode,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::Value;
-use std::{ffi::OsStr, path::Path, sync::Arc};
+use serde_json::{json, Value};
+use std::{ffi::OsStr, io::SeekFrom, path::Path, sync::Arc, time::Duration};
 use strum::Display;
-use tokio::{fs::File, net::TcpStream};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+    net::TcpStream,
+};
 use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
 
 pub(crate) type Sink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+/// `Retry-After` of a response in seconds
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+/// Sends requests of the client, recorded by its [tap](Client::tap) while tapping
+pub(crate) trait SendTapped {
+    async fn send_tapped(self, client: &Client) -> reqwest::Result<Response>;
+}
+
+impl SendTapped for RequestBuilder {
+    async fn send_tapped(self, client: &Client) -> reqwest::Result<Response> {
+        if !client.tapping() {
+            return self.send().await;
+        }
+        let (http, request) = self.build_split();
+        let request = request?;
+        client.tap.record_request(&request);
+        http.execute(request).await
+    }
+}
+
 impl Client {
-    pub(crate) async fn send<T: Serialize>(&self, msg: T) -> Result<()> {
+    /// send on the connection `link`, replies must use the one the frame arrived on
+    pub(crate) async fn send<T: Serialize>(&self, link: usize, msg: T) -> Result<()> {
+        self.send_frame(link, msg, false).await
+    }
+
+    /// [`send`](Self::send), recorded by the [tap](Client::tap) as an ACK when `ack`
+    pub(crate) async fn send_frame<T: Serialize>(
+        &self,
+        link: usize,
+        msg: T,
+        ack: bool,
+    ) -> Result<()> {
         let msg = serde_json::to_string(&msg)?;
-        self.send_message(Message::text(msg)).await
+        if self.tapping() {
+            self.tap.record_frame(&msg, ack);
+        }
+        self.send_message(link, Message::text(msg)).await
     }
 
-    pub(crate) async fn ping(&self) -> Result<()> {
-        self.send_message(Message::Ping(Vec::new())).await
+    pub(crate) async fn ping(&self, link: usize) -> Result<()> {
+        self.send_message(link, Message::Ping(self.ping_payload())).await
     }
 
-    pub(crate) async fn send_message(&self, msg: Message) -> Result<()> {
-        let mut sink = self.sink.lock().await;
-        let Some(sink) = sink.as_mut() else {
+    pub(crate) async fn send_message(&self, link: usize, msg: Message) -> Result<()> {
+        if self.is_mock() {
+            return Ok(());
+        }
+        let mut sinks = self.sinks.lock().await;
+        let Some(sink) = sinks.get_mut(&link) else {
             bail!("stream not connected");
         };
         sink.send(msg).await?;
@@ -38,65 +100,254 @@ impl Client {
         Ok(())
     }
 
-    pub(crate) async fn post_raw<T: Serialize>(
+    /// post with the access token of `tenant`, or the app's own one when `None`
+    pub(crate) async fn post_raw_as<T: Serialize>(
         &self,
+        tenant: Option<&TenantId>,
         url: impl AsRef<str>,
         data: T,
     ) -> Result<Response> {
+        self.request_raw_as(Method::POST, tenant, url, data).await
+    }
+
+    /// request with a json body and the access token of `tenant`, retried once with a fresh
+    /// token when it is rejected
+    pub(crate) async fn request_raw_as<T: Serialize>(
+        &self,
+        method: Method,
+        tenant: Option<&TenantId>,
+        url: impl AsRef<str>,
+        data: T,
+    ) -> Result<Response> {
+        let mut refreshed = false;
+        loop {
+            self.pace(url.as_ref()).await;
+            let access_token = self.token_for(tenant, refreshed).await?;
+            debug!(target: TOKEN, "{} with access token: {}", method, access_token);
+            let response = self
+                .client
+                .request(method.clone(), url.as_ref())
+                .header("x-acs-dingtalk-access-token", access_token)
+                .json(&data)
+                .send_tapped(self)
+                .await?;
+
+            if response.status() == StatusCode::UNAUTHORIZED {
+                let text = response.text().await?;
+                if !refreshed {
+                    warn!(target: TOKEN, "access token rejected, refresh and retry: {}", text);
+                    refreshed = true;
+                    continue;
+                }
+
+                self.on_auth_failed(format!("{} {}: {}", method, url.as_ref(), text));
+                bail!(
+                    "{} error: [{}] {:?}",
+                    method,
+                    StatusCode::UNAUTHORIZED,
+                    text
+                );
+            }
+
+            if !response.status().is_success() {
+                let status = response.status();
+                let retry_after = retry_after(&response);
+                let text = response.text().await?;
+                if let Some(e) = DingTalkError::rate_limited(status.as_u16(), retry_after, &text) {
+                    self.on_rate_limited(url.as_ref(), &e);
+                    return Err(e.into());
+                }
+                bail!("{} error: [{}] {:?}", method, status, text);
+            }
+
+            return Ok(response);
+        }
+    }
+
+    pub(crate) async fn post<T, U>(&self, url: impl AsRef<str>, data: T) -> Result<U>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        self.post_as(None, url, data).await
+    }
+
+    pub(crate) async fn post_as<T, U>(
+        &self,
+        tenant: Option<&TenantId>,
+        url: impl AsRef<str>,
+        data: T,
+    ) -> Result<U>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        let response = self.post_raw_as(tenant, url, data).await?;
+        let status = response.status();
+        let text = response.text().await?;
+        debug!(target: HTTP, "post ok: [{}] {}", status, text);
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub(crate) async fn put<T, U>(&self, url: impl AsRef<str>, data: T) -> Result<U>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        let response = self.request_raw_as(Method::PUT, None, url, data).await?;
+        let text = response.text().await?;
+        debug!(target: HTTP, "put ok: {}", text);
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    /// post to a legacy `oapi.dingtalk.com` path, which takes the token as query parameter
+    /// and reports errors inside the body
+    pub(crate) async fn post_oapi<T, U>(&self, url: impl AsRef<str>, data: T) -> Result<U>
+    where
+        T: Serialize,
+        U: DeserializeOwned,
+    {
+        let oapi_url = self.oapi_url(url.as_ref());
+        self.pace(&oapi_url).await;
         let access_token = self.token().await?;
-        debug!("post with access token: {}", access_token);
         let response = self
             .client
-            .post(url.as_ref())
-            .header("x-acs-dingtalk-access-token", access_token)
+            .post(format!("{}?access_token={}", oapi_url, access_token))
             .json(&data)
-            .send()
+            .send_tapped(self)
             .await?;
 
+        let status = response.status();
+        let retry_after = retry_after(&response);
+        let text = response.text().await?;
+        if let Some(e) = DingTalkError::rate_limited(status.as_u16(), retry_after, &text) {
+            self.on_rate_limited(&oapi_url, &e);
+            return Err(e.into());
+        }
+        if !status.is_success() {
+            bail!("post oapi http error: {} - {}", status, text);
+        }
+
+        debug!(target: HTTP, "post oapi ok: {}", text);
+        let res: OapiResponse<U> = serde_json::from_str(&text)?;
+        if res.errcode != 0 {
+            bail!("post oapi error: {} - {}", res.errcode, res.errmsg);
+        }
+        match res.result {
+            Some(result) => Ok(result),
+            None => bail!("post oapi error: missing result"),
+        }
+    }
+
+    /// post a message body to a session webhook of a received message
+    pub(crate) async fn post_webhook(&self, url: &str, body: &Value) -> Result<()> {
+        if self.skip_in_dry_run("webhook", Method::POST, url, body) {
+            return Ok(());
+        }
+
+        let response = self.client.post(url).json(body).send_tapped(self).await?;
         if !response.status().is_success() {
             bail!(
-                "post error: [{}] {:?}",
+                "webhook http error: {} - {}",
                 response.status(),
                 response.text().await?
             );
         }
 
-        Ok(response)
+        let res: WebhookResult = response.json().await?;
+        if res.errcode != 0 {
+            bail!("webhook error: {} - {}", res.errcode, res.errmsg);
+        }
+
+        Ok(())
     }
 
-    pub(crate) async fn post<T, U>(&self, url: impl AsRef<str>, data: T) -> Result<U>
-    where
-        T: Serialize,
-        U: DeserializeOwned,
-    {
-        let response = self.post_raw(url, data).await?;
-        let status = response.status();
-        let text = response.text().await?;
-        debug!("post ok: [{}] {}", status, text);
-        Ok(serde_json::from_str(&text)?)
+    /// recall messages sent to a group chat, by the
+    /// [`process_query_key`](SendResult::process_query_key) of their sends
+    pub async fn recall_group_messages(
+        &self,
+        conversation_id: &str,
+        process_query_keys: &[String],
+    ) -> Result<()> {
+        let body = json!({
+            "openConversationId": conversation_id,
+            "robotCode": self.current_robot_code(),
+            "processQueryKeys": process_query_keys,
+        });
+        let url = self.api_url(GROUP_RECALL_PATH);
+        if self.skip_in_dry_run("recall", Method::POST, &url, &body) {
+            return Ok(());
+        }
+        let res: RecallResult = self.post(url, body).await?;
+        if !res.failed_result.is_empty() {
+            bail!("recall failed for {:?}", res.failed_result);
+        }
+        Ok(())
     }
 
     /// upload file and return media id for
     /// - [`MessageTemplate::SampleFile`]
     /// - [`MessageTemplate::SampleVideo`]
     /// - [`MessageTemplate::SampleAudio`]
+    ///
+    /// Files DingTalk would reject are refused with a [`DingTalkError`] before any bytes are
+    /// sent, see [`UploadType::validate`].
     pub async fn upload(&self, file: impl AsRef<Path>, file_type: UploadType) -> Result<String> {
-        let access_token = self.token().await?;
-        let file = file.as_ref();
-        let filename = file
+        let path = file.as_ref();
+        let filename = path
             .file_name()
             .unwrap_or(OsStr::new("<unknown>"))
             .to_string_lossy()
             .to_string();
-        let file = File::open(file).await?;
+        let mut file = File::open(path).await?;
+        let size = file.metadata().await?.len();
+        let mut head = [0; SNIFF_LEN];
+        let read = file.read(&mut head).await?;
+        file.seek(SeekFrom::Start(0)).await?;
+        file_type.validate(&filename, size, &head[..read])?;
+
+        let what = format!("{} as {}", path.display(), file_type);
+        if self.skip_in_dry_run("upload", Method::POST, &self.upload_url(), what) {
+            return Ok(format!("dry-run-{}", file_type));
+        }
+
+        self.upload_part(Part::stream(file).file_name(filename), file_type)
+            .await
+    }
+
+    /// upload in-memory `bytes` named `file_name`, see [`Client::upload`]
+    pub async fn upload_bytes(
+        &self,
+        bytes: impl Into<Vec<u8>>,
+        file_name: impl Into<String>,
+        file_type: UploadType,
+    ) -> Result<String> {
+        let file_name = file_name.into();
+        let bytes = bytes.into();
+        file_type.validate(&file_name, bytes.len() as u64, &bytes)?;
+        let what = format!("{} as {}", file_name, file_type);
+        if self.skip_in_dry_run("upload", Method::POST, &self.upload_url(), what) {
+            return Ok(format!("dry-run-{}", file_type));
+        }
+
+        self.upload_part(Part::bytes(bytes).file_name(file_name), file_type)
+            .await
+    }
+
+    fn upload_url(&self) -> String {
+        self.config.lock().unwrap().endpoints.upload.clone()
+    }
+
+    async fn upload_part(&self, part: Part, file_type: UploadType) -> Result<String> {
+        let access_token = self.token().await?;
         let form = Form::new()
-            .part("media", Part::stream(file).file_name(filename))
+            .part("media", part)
             .text("type", file_type.to_string());
         let response = self
             .client
-            .post(format!("{}?access_token={}", UPLOAD_URL, access_token))
+            .post(format!("{}?access_token={}", self.upload_url(), access_token))
             .multipart(form)
-            .send()
+            .send_tapped(self)
             .await?;
 
         if !response.status().is_success() {
@@ -116,6 +367,22 @@ impl Client {
     }
 }
 
+#[derive(Deserialize)]
+struct OapiResponse<T> {
+    errcode: u32,
+    #[serde(default)]
+    errmsg: String,
+    result: Option<T>,
+}
+
+#[derive(Deserialize)]
+struct WebhookResult {
+    #[serde(default)]
+    errcode: u32,
+    #[serde(default)]
+    errmsg: String,
+}
+
 #[derive(Deserialize)]
 struct UploadResult {
     errcode: u32,
@@ -131,7 +398,8 @@ struct UploadResult {
 }
 
 /// Upload enum for [`Client::upload`]
-#[derive(Display)]
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
 #[strum(serialize_all = "snake_case")]
 pub enum UploadType {
     Image,
@@ -140,39 +408,73 @@ pub enum UploadType {
     File,
 }
 
-#[derive(Debug, Default, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct ClientUpStream {
-    pub code: u32,
-    pub headers: StreamUpHeader,
-    pub message: String,
-    pub data: String,
-}
+/// leading bytes inspected by [`UploadType::validate`]
+const SNIFF_LEN: usize = 12;
+const MIB: u64 = 1024 * 1024;
 
-impl ClientUpStream {
-    pub fn new(data: impl Into<String>, message_id: impl Into<String>) -> Self {
-        let data = data.into();
-        let message_id = message_id.into();
+impl UploadType {
+    /// largest media DingTalk accepts, in bytes
+    pub fn max_size(&self) -> u64 {
+        match self {
+            UploadType::Voice => 2 * MIB,
+            UploadType::Image | UploadType::Video | UploadType::File => 20 * MIB,
+        }
+    }
 
-        Self {
-            code: 200,
-            headers: StreamUpHeader {
-                message_id,
-                content_type: "application/json".to_owned(),
-            },
-            message: "OK".to_owned(),
-            data,
+    /// accepted file extensions, lowercase
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            UploadType::Image => &["jpg", "jpeg", "gif", "png", "bmp"],
+            UploadType::Voice => &["amr", "mp3", "wav"],
+            UploadType::Video => &["mp4"],
+            UploadType::File => &[
+                "doc", "docx", "xls", "xlsx", "ppt", "pptx", "zip", "pdf", "rar",
+            ],
         }
     }
-}
 
-#[derive(Debug, Default, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct StreamUpHeader {
-    pub content_type: String, // always application/json
-    pub message_id: String,   // same StreamDownHeaders::message_id
+    /// check a media named `name` of `size` bytes, starting with `head`, against the limits
+    /// of this type
+    ///
+    /// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/upload-media-files) for the limits
+    pub fn validate(&self, name: &str, size: u64, head: &[u8]) -> Result<(), DingTalkError> {
+        let unsupported = || DingTalkError::UnsupportedMedia {
+            file_type: *self,
+            name: name.to_owned(),
+        };
+        let extension = Path::new(name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if !self.extensions().contains(&extension.as_str()) {
+            return Err(unsupported());
+        }
+        if !self.sniff(head) {
+            return Err(unsupported());
+        }
+        if size > self.max_size() {
+            return Err(DingTalkError::MediaTooLarge {
+                limit: self.max_size(),
+                actual: size,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// whether the leading bytes look like this type, files are not checked
+    fn sniff(&self, head: &[u8]) -> bool {
+        let magic: &[&[u8]] = match self {
+            UploadType::Image => &[b"\xFF\xD8\xFF", b"\x89PNG", b"GIF8", b"BM"],
+            UploadType::Voice => &[b"#!AMR", b"RIFF", b"ID3", b"\xFF\xFB", b"\xFF\xF3"],
+            UploadType::Video => return head.get(4..8) == Some(&b"ftyp"[..]),
+            UploadType::File => return true,
+        };
+        magic.iter().any(|m| head.starts_with(m))
+    }
 }
 
+
 /// Message type to be sent to DingTalk server
 ///
 /// Please refer to the official document [batches](https://open.dingtalk.com/document/orgapp/chatbots-send-one-on-one-chat-messages-in-batches) and
@@ -186,13 +488,28 @@ pub struct RobotSendMessage {
     msg_key: String,
     msg_param: String,
 
+    #[serde(skip_serializing)]
+    tenant: Option<TenantId>,
+    #[serde(skip_serializing)]
+    idempotency_key: String,
+    #[serde(skip_serializing)]
+    urgent: bool,
     #[serde(skip_serializing)]
     client: Arc<Client>,
 }
 
-const BATCH_SEND_URL: &str = "https://api.dingtalk.com/v1.0/robot/oToMessages/batchSend";
-const GROUP_SEND_URL: &str = "https://api.dingtalk.com/v1.0/robot/groupMessages/send";
-const UPLOAD_URL: &str = "https://oapi.dingtalk.com/media/upload";
+/// map page used by [`MessageTemplate::location`], takes `longitude,latitude`
+const MAP_MARKER_URL: &str = "https://uri.amap.com/marker";
+const BATCH_SEND_PATH: &str = "/v1.0/robot/oToMessages/batchSend";
+const GROUP_SEND_PATH: &str = "/v1.0/robot/groupMessages/send";
+const GROUP_RECALL_PATH: &str = "/v1.0/robot/groupMessages/recall";
+/// confirmed idempotency keys are kept this long
+const SENT_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// random key for sends that were not given one
+pub(crate) fn new_idempotency_key() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
 
 impl RobotSendMessage {
     /// construct message to group chat
@@ -201,35 +518,88 @@ impl RobotSendMessage {
         conversation_id: impl Into<String>,
         message: MessageTemplate,
     ) -> Result<Self> {
-        let client_id = client.config.lock().unwrap().client_id.clone();
         Ok(Self {
-            robot_code: client_id,
+            robot_code: client.current_robot_code(),
             target: SendMessageTarget::Group {
                 open_conversation_id: conversation_id.into(),
             },
             msg_key: message.to_string(),
             msg_param: message.try_into()?,
+            tenant: None,
+            idempotency_key: new_idempotency_key(),
+            urgent: false,
             client,
         })
     }
 
     /// send to constructed message
-    pub async fn send(&self) -> Result<()> {
-        debug!("send: {}", serde_json::to_string(self).unwrap());
-        let _: Value = self
+    ///
+    /// Does nothing when a send with the same [idempotency key](Self::idempotency_key) was
+    /// already confirmed, keys are kept in the client's [`Storage`](crate::storage::Storage).
+    /// During [quiet hours](Client::quiet_hours) non-urgent group sends are stored and
+    /// [held](SendResult#structfield.held) instead of sent.
+    pub async fn send(&self) -> Result<SendResult> {
+        let body = serde_json::to_string(self).unwrap();
+        let url = self.client.api_url(match self.target {
+            SendMessageTarget::Batch { .. } => BATCH_SEND_PATH,
+            SendMessageTarget::Group { .. } => GROUP_SEND_PATH,
+        });
+        if self.client.remembered(SENT, &self.idempotency_key) {
+            debug!("skip send {}, already confirmed", self.idempotency_key);
+            return Ok(SendResult::skipped());
+        }
+        // held in dry run too, the skipped send is recorded once released
+        if self.quiet_for().is_some() {
+            return self.hold();
+        }
+        if self.client.skip_in_dry_run("send", Method::POST, &url, &body) {
+            return Ok(SendResult::skipped());
+        }
+        debug!(target: HTTP, "send: {}", body);
+        let result: Result<SendResult> = self
             .client
-            .post(
-                {
-                    match self.target {
-                        SendMessageTarget::Batch { .. } => BATCH_SEND_URL,
-                        SendMessageTarget::Group { .. } => GROUP_SEND_URL,
-                    }
-                },
-                self,
-            )
-            .await?;
+            .post_as(self.tenant.as_ref(), url, self)
+            .await;
 
-        Ok(())
+        match &self.target {
+            SendMessageTarget::Group {
+                open_conversation_id,
+            } => self
+                .client
+                .record_sent(open_conversation_id, result.is_ok()),
+            SendMessageTarget::Batch { user_ids } => user_ids.iter().for_each(|id| {
+                let delivered = result.as_ref().is_ok_and(|r| r.delivered_to(id));
+                self.client.record_sent(id, delivered)
+            }),
+        }
+        let result = result?;
+        if !result.flow_controlled_staff_id_list.is_empty() {
+            warn!(
+                "send {} flow controlled for {:?}",
+                result.process_query_key, result.flow_controlled_staff_id_list
+            );
+        }
+        self.client
+            .remember(SENT, &self.idempotency_key, SENT_TTL_SECS);
+
+        Ok(result)
+    }
+
+    /// same message to only `user_ids`, with a fresh idempotency key
+    ///
+    /// Meant for retrying the [throttled](SendResult::flow_controlled_staff_id_list) part of
+    /// a batch send.
+    pub fn resend_to(&self, user_ids: Vec<String>) -> Self {
+        Self {
+            robot_code: self.robot_code.clone(),
+            target: SendMessageTarget::Batch { user_ids },
+            msg_key: self.msg_key.clone(),
+            msg_param: self.msg_param.clone(),
+            tenant: self.tenant.clone(),
+            idempotency_key: new_idempotency_key(),
+            urgent: self.urgent,
+            client: self.client.clone(),
+        }
     }
 
     /// construct batch message to multiple users
@@ -238,16 +608,103 @@ impl RobotSendMessage {
         user_ids: Vec<String>,
         message: MessageTemplate,
     ) -> Result<Self> {
-        let client_id = client.config.lock().unwrap().client_id.clone();
         Ok(Self {
-            robot_code: client_id,
+            robot_code: client.current_robot_code(),
             target: SendMessageTarget::Batch { user_ids },
             msg_key: message.to_string(),
             msg_param: message.try_into()?,
+            tenant: None,
+            idempotency_key: new_idempotency_key(),
+            urgent: false,
             client,
         })
     }
 
+    /// send through another robot of the same app than the configured one
+    pub fn robot_code(mut self, robot_code: impl Into<String>) -> Self {
+        self.robot_code = robot_code.into();
+        self
+    }
+
+    /// Key identifying this send across restarts, random unless set
+    ///
+    /// Use a stable key such as `"round-42-result"` for announcements that must go out once even
+    /// if the game crashes and queues them again.
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = key.into();
+        self
+    }
+
+    /// Send during [quiet hours](Client::quiet_hours) instead of waiting for their end
+    pub fn urgent(mut self, urgent: bool) -> Self {
+        self.urgent = urgent;
+        self
+    }
+
+    /// store this group send until the quiet hours of its conversation end
+    pub(crate) fn hold(&self) -> Result<SendResult> {
+        debug!("hold send {} until quiet hours end", self.idempotency_key);
+        self.client.hold(&Held::Message(self.to_held()?))?;
+        Ok(SendResult::held())
+    }
+
+    /// this group send as stored while held
+    pub(crate) fn to_held(&self) -> Result<HeldSend> {
+        let SendMessageTarget::Group {
+            open_conversation_id,
+        } = &self.target
+        else {
+            bail!("only group sends are held by quiet hours");
+        };
+        Ok(HeldSend {
+            idempotency_key: self.idempotency_key.clone(),
+            tenant: self.tenant.as_ref().map(|tenant| tenant.0.clone()),
+            robot_code: self.robot_code.clone(),
+            conversation_id: open_conversation_id.clone(),
+            msg_key: self.msg_key.clone(),
+            msg_param: self.msg_param.clone(),
+        })
+    }
+
+    /// forget that this send was confirmed, so sending it again is not skipped
+    pub(crate) fn forget_sent(&self) {
+        if let Err(e) = self.client.store().remove(SENT, &self.idempotency_key) {
+            warn!("remove {} store error: {:?}", SENT, e);
+        }
+    }
+
+    /// the send `held`, urgent since its quiet hours ended
+    pub(crate) fn from_held(client: Arc<Client>, held: HeldSend) -> Self {
+        Self {
+            robot_code: held.robot_code,
+            target: SendMessageTarget::Group {
+                open_conversation_id: held.conversation_id,
+            },
+            msg_key: held.msg_key,
+            msg_param: held.msg_param,
+            tenant: held.tenant.map(TenantId),
+            idempotency_key: held.idempotency_key,
+            urgent: true,
+            client,
+        }
+    }
+
+    /// time this send is held back by quiet hours of its conversation
+    pub fn quiet_for(&self) -> Option<std::time::Duration> {
+        match &self.target {
+            SendMessageTarget::Group {
+                open_conversation_id,
+            } if !self.urgent => self.client.quiet_for(open_conversation_id),
+            _ => None,
+        }
+    }
+
+    /// send in the context of another corp, see [`Client::multi_tenant`]
+    pub fn in_tenant(mut self, tenant: TenantId) -> Self {
+        self.tenant = Some(tenant);
+        self
+    }
+
     /// construct message to single user
     pub fn single(
         client: Arc<Client>,
@@ -258,28 +715,200 @@ impl RobotSendMessage {
     }
 }
 
-/// Event ack message type
+impl RobotRecvMessage {
+    /// true once the session webhook of this message can no longer be used, by the
+    /// [`Clock`](crate::clock::Clock) of `client`
+    pub fn webhook_expired(&self, client: &Client) -> bool {
+        self.webhook_expired_at(client.current_clock().now())
+    }
+
+    /// [`webhook_expired`](Self::webhook_expired) at `now`
+    pub fn webhook_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.session_webhook.is_empty() || now >= self.webhook_expires_at()
+    }
+
+    /// Quick text (or emoji) reply to acknowledge a slow command before the real answer is ready
+    ///
+    /// Goes through the session webhook while it is valid, and falls back to the normal group or
+    /// 1:1 send API once it has expired or failed.
+    pub async fn ack_with(&self, client: &Arc<Client>, text: impl Into<String>) -> Result<()> {
+        self.reply(
+            client,
+            MessageTemplate::SampleText {
+                content: text.into(),
+            },
+        )
+        .await
+    }
+
+    /// Reply in the conversation this message came from
+    ///
+    /// Text and markdown go through the session webhook while it is valid, everything else (and
+    /// any webhook failure) uses the normal group or 1:1 send API.
+    pub async fn reply(&self, client: &Arc<Client>, message: MessageTemplate) -> Result<()> {
+        if !self.webhook_expired(client) {
+            if let Some(body) = message.webhook_body() {
+                match client.post_webhook(&self.session_webhook, &body).await {
+                    Ok(()) => return Ok(()),
+                    Err(e) => warn!(
+                        "reply via session webhook failed, fallback to send api: {:?}",
+                        e
+                    ),
+                }
+            }
+        }
+
+        // answers to someone who just wrote are not held by quiet hours
+        let mut send = if self.is_direct() {
+            RobotSendMessage::single(client.clone(), self.sender_staff_id.clone(), message)?
+        } else {
+            RobotSendMessage::group(client.clone(), self.conversation_id.clone(), message)?
+        }
+        .urgent(true);
+        if client.is_multi_tenant() {
+            send = send.in_tenant(self.tenant());
+        }
+        send.send().await.map(|_| ())
+    }
+
+    /// Reply that visually quotes this message, see [`MessageTemplate::quoting`]
+    pub async fn reply_quoted(&self, client: &Arc<Client>, message: MessageTemplate) -> Result<()> {
+        self.reply(client, message.quoting(&self.quote())).await
+    }
+
+    /// corp the message was sent in
+    pub fn tenant(&self) -> TenantId {
+        TenantId::new(self.chatbot_corp_id.clone())
+    }
+
+    /// quote context pointing back at this message
+    pub fn quote(&self) -> QuoteContext {
+        QuoteContext {
+            msg_id: self.msg_id.clone(),
+            sender_nick: self.sender_nick.clone(),
+            sender_staff_id: self.sender_staff_id.clone(),
+            excerpt: self.content.summary(),
+        }
+    }
+}
+
+/// Reference to a received message that a reply attaches to
 ///
-/// Found it in other programming language's SDK, not found in any official document though.
-#[derive(Serialize)]
-pub struct EventAckData {
-    pub status: &'static str,
+/// The robot send APIs have no reply-to field, so the quote is rendered into the message body as
+/// a markdown block quote naming the original sender.
+#[derive(Debug, Clone, Default)]
+pub struct QuoteContext {
+    pub msg_id: String,
+    pub sender_nick: String,
+    pub sender_staff_id: String,
+    pub excerpt: String,
+}
+
+impl QuoteContext {
+    /// longest excerpt kept in the quote, in chars
+    pub const MAX_EXCERPT: usize = 60;
+
+    fn block(&self) -> String {
+        let mut excerpt: String = self.excerpt.chars().take(Self::MAX_EXCERPT).collect();
+        if excerpt.len() < self.excerpt.len() {
+            excerpt.push('…');
+        }
+        format!("> {}: {}\n\n", self.sender_nick, excerpt.replace('\n', " "))
+    }
+}
+
+impl MessageTemplate {
+    /// Prefix a quote of the original message
+    ///
+    /// Text becomes markdown so the quote renders, markdown gets the quote prepended, other
+    /// templates have no place for a quote and are returned unchanged.
+    pub fn quoting(self, quote: &QuoteContext) -> Self {
+        match self {
+            MessageTemplate::SampleText { content } => MessageTemplate::SampleMarkdown {
+                title: content.chars().take(QuoteContext::MAX_EXCERPT).collect(),
+                text: format!("{}{}", quote.block(), content),
+            },
+            MessageTemplate::SampleMarkdown { title, text } => MessageTemplate::SampleMarkdown {
+                title,
+                text: format!("{}{}", quote.block(), text),
+            },
+            other => other,
+        }
+    }
+
+    /// body accepted by session webhooks, only text and markdown are supported there
+    fn webhook_body(&self) -> Option<Value> {
+        match self {
+            MessageTemplate::SampleText { content } => {
+                Some(json!({ "msgtype": "text", "text": { "content": content } }))
+            }
+            MessageTemplate::SampleMarkdown { title, text } => {
+                Some(json!({ "msgtype": "markdown", "markdown": { "title": title, "text": text } }))
+            }
+            _ => None,
+        }
+    }
+}
+
+
+/// Response of a group or batch send
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/chatbots-send-one-on-one-chat-messages-in-batches) for the meaning of each field
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendResult {
+    /// key to query read status or recall the message, empty when the send was skipped
     #[serde(default)]
-    pub message: String,
+    pub process_query_key: String,
+    /// batch recipients that are not valid staff ids
+    #[serde(default)]
+    pub invalid_staff_id_list: Vec<String>,
+    /// batch recipients that were throttled and did not get the message
+    #[serde(default)]
+    pub flow_controlled_staff_id_list: Vec<String>,
+    /// true when nothing was sent, because of dry run or an already confirmed idempotency key
+    #[serde(skip)]
+    pub skipped: bool,
+    /// true when quiet hours hold the send back, it goes out with [`Client::release_held`]
+    #[serde(skip)]
+    pub held: bool,
 }
 
-impl Default for EventAckData {
-    fn default() -> Self {
+impl SendResult {
+    fn skipped() -> Self {
         Self {
-            status: EventAckData::SUCCESS,
-            message: Default::default(),
+            skipped: true,
+            ..Default::default()
         }
     }
+
+    pub(crate) fn held() -> Self {
+        Self {
+            held: true,
+            ..Default::default()
+        }
+    }
+
+    /// whether `user_id` of a batch send actually got the message
+    pub fn delivered_to(&self, user_id: &str) -> bool {
+        !self.invalid_staff_id_list.iter().any(|id| id == user_id)
+            && !self
+                .flow_controlled_staff_id_list
+                .iter()
+                .any(|id| id == user_id)
+    }
+
+    /// whether every recipient got the message
+    pub fn is_complete(&self) -> bool {
+        self.invalid_staff_id_list.is_empty() && self.flow_controlled_staff_id_list.is_empty()
+    }
 }
 
-impl EventAckData {
-    pub const SUCCESS: &'static str = "SUCCESS";
-    pub const LATER: &'static str = "LATER";
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct RecallResult {
+    /// process query key → error code
+    failed_result: serde_json::Map<String, Value>,
 }
 
 #[derive(Serialize)]
@@ -291,129 +920,143 @@ enum SendMessageTarget {
     Batch { user_ids: Vec<String> },
 }
 
-/// Message enum to be sent to DingTalk server
-///
-/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/types-of-messages-sent-by-robots) for the definition of each field
-#[derive(Serialize, strum::Display, Clone)]
-#[serde(rename_all = "camelCase", untagged)]
-#[strum(serialize_all = "camelCase")]
-pub enum MessageTemplate {
-    #[serde(rename_all = "camelCase")]
-    SampleText { content: String },
-    #[serde(rename_all = "camelCase")]
-    SampleMarkdown { title: String, text: String },
-    #[serde(rename_all = "camelCase")]
-    SampleImageMsg {
-        #[serde(rename = "photoURL")]
-        photo_url: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    SampleLink {
-        text: String,
-        title: String,
-        pic_url: String,
-        message_url: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    SampleActionCard {
-        title: String,
-        text: String,
-        single_title: String,
-        #[serde(rename = "singleURL")]
-        single_url: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    SampleActionCard2 {
-        title: String,
-        text: String,
-        action_title_1: String,
-        #[serde(rename = "actionURL1")]
-        action_url_1: String,
-        action_title_2: String,
-        #[serde(rename = "actionURL2")]
-        action_url_2: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    SampleActionCard3 {
-        title: String,
-        text: String,
-        action_title_1: String,
-        #[serde(rename = "actionURL1")]
-        action_url_1: String,
-        action_title_2: String,
-        #[serde(rename = "actionURL2")]
-        action_url_2: String,
-        action_title_3: String,
-        #[serde(rename = "actionURL3")]
-        action_url_3: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    SampleActionCard4 {
-        title: String,
-        text: String,
-        action_title_1: String,
-        #[serde(rename = "actionURL1")]
-        action_url_1: String,
-        action_title_2: String,
-        #[serde(rename = "actionURL2")]
-        action_url_2: String,
-        action_title_3: String,
-        #[serde(rename = "actionURL3")]
-        action_url_3: String,
-        action_title_4: String,
-        #[serde(rename = "actionURL4")]
-        action_url_4: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    SampleActionCard5 {
-        title: String,
-        text: String,
-        action_title_1: String,
-        #[serde(rename = "actionURL1")]
-        action_url_1: String,
-        action_title_2: String,
-        #[serde(rename = "actionURL2")]
-        action_url_2: String,
-        action_title_3: String,
-        #[serde(rename = "actionURL3")]
-        action_url_3: String,
-        action_title_4: String,
-        #[serde(rename = "actionURL4")]
-        action_url_4: String,
-        action_title_5: String,
-        #[serde(rename = "actionURL5")]
-        action_url_5: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    SampleActionCard6 {
-        title: String,
-        text: String,
-        button_title_1: String,
-        button_url_1: String,
-        button_title_2: String,
-        button_url_2: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    SampleAudio { media_id: String, duration: String },
-    #[serde(rename_all = "camelCase")]
-    SampleFile {
-        media_id: String,
-        file_name: String,
-        file_type: String,
-    },
-    #[serde(rename_all = "camelCase")]
-    SampleVideo {
-        duration: String,
-        video_media_id: String,
-        video_type: String,
-        pic_media_id: String,
-    },
+
+impl MessageTemplate {
+    /// Link opening a map at the given position
+    ///
+    /// Robots can not send location messages, this is the closest the send APIs allow.
+    pub fn location(
+        title: impl Into<String>,
+        address: impl Into<String>,
+        latitude: f64,
+        longitude: f64,
+    ) -> Self {
+        let title = title.into();
+        let mut url = url::Url::parse(MAP_MARKER_URL).unwrap();
+        url.query_pairs_mut()
+            .append_pair("position", &format!("{longitude},{latitude}"))
+            .append_pair("name", &title);
+        MessageTemplate::SampleLink {
+            text: address.into(),
+            title,
+            pic_url: String::new(),
+            message_url: url.to_string(),
+        }
+    }
+
+    /// Escape every user visible text so it renders literally, for templates filled with
+    /// untrusted input
+    ///
+    /// Markdown bodies go through [`markdown::escape`], titles and plain texts through
+    /// [`markdown::escape_mentions`]. Urls and media ids are left alone.
+    pub fn sanitized(mut self) -> Self {
+        match &mut self {
+            MessageTemplate::SampleText { content } => {
+                *content = markdown::escape_mentions(content)
+            }
+            MessageTemplate::SampleLink { text, title, .. } => {
+                *text = markdown::escape_mentions(text);
+                *title = markdown::escape_mentions(title);
+            }
+            MessageTemplate::SampleMarkdown { title, text }
+            | MessageTemplate::SampleActionCard { title, text, .. }
+            | MessageTemplate::SampleActionCard2 { title, text, .. }
+            | MessageTemplate::SampleActionCard3 { title, text, .. }
+            | MessageTemplate::SampleActionCard4 { title, text, .. }
+            | MessageTemplate::SampleActionCard5 { title, text, .. }
+            | MessageTemplate::SampleActionCard6 { title, text, .. } => {
+                *title = markdown::escape_mentions(title);
+                *text = markdown::escape(text);
+            }
+            MessageTemplate::SampleImageMsg { .. }
+            | MessageTemplate::SampleAudio { .. }
+            | MessageTemplate::SampleFile { .. }
+            | MessageTemplate::SampleVideo { .. }
+            | MessageTemplate::Raw { .. } => {}
+        }
+        self
+    }
 }
 
-impl TryInto<String> for MessageTemplate {
-    type Error = serde_json::Error;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MP4: &[u8] = b"\0\0\0\x18ftypmp42";
+
+    #[test]
+    fn accepts_one_file_of_each_type() {
+        let cases: &[(UploadType, &str, &[u8])] = &[
+            (UploadType::Image, "photo.JPG", b"\xFF\xD8\xFF\xE0"),
+            (UploadType::Image, "chart.png", b"\x89PNG\r\n\x1a\n"),
+            (UploadType::Voice, "memo.amr", b"#!AMR\n"),
+            (UploadType::Voice, "memo.wav", b"RIFF\0\0\0\0WAVE"),
+            (UploadType::Video, "clip.mp4", MP4),
+            (UploadType::File, "report.pdf", b"%PDF-1.7"),
+        ];
+        for (file_type, name, head) in cases {
+            let result = file_type.validate(name, file_type.max_size(), head);
+            assert!(result.is_ok(), "{file_type} {name}: {result:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_oversize_files() {
+        let cases: &[(UploadType, &str, &[u8])] = &[
+            (UploadType::Image, "photo.png", b"\x89PNG"),
+            (UploadType::Voice, "memo.mp3", b"ID3\x04"),
+            (UploadType::Video, "clip.mp4", MP4),
+            (UploadType::File, "data.zip", b"PK\x03\x04"),
+        ];
+        for (file_type, name, head) in cases {
+            let size = file_type.max_size() + 1;
+            assert!(
+                matches!(
+                    file_type.validate(name, size, head),
+                    Err(DingTalkError::MediaTooLarge { limit, actual })
+                        if limit == file_type.max_size() && actual == size
+                ),
+                "{file_type} {name}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_bad_extensions() {
+        let cases: &[(UploadType, &str, &[u8])] = &[
+            (UploadType::Image, "photo.webp", b"RIFF\0\0\0\0WEBP"),
+            (UploadType::Voice, "memo.ogg", b"OggS"),
+            (UploadType::Video, "clip.mov", MP4),
+            (UploadType::File, "setup.exe", b"MZ"),
+            (UploadType::File, "README", b"#"),
+        ];
+        for (file_type, name, head) in cases {
+            assert!(
+                matches!(
+                    file_type.validate(name, 1, head),
+                    Err(DingTalkError::UnsupportedMedia { .. })
+                ),
+                "{file_type} {name}"
+            );
+        }
+    }
 
-    fn try_into(self) -> std::result::Result<String, Self::Error> {
-        serde_json::to_string(&self)
+    #[test]
+    fn rejects_content_not_matching_the_type() {
+        let cases: &[(UploadType, &str, &[u8])] = &[
+            (UploadType::Image, "photo.jpg", b"%PDF-1.7"),
+            (UploadType::Voice, "memo.mp3", b"\x89PNG"),
+            (UploadType::Video, "clip.mp4", b"RIFF\0\0\0\0AVI "),
+            (UploadType::Video, "clip.mp4", b"\0\0"),
+        ];
+        for (file_type, name, head) in cases {
+            assert!(
+                matches!(
+                    file_type.validate(name, 1, head),
+                    Err(DingTalkError::UnsupportedMedia { .. })
+                ),
+                "{file_type} {name}"
+            );
+        }
     }
 }
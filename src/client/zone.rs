@@ -0,0 +1,165 @@
+//! Time zones of conversations and users
+//!
+//! Named IANA zones such as `Asia/Shanghai` need the `tz` feature, fixed offsets and the zone of
+//! the machine always work.
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Error, Result};
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDateTime, NaiveTime, TimeZone, Utc};
+
+use crate::client::down::RobotRecvMessage;
+use crate::client::Client;
+
+/// Time zone used to read times of day, e.g. of [`QuietHours`](crate::client::quiet::QuietHours)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Zone {
+    /// zone of the machine running the bot
+    #[default]
+    Local,
+    Fixed(FixedOffset),
+    /// IANA zone, follows daylight saving changes
+    #[cfg(feature = "tz")]
+    Named(chrono_tz::Tz),
+}
+
+impl Zone {
+    /// wall clock time of `at` in this zone
+    pub fn local_time(&self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Local => at.with_timezone(&Local).naive_local(),
+            Zone::Fixed(offset) => at.with_timezone(offset).naive_local(),
+            #[cfg(feature = "tz")]
+            Zone::Named(tz) => at.with_timezone(tz).naive_local(),
+        }
+    }
+
+    /// `at` with the offset of this zone at that instant, e.g. to format a
+    /// [`created_at`](crate::client::down::RobotRecvMessage::created_at) for its reader
+    pub fn localize(&self, at: DateTime<Utc>) -> DateTime<FixedOffset> {
+        match self {
+            Zone::Local => at.with_timezone(&Local).fixed_offset(),
+            Zone::Fixed(offset) => at.with_timezone(offset),
+            #[cfg(feature = "tz")]
+            Zone::Named(tz) => at.with_timezone(tz).fixed_offset(),
+        }
+    }
+
+    /// instant of a wall clock time, the earlier one when a clock change repeats it and `None`
+    /// when a clock change skips it
+    pub fn to_utc(&self, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            Zone::Local => Local
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|at| at.with_timezone(&Utc)),
+            Zone::Fixed(offset) => offset
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|at| at.with_timezone(&Utc)),
+            #[cfg(feature = "tz")]
+            Zone::Named(tz) => tz
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|at| at.with_timezone(&Utc)),
+        }
+    }
+
+    /// first instant after `after` at which the wall clock shows `time`
+    ///
+    /// A time skipped by a clock change happens an hour later that day.
+    pub fn next_at(&self, time: NaiveTime, after: DateTime<Utc>) -> DateTime<Utc> {
+        let today = self.local_time(after).date();
+        (0..3)
+            .filter_map(|day| {
+                let local = (today + Duration::days(day)).and_time(time);
+                self.to_utc(local)
+                    .or_else(|| self.to_utc(local + Duration::hours(1)))
+            })
+            .find(|at| *at > after)
+            .unwrap_or(after + Duration::days(1))
+    }
+}
+
+impl FromStr for Zone {
+    type Err = Error;
+
+    /// `local`, `UTC`, an offset like `+08:00` or `-0530`, or an IANA name with the `tz` feature
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(Zone::Local);
+        }
+        if s.eq_ignore_ascii_case("utc") || s == "Z" {
+            return Ok(Zone::Fixed(FixedOffset::east_opt(0).unwrap()));
+        }
+        if let Some(sign) = s.strip_prefix(['+', '-']).map(|_| &s[..1]) {
+            let digits: String = s[1..].chars().filter(|c| *c != ':').collect();
+            let (hours, minutes) = match digits.len() {
+                1 | 2 => (digits.as_str(), "0"),
+                4 => digits.split_at(2),
+                _ => bail!("invalid utc offset {}", s),
+            };
+            let seconds = hours.parse::<i32>()? * 3600 + minutes.parse::<i32>()? * 60;
+            let seconds = if sign == "-" { -seconds } else { seconds };
+            return FixedOffset::east_opt(seconds)
+                .map(Zone::Fixed)
+                .with_context(|| format!("utc offset {} out of range", s));
+        }
+        #[cfg(feature = "tz")]
+        {
+            s.parse::<chrono_tz::Tz>()
+                .map(Zone::Named)
+                .map_err(|e| anyhow::anyhow!("unknown time zone {}: {}", s, e))
+        }
+        #[cfg(not(feature = "tz"))]
+        bail!("named time zone {} needs the `tz` feature", s)
+    }
+}
+
+impl Client {
+    /// Zone of conversations and users without their own, default is the machine's
+    pub fn default_timezone(self: Arc<Self>, zone: Zone) -> Arc<Self> {
+        self.config.lock().unwrap().timezone = zone;
+        self
+    }
+
+    /// Zone of a conversation or user, `id` is an open conversation id or a staff id
+    pub fn timezone(self: Arc<Self>, id: impl Into<String>, zone: Zone) -> Arc<Self> {
+        self.config
+            .lock()
+            .unwrap()
+            .timezones
+            .insert(id.into(), zone);
+        self
+    }
+
+    /// zone of a conversation or user: the configured one, else the one in the cached
+    /// [profile](crate::client::contact::UserProfile::timezone), else the default
+    pub fn zone_of(&self, id: &str) -> Zone {
+        let config = self.config.lock().unwrap();
+        if let Some(zone) = config.timezones.get(id) {
+            return *zone;
+        }
+        let default = config.timezone;
+        drop(config);
+        self.cached_user(id)
+            .and_then(|profile| profile.timezone())
+            .unwrap_or(default)
+    }
+
+    /// when `message` was sent, in the zone of its conversation if one is configured, else in
+    /// the sender's
+    pub fn local_time(&self, message: &RobotRecvMessage) -> DateTime<FixedOffset> {
+        let configured = self
+            .config
+            .lock()
+            .unwrap()
+            .timezones
+            .get(&message.conversation_id)
+            .copied();
+        let zone = configured.unwrap_or_else(|| self.zone_of(&message.sender_staff_id));
+        zone.localize(message.created_at())
+    }
+}
@@ -0,0 +1,77 @@
+//! Source of the current time for expiry checks, schedules and cooldowns
+//!
+//! The client reads time through its [`Clock`], set with
+//! [`Client::clock`](crate::client::Client::clock) or
+//! [`StreamDingTalkPlugin::clock`](crate::prelude::StreamDingTalkPlugin::clock). Tests swap in a
+//! [`ManualClock`] and move it forward instead of sleeping.
+
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, Utc};
+
+pub trait Clock: Debug + Send + Sync {
+    /// wall clock time
+    fn now(&self) -> DateTime<Utc>;
+
+    /// monotonic time for intervals
+    fn instant(&self) -> Instant;
+
+    fn local(&self) -> DateTime<Local> {
+        self.now().with_timezone(&Local)
+    }
+}
+
+/// The system clocks, the default
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Clock that only moves when told to
+#[derive(Debug)]
+pub struct ManualClock(Mutex<(DateTime<Utc>, Instant)>);
+
+impl ManualClock {
+    /// clock standing at `now`
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self(Mutex::new((now, Instant::now())))
+    }
+
+    /// move both the wall clock and the monotonic time forward
+    pub fn advance(&self, by: Duration) {
+        let mut clock = self.0.lock().unwrap();
+        clock.0 += chrono::Duration::from_std(by).unwrap_or(chrono::Duration::MAX);
+        clock.1 += by;
+    }
+
+    /// set the wall clock only, like a correction of the system time
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.0.lock().unwrap().0 = now;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(Utc::now())
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0.lock().unwrap().0
+    }
+
+    fn instant(&self) -> Instant {
+        self.0.lock().unwrap().1
+    }
+}
@@ -0,0 +1,720 @@
+//! Chat commands parsed from robot messages
+//!
+//! Derive [`BotCommand`] for an enum and add a [`CommandRouter`] for it, every matching message
+//! then arrives as a [`CommandReceived`] event.
+
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    marker::PhantomData,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+pub use bevy_stream_dingtalk_derive::BotCommand;
+
+use crate::client::down::{MsgContent, RobotRecvMessage};
+use crate::client::up::MessageTemplate;
+use crate::client::DingTalkClient;
+use crate::event::RobotMessageReceived;
+use crate::markdown;
+use crate::outbound::{Outbound, OutboundQueue};
+use crate::system::handle_network_events;
+
+/// A set of chat commands, usually implemented with `#[derive(BotCommand)]`
+pub trait BotCommand: Sized + Send + Sync + 'static {
+    /// parse text without the router prefix, e.g. `join red 2`
+    fn parse(text: &str) -> Result<Self, CommandParseError>;
+
+    /// every command with its help text
+    fn commands() -> Vec<CommandInfo>;
+
+    /// help line of the command called `name`, aliases included
+    fn info(name: &str) -> Option<CommandInfo> {
+        Self::commands()
+            .into_iter()
+            .find(|c| c.name == name || c.aliases.contains(&name))
+    }
+
+    /// one line per public command, `prefix` is prepended to the usage
+    fn help(prefix: &str) -> String {
+        Self::commands()
+            .iter()
+            .filter(|c| !c.hidden && !c.admin)
+            .map(|c| {
+                if c.description.is_empty() {
+                    format!("{prefix}{}", c.usage)
+                } else {
+                    format!("{prefix}{} - {}", c.usage, c.description)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// markdown list of the commands visible to the sender, admin commands only for admins
+    fn help_markdown(prefix: &str, admin: bool) -> String {
+        let mut text = String::from("#### Commands\n\n");
+        for c in Self::commands()
+            .iter()
+            .filter(|c| !c.hidden && (admin || !c.admin))
+        {
+            text.push_str(&format!("- `{prefix}{}`", c.usage));
+            if !c.description.is_empty() {
+                text.push_str(&format!(" {}", c.description));
+            }
+            if c.admin {
+                text.push_str(" *(admin)*");
+            }
+            text.push('\n');
+        }
+        text.push_str(&format!("\nSend `{prefix}help <command>` for details"));
+        text
+    }
+
+    /// markdown details of one command, `None` when it is unknown or not visible to the sender
+    fn help_command_markdown(prefix: &str, name: &str, admin: bool) -> Option<String> {
+        let c = Self::info(name).filter(|c| !c.hidden && (admin || !c.admin))?;
+        let mut text = format!("#### {prefix}{}\n\n", c.name);
+        if !c.description.is_empty() {
+            text.push_str(&format!("{}\n\n", c.description));
+        }
+        text.push_str(&format!("usage: `{prefix}{}`", c.usage));
+        if !c.aliases.is_empty() {
+            let aliases: Vec<_> = c.aliases.iter().map(|a| format!("`{prefix}{a}`")).collect();
+            text.push_str(&format!("\n\naliases: {}", aliases.join(", ")));
+        }
+        Some(text)
+    }
+}
+
+/// Name and help text of one command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub description: &'static str,
+    /// e.g. `join <team> [count=1]`
+    pub usage: &'static str,
+    /// never listed in help
+    pub hidden: bool,
+    /// only admins may run it or see it in help
+    pub admin: bool,
+}
+
+/// Why a text is not a valid command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandParseError {
+    /// empty text
+    NotACommand,
+    Unknown(String),
+    MissingArgument {
+        command: String,
+        argument: String,
+    },
+    InvalidArgument {
+        command: String,
+        argument: String,
+        value: String,
+        reason: String,
+    },
+    TooManyArguments {
+        command: String,
+        extra: String,
+    },
+}
+
+impl CommandParseError {
+    /// the command name, for errors that happen after it was recognized
+    pub fn command(&self) -> Option<&str> {
+        match self {
+            CommandParseError::NotACommand | CommandParseError::Unknown(_) => None,
+            CommandParseError::MissingArgument { command, .. }
+            | CommandParseError::InvalidArgument { command, .. }
+            | CommandParseError::TooManyArguments { command, .. } => Some(command),
+        }
+    }
+}
+
+impl Display for CommandParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandParseError::NotACommand => write!(f, "not a command"),
+            CommandParseError::Unknown(name) => write!(f, "unknown command `{name}`"),
+            CommandParseError::MissingArgument { command, argument } => {
+                write!(f, "`{command}` is missing <{argument}>")
+            }
+            CommandParseError::InvalidArgument {
+                command,
+                argument,
+                value,
+                reason,
+            } => write!(
+                f,
+                "`{command}` got invalid <{argument}> `{value}`: {reason}"
+            ),
+            CommandParseError::TooManyArguments { command, extra } => {
+                write!(f, "`{command}` got unexpected `{extra}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for CommandParseError {}
+
+/// Argument reader used by the code `#[derive(BotCommand)]` generates
+///
+/// Words are separated by whitespace, double quotes keep spaces inside one argument.
+#[derive(Debug)]
+pub struct CommandArgs {
+    command: String,
+    remaining: String,
+}
+
+impl CommandArgs {
+    pub fn new(text: &str) -> Result<Self, CommandParseError> {
+        let mut args = Self {
+            command: String::new(),
+            remaining: text.to_owned(),
+        };
+        args.command = args
+            .next_word()
+            .ok_or(CommandParseError::NotACommand)?
+            .to_lowercase();
+        Ok(args)
+    }
+
+    /// lowercased command name
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn required<T>(&mut self, name: &str) -> Result<T, CommandParseError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.optional(name)?.ok_or_else(|| self.missing(name))
+    }
+
+    pub fn optional<T>(&mut self, name: &str) -> Result<Option<T>, CommandParseError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.next_word()
+            .map(|word| self.parse_value(name, word))
+            .transpose()
+    }
+
+    /// everything left, spaces included
+    pub fn rest<T>(&mut self, name: &str) -> Result<T, CommandParseError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.optional_rest(name)?.ok_or_else(|| self.missing(name))
+    }
+
+    pub fn optional_rest<T>(&mut self, name: &str) -> Result<Option<T>, CommandParseError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let rest = std::mem::take(&mut self.remaining).trim().to_owned();
+        if rest.is_empty() {
+            return Ok(None);
+        }
+        self.parse_value(name, rest).map(Some)
+    }
+
+    pub fn parse_default<T>(&self, name: &str, default: &str) -> Result<T, CommandParseError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse_value(name, default.to_owned())
+    }
+
+    /// fails when arguments are left over
+    pub fn finish(&mut self) -> Result<(), CommandParseError> {
+        let extra = self.remaining.trim();
+        if extra.is_empty() {
+            Ok(())
+        } else {
+            Err(CommandParseError::TooManyArguments {
+                command: self.command.clone(),
+                extra: extra.to_owned(),
+            })
+        }
+    }
+
+    fn parse_value<T>(&self, name: &str, value: String) -> Result<T, CommandParseError>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        value
+            .parse()
+            .map_err(|e: T::Err| CommandParseError::InvalidArgument {
+                command: self.command.clone(),
+                argument: name.to_owned(),
+                value,
+                reason: e.to_string(),
+            })
+    }
+
+    fn missing(&self, name: &str) -> CommandParseError {
+        CommandParseError::MissingArgument {
+            command: self.command.clone(),
+            argument: name.to_owned(),
+        }
+    }
+
+    fn next_word(&mut self) -> Option<String> {
+        let text = self.remaining.trim_start();
+        if text.is_empty() {
+            self.remaining.clear();
+            return None;
+        }
+
+        let (word, rest) = match text.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], &quoted[end + 1..]),
+                None => (quoted, ""),
+            },
+            None => match text.find(char::is_whitespace) {
+                Some(end) => (&text[..end], &text[end..]),
+                None => (text, ""),
+            },
+        };
+        let word = word.to_owned();
+        self.remaining = rest.to_owned();
+        Some(word)
+    }
+}
+
+/// A robot message that parsed as command `C`
+#[derive(Event, Debug)]
+pub struct CommandReceived<C: BotCommand> {
+    pub command: C,
+    pub message: RobotRecvMessage,
+}
+
+/// A command was dropped because its sender or conversation is cooling down
+#[derive(Event, Debug, Clone)]
+pub struct RateLimitedUserEvent {
+    pub sender_id: String,
+    pub sender_nick: String,
+    pub conversation_id: String,
+    /// time until the next command is accepted
+    pub retry_after: Duration,
+}
+
+/// Minimum time between commands, see [`CommandRouter::cooldown`]
+#[derive(Debug, Clone)]
+pub struct Cooldown {
+    /// between two commands of the same sender, zero to disable
+    pub per_user: Duration,
+    /// between two commands in the same conversation, zero to disable
+    pub per_conversation: Duration,
+    /// answer sent once per cooldown, `{nick}` and `{seconds}` are replaced, `None` to stay silent
+    pub reply: Option<String>,
+}
+
+impl Default for Cooldown {
+    fn default() -> Self {
+        Self {
+            per_user: Duration::from_secs(3),
+            per_conversation: Duration::ZERO,
+            reply: Some("{nick}, slow down, try again in {seconds}s".to_owned()),
+        }
+    }
+}
+
+/// last accepted command and whether the slow down reply was sent since
+#[derive(Debug, Default)]
+struct CooldownState {
+    users: HashMap<String, (Instant, bool)>,
+    conversations: HashMap<String, (Instant, bool)>,
+}
+
+/// entries kept before expired ones are swept
+const COOLDOWN_SWEEP: usize = 1024;
+
+impl CooldownState {
+    /// `None` when the command may run, otherwise the wait and whether to reply
+    fn check(
+        &mut self,
+        cooldown: &Cooldown,
+        user: &str,
+        conversation: &str,
+        now: Instant,
+    ) -> Option<(Duration, bool)> {
+        let user_wait = Self::wait(&self.users, user, cooldown.per_user, now);
+        let conversation_wait = Self::wait(
+            &self.conversations,
+            conversation,
+            cooldown.per_conversation,
+            now,
+        );
+
+        if user_wait.is_zero() && conversation_wait.is_zero() {
+            for (map, key, period) in [
+                (&mut self.users, user, cooldown.per_user),
+                (
+                    &mut self.conversations,
+                    conversation,
+                    cooldown.per_conversation,
+                ),
+            ] {
+                if period.is_zero() {
+                    continue;
+                }
+                if map.len() >= COOLDOWN_SWEEP {
+                    map.retain(|_, (last, _)| now.duration_since(*last) < period);
+                }
+                map.insert(key.to_owned(), (now, false));
+            }
+            return None;
+        }
+
+        let mut reply = false;
+        for (map, key, wait) in [
+            (&mut self.users, user, user_wait),
+            (&mut self.conversations, conversation, conversation_wait),
+        ] {
+            if let Some((_, warned)) = map.get_mut(key).filter(|_| !wait.is_zero()) {
+                reply |= !*warned;
+                *warned = true;
+            }
+        }
+        Some((user_wait.max(conversation_wait), reply))
+    }
+
+    fn wait(
+        map: &HashMap<String, (Instant, bool)>,
+        key: &str,
+        period: Duration,
+        now: Instant,
+    ) -> Duration {
+        map.get(key)
+            .map(|(last, _)| period.saturating_sub(now.duration_since(*last)))
+            .unwrap_or_default()
+    }
+}
+
+/// Plugin turning [`RobotMessageReceived`] events into [`CommandReceived<C>`] events
+///
+/// Only text messages starting with the prefix (default `/`) are considered. Invalid commands
+/// are answered with the error and the command's usage unless disabled with
+/// [`CommandRouter::reply_errors`]. A built-in `help` command lists the commands the sender may
+/// use, unless `C` defines its own. With a [`Cooldown`], senders typing commands too fast are
+/// answered once and reported as [`RateLimitedUserEvent`].
+pub struct CommandRouter<C> {
+    prefix: String,
+    reply_errors: bool,
+    help: bool,
+    cooldown: Option<Cooldown>,
+    marker: PhantomData<fn() -> C>,
+}
+
+impl<C: BotCommand> Default for CommandRouter<C> {
+    fn default() -> Self {
+        Self {
+            prefix: "/".to_owned(),
+            reply_errors: true,
+            help: true,
+            cooldown: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<C: BotCommand> CommandRouter<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// text a message must start with, empty to treat every text message as a command
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// answer invalid commands in the chat
+    pub fn reply_errors(mut self, value: bool) -> Self {
+        self.reply_errors = value;
+        self
+    }
+
+    /// answer `help` with the generated command list
+    pub fn help(mut self, value: bool) -> Self {
+        self.help = value;
+        self
+    }
+
+    /// limit how often senders and conversations may run commands, help included
+    pub fn cooldown(mut self, cooldown: Cooldown) -> Self {
+        self.cooldown = Some(cooldown);
+        self
+    }
+}
+
+#[derive(Resource)]
+struct RouterSettings<C> {
+    prefix: String,
+    reply_errors: bool,
+    help: bool,
+    cooldown: Option<Cooldown>,
+    cooldown_state: CooldownState,
+    marker: PhantomData<fn() -> C>,
+}
+
+impl<C: BotCommand> Plugin for CommandRouter<C> {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CommandReceived<C>>()
+            .add_event::<RateLimitedUserEvent>()
+            .insert_resource(RouterSettings::<C> {
+                prefix: self.prefix.clone(),
+                reply_errors: self.reply_errors,
+                help: self.help,
+                cooldown: self.cooldown.clone(),
+                cooldown_state: CooldownState::default(),
+                marker: PhantomData,
+            })
+            .add_systems(Update, route_commands::<C>.after(handle_network_events));
+    }
+}
+
+fn route_commands<C: BotCommand>(
+    mut settings: ResMut<RouterSettings<C>>,
+    client: Res<DingTalkClient>,
+    queue: Res<OutboundQueue>,
+    mut messages: EventReader<RobotMessageReceived>,
+    mut commands: EventWriter<CommandReceived<C>>,
+    mut limited: EventWriter<RateLimitedUserEvent>,
+) {
+    let settings = &mut *settings;
+    let prefix = settings.prefix.as_str();
+    for event in messages.read() {
+        let message = &event.message;
+        let MsgContent::Text { content } = &message.content else {
+            continue;
+        };
+        let Some(text) = content.trim().strip_prefix(prefix) else {
+            continue;
+        };
+        let reply = |template| {
+            queue.push(Outbound::Reply {
+                message: Box::new(message.clone()),
+                template,
+            })
+        };
+
+        if let Some(cooldown) = &settings.cooldown {
+            let check = settings.cooldown_state.check(
+                cooldown,
+                &message.sender_id,
+                &message.conversation_id,
+                client.current_clock().instant(),
+            );
+            if let Some((retry_after, warn)) = check {
+                debug!(
+                    "{} is rate limited for {:?}",
+                    message.sender_nick, retry_after
+                );
+                if let Some(template) = cooldown.reply.as_ref().filter(|_| warn) {
+                    let seconds = retry_after.as_secs_f32().ceil() as u64;
+                    reply(MessageTemplate::SampleText {
+                        content: template
+                            .replace("{nick}", &message.sender_nick)
+                            .replace("{seconds}", &seconds.to_string()),
+                    });
+                }
+                limited.send(RateLimitedUserEvent {
+                    sender_id: message.sender_id.clone(),
+                    sender_nick: message.sender_nick.clone(),
+                    conversation_id: message.conversation_id.clone(),
+                    retry_after,
+                });
+                continue;
+            }
+        }
+
+        let mut words = text.split_whitespace();
+        let name = words.next().unwrap_or_default().to_lowercase();
+        if settings.help && name == "help" && C::info("help").is_none() {
+            let text = match words.next() {
+                Some(command) => {
+                    C::help_command_markdown(prefix, &command.to_lowercase(), message.is_admin)
+                        .unwrap_or_else(|| format!("unknown command {}", markdown::escape(command)))
+                }
+                None => C::help_markdown(prefix, message.is_admin),
+            };
+            reply(MessageTemplate::SampleMarkdown {
+                title: "help".to_owned(),
+                text,
+            });
+            continue;
+        }
+
+        match C::parse(text) {
+            Ok(_) if C::info(&name).is_some_and(|c| c.admin) && !message.is_admin => {
+                debug!("{} is not allowed to run {}", message.sender_nick, name);
+                if settings.reply_errors {
+                    reply(MessageTemplate::SampleText {
+                        content: format!("`{name}` is for admins only"),
+                    });
+                }
+            }
+            Ok(command) => {
+                commands.send(CommandReceived {
+                    command,
+                    message: message.clone(),
+                });
+            }
+            Err(CommandParseError::NotACommand) => {}
+            Err(e) => {
+                debug!("invalid command from {}: {}", message.sender_nick, e);
+                if !settings.reply_errors {
+                    continue;
+                }
+                let mut content = e.to_string();
+                match e.command().and_then(C::info) {
+                    Some(info) => content.push_str(&format!("\nusage: {prefix}{}", info.usage)),
+                    None if settings.help => content.push_str(&format!(", send {prefix}help")),
+                    None => {}
+                }
+                reply(MessageTemplate::SampleText { content }.sanitized());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+
+    #[test]
+    fn command_name_is_lowercased() {
+        let args = CommandArgs::new("  JOIN red").unwrap();
+        assert_eq!(args.command(), "join");
+        assert_eq!(
+            CommandArgs::new("   ").unwrap_err(),
+            CommandParseError::NotACommand
+        );
+    }
+
+    #[test]
+    fn quotes_keep_spaces() {
+        let mut args = CommandArgs::new(r#"rename "red team" "blue" "unclosed quote"#).unwrap();
+        assert_eq!(args.required::<String>("from").unwrap(), "red team");
+        assert_eq!(args.required::<String>("to").unwrap(), "blue");
+        assert_eq!(args.required::<String>("note").unwrap(), "unclosed quote");
+        assert_eq!(args.optional::<String>("extra").unwrap(), None);
+        args.finish().unwrap();
+    }
+
+    #[test]
+    fn rest_takes_remaining_text() {
+        let mut args = CommandArgs::new("say  hello   world ").unwrap();
+        assert_eq!(args.rest::<String>("text").unwrap(), "hello   world");
+        assert_eq!(args.optional_rest::<String>("more").unwrap(), None);
+        assert_eq!(
+            args.rest::<String>("more").unwrap_err(),
+            CommandParseError::MissingArgument {
+                command: "say".to_owned(),
+                argument: "more".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_and_default_values() {
+        let mut args = CommandArgs::new("roll many").unwrap();
+        let err = args.optional::<u32>("count").unwrap_err();
+        assert!(matches!(
+            err,
+            CommandParseError::InvalidArgument { ref argument, ref value, .. }
+                if argument == "count" && value == "many"
+        ));
+        assert_eq!(args.parse_default::<u32>("count", "6").unwrap(), 6);
+        assert!(args.parse_default::<u32>("count", "six").is_err());
+    }
+
+    #[test]
+    fn leftover_arguments_fail() {
+        let mut args = CommandArgs::new("score red blue").unwrap();
+        assert_eq!(args.required::<String>("team").unwrap(), "red");
+        assert_eq!(
+            args.finish().unwrap_err(),
+            CommandParseError::TooManyArguments {
+                command: "score".to_owned(),
+                extra: "blue".to_owned(),
+            }
+        );
+    }
+
+    fn cooldown(per_user: u64, per_conversation: u64) -> Cooldown {
+        Cooldown {
+            per_user: Duration::from_secs(per_user),
+            per_conversation: Duration::from_secs(per_conversation),
+            reply: None,
+        }
+    }
+
+    #[test]
+    fn cooldown_per_user() {
+        let clock = ManualClock::default();
+        let cooldown = cooldown(10, 0);
+        let mut state = CooldownState::default();
+        assert_eq!(
+            state.check(&cooldown, "zhangsan", "cid", clock.instant()),
+            None
+        );
+
+        clock.advance(Duration::from_secs(4));
+        assert_eq!(
+            state.check(&cooldown, "zhangsan", "cid", clock.instant()),
+            Some((Duration::from_secs(6), true))
+        );
+        // warned once per cooldown
+        assert_eq!(
+            state.check(&cooldown, "zhangsan", "cid", clock.instant()),
+            Some((Duration::from_secs(6), false))
+        );
+        assert_eq!(state.check(&cooldown, "lisi", "cid", clock.instant()), None);
+
+        clock.advance(Duration::from_secs(6));
+        assert_eq!(
+            state.check(&cooldown, "zhangsan", "cid", clock.instant()),
+            None
+        );
+    }
+
+    #[test]
+    fn cooldown_per_conversation() {
+        let clock = ManualClock::default();
+        let cooldown = cooldown(0, 5);
+        let mut state = CooldownState::default();
+        assert_eq!(
+            state.check(&cooldown, "zhangsan", "cid", clock.instant()),
+            None
+        );
+        assert_eq!(
+            state.check(&cooldown, "lisi", "cid", clock.instant()),
+            Some((Duration::from_secs(5), true))
+        );
+        assert_eq!(
+            state.check(&cooldown, "lisi", "other", clock.instant()),
+            None
+        );
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(state.check(&cooldown, "lisi", "cid", clock.instant()), None);
+    }
+}
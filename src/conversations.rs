@@ -0,0 +1,97 @@
+//! Tags and other small metadata attached to conversations
+//!
+//! [`Conversations`] keeps string key-value pairs per conversation in the client's [`Storage`],
+//! e.g. which guild a group chat belongs to, so broadcasts can pick their targets with
+//! [`DingTalk::broadcast`](crate::param::DingTalk::broadcast) instead of a hard-coded list.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bevy::prelude::*;
+
+use crate::storage::{Storage, CONVERSATIONS};
+
+/// separates conversation id and tag key in keys, never part of a conversation id
+const SEPARATOR: char = '|';
+
+/// Tags per conversation, inserted by [`StreamDingTalkPlugin`](crate::plugin::StreamDingTalkPlugin)
+///
+/// Cheap to clone, clones share the same store.
+#[derive(Resource, Clone)]
+pub struct Conversations {
+    storage: Arc<dyn Storage>,
+}
+
+impl Conversations {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self { storage }
+    }
+
+    /// set tag `key` of `conversation_id` to `value`, replacing an earlier value
+    pub fn set_tag(&self, conversation_id: &str, key: &str, value: &str) -> Result<()> {
+        self.storage.put(
+            CONVERSATIONS,
+            &tag_key(conversation_id, key),
+            value.as_bytes(),
+        )
+    }
+
+    pub fn tag(&self, conversation_id: &str, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .storage
+            .get(CONVERSATIONS, &tag_key(conversation_id, key))?
+            .map(|value| String::from_utf8_lossy(&value).into_owned()))
+    }
+
+    /// every tag of `conversation_id`
+    pub fn tags(&self, conversation_id: &str) -> Result<BTreeMap<String, String>> {
+        let prefix = prefix(conversation_id);
+        Ok(self
+            .storage
+            .scan(CONVERSATIONS, &prefix)?
+            .into_iter()
+            .map(|(key, value)| {
+                (
+                    key[prefix.len()..].to_owned(),
+                    String::from_utf8_lossy(&value).into_owned(),
+                )
+            })
+            .collect())
+    }
+
+    pub fn remove_tag(&self, conversation_id: &str, key: &str) -> Result<()> {
+        self.storage
+            .remove(CONVERSATIONS, &tag_key(conversation_id, key))
+    }
+
+    /// drop every tag of `conversation_id`, e.g. after the bot left the group
+    pub fn forget(&self, conversation_id: &str) -> Result<()> {
+        for (key, _) in self.storage.scan(CONVERSATIONS, &prefix(conversation_id))? {
+            self.storage.remove(CONVERSATIONS, &key)?;
+        }
+        Ok(())
+    }
+
+    /// conversations whose tag `key` is `value`, ordered by id
+    pub fn tagged(&self, key: &str, value: &str) -> Result<Vec<String>> {
+        Ok(self
+            .storage
+            .scan(CONVERSATIONS, "")?
+            .into_iter()
+            .filter(|(_, v)| v == value.as_bytes())
+            .filter_map(|(k, _)| {
+                let (conversation_id, tag) = k.split_once(SEPARATOR)?;
+                (tag == key).then(|| conversation_id.to_owned())
+            })
+            .collect())
+    }
+}
+
+fn prefix(conversation_id: &str) -> String {
+    format!("{conversation_id}{SEPARATOR}")
+}
+
+fn tag_key(conversation_id: &str, key: &str) -> String {
+    format!("{conversation_id}{SEPARATOR}{key}")
+}
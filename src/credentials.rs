@@ -0,0 +1,123 @@
+//! Loading app credentials without embedding them in the binary
+//!
+//! - `keyring` feature: read from the OS credential store
+//! - `encrypted-credentials` feature: read from a passphrase protected file
+
+#[cfg(any(feature = "keyring", feature = "encrypted-credentials"))]
+use anyhow::Result;
+
+/// client_id / client_secret pair of a DingTalk app
+#[derive(Clone)]
+pub struct Credentials {
+    pub client_id: String,
+    pub client_secret: String,
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("client_id", &self.client_id)
+            .field("client_secret", &"***")
+            .finish()
+    }
+}
+
+#[cfg(feature = "keyring")]
+const KEYRING_CLIENT_ID: &str = "client_id";
+#[cfg(feature = "keyring")]
+const KEYRING_CLIENT_SECRET: &str = "client_secret";
+
+impl Credentials {
+    pub fn new(client_id: impl Into<String>, client_secret: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+        }
+    }
+
+    /// read both values from the OS keyring entries `client_id` and `client_secret` of `service`
+    #[cfg(feature = "keyring")]
+    pub fn from_keyring(service: &str) -> Result<Self> {
+        Ok(Self {
+            client_id: keyring::Entry::new(service, KEYRING_CLIENT_ID)?.get_password()?,
+            client_secret: keyring::Entry::new(service, KEYRING_CLIENT_SECRET)?.get_password()?,
+        })
+    }
+
+    /// store both values in the OS keyring, the counterpart of [`Credentials::from_keyring`]
+    #[cfg(feature = "keyring")]
+    pub fn store_in_keyring(&self, service: &str) -> Result<()> {
+        keyring::Entry::new(service, KEYRING_CLIENT_ID)?.set_password(&self.client_id)?;
+        keyring::Entry::new(service, KEYRING_CLIENT_SECRET)?.set_password(&self.client_secret)?;
+        Ok(())
+    }
+
+    /// decrypt a file written by [`Credentials::write_encrypted_file`]
+    #[cfg(feature = "encrypted-credentials")]
+    pub fn from_encrypted_file(
+        path: impl AsRef<std::path::Path>,
+        passphrase: &str,
+    ) -> Result<Self> {
+        let data = std::fs::read(path)?;
+        let plain = encrypted::decrypt(&data, passphrase)?;
+        let (client_id, client_secret) = serde_json::from_slice::<(String, String)>(&plain)?;
+        Ok(Self::new(client_id, client_secret))
+    }
+
+    /// encrypt with AES-256-GCM, the key being derived from `passphrase` with PBKDF2
+    #[cfg(feature = "encrypted-credentials")]
+    pub fn write_encrypted_file(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        passphrase: &str,
+    ) -> Result<()> {
+        let plain = serde_json::to_vec(&(&self.client_id, &self.client_secret))?;
+        std::fs::write(path, encrypted::encrypt(&plain, passphrase)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "encrypted-credentials")]
+mod encrypted {
+    use aes_gcm::aead::{Aead, KeyInit};
+    use aes_gcm::{Aes256Gcm, Nonce};
+    use anyhow::{anyhow, bail, Result};
+    use rand::Rng;
+    use sha2::Sha256;
+
+    /// file layout: MAGIC | salt | nonce | ciphertext
+    const MAGIC: &[u8] = b"DTC1";
+    const SALT_LEN: usize = 16;
+    const NONCE_LEN: usize = 12;
+    const ROUNDS: u32 = 100_000;
+
+    fn cipher(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm> {
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, ROUNDS, &mut key);
+        Ok(Aes256Gcm::new_from_slice(&key)?)
+    }
+
+    pub fn encrypt(plain: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let mut rng = rand::thread_rng();
+        let salt: [u8; SALT_LEN] = rng.gen();
+        let nonce: [u8; NONCE_LEN] = rng.gen();
+        let ciphertext = cipher(passphrase, &salt)?
+            .encrypt(Nonce::from_slice(&nonce), plain)
+            .map_err(|e| anyhow!("encrypt credentials error: {e}"))?;
+        Ok([MAGIC, &salt, &nonce, &ciphertext].concat())
+    }
+
+    pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+        let Some(data) = data.strip_prefix(MAGIC) else {
+            bail!("not an encrypted credentials file");
+        };
+        if data.len() < SALT_LEN + NONCE_LEN {
+            bail!("encrypted credentials file truncated");
+        }
+        let (salt, rest) = data.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        cipher(passphrase, salt)?
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("wrong passphrase or corrupted credentials file"))
+    }
+}
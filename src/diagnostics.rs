@@ -0,0 +1,59 @@
+//! Connection counters as Bevy diagnostics
+//!
+//! Add [`DingTalkDiagnosticsPlugin`] next to the stream plugin, the values then show up in
+//! [`DiagnosticsStore`](bevy::diagnostic::DiagnosticsStore) and in the output of
+//! [`LogDiagnosticsPlugin`](bevy::diagnostic::LogDiagnosticsPlugin).
+
+use bevy::diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic};
+use bevy::prelude::*;
+
+use crate::client::DingTalkClient;
+use crate::outbound::OutboundQueue;
+
+/// Measures [`Client::connection_stats`](crate::client::Client::connection_stats) and the
+/// outbound queue every frame
+#[derive(Default)]
+pub struct DingTalkDiagnosticsPlugin;
+
+impl DingTalkDiagnosticsPlugin {
+    /// round trip of the latest websocket ping
+    pub const RTT: DiagnosticPath = DiagnosticPath::const_new("dingtalk/rtt");
+    /// server clock minus local clock
+    pub const CLOCK_DRIFT: DiagnosticPath = DiagnosticPath::const_new("dingtalk/clock_drift");
+    pub const FRAMES_RECEIVED: DiagnosticPath =
+        DiagnosticPath::const_new("dingtalk/frames_received");
+    pub const MESSAGES_SENT: DiagnosticPath = DiagnosticPath::const_new("dingtalk/messages_sent");
+    pub const RECONNECTS: DiagnosticPath = DiagnosticPath::const_new("dingtalk/reconnects");
+    /// items queued through [`DingTalk`](crate::param::DingTalk) and not yet picked up
+    pub const OUTBOUND_QUEUE: DiagnosticPath = DiagnosticPath::const_new("dingtalk/outbound_queue");
+
+    fn measure(
+        mut diagnostics: Diagnostics,
+        client: Res<DingTalkClient>,
+        queue: Res<OutboundQueue>,
+    ) {
+        let stats = client.connection_stats();
+        if let Some(rtt) = stats.rtt {
+            diagnostics.add_measurement(&Self::RTT, || rtt.as_secs_f64() * 1000.0);
+        }
+        if let Some(drift) = stats.clock_drift_ms {
+            diagnostics.add_measurement(&Self::CLOCK_DRIFT, || drift as f64);
+        }
+        diagnostics.add_measurement(&Self::FRAMES_RECEIVED, || stats.frames_received as f64);
+        diagnostics.add_measurement(&Self::MESSAGES_SENT, || stats.messages_sent as f64);
+        diagnostics.add_measurement(&Self::RECONNECTS, || stats.reconnects as f64);
+        diagnostics.add_measurement(&Self::OUTBOUND_QUEUE, || queue.pending() as f64);
+    }
+}
+
+impl Plugin for DingTalkDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_diagnostic(Diagnostic::new(Self::RTT).with_suffix("ms"))
+            .register_diagnostic(Diagnostic::new(Self::CLOCK_DRIFT).with_suffix("ms"))
+            .register_diagnostic(Diagnostic::new(Self::FRAMES_RECEIVED).with_smoothing_factor(0.0))
+            .register_diagnostic(Diagnostic::new(Self::MESSAGES_SENT).with_smoothing_factor(0.0))
+            .register_diagnostic(Diagnostic::new(Self::RECONNECTS).with_smoothing_factor(0.0))
+            .register_diagnostic(Diagnostic::new(Self::OUTBOUND_QUEUE))
+            .add_systems(Update, Self::measure);
+    }
+}
@@ -0,0 +1,230 @@
+//! Batching chatty notifications into one summary message
+//!
+//! Lines pushed to the [`Digest`] resource are held per conversation. The first line opens a
+//! window, when it ends every line of the window is sent as a single markdown message:
+//!
+//! ```ignore
+//! app.add_plugins(
+//!     DigestPlugin::new(Duration::from_secs(600))
+//!         .title("Build status")
+//!         .source(|e: &BuildFinished| Some((OPS.to_owned(), format!("{} {}", e.job, e.status)))),
+//! );
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use chrono::{DateTime, Local};
+
+use crate::client::up::{new_idempotency_key, MessageTemplate};
+use crate::client::DingTalkClient;
+use crate::clock::{Clock, SystemClock};
+use crate::outbound::{Outbound, OutboundQueue};
+
+/// A pushed line and when it happened
+#[derive(Debug, Clone)]
+pub struct DigestItem {
+    pub text: String,
+    pub at: DateTime<Local>,
+}
+
+#[derive(Debug)]
+struct Window {
+    opened: Instant,
+    items: Vec<DigestItem>,
+}
+
+/// Lines waiting to be summarized, by conversation
+#[derive(Resource, Debug)]
+pub struct Digest {
+    /// window length of conversations without their own
+    pub interval: Duration,
+    pub title: String,
+    /// lines listed in one message, the rest is counted
+    pub max_items: usize,
+    intervals: HashMap<String, Duration>,
+    windows: HashMap<String, Window>,
+    /// the client's clock once [`DigestPlugin`] is added
+    clock: Arc<dyn Clock>,
+}
+
+impl Digest {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            title: "Digest".to_owned(),
+            max_items: 50,
+            intervals: HashMap::new(),
+            windows: HashMap::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// add a markdown line to the digest of a group chat
+    pub fn push(&mut self, conversation_id: impl Into<String>, text: impl Into<String>) {
+        let clock = &self.clock;
+        self.windows
+            .entry(conversation_id.into())
+            .or_insert_with(|| Window {
+                opened: clock.instant(),
+                items: vec![],
+            })
+            .items
+            .push(DigestItem {
+                text: text.into(),
+                at: clock.local(),
+            });
+    }
+
+    /// use another window length for one conversation
+    pub fn set_interval(&mut self, conversation_id: impl Into<String>, interval: Duration) {
+        self.intervals.insert(conversation_id.into(), interval);
+    }
+
+    /// lines waiting for `conversation_id`
+    pub fn pending(&self, conversation_id: &str) -> &[DigestItem] {
+        self.windows
+            .get(conversation_id)
+            .map_or(&[], |w| w.items.as_slice())
+    }
+
+    /// end the window of `conversation_id` now, its message is sent with the next check
+    pub fn flush(&mut self, conversation_id: &str) {
+        let interval = self.interval_of(conversation_id);
+        if let Some(window) = self.windows.get_mut(conversation_id) {
+            window.opened = self
+                .clock
+                .instant()
+                .checked_sub(interval)
+                .unwrap_or(window.opened);
+        }
+    }
+
+    fn interval_of(&self, conversation_id: &str) -> Duration {
+        self.intervals
+            .get(conversation_id)
+            .copied()
+            .unwrap_or(self.interval)
+    }
+
+    /// remove windows that ended and render their messages
+    fn take_due(&mut self) -> Vec<(String, MessageTemplate)> {
+        let now = self.clock.instant();
+        let due: Vec<String> = self
+            .windows
+            .iter()
+            .filter(|(conversation_id, w)| {
+                now.duration_since(w.opened) >= self.interval_of(conversation_id)
+            })
+            .map(|(conversation_id, _)| conversation_id.clone())
+            .collect();
+        due.into_iter()
+            .filter_map(|conversation_id| {
+                let window = self.windows.remove(&conversation_id)?;
+                Some((conversation_id, self.render(&window.items)))
+            })
+            .collect()
+    }
+
+    fn render(&self, items: &[DigestItem]) -> MessageTemplate {
+        let mut text = format!("#### {} ({})\n\n", self.title, items.len());
+        for item in items.iter().take(self.max_items) {
+            text.push_str(&format!("- {} {}\n", item.at.format("%H:%M"), item.text));
+        }
+        if items.len() > self.max_items {
+            text.push_str(&format!("\n*and {} more*\n", items.len() - self.max_items));
+        }
+        MessageTemplate::SampleMarkdown {
+            title: self.title.clone(),
+            text,
+        }
+    }
+}
+
+type SourceFn = Arc<dyn Fn(&mut App) + Send + Sync>;
+
+/// Sends the [`Digest`], add it after [`StreamDingTalkPlugin`](crate::plugin::StreamDingTalkPlugin)
+pub struct DigestPlugin {
+    pub interval: Duration,
+    pub title: String,
+    pub max_items: usize,
+    /// systems feeding events into the digest, added by [`DigestPlugin::source`]
+    sources: Vec<SourceFn>,
+}
+
+impl DigestPlugin {
+    /// one message per conversation every `interval` at most
+    pub fn new(interval: Duration) -> Self {
+        let digest = Digest::new(interval);
+        Self {
+            interval,
+            title: digest.title,
+            max_items: digest.max_items,
+            sources: vec![],
+        }
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    pub fn max_items(mut self, max_items: usize) -> Self {
+        self.max_items = max_items;
+        self
+    }
+
+    /// push events of type `E`, `line` gives the conversation and text or `None` to skip one
+    pub fn source<E: Event>(
+        mut self,
+        line: impl Fn(&E) -> Option<(String, String)> + Send + Sync + Clone + 'static,
+    ) -> Self {
+        self.sources.push(Arc::new(move |app: &mut App| {
+            let line = line.clone();
+            app.add_systems(
+                Update,
+                move |mut events: EventReader<E>, mut digest: ResMut<Digest>| {
+                    for event in events.read() {
+                        if let Some((conversation_id, text)) = line(event) {
+                            digest.push(conversation_id, text);
+                        }
+                    }
+                },
+            );
+        }));
+        self
+    }
+}
+
+impl Plugin for DigestPlugin {
+    fn build(&self, app: &mut App) {
+        let mut digest = Digest::new(self.interval);
+        digest.title = self.title.clone();
+        digest.max_items = self.max_items;
+        if let Some(client) = app.world.get_resource::<DingTalkClient>() {
+            digest.clock = client.current_clock();
+        }
+        app.insert_resource(digest).add_systems(
+            Update,
+            send_digests.run_if(on_timer(Duration::from_secs(1))),
+        );
+        for source in &self.sources {
+            source(app);
+        }
+    }
+}
+
+fn send_digests(mut digest: ResMut<Digest>, queue: Res<OutboundQueue>) {
+    for (conversation_id, message) in digest.take_due() {
+        queue.push(Outbound::Group {
+            tenant: None,
+            conversation_id,
+            message,
+            idempotency_key: new_idempotency_key(),
+            urgent: false,
+        });
+    }
+}
@@ -0,0 +1,163 @@
+//! [`UserDirectory`] resource resolving staff ids into profiles
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use bevy::log::{debug, warn};
+use bevy::prelude::{Assets, Handle, Image, Resource};
+use bevy::render::render_asset::RenderAssetUsages;
+use bevy::render::texture::{CompressedImageFormats, ImageSampler, ImageType};
+use tokio::runtime;
+
+use crate::client::contact::UserProfile;
+use crate::client::Client;
+use crate::event::UserProfileResolved;
+
+/// Lazily resolves staff ids through the contacts API
+///
+/// Lookups never block a system: [`UserDirectory::get`] answers from the client's cache and queues
+/// a batched fetch for misses, whose profiles arrive as [`UserProfileResolved`] events. Org change
+/// events invalidate affected entries automatically.
+#[derive(Resource, Clone)]
+pub struct UserDirectory {
+    client: Arc<Client>,
+    runtime: runtime::Handle,
+    pending: Arc<Mutex<HashSet<String>>>,
+    avatars: Arc<Mutex<HashMap<String, Handle<Image>>>>,
+}
+
+impl UserDirectory {
+    pub(crate) fn new(client: Arc<Client>, runtime: runtime::Handle) -> Self {
+        Self {
+            client,
+            runtime,
+            pending: Default::default(),
+            avatars: Default::default(),
+        }
+    }
+
+    /// cached profile, a fetch is queued when missing or expired
+    pub fn get(&self, staff_id: impl AsRef<str>) -> Option<UserProfile> {
+        let staff_id = staff_id.as_ref();
+        let profile = self.client.cached_user(staff_id);
+        if profile.is_none() {
+            self.request([staff_id]);
+        }
+        profile
+    }
+
+    /// queue one batched fetch for all given ids not already in flight
+    pub fn request(&self, staff_ids: impl IntoIterator<Item = impl Into<String>>) {
+        let ids: Vec<String> = {
+            let mut pending = self.pending.lock().unwrap();
+            staff_ids
+                .into_iter()
+                .map(Into::into)
+                .filter(|id| pending.insert(id.clone()))
+                .collect()
+        };
+        if ids.is_empty() {
+            return;
+        }
+
+        let client = self.client.clone();
+        let pending = self.pending.clone();
+        self.runtime.spawn(async move {
+            let resolved = client.resolve_users(ids.iter().cloned()).await;
+            {
+                let mut pending = pending.lock().unwrap();
+                ids.iter().for_each(|id| {
+                    pending.remove(id);
+                });
+            }
+            for profile in resolved.into_values() {
+                client.bridge.send_event(UserProfileResolved(profile));
+            }
+        });
+    }
+
+    /// Handle to the user's avatar, downloaded in the background on first use
+    ///
+    /// The handle is valid immediately and the image is filled in once loaded, so it can be put
+    /// straight into a `UiImage` or sprite. Users without an avatar keep an empty handle, failed
+    /// downloads are tried again on the next call.
+    pub fn avatar_handle(
+        &self,
+        staff_id: impl AsRef<str>,
+        images: &Assets<Image>,
+    ) -> Handle<Image> {
+        let staff_id = staff_id.as_ref();
+        let mut avatars = self.avatars.lock().unwrap();
+        if let Some(handle) = avatars.get(staff_id) {
+            return handle.clone();
+        }
+
+        let handle = images.reserve_handle();
+        avatars.insert(staff_id.to_owned(), handle.clone());
+
+        let client = self.client.clone();
+        let avatars = self.avatars.clone();
+        let staff_id = staff_id.to_owned();
+        let id = handle.id();
+        self.runtime.spawn(async move {
+            match load_avatar(&client, &staff_id).await {
+                Ok(Some(image)) => client.bridge.run(move |world| {
+                    world.resource_mut::<Assets<Image>>().insert(id, image);
+                }),
+                Ok(None) => debug!("{} has no avatar", staff_id),
+                Err(e) => {
+                    warn!("load avatar of {} error: {:?}", staff_id, e);
+                    let mut avatars = avatars.lock().unwrap();
+                    // unless invalidated and reserved again meanwhile
+                    if avatars.get(&staff_id).is_some_and(|h| h.id() == id) {
+                        avatars.remove(&staff_id);
+                    }
+                }
+            }
+        });
+
+        handle
+    }
+
+    /// forget one cached profile and avatar
+    pub fn invalidate(&self, staff_id: impl AsRef<str>) {
+        self.client.users.invalidate(staff_id.as_ref());
+        self.avatars.lock().unwrap().remove(staff_id.as_ref());
+    }
+
+    /// forget all cached profiles and avatars
+    pub fn invalidate_all(&self) {
+        self.client.users.invalidate_all();
+        self.avatars.lock().unwrap().clear();
+    }
+
+    /// change how long profiles stay cached, default is one hour
+    pub fn set_ttl(&self, ttl: Duration) {
+        self.client.user_cache_ttl(ttl);
+    }
+}
+
+/// the avatar of `staff_id`, `None` when the user has none
+async fn load_avatar(client: &Client, staff_id: &str) -> Result<Option<Image>> {
+    let Some(profile) = client.resolve_users([staff_id]).await.remove(staff_id) else {
+        bail!("user not resolved");
+    };
+    if profile.avatar.is_empty() {
+        return Ok(None);
+    }
+
+    let (bytes, content_type) = client.get_bytes(&profile.avatar).await?;
+    let content_type = content_type.unwrap_or_else(|| "image/jpeg".to_owned());
+    Ok(Some(Image::from_buffer(
+        &bytes,
+        ImageType::MimeType(&content_type),
+        CompressedImageFormats::NONE,
+        true,
+        ImageSampler::Default,
+        RenderAssetUsages::default(),
+    )?))
+}
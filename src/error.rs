@@ -0,0 +1,246 @@
+//! Typed errors that callers may want to match on
+//!
+//! Everything is still returned through [`anyhow::Result`], use `downcast_ref` to inspect.
+
+use std::fmt;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::client::up::UploadType;
+
+/// Error body returned by the stream gateway when opening a connection fails
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/error-code) for the list of codes
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct GatewayError {
+    /// http status of the response
+    #[serde(skip)]
+    pub status: u16,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub requestid: String,
+    /// what a `Forbidden.AccessDenied` error lacks
+    #[serde(default)]
+    pub accessdenieddetail: Option<AccessDeniedDetail>,
+}
+
+/// Permission details the gateway adds when it refuses for lack of a permission
+#[derive(Debug, Clone, Default, Deserialize)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+#[serde(rename_all = "camelCase")]
+pub struct AccessDeniedDetail {
+    /// permission scopes the app has to be granted, e.g. `qyapi_robot_sendmsg`
+    #[serde(default)]
+    pub required_scopes: Vec<String>,
+}
+
+/// Common causes of [`GatewayError`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayErrorKind {
+    /// client_id / client_secret are wrong or the app was deleted
+    InvalidCredentials,
+    /// the server's outbound ip is not in the app's whitelist
+    IpNotWhitelisted,
+    /// a subscription topic was rejected
+    SubscriptionRejected,
+    /// the app lacks a permission required by the gateway
+    PermissionDenied,
+    /// too many connection attempts
+    Throttled,
+    Other,
+}
+
+impl GatewayError {
+    pub(crate) fn from_body(status: u16, body: &str) -> Self {
+        let mut error =
+            serde_json::from_str::<GatewayError>(body).unwrap_or_else(|_| GatewayError {
+                message: body.to_owned(),
+                ..Default::default()
+            });
+        error.status = status;
+        error
+    }
+
+    /// permission scopes missing from the app, empty when the gateway did not name them
+    pub fn missing_scopes(&self) -> &[String] {
+        self.accessdenieddetail
+            .as_ref()
+            .map_or(&[], |detail| &detail.required_scopes)
+    }
+
+    pub fn kind(&self) -> GatewayErrorKind {
+        let code = self.code.to_lowercase();
+        if code.contains("whitelist") {
+            GatewayErrorKind::IpNotWhitelisted
+        } else if code.contains("invalidauthentication")
+            || code.contains("clientid")
+            || code.contains("appkey")
+            || self.status == 401
+        {
+            GatewayErrorKind::InvalidCredentials
+        } else if code.contains("subscription") || code.contains("invalidparameter") {
+            GatewayErrorKind::SubscriptionRejected
+        } else if code.contains("forbidden")
+            || code.contains("accessdenied")
+            || self.status == 403
+            || !self.missing_scopes().is_empty()
+        {
+            GatewayErrorKind::PermissionDenied
+        } else if code.contains("throttl") || code.contains("limit") || self.status == 429 {
+            GatewayErrorKind::Throttled
+        } else {
+            GatewayErrorKind::Other
+        }
+    }
+
+    /// a hint on what to change in the DingTalk developer console
+    pub fn guidance(&self) -> &'static str {
+        match self.kind() {
+            GatewayErrorKind::InvalidCredentials => {
+                "check the AppKey/AppSecret in the developer console"
+            }
+            GatewayErrorKind::IpNotWhitelisted => {
+                "add this server's public ip to the app's server outbound ip whitelist"
+            }
+            GatewayErrorKind::SubscriptionRejected => {
+                "check the subscribed topics and that stream mode is enabled for the app"
+            }
+            GatewayErrorKind::PermissionDenied => {
+                "grant the missing permission in the app's permission management page"
+            }
+            GatewayErrorKind::Throttled => "wait before reconnecting",
+            GatewayErrorKind::Other => "see the error code document",
+        }
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gateway error [{}] {}: {} (requestid: {}), {}",
+            self.status,
+            self.code,
+            self.message,
+            self.requestid,
+            self.guidance()
+        )?;
+        if !self.missing_scopes().is_empty() {
+            write!(f, ", missing scopes: {}", self.missing_scopes().join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for GatewayError {}
+
+/// Errors raised by the client itself, mostly before a request is made
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DingTalkError {
+    /// media exceeds the [size limit](UploadType::max_size) of its type, in bytes
+    MediaTooLarge { limit: u64, actual: u64 },
+    /// extension or content of `name` is not accepted for `file_type`
+    UnsupportedMedia { file_type: UploadType, name: String },
+    /// DingTalk's flow control refused the request, the client holds back further requests
+    /// within `scope` for `retry_after`
+    RateLimited {
+        scope: RateLimitScope,
+        retry_after: Duration,
+    },
+    /// [`Client::connect`](crate::client::Client::connect) gave up, the credentials were
+    /// rejected or the server closed with a code that stops reconnecting
+    ReconnectStopped { reason: String },
+}
+
+/// What a flow control limit counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitScope {
+    /// calls of one API by this app
+    Api,
+    /// calls of any API by this app
+    App,
+    /// calls of any API by every app of the corp
+    Corp,
+}
+
+/// wait after a flow control answer without `Retry-After`, DingTalk counts calls per second
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+impl DingTalkError {
+    /// the flow control error in a failed response, if it is one
+    ///
+    /// Recognizes HTTP 429, the `Forbidden.AccessDenied.QpsLimit*` and `Throttling` codes of the
+    /// new API and the errcodes 90018 and 90019 of legacy `oapi` paths.
+    pub(crate) fn rate_limited(
+        status: u16,
+        retry_after: Option<Duration>,
+        body: &str,
+    ) -> Option<Self> {
+        let body: serde_json::Value = serde_json::from_str(body).unwrap_or_default();
+        let code = body["code"].as_str().unwrap_or_default().to_lowercase();
+        let scope = match body["errcode"].as_u64() {
+            Some(90018) => RateLimitScope::Api,
+            Some(90019) => RateLimitScope::Corp,
+            _ if code.contains("qpslimitforappkeyandapi") || code.contains("qpslimitforapi") => {
+                RateLimitScope::Api
+            }
+            _ if code.contains("qpslimitforappkey") => RateLimitScope::App,
+            _ if code.contains("qpslimitforcorp") => RateLimitScope::Corp,
+            _ if code.contains("throttling") || status == 429 => RateLimitScope::Api,
+            _ => return None,
+        };
+        Some(DingTalkError::RateLimited {
+            scope,
+            retry_after: retry_after.unwrap_or(DEFAULT_RETRY_AFTER),
+        })
+    }
+}
+
+impl fmt::Display for DingTalkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DingTalkError::MediaTooLarge { limit, actual } => {
+                write!(f, "media too large: {actual} bytes, limit is {limit}")
+            }
+            DingTalkError::UnsupportedMedia { file_type, name } => {
+                write!(
+                    f,
+                    "unsupported media {name} for {file_type}, expected one of {:?}",
+                    file_type.extensions()
+                )
+            }
+            DingTalkError::RateLimited { scope, retry_after } => {
+                write!(f, "rate limited per {scope:?}, retry after {retry_after:?}")
+            }
+            DingTalkError::ReconnectStopped { reason } => {
+                write!(f, "{reason}, stop reconnecting")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DingTalkError {}
+
+/// Where a [`DingTalkErrorEvent`](crate::event::DingTalkErrorEvent) comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub enum ErrorContext {
+    /// a message, card or upload could not be sent
+    Send,
+    /// a received frame could not be parsed
+    Parse,
+    /// a callback, listener or hook returned an error
+    Callback,
+    /// a frame could not be acknowledged
+    Ack,
+    /// opening or keeping a stream connection failed
+    Connection,
+    /// the connection stopped for good, it is not retried until
+    /// [`Client::connect`](crate::client::Client::connect) is called again
+    ReconnectStopped,
+}
@@ -0,0 +1,267 @@
+//! Bevy events emitted by the plugin
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::prelude::{Deref, Event};
+
+use crate::client::ack::AckToken;
+use crate::client::assistant::GraphRequest;
+use crate::client::auth::{DingTalkUser, UserAccessToken};
+use crate::client::card::CardUser;
+use crate::client::contact::UserProfile;
+use crate::client::down::{DownstreamEnvelope, EmotionContent, RobotRecvMessage, StickerContent};
+use crate::client::group::{GroupMembersChanged, GroupTitleChanged};
+use crate::client::health::HealthReport;
+use crate::client::prompt::Prompt;
+use crate::client::tenant::TenantId;
+use crate::client::up::UploadType;
+use crate::error::{ErrorContext, GatewayError};
+
+/// A robot message that passed the plugin's [`MessageFilter`](crate::client::down::MessageFilter)
+///
+/// In [at-least-once](crate::plugin::StreamDingTalkPlugin::at_least_once) mode a system must
+/// decide `ack`, or DingTalk delivers the message again.
+#[derive(Event, Debug)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct RobotMessageReceived {
+    /// corp the message was sent in
+    pub tenant: TenantId,
+    pub message: RobotRecvMessage,
+    /// set in at-least-once mode
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    pub ack: Option<AckToken>,
+    /// times this message was put off with [`AckToken::retry_later`] before, 0 on first delivery
+    pub redelivery_count: u32,
+    /// the frame the message arrived in
+    pub envelope: DownstreamEnvelope,
+}
+
+/// A frame on a topic added with [`StreamDingTalkPlugin::raw_topic`](crate::plugin::StreamDingTalkPlugin::raw_topic)
+#[derive(Event, Debug, Clone, Deref)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct RawFrameReceived(pub DownstreamEnvelope);
+
+/// Result of an upload queued through [`DingTalk::upload`](crate::param::DingTalk::upload)
+#[derive(Event, Debug)]
+pub struct MediaUploaded {
+    pub path: PathBuf,
+    pub file_type: UploadType,
+    /// media id on success
+    pub result: anyhow::Result<String>,
+}
+
+/// Ask for a new scene group, answered by a [`CreateGroupResult`]
+///
+/// See [`Client::create_scene_group`](crate::client::Client::create_scene_group), e.g. one group
+/// per match of a lobby.
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct CreateGroupRequest {
+    pub name: String,
+    /// user id of the group owner
+    pub owner: String,
+    pub members: Vec<String>,
+    pub template_id: String,
+}
+
+/// Outcome of a [`CreateGroupRequest`]
+#[derive(Event, Debug)]
+pub struct CreateGroupResult {
+    pub request: CreateGroupRequest,
+    /// open conversation id of the new group on success
+    pub result: anyhow::Result<String>,
+}
+
+/// Members were added to a group the robot is in (`chat_add_member`)
+#[derive(Event, Debug, Clone, Deref)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct GroupMemberJoined(pub GroupMembersChanged);
+
+/// Members were removed from a group the robot is in (`chat_remove_member`)
+#[derive(Event, Debug, Clone, Deref)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct GroupMemberLeft(pub GroupMembersChanged);
+
+/// A group the robot is in was renamed (`chat_update_title`)
+#[derive(Event, Debug, Clone, Deref)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct GroupTitleUpdated(pub GroupTitleChanged);
+
+/// A profile requested through [`UserDirectory`](crate::directory::UserDirectory) was fetched
+#[derive(Event, Debug, Clone, Deref)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct UserProfileResolved(pub UserProfile);
+
+/// The access token was rejected even after a refresh, or the app credentials are invalid
+///
+/// The client stops reconnecting until [`Client::connect`](crate::client::Client::connect) is
+/// called again.
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct AuthFailedEvent {
+    pub reason: String,
+}
+
+/// The server closed a stream connection with a code the
+/// [`ClosePolicy`](crate::client::net::ClosePolicy) maps to
+/// [`CloseAction::Stop`](crate::client::net::CloseAction::Stop)
+///
+/// The connection does not reconnect until [`Client::connect`](crate::client::Client::connect) is
+/// called again.
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct FatalCloseEvent {
+    pub link: usize,
+    pub code: u16,
+    pub reason: String,
+}
+
+/// The clock jumped ahead, most likely the system slept, and the connections are reopened
+///
+/// See [`Client::suspend_threshold`](crate::client::Client::suspend_threshold).
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct ResumedFromSuspend {
+    /// time the process did not run, roughly
+    pub slept: Duration,
+}
+
+/// Any significant failure of the client, next to the log line it also writes
+///
+/// More specific events like [`FrameErrorEvent`] or [`AuthFailedEvent`] are still sent, this one
+/// lets apps handle every failure in one place, e.g. to show a status in the UI.
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct DingTalkErrorEvent {
+    pub context: ErrorContext,
+    /// what was being done, e.g. `send card 3f2a…`
+    pub what: String,
+    pub error: String,
+}
+
+/// Opening a stream connection was refused by the gateway, see [`GatewayError::guidance`]
+#[derive(Event, Debug, Clone, Deref)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct GatewayErrorEvent(pub GatewayError);
+
+/// A received frame could not be parsed or handled, the connection stays up
+///
+/// DingTalk redelivers CALLBACK and EVENT frames that were not acknowledged.
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct FrameErrorEvent {
+    /// empty when the frame could not be parsed at all
+    pub message_id: String,
+    pub error: String,
+}
+
+/// An ACK could not be sent, DingTalk will push the frame again
+///
+/// Handlers should be idempotent for messages reported here.
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct AckFailedEvent {
+    pub message_id: String,
+    pub topic: String,
+    pub error: String,
+}
+
+/// A frame arrived again after its ACK failed
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct RedeliveryDetected {
+    pub message_id: String,
+    pub topic: String,
+}
+
+/// An AI assistant invoked a skill, answer it with
+/// [`DingTalk::respond_skill`](crate::param::DingTalk::respond_skill) and the same `request_id`
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct AssistantSkillInvoked {
+    pub request_id: String,
+    pub request: GraphRequest,
+}
+
+/// A player logged in with DingTalk, queued through
+/// [`DingTalk::authenticate_user`](crate::param::DingTalk::authenticate_user)
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct UserAuthenticatedEvent {
+    /// the `state` given to [`Client::login_url`](crate::client::Client::login_url)
+    pub state: String,
+    pub user: DingTalkUser,
+    pub token: UserAccessToken,
+}
+
+/// Result of the startup [health check](crate::plugin::StreamDingTalkPlugin::health_check)
+#[derive(Event, Debug, Clone, Deref)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct HealthCheckResultEvent(pub HealthReport);
+
+/// A sticker was sent to the robot, the message is also a [`RobotMessageReceived`]
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct StickerReceived {
+    pub tenant: TenantId,
+    pub message: RobotRecvMessage,
+    pub sticker: StickerContent,
+}
+
+/// An emotion was sent to the robot, the message is also a [`RobotMessageReceived`]
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct EmotionReceived {
+    pub tenant: TenantId,
+    pub message: RobotRecvMessage,
+    pub emotion: EmotionContent,
+}
+
+/// A button of an interactive card was clicked
+///
+/// Needs card callbacks enabled in [`DingTalkSubscriptions`](crate::subscriptions::DingTalkSubscriptions),
+/// [`CardActionRouter`](crate::card::CardActionRouter) does so and decodes the parameters.
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct CardActionEvent {
+    /// corp of the user
+    pub tenant: TenantId,
+    /// id the card was sent with
+    pub out_track_id: String,
+    /// id of the clicked action, the first one when a click triggers several
+    pub action_id: String,
+    /// parameters of the action, a json object
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    pub params: serde_json::Value,
+    pub user: CardUser,
+    /// conversation or space the card is in
+    pub space_id: String,
+    /// set when card callbacks are acknowledged [after the handler](crate::client::ack::AckOrder::AfterHandler)
+    #[cfg_attr(feature = "reflect", reflect(ignore))]
+    pub ack: Option<AckToken>,
+}
+
+/// A [`Prompt`] queued with [`DingTalk::ask`](crate::param::DingTalk::ask) was answered in time
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct PromptAnswered {
+    pub conversation_id: String,
+    pub event: CardActionEvent,
+}
+
+/// Nobody answered a [`Prompt`] in time, its card is already marked expired
+#[derive(Event, Debug, Clone)]
+pub struct PromptExpired {
+    pub prompt: Prompt,
+}
+
+/// An alert of the [`Notifier`](crate::notify::Notifier) was acknowledged, its escalation is off
+#[derive(Event, Debug, Clone)]
+#[cfg_attr(feature = "reflect", derive(bevy::reflect::Reflect))]
+pub struct AlertAcknowledgedEvent {
+    /// id of the [`Incident`](crate::notify::Incident), as in `ack <id>`
+    pub alert_id: u64,
+    /// staff id of who acknowledged it
+    pub by: String,
+}
@@ -0,0 +1,113 @@
+//! Payloads recorded from DingTalk, for tests of code built on this crate
+//!
+//! Enabled with the `fixtures` feature. The json files live in `fixtures/` of the crate and are
+//! also the seed corpus of the fuzz targets. Ids and names in them are scrubbed, every message
+//! comes from sender `Zhang San` in conversation [`CONVERSATION_ID`] of corp [`CORP_ID`].
+//!
+//! The helpers panic when a payload does not parse, which only happens when this crate's types
+//! drift from the recorded payloads.
+
+use serde_json::{json, Value};
+
+use crate::client::card::CardCallback;
+use crate::protocol::{ClientDownStream, MsgContent, RobotRecvMessage, StreamDownHeaders};
+use crate::topics::Topic;
+
+/// conversation of every recorded message
+pub const CONVERSATION_ID: &str = "cidOZ3Xq8bLr2ZK0TqKfJc1Tw==";
+/// corp of the sender and the robot
+pub const CORP_ID: &str = "ding9f50b15bccd16741";
+/// staff id of the sender
+pub const STAFF_ID: &str = "manager7421";
+
+/// robot message data, by msgtype
+pub mod robot_message {
+    pub const TEXT: &str = include_str!("../fixtures/robot_message/text.json");
+    pub const PICTURE: &str = include_str!("../fixtures/robot_message/picture.json");
+    pub const FILE: &str = include_str!("../fixtures/robot_message/file.json");
+    pub const AUDIO: &str = include_str!("../fixtures/robot_message/audio.json");
+    pub const VIDEO: &str = include_str!("../fixtures/robot_message/video.json");
+    pub const RICH_TEXT: &str = include_str!("../fixtures/robot_message/rich_text.json");
+    pub const LOCATION: &str = include_str!("../fixtures/robot_message/location.json");
+    pub const STICKER: &str = include_str!("../fixtures/robot_message/sticker.json");
+    pub const EMOTION: &str = include_str!("../fixtures/robot_message/emotion.json");
+    /// msgtype `unknownMsgType`, what older clients send for types the server cannot describe
+    pub const UNKNOWN: &str = include_str!("../fixtures/robot_message/unknown.json");
+
+    /// every sample above
+    pub const ALL: [&str; 10] = [
+        TEXT, PICTURE, FILE, AUDIO, VIDEO, RICH_TEXT, LOCATION, STICKER, EMOTION, UNKNOWN,
+    ];
+}
+
+/// card callback data
+pub mod card_callback {
+    /// click on button `join` with param `team` = `red`
+    pub const BUTTON: &str = include_str!("../fixtures/card_callback/button.json");
+}
+
+/// whole stream frames, headers included
+pub mod frame {
+    /// `SYSTEM` ping, to be answered with its data
+    pub const PING: &str = include_str!("../fixtures/frame/ping.json");
+    /// `SYSTEM` disconnect announcing a server restart
+    pub const DISCONNECT: &str = include_str!("../fixtures/frame/disconnect.json");
+    /// `CALLBACK` carrying the text message of [`TEXT`](super::robot_message::TEXT)
+    pub const ROBOT_MESSAGE: &str = include_str!("../fixtures/frame/robot_message.json");
+    /// `EVENT` of a group title change
+    pub const EVENT: &str = include_str!("../fixtures/frame/event.json");
+}
+
+/// parse one of the [`robot_message`] samples
+pub fn parse_robot_message(data: &str) -> RobotRecvMessage {
+    RobotRecvMessage::from_json(data).expect("robot message fixture")
+}
+
+/// the recorded text message with `content` as its text
+pub fn text_message(content: &str) -> RobotRecvMessage {
+    let mut msg = parse_robot_message(robot_message::TEXT);
+    msg.content = MsgContent::Text {
+        content: content.to_owned(),
+    };
+    msg
+}
+
+/// the recorded text message, moved to group `conversation_id` and mentioning the robot
+pub fn group_text_message(conversation_id: &str, content: &str) -> RobotRecvMessage {
+    let mut msg = text_message(content);
+    msg.conversation_id = conversation_id.to_owned();
+    msg.conversation_type = "2".to_owned();
+    msg.conversation_title = "fixture group".to_owned();
+    msg.is_in_at_list = true;
+    msg
+}
+
+/// parse the whole frame of one of the [`frame`] samples
+pub fn parse_frame(json: &str) -> ClientDownStream {
+    serde_json::from_str(json).expect("frame fixture")
+}
+
+/// `CALLBACK` frame of `topic` carrying `data`, with the headers of the recorded frames
+pub fn callback_frame(topic: Topic, data: impl Into<String>) -> ClientDownStream {
+    let recorded = parse_frame(frame::ROBOT_MESSAGE);
+    ClientDownStream {
+        data: data.into(),
+        headers: StreamDownHeaders {
+            topic: topic.into(),
+            ..recorded.headers
+        },
+        ..recorded
+    }
+}
+
+/// click on `action_id` of card `out_track_id` by [`STAFF_ID`], `params` being a json object
+pub fn card_callback(out_track_id: &str, action_id: &str, params: Value) -> CardCallback {
+    let mut callback: CardCallback =
+        serde_json::from_str(card_callback::BUTTON).expect("card callback fixture");
+    callback.out_track_id = out_track_id.to_owned();
+    callback.content = json!({
+        "cardPrivateData": { "actionIds": [action_id], "params": params }
+    })
+    .to_string();
+    callback
+}
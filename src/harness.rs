@@ -0,0 +1,257 @@
+//! End-to-end tests of bots without a network
+//!
+//! Enabled with the `harness` feature. [`TestHarness`] builds a minimal app with
+//! [`StreamDingTalkPlugin`] in [mock mode](crate::client::Client::mock) on a
+//! [current thread](StreamDingTalkPlugin::current_thread) runtime. Frames are injected, the
+//! runtime is driven between updates and everything the bot sends is recorded:
+//!
+//! ```
+//! # use bevy_stream_dingtalk::prelude::*;
+//! #[derive(BotCommand)]
+//! enum GameCommand {
+//!     Score,
+//! }
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let mut harness = TestHarness::new();
+//! harness.app.add_plugins(CommandRouter::<GameCommand>::new());
+//! harness.send_text("/score")?;
+//! assert_eq!(harness.events::<CommandReceived<GameCommand>>().len(), 1);
+//! // unknown commands are answered with the usage
+//! harness.send_text("/dance")?;
+//! let replies = harness.outgoing();
+//! let [.., Outgoing::Request(reply)] = &replies[..] else {
+//!     panic!("the usage is sent");
+//! };
+//! assert!(reply.path().ends_with("/batchSend"));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Everything goes to the client's [`Tap`](crate::client::tap::Tap).
+//! [`sent_acks`](TestHarness::sent_acks) and [`sent_http`](TestHarness::sent_http) query it
+//! without consuming:
+//!
+//! ```
+//! # use bevy_stream_dingtalk::{fixtures, prelude::*};
+//! # #[derive(BotCommand)]
+//! # enum GameCommand {
+//! #     Score,
+//! # }
+//! # fn main() -> anyhow::Result<()> {
+//! # let mut harness = TestHarness::new();
+//! # harness.app.add_plugins(CommandRouter::<GameCommand>::new());
+//! harness.send_group_text(fixtures::CONVERSATION_ID, "/dance")?;
+//! assert_eq!(harness.sent_acks().len(), 1);
+//! assert_eq!(harness.sent_http("groupMessages/send").len(), 1);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Time only moves through [`TestHarness::advance`], for Bevy's [`Time`] and the client's
+//! [`Clock`](crate::clock::Clock) alike, so quiet hours end with it. Tokio timers, e.g. of
+//! prompt timeouts, still run on real time and do not fire while nothing drives the runtime.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::schedule::{ExecutorKind, Schedules};
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use serde_json::{json, Value};
+
+use crate::client::mock::Outgoing;
+use crate::client::tap::TappedRequest;
+use crate::client::{AsyncRuntime, Client, DingTalkClient};
+use crate::clock::ManualClock;
+use crate::fixtures;
+use crate::plugin::StreamDingTalkPlugin;
+use crate::protocol::{ClientDownStream, ClientUpStream};
+use crate::topics::Topic;
+
+/// yields given to the runtime per drive, enough for a frame to travel from listener to bridge
+const DRIVE_YIELDS: usize = 64;
+
+/// A minimal app running the plugin in mock mode, stepped by hand
+pub struct TestHarness {
+    /// add the bot's plugins and systems here
+    pub app: App,
+    clock: Arc<ManualClock>,
+    /// a `ManualEventReader<E>` per event type read through [`TestHarness::events`]
+    readers: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    injected: u64,
+    /// the app was finished and connected, plugins can no longer be added
+    started: bool,
+}
+
+impl Default for TestHarness {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestHarness {
+    pub fn new() -> Self {
+        Self::with_plugin(StreamDingTalkPlugin::new("harness", "harness"))
+    }
+
+    /// run `plugin`, switched to mock mode on a current thread runtime and the harness clock
+    ///
+    /// The bot's plugins can be added to [`app`](Self::app) until the first update, injection
+    /// or advance starts it.
+    pub fn with_plugin(plugin: StreamDingTalkPlugin) -> Self {
+        let clock = Arc::new(ManualClock::default());
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO))
+            .add_plugins(
+                plugin
+                    .mock(true)
+                    .current_thread(true)
+                    .health_check(false)
+                    .clock(clock.clone()),
+            );
+        Self {
+            app,
+            clock,
+            readers: HashMap::new(),
+            injected: 0,
+            started: false,
+        }
+    }
+
+    /// finish the app and let it connect, once
+    fn start(&mut self) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        self.app.finish();
+        self.app.cleanup();
+        // advances are taken whole instead of in steps of at most 250 ms
+        self.app
+            .world
+            .resource_mut::<Time<Virtual>>()
+            .set_max_delta(Duration::MAX);
+        for (_, schedule) in self.app.world.resource_mut::<Schedules>().iter_mut() {
+            schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+        }
+        // Bevy's time starts on the first update, the first connect attempt a second later
+        // registers the listeners
+        self.update();
+        self.advance(Duration::from_secs(1));
+    }
+
+    pub fn client(&self) -> Arc<Client> {
+        Arc::clone(self.app.world.resource::<DingTalkClient>())
+    }
+
+    pub fn clock(&self) -> &ManualClock {
+        &self.clock
+    }
+
+    /// let the runtime's tasks run until they wait on something
+    pub fn drive(&mut self) {
+        self.app.world.resource::<AsyncRuntime>().block_on(async {
+            for _ in 0..DRIVE_YIELDS {
+                tokio::task::yield_now().await;
+            }
+        });
+    }
+
+    /// drive the runtime, run one app update and drive it again for what the update queued
+    pub fn update(&mut self) {
+        self.start();
+        self.drive();
+        self.app.update();
+        self.drive();
+    }
+
+    /// move Bevy's time and the client's clock forward by `by` in one update
+    pub fn advance(&mut self, by: Duration) {
+        self.start();
+        self.clock.advance(by);
+        self.app
+            .insert_resource(TimeUpdateStrategy::ManualDuration(by));
+        self.update();
+        self.app
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+    }
+
+    /// handle `frame` as if it came over the stream, then [`update`](Self::update) twice
+    ///
+    /// The second update lets handlers running before the network drain see its events and
+    /// their sends get recorded.
+    pub fn inject(&mut self, frame: ClientDownStream) -> Result<()> {
+        self.start();
+        let client = self.client();
+        let result = self
+            .app
+            .world
+            .resource::<AsyncRuntime>()
+            .block_on(client.inject_frame(frame));
+        self.update();
+        self.update();
+        result
+    }
+
+    /// inject robot message `data`, a json object, under a fresh message id
+    pub fn inject_message(&mut self, mut data: Value) -> Result<()> {
+        self.injected += 1;
+        let id = format!("harness-{}", self.injected);
+        data["msgId"] = Value::String(id.clone());
+        let mut frame = fixtures::callback_frame(Topic::ROBOT_MESSAGES, data.to_string());
+        frame.headers.message_id = id;
+        self.inject(frame)
+    }
+
+    /// the recorded one-on-one text message with `content`, from [`fixtures::STAFF_ID`]
+    pub fn send_text(&mut self, content: &str) -> Result<()> {
+        let mut data: Value = serde_json::from_str(fixtures::robot_message::TEXT)?;
+        data["text"] = json!({ "content": content });
+        self.inject_message(data)
+    }
+
+    /// the recorded text message sent in group `conversation_id`, mentioning the robot
+    pub fn send_group_text(&mut self, conversation_id: &str, content: &str) -> Result<()> {
+        let mut data: Value = serde_json::from_str(fixtures::robot_message::TEXT)?;
+        data["text"] = json!({ "content": content });
+        data["conversationId"] = Value::String(conversation_id.to_owned());
+        data["conversationType"] = Value::String("2".to_owned());
+        data["isInAtList"] = Value::Bool(true);
+        self.inject_message(data)
+    }
+
+    /// events of type `E` sent since the last call for `E`
+    pub fn events<E: Event>(&mut self) -> Vec<&E> {
+        let reader = self
+            .readers
+            .entry(TypeId::of::<E>())
+            .or_insert_with(|| Box::new(ManualEventReader::<E>::default()))
+            .downcast_mut::<ManualEventReader<E>>()
+            .expect("reader of its event type");
+        match self.app.world.get_resource::<Events<E>>() {
+            Some(events) => reader.read(events).collect(),
+            None => vec![],
+        }
+    }
+
+    /// frames and requests the bot sent since the last call, see [`Client::take_outgoing`]
+    pub fn outgoing(&mut self) -> Vec<Outgoing> {
+        self.client().take_outgoing()
+    }
+
+    /// every ACK sent so far, ping answers aside, see [`Tap`](crate::client::tap::Tap)
+    pub fn sent_acks(&self) -> Vec<ClientUpStream> {
+        self.client().tap().sent_acks()
+    }
+
+    /// every request sent so far whose url path contains `path`, e.g. `groupMessages/send`
+    pub fn sent_http(&self, path: &str) -> Vec<TappedRequest> {
+        self.client().tap().sent_http(path)
+    }
+}
@@ -0,0 +1,306 @@
+//! Searchable history of recent robot messages
+//!
+//! [`MessageHistoryPlugin`] records every [`RobotMessageReceived`] into a [`Storage`], the
+//! client's one by default, so it is kept on disk when the plugin was given a `sled` or `sqlite`
+//! store. Bots can then answer questions about earlier conversation through
+//! [`MessageHistory::search`], or keep records with [`MessageHistory::export`].
+
+use std::ops::RangeBounds;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use bevy::prelude::*;
+use bevy::time::common_conditions::on_timer;
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::client::down::RobotRecvMessage;
+use crate::client::DingTalkClient;
+use crate::event::RobotMessageReceived;
+use crate::markdown;
+use crate::storage::{MemoryStorage, Storage, HISTORY};
+use crate::system::handle_network_events;
+
+/// separates conversation id, time and message id in keys, never part of a conversation id
+const SEPARATOR: char = '|';
+
+/// A recorded robot message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub msg_id: String,
+    pub conversation_id: String,
+    pub sender_id: String,
+    pub sender_nick: String,
+    /// text of the message, a short placeholder for media
+    pub text: String,
+    /// unix timestamp in milliseconds
+    pub create_at: u64,
+}
+
+impl HistoryEntry {
+    pub fn from_message(message: &RobotRecvMessage) -> Self {
+        Self {
+            msg_id: message.msg_id.clone(),
+            conversation_id: message.conversation_id.clone(),
+            sender_id: message.sender_id.clone(),
+            sender_nick: message.sender_nick.clone(),
+            text: message.content.summary(),
+            create_at: message.create_at,
+        }
+    }
+
+    pub fn time(&self) -> DateTime<Local> {
+        self.created_at().with_timezone(&Local)
+    }
+
+    pub fn created_at(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.create_at as i64).unwrap_or_default()
+    }
+
+    fn key(&self) -> String {
+        format!(
+            "{}{SEPARATOR}{:013}{SEPARATOR}{}",
+            self.conversation_id, self.create_at, self.msg_id
+        )
+    }
+}
+
+/// How long messages are kept
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// drop messages older than this, `None` keeps them regardless of age
+    pub max_age: Option<chrono::Duration>,
+    /// keep at most this many messages per conversation, `None` for no limit
+    pub max_per_conversation: Option<usize>,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            max_age: Some(chrono::Duration::days(7)),
+            max_per_conversation: Some(1000),
+        }
+    }
+}
+
+/// Recent messages per conversation, inserted by [`MessageHistoryPlugin`]
+///
+/// Cheap to clone, clones share the same store.
+#[derive(Resource, Clone)]
+pub struct MessageHistory {
+    storage: Arc<dyn Storage>,
+    retention: Retention,
+}
+
+impl MessageHistory {
+    pub fn new(storage: Arc<dyn Storage>, retention: Retention) -> Self {
+        Self { storage, retention }
+    }
+
+    /// add `message`, dropping the oldest ones of its conversation above the retention limit
+    pub fn record(&self, message: &RobotRecvMessage) -> Result<()> {
+        let entry = HistoryEntry::from_message(message);
+        self.storage.put_json(HISTORY, &entry.key(), &entry)?;
+
+        if let Some(limit) = self.retention.max_per_conversation {
+            let keys = self
+                .storage
+                .scan(HISTORY, &prefix(&entry.conversation_id))?;
+            for (key, _) in keys.iter().take(keys.len().saturating_sub(limit)) {
+                self.storage.remove(HISTORY, key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// messages of `conversation_id` within `range` containing `text`, ignoring case, oldest
+    /// first
+    ///
+    /// An empty `text` matches every message.
+    pub fn search(
+        &self,
+        conversation_id: &str,
+        text: &str,
+        range: impl RangeBounds<DateTime<Local>>,
+    ) -> Result<Vec<HistoryEntry>> {
+        let text = text.to_lowercase();
+        Ok(self
+            .entries(conversation_id)?
+            .into_iter()
+            .filter(|e| range.contains(&e.time()))
+            .filter(|e| text.is_empty() || e.text.to_lowercase().contains(&text))
+            .collect())
+    }
+
+    /// the last `limit` messages of `conversation_id`, oldest first
+    pub fn recent(&self, conversation_id: &str, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let mut entries = self.entries(conversation_id)?;
+        entries.drain(..entries.len().saturating_sub(limit));
+        Ok(entries)
+    }
+
+    /// transcript of the messages of `conversation_id` within `range`
+    pub fn export(
+        &self,
+        conversation_id: &str,
+        range: impl RangeBounds<DateTime<Local>>,
+        format: ExportFormat,
+    ) -> Result<String> {
+        let entries = self.search(conversation_id, "", range)?;
+        Ok(match format {
+            ExportFormat::Json => serde_json::to_string_pretty(&entries)?,
+            ExportFormat::Markdown => {
+                let mut text = format!("# {}\n\n", markdown::escape(conversation_id));
+                for e in &entries {
+                    text.push_str(&format!(
+                        "- **{}** {}: {}\n",
+                        e.time().format("%Y-%m-%d %H:%M:%S"),
+                        markdown::escape(&e.sender_nick),
+                        markdown::escape(&e.text).replace('\n', "  \n  ")
+                    ));
+                }
+                text
+            }
+            ExportFormat::Csv => {
+                let mut text = "time,msg_id,sender_id,sender_nick,text\n".to_owned();
+                for e in &entries {
+                    let row = [
+                        e.time().to_rfc3339(),
+                        e.msg_id.clone(),
+                        e.sender_id.clone(),
+                        e.sender_nick.clone(),
+                        e.text.clone(),
+                    ];
+                    let row: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+                    text.push_str(&row.join(","));
+                    text.push('\n');
+                }
+                text
+            }
+        })
+    }
+
+    /// write the [transcript](Self::export) to `path`
+    pub fn export_to_file(
+        &self,
+        conversation_id: &str,
+        range: impl RangeBounds<DateTime<Local>>,
+        format: ExportFormat,
+        path: impl AsRef<Path>,
+    ) -> Result<()> {
+        std::fs::write(path, self.export(conversation_id, range, format)?)?;
+        Ok(())
+    }
+
+    /// drop messages older than the retention's `max_age` at `now`, returns how many were removed
+    pub fn prune(&self, now: DateTime<Local>) -> Result<usize> {
+        let Some(max_age) = self.retention.max_age else {
+            return Ok(0);
+        };
+        let oldest = (now - max_age).timestamp_millis().max(0) as u64;
+        let mut removed = 0;
+        for (key, value) in self.storage.scan(HISTORY, "")? {
+            let expired = serde_json::from_slice::<HistoryEntry>(&value)
+                .map_or(true, |e| e.create_at < oldest);
+            if expired {
+                self.storage.remove(HISTORY, &key)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn entries(&self, conversation_id: &str) -> Result<Vec<HistoryEntry>> {
+        self.storage
+            .scan(HISTORY, &prefix(conversation_id))?
+            .into_iter()
+            .map(|(_, value)| Ok(serde_json::from_slice(&value)?))
+            .collect()
+    }
+}
+
+/// Output of [`MessageHistory::export`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// array of [`HistoryEntry`]
+    Json,
+    /// one list item per message, escaped for DingTalk markdown
+    Markdown,
+    /// header row and one row per message
+    Csv,
+}
+
+/// quote `field` when it contains separators, quotes or line breaks
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn prefix(conversation_id: &str) -> String {
+    format!("{conversation_id}{SEPARATOR}")
+}
+
+/// Records robot messages into a [`MessageHistory`] resource
+///
+/// Add it after [`StreamDingTalkPlugin`](crate::plugin::StreamDingTalkPlugin) to share the
+/// client's [`Storage`].
+#[derive(Default)]
+pub struct MessageHistoryPlugin {
+    pub retention: Retention,
+    /// store of the history, the client's one when `None`
+    pub storage: Option<Arc<dyn Storage>>,
+}
+
+impl MessageHistoryPlugin {
+    pub fn new(retention: Retention) -> Self {
+        Self {
+            retention,
+            storage: None,
+        }
+    }
+
+    /// keep the history in `storage` instead of the client's store
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+}
+
+impl Plugin for MessageHistoryPlugin {
+    fn build(&self, app: &mut App) {
+        let storage = self.storage.clone().unwrap_or_else(|| {
+            app.world
+                .get_resource::<DingTalkClient>()
+                .map_or_else(|| MemoryStorage::new() as Arc<dyn Storage>, |c| c.store())
+        });
+        app.insert_resource(MessageHistory::new(storage, self.retention))
+            .add_systems(
+                Update,
+                (
+                    record_history.after(handle_network_events),
+                    prune_history.run_if(on_timer(Duration::from_secs(60))),
+                ),
+            );
+    }
+}
+
+fn record_history(history: Res<MessageHistory>, mut messages: EventReader<RobotMessageReceived>) {
+    for event in messages.read() {
+        if let Err(e) = history.record(&event.message) {
+            warn!("record message {} error: {:?}", event.message.msg_id, e);
+        }
+    }
+}
+
+fn prune_history(history: Res<MessageHistory>, client: Res<DingTalkClient>) {
+    match history.prune(client.now()) {
+        Ok(0) => {}
+        Ok(removed) => debug!("pruned {} history messages", removed),
+        Err(e) => warn!("prune history error: {:?}", e),
+    }
+}
@@ -0,0 +1,320 @@
+//! Ranked score tables for markdown messages and paged interactive cards
+//!
+//! A [`Leaderboard`] renders into a markdown message, DingTalk markdown has no tables so each
+//! row is a line. As a card it is filled with these variables:
+//!
+//! - `title`
+//! - `rows`: json list of `{"rank", "name", "score", "delta"}` of the shown page
+//! - `page`, `pages`: 1-based page number and page count
+//! - `hasPrev`, `hasNext`: `"true"` when the page buttons apply
+//!
+//! Page buttons submit `page` = the requested 1-based page. [`LeaderboardPlugin`] turns pages
+//! and updates boards in place through the [`Leaderboards`] resource.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use serde_json::{json, Map, Value};
+
+use crate::client::card::InteractiveCard;
+use crate::client::up::MessageTemplate;
+use crate::event::CardActionEvent;
+use crate::markdown;
+use crate::outbound::{Outbound, OutboundQueue};
+use crate::subscriptions::DingTalkSubscriptions;
+use crate::system::handle_network_events;
+
+/// Card parameter holding the requested page
+pub const LEADERBOARD_PAGE_PARAM: &str = "page";
+
+/// A row of a [`Leaderboard`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaderboardEntry {
+    pub name: String,
+    pub score: f64,
+    /// rank change since the previous board, positive when moving up
+    pub delta: Option<i64>,
+}
+
+/// Scores ranked from high to low, equal scores share a rank
+#[derive(Debug, Clone)]
+pub struct Leaderboard {
+    pub title: String,
+    entries: Vec<LeaderboardEntry>,
+    pub page_size: usize,
+}
+
+impl Leaderboard {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            entries: vec![],
+            page_size: 10,
+        }
+    }
+
+    pub fn entry(self, name: impl Into<String>, score: f64) -> Self {
+        self.push(LeaderboardEntry {
+            name: name.into(),
+            score,
+            delta: None,
+        })
+    }
+
+    pub fn entry_with_delta(self, name: impl Into<String>, score: f64, delta: i64) -> Self {
+        self.push(LeaderboardEntry {
+            name: name.into(),
+            score,
+            delta: Some(delta),
+        })
+    }
+
+    /// rows per page, default is 10
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size.max(1);
+        self
+    }
+
+    fn push(mut self, entry: LeaderboardEntry) -> Self {
+        let at = self.entries.partition_point(|e| e.score >= entry.score);
+        self.entries.insert(at, entry);
+        self
+    }
+
+    /// entries with their rank, best first
+    pub fn ranked(&self) -> impl Iterator<Item = (usize, &LeaderboardEntry)> {
+        let mut rank = 0;
+        let mut previous = None;
+        self.entries.iter().enumerate().map(move |(i, entry)| {
+            if previous != Some(entry.score) {
+                rank = i + 1;
+                previous = Some(entry.score);
+            }
+            (rank, entry)
+        })
+    }
+
+    /// number of pages, at least one
+    pub fn pages(&self) -> usize {
+        self.entries.len().div_ceil(self.page_size).max(1)
+    }
+
+    /// rows of the 0-based `page`, the last page when out of range
+    fn page(&self, page: usize) -> impl Iterator<Item = (usize, &LeaderboardEntry)> {
+        let page = page.min(self.pages() - 1);
+        self.ranked()
+            .skip(page * self.page_size)
+            .take(self.page_size)
+    }
+
+    /// markdown of the 0-based `page`
+    pub fn to_markdown(&self, page: usize) -> String {
+        let mut text = format!("#### {}\n\n", markdown::escape(&self.title));
+        if self.entries.is_empty() {
+            text.push_str("No scores yet\n");
+        }
+        for (rank, entry) in self.page(page) {
+            let medal = match rank {
+                1 => "🥇".to_owned(),
+                2 => "🥈".to_owned(),
+                3 => "🥉".to_owned(),
+                rank => format!("{rank}."),
+            };
+            text.push_str(&format!(
+                "{} **{}** {}",
+                medal,
+                markdown::escape(&entry.name),
+                entry.score
+            ));
+            if let Some(delta) = entry.delta.map(format_delta) {
+                text.push_str(&format!(" {delta}"));
+            }
+            text.push_str("\n\n");
+        }
+        if self.pages() > 1 {
+            text.push_str(&format!(
+                "*{}/{}*\n",
+                page.min(self.pages() - 1) + 1,
+                self.pages()
+            ));
+        }
+        text
+    }
+
+    /// markdown message of the 0-based `page`
+    pub fn message(&self, page: usize) -> MessageTemplate {
+        MessageTemplate::SampleMarkdown {
+            title: self.title.clone(),
+            text: self.to_markdown(page),
+        }
+    }
+
+    /// card of `template_id` showing the 0-based `page`
+    pub fn card(&self, template_id: impl Into<String>, page: usize) -> InteractiveCard {
+        let mut card = InteractiveCard::new(template_id);
+        card.params = self.card_params(page);
+        card
+    }
+
+    fn card_params(&self, page: usize) -> Map<String, Value> {
+        let page = page.min(self.pages() - 1);
+        let rows: Vec<Value> = self
+            .page(page)
+            .map(|(rank, entry)| {
+                json!({
+                    "rank": rank,
+                    "name": entry.name,
+                    "score": entry.score,
+                    "delta": entry.delta.map(format_delta).unwrap_or_default(),
+                })
+            })
+            .collect();
+        let mut params = Map::new();
+        params.insert("title".to_owned(), self.title.clone().into());
+        params.insert("rows".to_owned(), Value::Array(rows));
+        params.insert("page".to_owned(), (page + 1).into());
+        params.insert("pages".to_owned(), self.pages().into());
+        params.insert("hasPrev".to_owned(), (page > 0).to_string().into());
+        params.insert(
+            "hasNext".to_owned(),
+            (page + 1 < self.pages()).to_string().into(),
+        );
+        params
+    }
+}
+
+fn format_delta(delta: i64) -> String {
+    match delta {
+        0 => "-".to_owned(),
+        d if d > 0 => format!("▲{d}"),
+        d => format!("▼{}", -d),
+    }
+}
+
+struct PostedBoard {
+    board: Leaderboard,
+    page: usize,
+}
+
+/// Leaderboard cards kept up to date
+#[derive(Resource, Default)]
+pub struct Leaderboards {
+    posted: HashMap<String, PostedBoard>,
+    outgoing: Vec<Outbound>,
+}
+
+impl Leaderboards {
+    /// send `board` as a card to a group chat, returns the id to update it with
+    pub fn post(
+        &mut self,
+        conversation_id: impl Into<String>,
+        template_id: impl Into<String>,
+        board: Leaderboard,
+    ) -> String {
+        let card = board.card(template_id, 0);
+        let id = card.out_track_id.clone();
+        self.outgoing.push(Outbound::Card {
+            conversation_id: conversation_id.into(),
+            card,
+        });
+        self.posted
+            .insert(id.clone(), PostedBoard { board, page: 0 });
+        id
+    }
+
+    /// replace the scores of a posted board in place, the shown page is kept
+    pub fn update(&mut self, id: &str, board: Leaderboard) -> bool {
+        let Some(posted) = self.posted.get_mut(id) else {
+            return false;
+        };
+        posted.board = board;
+        self.show(id, None);
+        true
+    }
+
+    /// stop following a board, its card keeps the last state
+    pub fn remove(&mut self, id: &str) -> Option<Leaderboard> {
+        self.posted.remove(id).map(|posted| posted.board)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Leaderboard> {
+        self.posted.get(id).map(|posted| &posted.board)
+    }
+
+    /// update the card of `id` with the 0-based `page`, the current one when `None`
+    fn show(&mut self, id: &str, page: Option<usize>) {
+        let Some(posted) = self.posted.get_mut(id) else {
+            return;
+        };
+        posted.page = page.unwrap_or(posted.page).min(posted.board.pages() - 1);
+        self.outgoing.push(Outbound::UpdateCard {
+            out_track_id: id.to_owned(),
+            params: posted.board.card_params(posted.page),
+        });
+    }
+}
+
+/// Runs [`Leaderboards`], add it after [`StreamDingTalkPlugin`](crate::plugin::StreamDingTalkPlugin)
+///
+/// Enables card callbacks in [`DingTalkSubscriptions`].
+#[derive(Default)]
+pub struct LeaderboardPlugin;
+
+impl Plugin for LeaderboardPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(mut subscriptions) = app.world.get_resource_mut::<DingTalkSubscriptions>() {
+            subscriptions.set_card_callbacks(true);
+        }
+        app.init_resource::<Leaderboards>().add_systems(
+            Update,
+            (turn_pages, send_leaderboards)
+                .chain()
+                .after(handle_network_events),
+        );
+    }
+}
+
+fn turn_pages(mut boards: ResMut<Leaderboards>, mut clicks: EventReader<CardActionEvent>) {
+    for event in clicks.read() {
+        if !boards.posted.contains_key(&event.out_track_id) {
+            continue;
+        }
+        let page = match event.params.get(LEADERBOARD_PAGE_PARAM) {
+            Some(Value::String(s)) => s.parse::<usize>().ok(),
+            Some(Value::Number(n)) => n.as_u64().map(|n| n as usize),
+            _ => None,
+        };
+        if let Some(page) = page {
+            boards.show(&event.out_track_id, Some(page.saturating_sub(1)));
+        }
+    }
+}
+
+/// card updates of a frame go out together, a board may be pinned in many groups
+fn send_leaderboards(mut boards: ResMut<Leaderboards>, queue: Res<OutboundQueue>) {
+    let mut updates: Vec<(String, Map<String, Value>)> = Vec::new();
+    for item in boards.outgoing.drain(..) {
+        match item {
+            Outbound::UpdateCard {
+                out_track_id,
+                params,
+            } => {
+                // only the latest state of a card matters
+                updates.retain(|(id, _)| *id != out_track_id);
+                updates.push((out_track_id, params));
+            }
+            item => queue.push(item),
+        }
+    }
+    match updates.len() {
+        0 => {}
+        1 => {
+            let (out_track_id, params) = updates.remove(0);
+            queue.push(Outbound::UpdateCard {
+                out_track_id,
+                params,
+            });
+        }
+        _ => queue.push(Outbound::UpdateCards(updates)),
+    }
+}
@@ -1,5 +1,37 @@
+pub mod announce;
+pub mod asset;
+mod bridge;
+pub mod card;
 pub mod client;
+pub mod clock;
+pub mod command;
 mod constant;
+pub mod conversations;
+pub mod credentials;
+pub mod diagnostics;
+pub mod digest;
+pub mod directory;
+pub mod error;
+pub mod event;
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+#[cfg(feature = "harness")]
+pub mod harness;
+pub mod history;
+pub mod leaderboard;
+pub mod markdown;
+pub mod notify;
+mod outbound;
+pub mod param;
+pub mod poll;
 mod plugin;
 pub mod prelude;
+pub mod protocol;
+#[cfg(feature = "reflect")]
+mod reflect;
+pub mod storage;
+pub mod sub_app;
+pub mod subscriptions;
 mod system;
+pub mod targets;
+pub mod topics;
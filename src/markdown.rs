@@ -0,0 +1,176 @@
+//! Helpers for putting untrusted text into outgoing messages
+//!
+//! User input echoed into a markdown reply can break its formatting, smuggle in links or look
+//! like an @mention. [`escape`] makes such text render literally.
+
+/// marks that change rendering anywhere in a line
+const INLINE: &[char] = &['\\', '`', '*', '_', '[', ']', '(', ')', '!', '|', '~', '#'];
+
+/// zero width space, breaks mentions and auto linked urls without being visible
+const BREAK: char = '\u{200B}';
+
+/// Escape `text` for a markdown message, it renders exactly as typed
+///
+/// Markdown marks are backslash escaped, html is entity encoded, list and quote markers at line
+/// starts are neutralized and mentions and urls are broken up with [`escape_mentions`].
+pub fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for (i, line) in escape_mentions(text).split('\n').enumerate() {
+        if i > 0 {
+            result.push('\n');
+        }
+        let body = line.trim_start();
+        result.push_str(&line[..line.len() - body.len()]);
+        let marker = block_marker(body);
+        for (at, c) in body.char_indices() {
+            match c {
+                '<' => result.push_str("&lt;"),
+                '>' => result.push_str("&gt;"),
+                '&' => result.push_str("&amp;"),
+                c if INLINE.contains(&c) || Some(at) == marker => {
+                    result.push('\\');
+                    result.push(c);
+                }
+                c => result.push(c),
+            }
+        }
+    }
+    result
+}
+
+/// Break up `@name` mentions and `scheme://` urls, suitable for plain text messages
+pub fn escape_mentions(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        result.push(c);
+        match c {
+            '@' => result.push(BREAK),
+            ':' if chars.as_str().starts_with("//") => result.push(BREAK),
+            _ => {}
+        }
+    }
+    result
+}
+
+/// byte offset of the char making `line` a list item, `-`, `+` or the dot of `1.`
+///
+/// `>` starting a quote is already html encoded.
+fn block_marker(line: &str) -> Option<usize> {
+    if line.starts_with(['-', '+']) {
+        return Some(0);
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    (digits > 0 && line[digits..].starts_with(['.', ')'])).then_some(digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::up::MessageTemplate;
+
+    #[test]
+    fn escape_inline_marks() {
+        for (text, escaped) in [
+            ("*bold*", r"\*bold\*"),
+            ("_italic_", r"\_italic\_"),
+            ("[link](x)", r"\[link\]\(x\)"),
+            ("![img](x)", r"\!\[img\]\(x\)"),
+            ("# title", r"\# title"),
+            ("`code`", r"\`code\`"),
+            ("~~gone~~ | cell", r"\~\~gone\~\~ \| cell"),
+            (r"back\slash", r"back\\slash"),
+            ("plain text 中文", "plain text 中文"),
+        ] {
+            assert_eq!(escape(text), escaped, "{text}");
+        }
+    }
+
+    #[test]
+    fn escape_html() {
+        assert_eq!(escape("<b>a & b</b>"), "&lt;b&gt;a &amp; b&lt;/b&gt;");
+        assert_eq!(escape("> quote"), "&gt; quote");
+    }
+
+    #[test]
+    fn escape_block_markers_at_line_start() {
+        for (text, escaped) in [
+            ("- item", r"\- item"),
+            ("+ item", r"\+ item"),
+            ("1. first", r"1\. first"),
+            ("12) twelfth", r"12\) twelfth"),
+            ("  - nested", r"  \- nested"),
+            ("a - b + c", "a - b + c"),
+            ("2024 was a year", "2024 was a year"),
+            ("score\n-1 point", "score\n\\-1 point"),
+        ] {
+            assert_eq!(escape(text), escaped, "{text}");
+        }
+    }
+
+    #[test]
+    fn mentions_and_urls_are_broken_up() {
+        assert_eq!(escape_mentions("@all hi"), "@\u{200B}all hi");
+        assert_eq!(
+            escape_mentions("see https://example.com"),
+            "see https:\u{200B}//example.com"
+        );
+        assert_eq!(escape_mentions("time 10:30"), "time 10:30");
+        assert_eq!(escape("@manager7421"), "@\u{200B}manager7421");
+    }
+
+    #[test]
+    fn sanitized_escapes_text_only() {
+        let link = MessageTemplate::SampleLink {
+            text: "@all *now*".to_owned(),
+            title: "@all".to_owned(),
+            pic_url: "https://example.com/@a.png".to_owned(),
+            message_url: "https://example.com/join?team=red".to_owned(),
+        };
+        assert_eq!(
+            link.sanitized(),
+            MessageTemplate::SampleLink {
+                text: "@\u{200B}all *now*".to_owned(),
+                title: "@\u{200B}all".to_owned(),
+                pic_url: "https://example.com/@a.png".to_owned(),
+                message_url: "https://example.com/join?team=red".to_owned(),
+            }
+        );
+
+        let card = MessageTemplate::SampleActionCard {
+            title: "round 1".to_owned(),
+            text: "**win** @all".to_owned(),
+            single_title: "open".to_owned(),
+            single_url: "dingtalk://dingtalkclient/page/link?url=x".to_owned(),
+        };
+        assert_eq!(
+            card.sanitized(),
+            MessageTemplate::SampleActionCard {
+                title: "round 1".to_owned(),
+                text: "\\*\\*win\\*\\* @\u{200B}all".to_owned(),
+                single_title: "open".to_owned(),
+                single_url: "dingtalk://dingtalkclient/page/link?url=x".to_owned(),
+            }
+        );
+    }
+
+    #[test]
+    fn sanitized_leaves_media_alone() {
+        for template in [
+            MessageTemplate::SampleImageMsg {
+                photo_url: "https://example.com/@a_b.png".to_owned(),
+            },
+            MessageTemplate::SampleFile {
+                media_id: "@lAzPDe7s_x".to_owned(),
+                file_name: "a_b.txt".to_owned(),
+                file_type: "txt".to_owned(),
+            },
+            MessageTemplate::SampleAudio {
+                media_id: "@lAzPDe7s_y".to_owned(),
+                duration: "3000".to_owned(),
+            },
+        ] {
+            assert_eq!(template.clone().sanitized(), template);
+        }
+    }
+}
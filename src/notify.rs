@@ -0,0 +1,786 @@
+//! One call to alert the team, routed and styled by severity
+//!
+//! [`Notifier::notify`] picks the conversations configured for a [`Severity`] in
+//! [`Notifications`], renders a markdown message colored by severity and queues it:
+//!
+//! ```ignore
+//! app.add_plugins(
+//!     StreamDingTalkPlugin::new(CLIENT_ID, CLIENT_SECRET)
+//!         .ops_conversation(OPS)
+//!         .notifications(Notifications::new().route(Severity::Critical, ONCALL)),
+//! );
+//!
+//! fn watch(notifier: Res<Notifier>, lag: Res<Lag>) {
+//!     if lag.0 > 500 {
+//!         notifier.notify(Severity::Warn, format!("tick lag {} ms", lag.0));
+//!     }
+//! }
+//! ```
+//!
+//! Info and warn notices wait for the end of [quiet hours](crate::client::Client::quiet_hours),
+//! critical ones go out at once. Each severity has a minimum interval between messages, notices
+//! in between are dropped and counted in the next message.
+//!
+//! Notices with the same fingerprint, the text unless given with [`Notifier::notify_keyed`],
+//! collapse into one [`Incident`] for the [dedup window](Notifications::dedup_window), repeats
+//! only raise its counter. With an [escalation](Notifications::escalate) an incident nobody
+//! acknowledged in time by the [`Clock`](crate::clock::Clock) is sent one-on-one to the named
+//! staff, pending escalations are stored and survive a restart. Acknowledge it with
+//! [`Notifier::acknowledge`], by sending `ack <id>` to the robot or, with an
+//! [ack card](Notifications::ack_card), through its button. Every acknowledgment arrives as an
+//! [`AlertAcknowledgedEvent`].
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use tokio::runtime;
+
+use serde_json::{Map, Value};
+
+use crate::client::card::InteractiveCard;
+use crate::client::down::MsgContent;
+use crate::client::up::{MessageTemplate, RobotSendMessage};
+use crate::client::Client;
+use crate::error::ErrorContext;
+use crate::event::{AlertAcknowledgedEvent, CardActionEvent, RobotMessageReceived};
+use crate::param::DingTalk;
+use crate::storage::ESCALATIONS;
+
+/// action id of the button of an [ack card](Notifications::ack_card)
+pub const ACK_ACTION: &str = "acknowledge";
+
+/// How loud a notice is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Severity {
+    Info,
+    Warn,
+    Critical,
+}
+
+impl Severity {
+    pub const ALL: [Severity; 3] = [Severity::Info, Severity::Warn, Severity::Critical];
+
+    /// font color of the title
+    pub fn color(&self) -> &'static str {
+        match self {
+            Severity::Info => "#1E90FF",
+            Severity::Warn => "#FF8C00",
+            Severity::Critical => "#FF0000",
+        }
+    }
+
+    /// sent during quiet hours
+    pub fn is_urgent(&self) -> bool {
+        *self == Severity::Critical
+    }
+
+    /// default minimum interval between two messages
+    fn default_interval(&self) -> Duration {
+        match self {
+            Severity::Info => Duration::from_secs(300),
+            Severity::Warn => Duration::from_secs(60),
+            Severity::Critical => Duration::from_secs(10),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Info => "INFO",
+            Severity::Warn => "WARN",
+            Severity::Critical => "CRITICAL",
+        })
+    }
+}
+
+/// Where and how often notices of each severity are sent
+///
+/// A severity without routes goes to the
+/// [ops conversation](crate::prelude::StreamDingTalkPlugin::ops_conversation).
+#[derive(Debug, Clone)]
+pub struct Notifications {
+    /// title of the messages, after the severity
+    pub title: String,
+    /// line opening critical messages, default `@all`
+    ///
+    /// Robot group sends cannot mention, the line is a visual call-out only.
+    pub critical_mention: Option<String>,
+    /// time repeats of a fingerprint collapse into its incident, default 10 minutes
+    pub dedup_window: Duration,
+    /// word before the incident id in acknowledging messages, default `ack`
+    pub ack_keyword: String,
+    /// card template alerts are sent with instead of markdown, see [`Notifications::ack_card`]
+    pub ack_card: Option<String>,
+    routes: HashMap<Severity, Vec<String>>,
+    intervals: HashMap<Severity, Duration>,
+    escalations: HashMap<Severity, Escalation>,
+}
+
+/// Staff paged when an incident stays unacknowledged
+#[derive(Debug, Clone)]
+pub struct Escalation {
+    pub after: Duration,
+    pub staff_ids: Vec<String>,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Notifications {
+    pub fn new() -> Self {
+        Self {
+            title: "Notice".to_owned(),
+            critical_mention: Some("@all".to_owned()),
+            dedup_window: Duration::from_secs(600),
+            ack_keyword: "ack".to_owned(),
+            ack_card: None,
+            routes: HashMap::new(),
+            intervals: HashMap::new(),
+            escalations: HashMap::new(),
+        }
+    }
+
+    /// also send notices of `severity` to `conversation_id`
+    pub fn route(mut self, severity: Severity, conversation_id: impl Into<String>) -> Self {
+        self.routes
+            .entry(severity)
+            .or_default()
+            .push(conversation_id.into());
+        self
+    }
+
+    /// send notices of `severity` at most once per `interval`, zero sends every one
+    ///
+    /// Defaults are 5 minutes for info, 1 minute for warn and 10 seconds for critical.
+    pub fn min_interval(mut self, severity: Severity, interval: Duration) -> Self {
+        self.intervals.insert(severity, interval);
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+
+    /// collapse repeats of a fingerprint for `window`, zero opens an incident for every notice
+    pub fn dedup_window(mut self, window: Duration) -> Self {
+        self.dedup_window = window;
+        self
+    }
+
+    /// Send incidents of `severity` still unacknowledged `after` their notice one-on-one to
+    /// `staff_ids`
+    ///
+    /// Robot group sends cannot mention, so the escalation reaches the staff directly.
+    pub fn escalate(
+        mut self,
+        severity: Severity,
+        after: Duration,
+        staff_ids: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.escalations.insert(
+            severity,
+            Escalation {
+                after,
+                staff_ids: staff_ids.into_iter().map(Into::into).collect(),
+            },
+        );
+        self
+    }
+
+    pub fn escalation_of(&self, severity: Severity) -> Option<&Escalation> {
+        self.escalations.get(&severity)
+    }
+
+    /// acknowledge with `<keyword> <id>` instead of `ack <id>`
+    pub fn ack_keyword(mut self, keyword: impl Into<String>) -> Self {
+        self.ack_keyword = keyword.into();
+        self
+    }
+
+    /// Send alerts as cards of `template_id` carrying an acknowledge button
+    ///
+    /// The template gets the variables `title`, `text` as markdown, `color` and `alert_id`. Its
+    /// button has the action id [`ACK_ACTION`] and passes `alert_id` as a parameter. Once
+    /// acknowledged the card is updated with `acknowledged = "true"` and `acknowledged_by`.
+    pub fn ack_card(mut self, template_id: impl Into<String>) -> Self {
+        self.ack_card = Some(template_id.into());
+        self
+    }
+
+    /// `None` opens critical messages with the title like the others
+    pub fn critical_mention(mut self, mention: Option<String>) -> Self {
+        self.critical_mention = mention;
+        self
+    }
+
+    /// conversations of `severity`, `fallback` when none are routed
+    pub fn routes_of(&self, severity: Severity, fallback: Option<&str>) -> Vec<String> {
+        match self.routes.get(&severity) {
+            Some(routes) if !routes.is_empty() => routes.clone(),
+            _ => fallback.map(str::to_owned).into_iter().collect(),
+        }
+    }
+
+    pub fn interval_of(&self, severity: Severity) -> Duration {
+        self.intervals
+            .get(&severity)
+            .copied()
+            .unwrap_or_else(|| severity.default_interval())
+    }
+
+    /// markdown message of a notice, `notes` are added as italic lines
+    pub fn render(&self, severity: Severity, text: &str, notes: &[String]) -> MessageTemplate {
+        let title = format!("[{severity}] {}", self.title);
+        let mut body = String::new();
+        if let Some(mention) = self
+            .critical_mention
+            .as_ref()
+            .filter(|_| severity.is_urgent())
+        {
+            body.push_str(&format!("**{mention}**\n\n"));
+        }
+        body.push_str(&format!(
+            "#### <font color={}>{title}</font>\n\n{text}\n",
+            severity.color()
+        ));
+        for note in notes {
+            body.push_str(&format!("\n*{note}*\n"));
+        }
+        MessageTemplate::SampleMarkdown { title, text: body }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Limit {
+    last: Option<Instant>,
+    suppressed: usize,
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{count} {noun}")
+    } else {
+        format!("{count} {noun}s")
+    }
+}
+
+/// Notices sharing a fingerprint within the dedup window
+#[derive(Debug, Clone)]
+pub struct Incident {
+    /// what `ack` takes
+    pub id: u64,
+    pub severity: Severity,
+    /// notices collapsed into this incident, the first one included
+    pub count: usize,
+    pub opened_at: DateTime<Local>,
+    /// staff id of who acknowledged it
+    pub acknowledged_by: Option<String>,
+    opened: Instant,
+    /// kept past its window until the escalation ran
+    escalating: bool,
+    /// out track ids of its ack cards
+    cards: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Incidents {
+    next_id: u64,
+    by_fingerprint: HashMap<String, Incident>,
+}
+
+impl Incidents {
+    fn by_id(&mut self, id: u64) -> Option<&mut Incident> {
+        self.by_fingerprint.values_mut().find(|i| i.id == id)
+    }
+}
+
+/// An escalation waiting for its deadline, stored under [`ESCALATIONS`]
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingEscalation {
+    id: u64,
+    severity: Severity,
+    text: String,
+    /// unix time in milliseconds it is sent at
+    due: i64,
+}
+
+/// storage key of the escalation of incident `id`, ordered by id
+fn escalation_key(id: u64) -> String {
+    format!("{id:020}")
+}
+
+/// Sends notices by severity, inserted by [`StreamDingTalkPlugin`](crate::plugin::StreamDingTalkPlugin)
+///
+/// Cheap to clone, clones share the rate limits.
+#[derive(Resource, Clone)]
+pub struct Notifier {
+    client: Arc<Client>,
+    runtime: runtime::Handle,
+    notifications: Arc<Notifications>,
+    limits: Arc<Mutex<HashMap<Severity, Limit>>>,
+    incidents: Arc<Mutex<Incidents>>,
+}
+
+impl Notifier {
+    pub(crate) fn new(
+        client: Arc<Client>,
+        runtime: runtime::Handle,
+        notifications: Notifications,
+    ) -> Self {
+        // ids of incidents still escalating from before a restart stay taken
+        let next_id = client
+            .store()
+            .scan(ESCALATIONS, "")
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|(key, _)| key.parse().ok())
+            .max()
+            .unwrap_or_default();
+        Self {
+            client,
+            runtime,
+            notifications: Arc::new(notifications),
+            limits: Default::default(),
+            incidents: Arc::new(Mutex::new(Incidents {
+                next_id,
+                ..Default::default()
+            })),
+        }
+    }
+
+    pub fn notifications(&self) -> &Notifications {
+        &self.notifications
+    }
+
+    /// Send `text` as markdown to the conversations of `severity`
+    ///
+    /// Returns how many conversations were addressed, 0 when collapsed into an open incident,
+    /// rate limited or nothing is routed.
+    pub fn notify(&self, severity: Severity, text: impl Into<String>) -> usize {
+        let text = text.into();
+        self.notify_keyed(text.clone(), severity, text)
+    }
+
+    /// [`notify`](Self::notify) collapsing by `fingerprint` instead of the text, e.g.
+    /// `"lag:eu-1"` for a text carrying the changing lag
+    pub fn notify_keyed(
+        &self,
+        fingerprint: impl Into<String>,
+        severity: Severity,
+        text: impl Into<String>,
+    ) -> usize {
+        let fingerprint = fingerprint.into();
+        if self.collapse(&fingerprint) {
+            debug!(
+                "{} notice collapsed into incident {}",
+                severity, fingerprint
+            );
+            return 0;
+        }
+        let ops = self.client.config.lock().unwrap().ops_conversation.clone();
+        let targets = self.notifications.routes_of(severity, ops.as_deref());
+        if targets.is_empty() {
+            warn!("{} notice dropped, no conversation is routed", severity);
+            return 0;
+        }
+        let Some(suppressed) = self.admit(severity) else {
+            debug!("{} notice suppressed by its minimum interval", severity);
+            return 0;
+        };
+
+        let escalation = self.notifications.escalation_of(severity).cloned();
+        let incident = self.open(fingerprint, severity, escalation.is_some());
+        let mut notes = vec![];
+        if suppressed > 0 {
+            notes.push(format!(
+                "{} suppressed",
+                plural(suppressed, "earlier notice")
+            ));
+        }
+        if let Some(escalation) = escalation.as_ref().filter(|_| !self.uses_cards()) {
+            notes.push(format!(
+                "incident {}, send `{} {}` to the robot within {} minutes",
+                incident.id,
+                self.notifications.ack_keyword,
+                incident.id,
+                escalation.after.as_secs().div_ceil(60)
+            ));
+        }
+        let text = text.into();
+        let message = self.notifications.render(severity, &text, &notes);
+        if let Some(escalation) = escalation {
+            self.schedule_escalation(incident.id, severity, text, escalation.after);
+        }
+        for conversation_id in &targets {
+            match &self.notifications.ack_card {
+                Some(template_id) => {
+                    let card = self.card(template_id, incident.id, severity, &message);
+                    self.send_card(incident.id, conversation_id.clone(), severity, card)
+                }
+                None => self.send_markdown(conversation_id.clone(), severity, message.clone()),
+            }
+        }
+        targets.len()
+    }
+
+    fn uses_cards(&self) -> bool {
+        self.notifications.ack_card.is_some()
+    }
+
+    fn send_markdown(&self, conversation_id: String, severity: Severity, message: MessageTemplate) {
+        let client = self.client.clone();
+        self.runtime.spawn(async move {
+            let sending = RobotSendMessage::group(client.clone(), &conversation_id, message);
+            let result = match sending {
+                Ok(msg) => msg.urgent(severity.is_urgent()).send().await.map(drop),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                client.report_error(
+                    ErrorContext::Send,
+                    format!("send {severity} notice to {conversation_id}"),
+                    &e,
+                );
+            }
+        });
+    }
+
+    /// ack card of incident `id` showing the rendered `message`
+    fn card(
+        &self,
+        template_id: &str,
+        id: u64,
+        severity: Severity,
+        message: &MessageTemplate,
+    ) -> InteractiveCard {
+        let mut card = InteractiveCard::new(template_id)
+            .param("color", severity.color())
+            .param("alert_id", id.to_string());
+        if let MessageTemplate::SampleMarkdown { title, text } = message {
+            card = card.param("title", title).param("text", text);
+        }
+        card
+    }
+
+    /// send an ack card, held until the end of quiet hours unless `severity` is urgent
+    fn send_card(
+        &self,
+        id: u64,
+        conversation_id: String,
+        severity: Severity,
+        card: InteractiveCard,
+    ) {
+        if let Some(incident) = self.incidents.lock().unwrap().by_id(id) {
+            incident.cards.push(card.out_track_id.clone());
+        }
+        let client = self.client.clone();
+        if !severity.is_urgent() && client.quiet_for(&conversation_id).is_some() {
+            if let Err(e) = client.hold_card(&conversation_id, &card) {
+                client.report_error(
+                    ErrorContext::Send,
+                    format!("hold {severity} card to {conversation_id}"),
+                    &e,
+                );
+            }
+            return;
+        }
+        self.runtime.spawn(async move {
+            if let Err(e) = client.send_card(&conversation_id, &card).await {
+                client.report_error(
+                    ErrorContext::Send,
+                    format!("send {severity} card to {conversation_id}"),
+                    &e,
+                );
+            }
+        });
+    }
+
+    /// Acknowledge incident `id`, stopping its escalation and sending an
+    /// [`AlertAcknowledgedEvent`]
+    ///
+    /// Returns false when no open incident has the id or it was already acknowledged.
+    pub fn acknowledge(&self, id: u64, by: impl Into<String>) -> bool {
+        let by = by.into();
+        let store = self.client.store();
+        let key = escalation_key(id);
+        let escalating = matches!(store.get(ESCALATIONS, &key), Ok(Some(_)));
+        let cards = {
+            let mut incidents = self.incidents.lock().unwrap();
+            match incidents.by_id(id) {
+                Some(incident) if incident.acknowledged_by.is_none() => {
+                    incident.acknowledged_by = Some(by.clone());
+                    incident.cards.clone()
+                }
+                // opened before a restart, only its escalation is left
+                None if escalating => vec![],
+                _ => return false,
+            }
+        };
+        if escalating {
+            if let Err(e) = store.remove(ESCALATIONS, &key) {
+                warn!("remove escalation of incident {} error: {:?}", id, e);
+            }
+        }
+        info!("incident {} acknowledged by {}", id, by);
+        if !cards.is_empty() {
+            let mut params = Map::new();
+            params.insert("acknowledged".to_owned(), Value::String("true".to_owned()));
+            params.insert("acknowledged_by".to_owned(), Value::String(by.clone()));
+            let client = self.client.clone();
+            self.runtime.spawn(async move {
+                for out_track_id in cards {
+                    if let Err(e) = client.update_card(&out_track_id, &params).await {
+                        client.report_error(
+                            ErrorContext::Send,
+                            format!("mark card {out_track_id} acknowledged"),
+                            &e,
+                        );
+                    }
+                }
+            });
+        }
+        self.client
+            .bridge
+            .send_event(AlertAcknowledgedEvent { alert_id: id, by });
+        true
+    }
+
+    /// the incident of `fingerprint` while its dedup window is open
+    pub fn incident(&self, fingerprint: &str) -> Option<Incident> {
+        let now = self.client.current_clock().instant();
+        let window = self.notifications.dedup_window;
+        self.incidents
+            .lock()
+            .unwrap()
+            .by_fingerprint
+            .get(fingerprint)
+            .filter(|i| now.duration_since(i.opened) < window)
+            .cloned()
+    }
+
+    /// count a repeat of an open incident, false when there is none
+    fn collapse(&self, fingerprint: &str) -> bool {
+        let now = self.client.current_clock().instant();
+        let window = self.notifications.dedup_window;
+        let mut incidents = self.incidents.lock().unwrap();
+        match incidents.by_fingerprint.get_mut(fingerprint) {
+            Some(incident) if now.duration_since(incident.opened) < window => {
+                incident.count += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// new incident of `fingerprint`, dropping those whose window ended
+    fn open(&self, fingerprint: String, severity: Severity, escalating: bool) -> Incident {
+        let clock = self.client.current_clock();
+        let now = clock.instant();
+        let window = self.notifications.dedup_window;
+        let mut incidents = self.incidents.lock().unwrap();
+        incidents
+            .by_fingerprint
+            .retain(|_, i| i.escalating || now.duration_since(i.opened) < window);
+        incidents.next_id += 1;
+        let incident = Incident {
+            id: incidents.next_id,
+            severity,
+            count: 1,
+            opened_at: clock.local(),
+            acknowledged_by: None,
+            opened: now,
+            escalating,
+            cards: vec![],
+        };
+        let replaced = incidents
+            .by_fingerprint
+            .insert(fingerprint, incident.clone());
+        // the new incident escalates instead
+        if let Some(replaced) = replaced.filter(|i| i.escalating) {
+            let key = escalation_key(replaced.id);
+            if let Err(e) = self.client.store().remove(ESCALATIONS, &key) {
+                warn!(
+                    "remove escalation of incident {} error: {:?}",
+                    replaced.id, e
+                );
+            }
+        }
+        incident
+    }
+
+    /// store the escalation of incident `id`, due `after` now by the client's clock
+    fn schedule_escalation(&self, id: u64, severity: Severity, text: String, after: Duration) {
+        let pending = PendingEscalation {
+            id,
+            severity,
+            text,
+            due: (self.client.current_clock().now()
+                + chrono::Duration::from_std(after).unwrap_or(chrono::Duration::MAX))
+            .timestamp_millis(),
+        };
+        let stored = self
+            .client
+            .store()
+            .put_json(ESCALATIONS, &escalation_key(id), &pending);
+        if let Err(e) = stored {
+            self.client.report_error(
+                ErrorContext::Send,
+                format!("store escalation of incident {id}"),
+                &e,
+            );
+        }
+    }
+
+    /// Page the staff of the incidents still unacknowledged at their deadline by the
+    /// [`Clock`](crate::clock::Clock), returns how many were escalated
+    ///
+    /// [`StreamDingTalkPlugin`](crate::plugin::StreamDingTalkPlugin) calls this every second.
+    pub fn escalate_due(&self) -> usize {
+        let store = self.client.store();
+        let pending = match store.scan(ESCALATIONS, "") {
+            Ok(pending) => pending,
+            Err(e) => {
+                warn!("read {} store error: {:?}", ESCALATIONS, e);
+                return 0;
+            }
+        };
+        let now = self.client.current_clock().now().timestamp_millis();
+        let mut escalated = 0;
+        for (key, value) in pending {
+            let pending = match serde_json::from_slice::<PendingEscalation>(&value) {
+                Ok(pending) if pending.due > now => continue,
+                Ok(pending) => Some(pending),
+                Err(e) => {
+                    warn!("drop unreadable escalation {}: {:?}", key, e);
+                    None
+                }
+            };
+            if let Err(e) = store.remove(ESCALATIONS, &key) {
+                warn!("remove escalation {} error: {:?}", key, e);
+            }
+            if pending.is_some_and(|pending| self.escalate(pending)) {
+                escalated += 1;
+            }
+        }
+        escalated
+    }
+
+    /// send `pending` to its staff unless its incident was acknowledged
+    fn escalate(&self, pending: PendingEscalation) -> bool {
+        let PendingEscalation {
+            id, severity, text, ..
+        } = pending;
+        let count = {
+            let mut incidents = self.incidents.lock().unwrap();
+            match incidents.by_id(id) {
+                Some(incident) if incident.acknowledged_by.is_some() => return false,
+                Some(incident) => {
+                    incident.escalating = false;
+                    Some(incident.count)
+                }
+                // opened before a restart
+                None => None,
+            }
+        };
+        let Some(escalation) = self.notifications.escalation_of(severity) else {
+            debug!("{} incidents no longer escalate, {} dropped", severity, id);
+            return false;
+        };
+        let notes = [
+            match count {
+                Some(count) => format!(
+                    "incident {id} unacknowledged, seen {}",
+                    plural(count, "time")
+                ),
+                None => format!("incident {id} unacknowledged"),
+            },
+            format!(
+                "send `{} {id}` to the robot to acknowledge",
+                self.notifications.ack_keyword
+            ),
+        ];
+        let message = self.notifications.render(severity, &text, &notes);
+        let client = self.client.clone();
+        let staff_ids = escalation.staff_ids.clone();
+        self.runtime.spawn(async move {
+            let sending = RobotSendMessage::batch(client.clone(), staff_ids, message);
+            let result = match sending {
+                Ok(msg) => msg.urgent(true).send().await.map(drop),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                client.report_error(ErrorContext::Send, format!("escalate incident {id}"), &e);
+            }
+        });
+        true
+    }
+
+    /// count of notices dropped before this one, `None` when this one is dropped too
+    fn admit(&self, severity: Severity) -> Option<usize> {
+        let now = self.client.current_clock().instant();
+        let interval = self.notifications.interval_of(severity);
+        let mut limits = self.limits.lock().unwrap();
+        let limit = limits.entry(severity).or_default();
+        if limit
+            .last
+            .is_some_and(|last| now.duration_since(last) < interval)
+        {
+            limit.suppressed += 1;
+            return None;
+        }
+        limit.last = Some(now);
+        Some(std::mem::take(&mut limit.suppressed))
+    }
+}
+
+/// page the staff of incidents unacknowledged past their deadline
+pub(crate) fn escalate_incidents(notifier: Res<Notifier>) {
+    notifier.escalate_due();
+}
+
+/// `ack <id>` in a message to the robot or a click on an ack card acknowledges an incident
+pub(crate) fn acknowledge_incidents(
+    mut messages: EventReader<RobotMessageReceived>,
+    mut clicks: EventReader<CardActionEvent>,
+    notifier: Res<Notifier>,
+    dingtalk: DingTalk,
+) {
+    let keyword = &notifier.notifications.ack_keyword;
+    for event in messages.read() {
+        let MsgContent::Text { content } = &event.message.content else {
+            continue;
+        };
+        let Some(id) = content
+            .trim()
+            .split_once(char::is_whitespace)
+            .filter(|(word, _)| word.eq_ignore_ascii_case(keyword))
+            .and_then(|(_, id)| id.trim().parse().ok())
+        else {
+            continue;
+        };
+        let answer = if notifier.acknowledge(id, &event.message.sender_staff_id) {
+            format!("incident {id} acknowledged")
+        } else {
+            format!("no open incident {id}")
+        };
+        dingtalk.reply(
+            &event.message,
+            MessageTemplate::SampleText { content: answer },
+        );
+    }
+    for event in clicks.read().filter(|e| e.action_id == ACK_ACTION) {
+        if let Some(id) = event.param("alert_id").and_then(|id| id.parse().ok()) {
+            notifier.acknowledge(id, &event.user.user_id);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+//! Outbound queue drained by a task on the [`AsyncRuntime`](crate::client::AsyncRuntime)
+
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use bevy::log::error;
+use bevy::prelude::Resource;
+use futures::Future;
+use serde_json::{Map, Value};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::client::assistant::GraphResponse;
+use crate::client::bundle::MessageBundle;
+use crate::client::card::InteractiveCard;
+use crate::client::down::RobotRecvMessage;
+use crate::client::prompt::{Prompt, PromptOutcome};
+use crate::client::tenant::TenantId;
+use crate::client::up::{MessageTemplate, RobotSendMessage, UploadType};
+use crate::client::Client;
+use crate::error::ErrorContext;
+use crate::event::{
+    CreateGroupRequest, CreateGroupResult, MediaUploaded, PromptAnswered, PromptExpired,
+    UserAuthenticatedEvent,
+};
+
+/// Work items queued by systems, processed in order
+#[derive(Debug)]
+pub(crate) enum Outbound {
+    Group {
+        tenant: Option<TenantId>,
+        conversation_id: String,
+        message: MessageTemplate,
+        idempotency_key: String,
+        urgent: bool,
+    },
+    Upload {
+        path: PathBuf,
+        file_type: UploadType,
+    },
+    Reply {
+        message: Box<RobotRecvMessage>,
+        template: MessageTemplate,
+    },
+    SkillResponse {
+        request_id: String,
+        response: GraphResponse,
+    },
+    Authenticate {
+        code: String,
+        state: String,
+    },
+    Prompt(Prompt),
+    Bundle {
+        conversation_id: String,
+        bundle: MessageBundle,
+    },
+    Card {
+        conversation_id: String,
+        card: InteractiveCard,
+    },
+    UpdateCard {
+        out_track_id: String,
+        params: Map<String, Value>,
+    },
+    /// several cards updated concurrently, see [`Client::update_cards_bulk`]
+    UpdateCards(Vec<(String, Map<String, Value>)>),
+    CreateGroup(CreateGroupRequest),
+}
+
+#[derive(Debug, Resource, Clone)]
+pub(crate) struct OutboundQueue {
+    tx: UnboundedSender<Outbound>,
+    /// items the worker has not picked up yet
+    pending: Arc<AtomicUsize>,
+}
+
+impl OutboundQueue {
+    /// queue and the worker draining it, the worker must be spawned on the runtime
+    pub fn new(client: Arc<Client>) -> (Self, impl Future<Output = ()>) {
+        let (tx, rx) = unbounded_channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let worker = run(client, rx, pending.clone());
+        (Self { tx, pending }, worker)
+    }
+
+    pub fn push(&self, item: Outbound) {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        if self.tx.send(item).is_err() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            error!("outbound worker stopped, message dropped");
+        }
+    }
+
+    /// items waiting for the worker
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+async fn run(client: Arc<Client>, mut rx: UnboundedReceiver<Outbound>, pending: Arc<AtomicUsize>) {
+    while let Some(item) = rx.recv().await {
+        pending.fetch_sub(1, Ordering::Relaxed);
+        match item {
+            Outbound::Group {
+                tenant,
+                conversation_id,
+                message,
+                idempotency_key,
+                urgent,
+            } => {
+                let mut msg =
+                    match RobotSendMessage::group(client.clone(), conversation_id, message) {
+                        Ok(msg) => msg.idempotency_key(idempotency_key).urgent(urgent),
+                        Err(e) => {
+                            client.report_error(ErrorContext::Send, "send queued message", &e);
+                            continue;
+                        }
+                    };
+                if let Some(tenant) = tenant {
+                    msg = msg.in_tenant(tenant);
+                }
+                if let Err(e) = msg.send().await {
+                    client.report_error(ErrorContext::Send, "send queued message", &e);
+                }
+            }
+            Outbound::Reply { message, template } => {
+                if let Err(e) = message.reply(&client, template).await {
+                    client.report_error(
+                        ErrorContext::Send,
+                        format!("reply to {}", message.msg_id),
+                        &e,
+                    );
+                }
+            }
+            Outbound::SkillResponse {
+                request_id,
+                response,
+            } => {
+                if let Err(e) = client.respond_skill(&request_id, response).await {
+                    client.report_error(
+                        ErrorContext::Send,
+                        format!("respond skill {request_id}"),
+                        &e,
+                    );
+                }
+            }
+            Outbound::Authenticate { code, state } => match client.authenticate_user(&code).await {
+                Ok((user, token)) => {
+                    client
+                        .bridge
+                        .send_event(UserAuthenticatedEvent { state, user, token })
+                }
+                Err(e) => client.report_error(
+                    ErrorContext::Send,
+                    format!("authenticate user for {state}"),
+                    &e,
+                ),
+            },
+            Outbound::Bundle {
+                conversation_id,
+                bundle,
+            } => {
+                if let Err(e) = client.send_bundle(&conversation_id, bundle).await {
+                    client.report_error(
+                        ErrorContext::Send,
+                        format!("send bundle to {conversation_id}"),
+                        &e,
+                    );
+                }
+            }
+            Outbound::Card {
+                conversation_id,
+                card,
+            } => {
+                if let Err(e) = client.send_card(&conversation_id, &card).await {
+                    client.report_error(
+                        ErrorContext::Send,
+                        format!("send card {}", card.out_track_id),
+                        &e,
+                    );
+                }
+            }
+            Outbound::UpdateCard {
+                out_track_id,
+                params,
+            } => {
+                if let Err(e) = client.update_card(&out_track_id, &params).await {
+                    client.report_error(
+                        ErrorContext::Send,
+                        format!("update card {out_track_id}"),
+                        &e,
+                    );
+                }
+            }
+            Outbound::UpdateCards(updates) => {
+                for (out_track_id, e) in client.update_cards_bulk(updates).await.failed {
+                    client.report_error(
+                        ErrorContext::Send,
+                        format!("update card {out_track_id}"),
+                        &e,
+                    );
+                }
+            }
+            Outbound::CreateGroup(request) => {
+                let result = client
+                    .create_scene_group(
+                        &request.name,
+                        &request.owner,
+                        &request.members,
+                        &request.template_id,
+                    )
+                    .await;
+                if let Err(e) = &result {
+                    client.error_event(
+                        ErrorContext::Send,
+                        format!("create group {}", request.name),
+                        e,
+                    );
+                }
+                client
+                    .bridge
+                    .send_event(CreateGroupResult { request, result });
+            }
+            Outbound::Prompt(prompt) => {
+                // the card is sent in order, waiting for the answer must not hold the queue
+                let answer = match client.open_prompt(&prompt).await {
+                    Ok(answer) => answer,
+                    Err(e) => {
+                        client.report_error(
+                            ErrorContext::Send,
+                            format!("send prompt {}", prompt.out_track_id()),
+                            &e,
+                        );
+                        continue;
+                    }
+                };
+                let client = client.clone();
+                tokio::spawn(async move {
+                    let conversation_id = prompt.conversation_id.clone();
+                    match client.wait_prompt(prompt.clone(), answer).await {
+                        PromptOutcome::Answered(event) => {
+                            client.bridge.send_event(PromptAnswered {
+                                conversation_id,
+                                event: *event,
+                            })
+                        }
+                        PromptOutcome::Expired => {
+                            client.bridge.send_event(PromptExpired { prompt })
+                        }
+                    }
+                });
+            }
+            Outbound::Upload { path, file_type } => {
+                let result = client.upload(&path, file_type).await;
+                if let Err(e) = &result {
+                    client.error_event(ErrorContext::Send, format!("upload {}", path.display()), e);
+                }
+                client.bridge.send_event(MediaUploaded {
+                    path,
+                    file_type,
+                    result,
+                });
+            }
+        }
+    }
+}
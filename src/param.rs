@@ -0,0 +1,177 @@
+//! [`SystemParam`] for talking to DingTalk from ordinary systems
+
+use std::path::PathBuf;
+
+use anyhow::Result;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::Res;
+
+use crate::client::assistant::GraphResponse;
+use crate::client::bundle::MessageBundle;
+use crate::client::down::RobotRecvMessage;
+use crate::client::prompt::Prompt;
+use crate::client::tenant::TenantId;
+use crate::client::up::{new_idempotency_key, MessageTemplate, UploadType};
+use crate::conversations::Conversations;
+use crate::outbound::{Outbound, OutboundQueue};
+
+/// Queue messages and uploads without touching [`AsyncRuntime`](crate::client::AsyncRuntime) or the
+/// client directly
+///
+/// Everything is sent in order by a background task; upload results come back as
+/// [`MediaUploaded`](crate::event::MediaUploaded) events.
+#[derive(SystemParam)]
+pub struct DingTalk<'w> {
+    queue: Res<'w, OutboundQueue>,
+    conversations: Res<'w, Conversations>,
+}
+
+impl DingTalk<'_> {
+    /// send any message template to a group chat
+    pub fn send(&self, conversation_id: impl Into<String>, message: MessageTemplate) {
+        self.queue.push(Outbound::Group {
+            tenant: None,
+            conversation_id: conversation_id.into(),
+            message,
+            idempotency_key: new_idempotency_key(),
+            urgent: false,
+        });
+    }
+
+    /// send to a group chat even during its [quiet hours](crate::client::Client::quiet_hours)
+    pub fn send_urgent(&self, conversation_id: impl Into<String>, message: MessageTemplate) {
+        self.queue.push(Outbound::Group {
+            tenant: None,
+            conversation_id: conversation_id.into(),
+            message,
+            idempotency_key: new_idempotency_key(),
+            urgent: true,
+        });
+    }
+
+    /// send to a group chat unless a send with the same `key` was already confirmed, even by an
+    /// earlier run of the game
+    pub fn send_once(
+        &self,
+        key: impl Into<String>,
+        conversation_id: impl Into<String>,
+        message: MessageTemplate,
+    ) {
+        self.queue.push(Outbound::Group {
+            tenant: None,
+            conversation_id: conversation_id.into(),
+            message,
+            idempotency_key: key.into(),
+            urgent: false,
+        });
+    }
+
+    /// send to a group chat of another corp, e.g. the `tenant` of a received event
+    pub fn send_in(
+        &self,
+        tenant: TenantId,
+        conversation_id: impl Into<String>,
+        message: MessageTemplate,
+    ) {
+        self.queue.push(Outbound::Group {
+            tenant: Some(tenant),
+            conversation_id: conversation_id.into(),
+            message,
+            idempotency_key: new_idempotency_key(),
+            urgent: false,
+        });
+    }
+
+    /// send `message` to every conversation whose tag `key` is `value`, see
+    /// [`Conversations::set_tag`], returns how many conversations were addressed
+    pub fn broadcast(&self, key: &str, value: &str, message: MessageTemplate) -> Result<usize> {
+        let targets = self.conversations.tagged(key, value)?;
+        for conversation_id in &targets {
+            self.send(conversation_id, message.clone());
+        }
+        Ok(targets.len())
+    }
+
+    /// tags of the conversations, to set them from a system
+    pub fn conversations(&self) -> &Conversations {
+        &self.conversations
+    }
+
+    /// send the parts of `bundle` in order to a group chat, see [`Client::send_bundle`](crate::client::Client::send_bundle)
+    pub fn send_bundle(&self, conversation_id: impl Into<String>, bundle: MessageBundle) {
+        self.queue.push(Outbound::Bundle {
+            conversation_id: conversation_id.into(),
+            bundle,
+        });
+    }
+
+    /// answer a received message in its own conversation
+    pub fn reply(&self, message: &RobotRecvMessage, template: MessageTemplate) {
+        self.queue.push(Outbound::Reply {
+            message: Box::new(message.clone()),
+            template,
+        });
+    }
+
+    /// answer an [`AssistantSkillInvoked`](crate::event::AssistantSkillInvoked) event
+    pub fn respond_skill(&self, request_id: impl Into<String>, response: GraphResponse) {
+        self.queue.push(Outbound::SkillResponse {
+            request_id: request_id.into(),
+            response,
+        });
+    }
+
+    /// finish a DingTalk login, the account arrives as a
+    /// [`UserAuthenticatedEvent`](crate::event::UserAuthenticatedEvent)
+    ///
+    /// `code` and `state` are the query parameters of the redirect, see
+    /// [`Client::login_url`](crate::client::Client::login_url).
+    pub fn authenticate_user(&self, code: impl Into<String>, state: impl Into<String>) {
+        self.queue.push(Outbound::Authenticate {
+            code: code.into(),
+            state: state.into(),
+        });
+    }
+
+    /// send a prompt card, its outcome arrives as a [`PromptAnswered`](crate::event::PromptAnswered)
+    /// or [`PromptExpired`](crate::event::PromptExpired) event
+    ///
+    /// Needs card callbacks enabled in [`DingTalkSubscriptions`](crate::subscriptions::DingTalkSubscriptions).
+    pub fn ask(&self, prompt: Prompt) {
+        self.queue.push(Outbound::Prompt(prompt));
+    }
+
+    /// send plain text to a group chat
+    pub fn send_text(&self, conversation_id: impl Into<String>, text: impl Into<String>) {
+        self.send(
+            conversation_id,
+            MessageTemplate::SampleText {
+                content: text.into(),
+            },
+        );
+    }
+
+    /// send markdown to a group chat
+    pub fn send_markdown(
+        &self,
+        conversation_id: impl Into<String>,
+        title: impl Into<String>,
+        text: impl Into<String>,
+    ) {
+        self.send(
+            conversation_id,
+            MessageTemplate::SampleMarkdown {
+                title: title.into(),
+                text: text.into(),
+            },
+        );
+    }
+
+    /// upload a file, the media id arrives later as a [`MediaUploaded`](crate::event::MediaUploaded) event
+    pub fn upload(&self, file: impl Into<PathBuf>, file_type: UploadType) {
+        self.queue.push(Outbound::Upload {
+            path: file.into(),
+            file_type,
+        });
+    }
+}
@@ -1,17 +1,301 @@
-use std::time::Duration;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
+use bevy::ecs::schedule::{InternedScheduleLabel, ScheduleLabel};
 use bevy::prelude::*;
 use bevy::tasks::TaskPoolBuilder;
 use bevy::time::common_conditions::on_timer;
 use tokio::runtime;
 
 
-use crate::client::{ConnectionState, Client, DingTalkClient, AsyncRuntime};
+use crate::announce::{announce_exit, Announcement, Announcing};
+use crate::client::{ConnectionState, Client, DingTalkClient, AsyncRuntime, KeepConnected};
+use crate::client::ack::AckOrder;
+use crate::client::down::MessageFilter;
+use crate::client::net::HttpPoolOptions;
+use crate::client::quiet::QuietHours;
+use crate::client::zone::Zone;
+use crate::clock::Clock;
+use crate::conversations::Conversations;
+use crate::credentials::Credentials;
+use crate::event::*;
+use crate::directory::UserDirectory;
+use crate::notify::{acknowledge_incidents, escalate_incidents, Notifications, Notifier};
+use crate::outbound::OutboundQueue;
+use crate::storage::Storage;
+use crate::subscriptions::DingTalkSubscriptions;
 use crate::system::*;
+use crate::topics::Topic;
+
+type ConnectionPolicyFn = Box<dyn Fn(&mut App) + Send + Sync>;
 
 pub struct StreamDingTalkPlugin {
     pub client_id: String,
     pub client_secret: String,
+    /// which robot messages are forwarded as [`RobotMessageReceived`] events
+    pub message_filter: MessageFilter,
+    /// robot code used for sends, defaults to `client_id`
+    pub robot_code: Option<String>,
+    /// persistence for tokens and dedupe state, in memory when `None`
+    pub storage: Option<Arc<dyn Storage>>,
+    /// run [`Client::health_check`] at startup and send [`HealthCheckResultEvent`]
+    pub health_check: bool,
+    /// conversation receiving the health check canary
+    pub ops_conversation: Option<String>,
+    /// https client used for API requests, see [`Client::new_with_http`]
+    pub http_client: Option<reqwest::Client>,
+    /// connection pool of the https client, ignored with an `http_client`
+    pub http_pool: HttpPoolOptions,
+    /// quiet hours by conversation, see [`Client::quiet_hours`]
+    pub quiet_hours: HashMap<String, QuietHours>,
+    /// zones by conversation or user, see [`Client::timezone`]
+    pub timezones: HashMap<String, Zone>,
+    /// callback topics acknowledged after their handlers, see [`Client::ack_order`]
+    pub ack_orders: HashMap<String, AckOrder>,
+    /// worker threads of the embedded tokio runtime, default 2
+    pub worker_threads: usize,
+    /// run the runtime on the thread driving it instead, see [`StreamDingTalkPlugin::current_thread`]
+    pub current_thread: bool,
+    /// name of the runtime's threads, default `dingtalk-worker`
+    pub thread_name: String,
+    /// limit of the runtime's blocking threads, tokio's default of 512 when `None`
+    pub max_blocking_threads: Option<usize>,
+    /// period of the connection summary logged at INFO, off when `None`
+    pub stats_log_interval: Option<Duration>,
+    /// schedule draining network events into the world, default [`Update`]
+    pub network_schedule: InternedScheduleLabel,
+    /// callback topics delivered unparsed as [`RawFrameReceived`] events
+    pub raw_topics: Vec<String>,
+    /// time source of the client, the system clock when `None`
+    pub clock: Option<Arc<dyn Clock>>,
+    /// never connect and record outgoing traffic, see [`Client::mock`]
+    pub mock: bool,
+    /// online and shutdown messages, see [`StreamDingTalkPlugin::announce`]
+    pub announcement: Option<Announcement>,
+    /// routes and rate limits of the [`Notifier`]
+    pub notifications: Notifications,
+    /// systems added by [`StreamDingTalkPlugin::connect_in_states`]
+    connection_policy: Option<ConnectionPolicyFn>,
+}
+
+impl StreamDingTalkPlugin {
+    pub fn new(client_id: impl Into<String>, client_secret: impl Into<String>) -> Self {
+        Self {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            message_filter: MessageFilter::default(),
+            robot_code: None,
+            storage: None,
+            health_check: false,
+            ops_conversation: None,
+            http_client: None,
+            http_pool: HttpPoolOptions::default(),
+            quiet_hours: HashMap::new(),
+            timezones: HashMap::new(),
+            ack_orders: HashMap::new(),
+            worker_threads: 2,
+            current_thread: false,
+            thread_name: "dingtalk-worker".to_owned(),
+            max_blocking_threads: None,
+            stats_log_interval: None,
+            network_schedule: Update.intern(),
+            raw_topics: Vec::new(),
+            clock: None,
+            mock: false,
+            announcement: None,
+            notifications: Notifications::default(),
+            connection_policy: None,
+        }
+    }
+
+    /// Send as this robot when the app has more than one
+    pub fn robot_code(mut self, robot_code: impl Into<String>) -> Self {
+        self.robot_code = Some(robot_code.into());
+        self
+    }
+
+    /// Send API requests through `http_client`, e.g. one with a custom pool or local address
+    pub fn http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Tune the connection pool of the https client, e.g. more idle connections for bursts of
+    /// sends
+    pub fn http_pool(mut self, pool: HttpPoolOptions) -> Self {
+        self.http_pool = pool;
+        self
+    }
+
+    /// Keep tokens and dedupe state in `storage`, e.g. a `SledStorage` of the `sled` feature or
+    /// the game's own save system
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Read the time from `clock`, see [`Client::clock`]
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Check credentials and gateway at startup, failures are logged as errors and reported
+    /// through [`HealthCheckResultEvent`]
+    pub fn health_check(mut self, value: bool) -> Self {
+        self.health_check = value;
+        self
+    }
+
+    /// Send a canary message to `conversation_id` during the startup health check, enables it
+    pub fn ops_conversation(mut self, conversation_id: impl Into<String>) -> Self {
+        self.ops_conversation = Some(conversation_id.into());
+        self.health_check = true;
+        self
+    }
+
+    /// Hold non-urgent sends to `conversation_id` back during `hours`
+    pub fn quiet_hours(mut self, conversation_id: impl Into<String>, hours: QuietHours) -> Self {
+        self.quiet_hours.insert(conversation_id.into(), hours);
+        self
+    }
+
+    /// Read times of day for a conversation or user in `zone`, see [`Client::timezone`]
+    pub fn timezone(mut self, id: impl Into<String>, zone: Zone) -> Self {
+        self.timezones.insert(id.into(), zone);
+        self
+    }
+
+    /// Acknowledge robot messages only once a system decided with their [`AckToken`](crate::client::ack::AckToken)
+    pub fn at_least_once(self, value: bool) -> Self {
+        let order = if value {
+            AckOrder::AfterHandler
+        } else {
+            AckOrder::BeforeHandler
+        };
+        self.ack_order(Topic::ROBOT_MESSAGES, order)
+    }
+
+    /// Acknowledge CALLBACK frames on `topic` before or after their handler, see
+    /// [`Client::ack_order`]
+    pub fn ack_order(mut self, topic: impl Into<String>, order: AckOrder) -> Self {
+        self.ack_orders.insert(topic.into(), order);
+        self
+    }
+
+    /// Run the embedded tokio runtime on `count` worker threads, at least one
+    ///
+    /// The default of 2 leaves the cores to Bevy's task pools, raise it for bots sending a lot.
+    pub fn worker_threads(mut self, count: usize) -> Self {
+        self.worker_threads = count.max(1);
+        self
+    }
+
+    /// Run the embedded tokio runtime without threads of its own
+    ///
+    /// Its tasks then only progress inside [`AsyncRuntime`]'s `block_on`, which makes their order
+    /// deterministic. Meant for tests, see `TestHarness` of the `harness` feature.
+    pub fn current_thread(mut self, value: bool) -> Self {
+        self.current_thread = value;
+        self
+    }
+
+    /// Never connect, record outgoing frames and requests instead, see [`Client::mock`]
+    pub fn mock(mut self, value: bool) -> Self {
+        self.mock = value;
+        self
+    }
+
+    /// Name the runtime's threads, as shown by debuggers and `top -H`
+    pub fn thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = name.into();
+        self
+    }
+
+    /// Limit the threads the runtime starts for blocking work such as file uploads
+    pub fn max_blocking_threads(mut self, count: usize) -> Self {
+        self.max_blocking_threads = Some(count.max(1));
+        self
+    }
+
+    /// Log a connection summary like `in=123 out=45 reconnects=0 rtt=82ms queue=0` every
+    /// `interval`, under the [`STATS`](crate::targets::STATS) target
+    pub fn stats_log_interval(mut self, interval: Duration) -> Self {
+        self.stats_log_interval = Some(interval);
+        self
+    }
+
+    /// Drain network events in `schedule` instead of [`Update`], e.g. [`FixedUpdate`] so the
+    /// processing cadence does not follow an uncapped frame rate
+    ///
+    /// Events are still readable from [`Update`] systems; systems ordered after the drain must
+    /// run in the same schedule.
+    pub fn network_schedule(mut self, schedule: impl ScheduleLabel) -> Self {
+        self.network_schedule = schedule.intern();
+        self
+    }
+
+    /// Subscribe to the callback `topic` and send its frames as [`RawFrameReceived`] events,
+    /// for topics this crate has no types for
+    pub fn raw_topic(mut self, topic: impl Into<String>) -> Self {
+        self.raw_topics.push(topic.into());
+        self
+    }
+
+    /// Post `announcement` when the bot comes online and when the app exits, to the
+    /// [ops conversation](Self::ops_conversation) unless it names its own
+    pub fn announce(mut self, announcement: Announcement) -> Self {
+        self.announcement = Some(announcement);
+        self
+    }
+
+    /// Route and rate limit the notices of the [`Notifier`] resource
+    pub fn notifications(mut self, notifications: Notifications) -> Self {
+        self.notifications = notifications;
+        self
+    }
+
+    /// Only stay connected while the app is in one of `states`, e.g. disconnect during a match
+    /// to save bandwidth
+    ///
+    /// Drives [`KeepConnected`] on transitions of `S`.
+    pub fn connect_in_states<S: States>(mut self, states: impl IntoIterator<Item = S>) -> Self {
+        let states: Vec<S> = states.into_iter().collect();
+        self.connection_policy = Some(Box::new(move |app: &mut App| {
+            app.add_systems(
+                Update,
+                keep_connected_in_states(states.clone()).run_if(state_changed::<S>),
+            );
+        }));
+        self
+    }
+
+    /// Plugin using credentials loaded through [`Credentials`]
+    pub fn from_credentials(credentials: Credentials) -> Self {
+        Self::new(credentials.client_id, credentials.client_secret)
+    }
+
+    /// Plugin using credentials stored in the OS keyring under `service_name`,
+    /// see [`Credentials::from_keyring`]
+    #[cfg(feature = "keyring")]
+    pub fn from_keyring(service_name: &str) -> anyhow::Result<Self> {
+        Ok(Self::from_credentials(Credentials::from_keyring(
+            service_name,
+        )?))
+    }
+
+    /// Only forward messages accepted by `filter`, e.g. [`MessageFilter::MentionedOrDirect`]
+    pub fn message_filter(mut self, filter: MessageFilter) -> Self {
+        self.message_filter = filter;
+        self
+    }
+}
+
+/// Plugin options needed by systems after build
+#[derive(Debug, Resource)]
+pub(crate) struct DingTalkSettings {
+    pub message_filter: MessageFilter,
+    pub health_check: bool,
+    pub raw_topics: Vec<String>,
 }
 
 impl Plugin for StreamDingTalkPlugin {
@@ -21,24 +305,221 @@ impl Plugin for StreamDingTalkPlugin {
             "StreamDingTalkPlugin init with client_id: {}, client_secret: {}",
             self.client_id, self.client_secret
         );
-        let async_runtime = runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .unwrap();
+        let mut builder = if self.current_thread {
+            runtime::Builder::new_current_thread()
+        } else {
+            let mut builder = runtime::Builder::new_multi_thread();
+            builder.worker_threads(self.worker_threads);
+            builder
+        };
+        builder.thread_name(self.thread_name.clone()).enable_all();
+        if let Some(count) = self.max_blocking_threads {
+            builder.max_blocking_threads(count);
+        }
+        let async_runtime = builder.build().unwrap();
+        let client = match &self.http_client {
+            Some(http) => DingTalkClient::new_with_http(
+                self.client_id.clone(),
+                self.client_secret.clone(),
+                http.clone(),
+            ),
+            None => DingTalkClient::new_with_pool(
+                self.client_id.clone(),
+                self.client_secret.clone(),
+                self.http_pool,
+            ),
+        }
+        .unwrap();
+        client.config.lock().unwrap().robot_code = self.robot_code.clone();
+        client.config.lock().unwrap().ops_conversation = self.ops_conversation.clone();
+        client.config.lock().unwrap().quiet_hours = self.quiet_hours.clone();
+        client.config.lock().unwrap().timezones = self.timezones.clone();
+        client.config.lock().unwrap().ack_orders = self.ack_orders.clone();
+        if let Some(storage) = &self.storage {
+            client.clone().storage(storage.clone());
+        }
+        if let Some(clock) = &self.clock {
+            client.clone().clock(clock.clone());
+        }
+        if self.mock {
+            client.clone().mock(true);
+        }
+        let bridge = client.bridge.attach();
+        let (outbound, worker) = OutboundQueue::new(client.clone());
+        async_runtime.spawn(worker);
+        let directory = UserDirectory::new(client.clone(), async_runtime.handle().clone());
+        let conversations = Conversations::new(client.store());
+        let notifier = Notifier::new(
+            client.clone(),
+            async_runtime.handle().clone(),
+            self.notifications.clone(),
+        );
         app
             .insert_resource(AsyncRuntime(async_runtime))
-            .insert_resource(DingTalkClient::new(
-                    self.client_id.clone(),
-                    self.client_secret.clone(),
-                ).unwrap()
-            )
+            .insert_resource(client)
+            .insert_resource(bridge)
+            .insert_resource(outbound)
+            .insert_resource(directory)
+            .insert_resource(conversations)
+            .insert_resource(notifier)
+            .init_resource::<DingTalkSubscriptions>()
+            .init_resource::<KeepConnected>()
+            .insert_resource(DingTalkSettings {
+                message_filter: self.message_filter,
+                health_check: self.health_check,
+                raw_topics: self.raw_topics.clone(),
+            })
+            .add_event::<AuthFailedEvent>()
+            .add_event::<FatalCloseEvent>()
+            .add_event::<ResumedFromSuspend>()
+            .add_event::<GatewayErrorEvent>()
+            .add_event::<FrameErrorEvent>()
+            .add_event::<DingTalkErrorEvent>()
+            .add_event::<AckFailedEvent>()
+            .add_event::<RedeliveryDetected>()
+            .add_event::<AssistantSkillInvoked>()
+            .add_event::<CardActionEvent>()
+            .add_event::<UserAuthenticatedEvent>()
+            .add_event::<MediaUploaded>()
+            .add_event::<CreateGroupRequest>()
+            .add_event::<CreateGroupResult>()
+            .add_event::<RobotMessageReceived>()
+            .add_event::<RawFrameReceived>()
+            .add_event::<StickerReceived>()
+            .add_event::<EmotionReceived>()
+            .add_event::<GroupMemberJoined>()
+            .add_event::<GroupMemberLeft>()
+            .add_event::<GroupTitleUpdated>()
+            .add_event::<UserProfileResolved>()
+            .add_event::<HealthCheckResultEvent>()
+            .add_event::<PromptAnswered>()
+            .add_event::<PromptExpired>()
+            .add_event::<AlertAcknowledgedEvent>()
         .init_state::<ConnectionState>();
+        #[cfg(feature = "reflect")]
+        register_types(app);
+        let mut subscriptions = app.world.resource_mut::<DingTalkSubscriptions>();
+        for topic in &self.raw_topics {
+            subscriptions.subscribe("CALLBACK", topic.clone());
+        }
+        if self.notifications.ack_card.is_some() {
+            subscriptions.set_card_callbacks(true);
+        }
+        app.add_systems(Startup, run_health_check);
         app.add_systems(
             Update,
             connect_to_server
                 .run_if(in_state(ConnectionState::Disconnected))
+                .run_if(resource_equals(KeepConnected(true)))
                 .run_if(on_timer(Duration::from_secs_f64(1.0))),
         )
-        .add_systems(Update, handle_network_events);
+        .add_systems(
+            Update,
+            (release_held_sends, escalate_incidents).run_if(on_timer(Duration::from_secs(1))),
+        )
+        .add_systems(self.network_schedule, handle_network_events)
+        .add_systems(
+            Update,
+            (
+                apply_subscriptions,
+                apply_keep_connected,
+                queue_group_creation,
+                acknowledge_incidents,
+            ),
+        );
+        if let Some(interval) = self.stats_log_interval {
+            app.add_systems(Update, log_connection_stats.run_if(on_timer(interval)));
+        }
+        if let Some(announcement) = &self.announcement {
+            let conversation_id = announcement
+                .conversation_id
+                .clone()
+                .or_else(|| self.ops_conversation.clone());
+            match conversation_id {
+                Some(conversation_id) => {
+                    let announcing = Announcing {
+                        conversation_id,
+                        announcement: announcement.clone(),
+                    };
+                    announcing.register(app.world.resource::<DingTalkClient>());
+                    app.insert_resource(announcing)
+                        .add_systems(Last, announce_exit);
+                }
+                None => warn!(
+                    "announcement skipped, it has no conversation and there is no ops conversation"
+                ),
+            }
+        }
+        if let Some(policy) = &self.connection_policy {
+            policy(app);
+        }
     }
 }
+
+/// make messages, events and stats known to inspectors and scenes
+#[cfg(feature = "reflect")]
+fn register_types(app: &mut App) {
+    use crate::client::assistant::{GraphRequest, RequestLine};
+    use crate::client::auth::{DingTalkUser, UserAccessToken};
+    use crate::client::card::CardUser;
+    use crate::client::contact::UserProfile;
+    use crate::client::down::{
+        DownstreamEnvelope, EmotionContent, EventData, MsgContent, RichText, RobotRecvMessage,
+        StickerContent, User,
+    };
+    use crate::client::group::{GroupMembersChanged, GroupTitleChanged};
+    use crate::client::health::HealthReport;
+    use crate::client::stats::{ConnectionStats, MessageCounts};
+    use crate::client::tenant::TenantId;
+    use crate::client::up::{MessageTemplate, UploadType};
+    use crate::error::{AccessDeniedDetail, ErrorContext, GatewayError};
+
+    app.register_type::<RobotRecvMessage>()
+        .register_type::<MsgContent>()
+        .register_type::<User>()
+        .register_type::<RichText>()
+        .register_type::<StickerContent>()
+        .register_type::<EmotionContent>()
+        .register_type::<DownstreamEnvelope>()
+        .register_type::<EventData>()
+        .register_type::<MessageTemplate>()
+        .register_type::<UploadType>()
+        .register_type::<TenantId>()
+        .register_type::<CardUser>()
+        .register_type::<GroupMembersChanged>()
+        .register_type::<GroupTitleChanged>()
+        .register_type::<UserProfile>()
+        .register_type::<GraphRequest>()
+        .register_type::<RequestLine>()
+        .register_type::<DingTalkUser>()
+        .register_type::<UserAccessToken>()
+        .register_type::<HealthReport>()
+        .register_type::<GatewayError>()
+        .register_type::<AccessDeniedDetail>()
+        .register_type::<ErrorContext>()
+        .register_type::<ConnectionStats>()
+        .register_type::<MessageCounts>()
+        .register_type::<RobotMessageReceived>()
+        .register_type::<RawFrameReceived>()
+        .register_type::<StickerReceived>()
+        .register_type::<EmotionReceived>()
+        .register_type::<CardActionEvent>()
+        .register_type::<PromptAnswered>()
+        .register_type::<AlertAcknowledgedEvent>()
+        .register_type::<CreateGroupRequest>()
+        .register_type::<GroupMemberJoined>()
+        .register_type::<GroupMemberLeft>()
+        .register_type::<GroupTitleUpdated>()
+        .register_type::<UserProfileResolved>()
+        .register_type::<AuthFailedEvent>()
+        .register_type::<FatalCloseEvent>()
+        .register_type::<ResumedFromSuspend>()
+        .register_type::<DingTalkErrorEvent>()
+        .register_type::<GatewayErrorEvent>()
+        .register_type::<FrameErrorEvent>()
+        .register_type::<AckFailedEvent>()
+        .register_type::<RedeliveryDetected>()
+        .register_type::<AssistantSkillInvoked>()
+        .register_type::<UserAuthenticatedEvent>()
+        .register_type::<HealthCheckResultEvent>();
+}
@@ -0,0 +1,289 @@
+//! Polls voted on with the buttons of an interactive card
+//!
+//! Start a [`Poll`] through the [`Polls`] resource once [`PollPlugin`] is added. The card is
+//! filled with these variables, its template should render them:
+//!
+//! - `question`: the question text
+//! - `options`: json list of `{"index", "text"}`, a vote button submits `option` = index
+//! - `results`: json list of `{"text", "votes", "percent"}`, updated after every vote
+//! - `total`: number of voters
+//! - `closed`: `"true"` once the deadline passed
+//!
+//! Each user has one vote, voting again moves it. The outcome arrives as a [`PollFinishedEvent`].
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use chrono::{DateTime, Duration, Local};
+use serde_json::{json, Map, Value};
+
+use crate::client::card::InteractiveCard;
+use crate::client::DingTalkClient;
+use crate::event::CardActionEvent;
+use crate::outbound::{Outbound, OutboundQueue};
+use crate::subscriptions::DingTalkSubscriptions;
+use crate::system::handle_network_events;
+
+/// Card parameter holding the index of the chosen option
+pub const POLL_OPTION_PARAM: &str = "option";
+
+/// A question with fixed options, open until its deadline
+#[derive(Debug, Clone)]
+pub struct Poll {
+    pub conversation_id: String,
+    pub template_id: String,
+    pub question: String,
+    pub options: Vec<String>,
+    /// time the poll stays open once started
+    pub duration: Duration,
+    /// set when the poll starts, read from the client's clock
+    deadline: Option<DateTime<Local>>,
+    /// card id, random unless set
+    pub id: String,
+    /// chosen option by user id
+    votes: HashMap<String, usize>,
+}
+
+impl Poll {
+    /// poll sent with card template `template_id`, open for `duration`
+    pub fn new<S: Into<String>>(
+        conversation_id: impl Into<String>,
+        template_id: impl Into<String>,
+        question: impl Into<String>,
+        options: impl IntoIterator<Item = S>,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            conversation_id: conversation_id.into(),
+            template_id: template_id.into(),
+            question: question.into(),
+            options: options.into_iter().map(Into::into).collect(),
+            duration,
+            deadline: None,
+            id: format!("{:032x}", rand::random::<u128>()),
+            votes: HashMap::new(),
+        }
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = id.into();
+        self
+    }
+
+    /// `None` until [`Polls`] started the poll
+    pub fn deadline(&self) -> Option<DateTime<Local>> {
+        self.deadline
+    }
+
+    /// number of votes of each option, in option order
+    pub fn tally(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.options.len()];
+        for option in self.votes.values() {
+            counts[*option] += 1;
+        }
+        counts
+    }
+
+    /// option chosen by `user_id`
+    pub fn vote_of(&self, user_id: &str) -> Option<&str> {
+        self.votes
+            .get(user_id)
+            .map(|option| self.options[*option].as_str())
+    }
+
+    pub fn voters(&self) -> usize {
+        self.votes.len()
+    }
+
+    /// record the vote of `user_id`, false when `option` is out of range or unchanged
+    fn vote(&mut self, user_id: &str, option: usize) -> bool {
+        if option >= self.options.len() {
+            return false;
+        }
+        self.votes.insert(user_id.to_owned(), option) != Some(option)
+    }
+
+    fn card(&self) -> InteractiveCard {
+        let options: Vec<Value> = self
+            .options
+            .iter()
+            .enumerate()
+            .map(|(index, text)| json!({ "index": index, "text": text }))
+            .collect();
+        let mut card = InteractiveCard::new(&self.template_id)
+            .out_track_id(&self.id)
+            .param("question", &self.question)
+            .param("options", options);
+        card.params.extend(self.result_params(false));
+        card
+    }
+
+    fn result_params(&self, closed: bool) -> Map<String, Value> {
+        let total = self.voters();
+        let results: Vec<Value> = self
+            .options
+            .iter()
+            .zip(self.tally())
+            .map(|(text, votes)| {
+                let percent = (votes * 100).checked_div(total).unwrap_or(0);
+                json!({ "text": text, "votes": votes, "percent": percent })
+            })
+            .collect();
+        let mut params = Map::new();
+        params.insert("results".to_owned(), Value::Array(results));
+        params.insert("total".to_owned(), total.into());
+        params.insert("closed".to_owned(), closed.to_string().into());
+        params
+    }
+}
+
+/// Outcome of a closed [`Poll`]
+#[derive(Event, Debug, Clone)]
+pub struct PollFinishedEvent {
+    pub poll_id: String,
+    pub conversation_id: String,
+    pub question: String,
+    /// options with their number of votes, in option order
+    pub results: Vec<(String, usize)>,
+    /// options with the most votes, several on a tie, empty without votes
+    pub winners: Vec<String>,
+}
+
+impl From<&Poll> for PollFinishedEvent {
+    fn from(poll: &Poll) -> Self {
+        let results: Vec<(String, usize)> =
+            poll.options.iter().cloned().zip(poll.tally()).collect();
+        let max = results.iter().map(|(_, votes)| *votes).max().unwrap_or(0);
+        let winners = results
+            .iter()
+            .filter(|(_, votes)| max > 0 && *votes == max)
+            .map(|(text, _)| text.clone())
+            .collect();
+        Self {
+            poll_id: poll.id.clone(),
+            conversation_id: poll.conversation_id.clone(),
+            question: poll.question.clone(),
+            results,
+            winners,
+        }
+    }
+}
+
+/// Running polls
+#[derive(Resource, Debug, Default)]
+pub struct Polls {
+    open: HashMap<String, Poll>,
+    starting: Vec<Poll>,
+    closing: Vec<String>,
+}
+
+impl Polls {
+    /// send the card of `poll` and start counting votes, returns the poll id
+    pub fn start(&mut self, poll: Poll) -> String {
+        let id = poll.id.clone();
+        self.starting.push(poll);
+        id
+    }
+
+    /// close a poll before its deadline
+    pub fn close(&mut self, id: impl Into<String>) {
+        self.closing.push(id.into());
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Poll> {
+        self.open.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Poll> {
+        self.open.values()
+    }
+}
+
+/// Runs [`Polls`], add it after [`StreamDingTalkPlugin`](crate::plugin::StreamDingTalkPlugin)
+///
+/// Enables card callbacks in [`DingTalkSubscriptions`].
+#[derive(Default)]
+pub struct PollPlugin;
+
+impl Plugin for PollPlugin {
+    fn build(&self, app: &mut App) {
+        if let Some(mut subscriptions) = app.world.get_resource_mut::<DingTalkSubscriptions>() {
+            subscriptions.set_card_callbacks(true);
+        }
+        app.add_event::<PollFinishedEvent>()
+            .init_resource::<Polls>()
+            .add_systems(
+                Update,
+                (start_polls, count_votes, close_polls)
+                    .chain()
+                    .after(handle_network_events),
+            );
+    }
+}
+
+fn start_polls(mut polls: ResMut<Polls>, client: Res<DingTalkClient>, queue: Res<OutboundQueue>) {
+    let polls = &mut *polls;
+    for mut poll in polls.starting.drain(..) {
+        poll.deadline = Some(client.now() + poll.duration);
+        queue.push(Outbound::Card {
+            conversation_id: poll.conversation_id.clone(),
+            card: poll.card(),
+        });
+        polls.open.insert(poll.id.clone(), poll);
+    }
+}
+
+fn count_votes(
+    mut polls: ResMut<Polls>,
+    queue: Res<OutboundQueue>,
+    mut clicks: EventReader<CardActionEvent>,
+) {
+    for event in clicks.read() {
+        let Some(poll) = polls.open.get_mut(&event.out_track_id) else {
+            continue;
+        };
+        let option = match event.params.get(POLL_OPTION_PARAM) {
+            Some(Value::String(s)) => s.parse().ok(),
+            Some(Value::Number(n)) => n.as_u64().map(|n| n as usize),
+            _ => None,
+        };
+        let Some(option) = option else {
+            debug!("poll {} click without option: {}", poll.id, event.params);
+            continue;
+        };
+        if poll.vote(&event.user.user_id, option) {
+            queue.push(Outbound::UpdateCard {
+                out_track_id: poll.id.clone(),
+                params: poll.result_params(false),
+            });
+        }
+    }
+}
+
+fn close_polls(
+    mut polls: ResMut<Polls>,
+    client: Res<DingTalkClient>,
+    queue: Res<OutboundQueue>,
+    mut finished: EventWriter<PollFinishedEvent>,
+) {
+    let now = client.now();
+    let polls = &mut *polls;
+    let mut ids: Vec<String> = polls.closing.drain(..).collect();
+    ids.extend(
+        polls
+            .open
+            .values()
+            .filter(|poll| poll.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|poll| poll.id.clone()),
+    );
+    for id in ids {
+        let Some(poll) = polls.open.remove(&id) else {
+            continue;
+        };
+        queue.push(Outbound::UpdateCard {
+            out_track_id: poll.id.clone(),
+            params: poll.result_params(true),
+        });
+        finished.send(PollFinishedEvent::from(&poll));
+    }
+}
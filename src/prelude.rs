@@ -1 +1,64 @@
+pub use crate::announce::Announcement;
+pub use crate::asset::{MessageTemplateAsset, MessageTemplatePlugin};
+pub use crate::card::{CardActionReceived, CardActionRouter};
+pub use crate::client::ack::{AckOrder, AckToken, PendingRedelivery};
+pub use crate::client::assistant::{GraphRequest, GraphResponse};
+pub use crate::client::auth::{AccessToken, DingTalkUser, SuiteCredentials, UserAccessToken};
+pub use crate::client::bundle::{BundlePart, MessageBundle};
+pub use crate::client::card::{
+    BulkCardUpdate, CardForm, CardUser, FormField, FormFieldKind, FormValues, InteractiveCard,
+};
+#[cfg(feature = "chaos")]
+pub use crate::client::chaos::Chaos;
+pub use crate::client::contact::UserProfile;
+pub use crate::client::down::{
+    CustomContent, DownstreamEnvelope, EmotionContent, MessageFilter, MsgContent, RobotRecvMessage,
+    StickerContent,
+};
+pub use crate::client::drive::{DriveFallback, DriveFile};
+pub use crate::client::group::{GroupSettings, MentionAll};
+pub use crate::client::health::HealthReport;
+pub use crate::client::media::VideoMetadata;
+pub use crate::client::mock::Outgoing;
+pub use crate::client::net::{
+    AddressFamily, CloseAction, ClosePolicy, ConnectOptions, HttpPoolOptions,
+};
+pub use crate::client::prompt::{Prompt, PromptOutcome};
+pub use crate::client::quiet::QuietHours;
+pub use crate::client::tap::{Tap, TappedRequest};
+pub use crate::client::tenant::TenantId;
+pub use crate::client::up::{AckStatus, EventAckData, MessageTemplate, SendResult, UploadType};
+pub use crate::client::zone::Zone;
+pub use crate::client::KeepConnected;
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+pub use crate::command::{
+    BotCommand, CommandReceived, CommandRouter, Cooldown, RateLimitedUserEvent,
+};
+pub use crate::conversations::Conversations;
+pub use crate::diagnostics::DingTalkDiagnosticsPlugin;
+pub use crate::digest::{Digest, DigestItem, DigestPlugin};
+pub use crate::directory::UserDirectory;
+pub use crate::error::{
+    AccessDeniedDetail, DingTalkError, ErrorContext, GatewayError, GatewayErrorKind, RateLimitScope,
+};
+pub use crate::event::{
+    AckFailedEvent, AlertAcknowledgedEvent, AssistantSkillInvoked, AuthFailedEvent,
+    CardActionEvent, CreateGroupRequest, CreateGroupResult, DingTalkErrorEvent, EmotionReceived,
+    FatalCloseEvent, FrameErrorEvent, GatewayErrorEvent, GroupMemberJoined, GroupMemberLeft,
+    GroupTitleUpdated, HealthCheckResultEvent, MediaUploaded, PromptAnswered, PromptExpired,
+    RawFrameReceived, RedeliveryDetected, ResumedFromSuspend, RobotMessageReceived,
+    StickerReceived, UserAuthenticatedEvent, UserProfileResolved,
+};
+#[cfg(feature = "harness")]
+pub use crate::harness::TestHarness;
+pub use crate::history::{
+    ExportFormat, HistoryEntry, MessageHistory, MessageHistoryPlugin, Retention,
+};
+pub use crate::leaderboard::{Leaderboard, LeaderboardEntry, LeaderboardPlugin, Leaderboards};
+pub use crate::notify::{Escalation, Incident, Notifications, Notifier, Severity};
+pub use crate::param::DingTalk;
 pub use crate::plugin::StreamDingTalkPlugin;
+pub use crate::poll::{Poll, PollFinishedEvent, PollPlugin, Polls};
+pub use crate::sub_app::{DingTalkSubApp, DingTalkSubAppPlugin};
+pub use crate::subscriptions::DingTalkSubscriptions;
+pub use crate::topics::Topic;
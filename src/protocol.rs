@@ -0,0 +1,15 @@
+//! Wire types of the DingTalk stream protocol and robot messages
+//!
+//! Only serde, serde_json and strum are used here, no runtime, http client or Bevy types, so
+//! servers, mocks and analyzers can share the schema with the client. The client re-exports
+//! these types from [`client::down`](crate::client::down) and [`client::up`](crate::client::up),
+//! which also adds the chrono timestamp accessors.
+
+pub mod down;
+pub mod up;
+
+pub use down::{
+    json_path, ClientDownStream, CustomContent, DownstreamEnvelope, EmotionContent, EventData,
+    MsgContent, RichText, RobotRecvMessage, StickerContent, StreamDownHeaders, User,
+};
+pub use up::{AckStatus, ClientUpStream, EventAckData, MessageTemplate, StreamUpHeader};
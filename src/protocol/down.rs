@@ -0,0 +1,598 @@
+//! Frames and messages received from DingTalk
+
+use std::{any::Any, sync::Arc};
+
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer,
+};
+use serde_json::{json, Value};
+
+/// Frame pushed over the stream connection
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "strict-protocol", serde(deny_unknown_fields))]
+pub struct ClientDownStream {
+    pub spec_version: String,
+    pub r#type: String,
+    pub headers: StreamDownHeaders,
+    pub data: String,
+    /// connection the frame arrived on, set by the client
+    #[serde(skip)]
+    pub link: usize,
+}
+
+/// Headers of a [`ClientDownStream`]
+///
+/// Not checked by the `strict-protocol` feature, serde cannot deny unknown fields next to the
+/// flattened [`EventData`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamDownHeaders {
+    #[serde(default)]
+    pub app_id: String,
+    #[serde(default)]
+    pub connection_id: String,
+    pub content_type: String,
+    pub message_id: String,
+    pub time: String,
+    pub topic: String,
+    #[serde(flatten)]
+    pub event: EventData,
+}
+
+/// Stable view of a received frame, passed to raw listeners and carried by Bevy events
+///
+/// Fields are read through methods, so it keeps working when the wire structs
+/// [`ClientDownStream`] and [`StreamDownHeaders`] change.
+#[derive(Debug, Clone, Default)]
+pub struct DownstreamEnvelope {
+    pub(crate) frame_type: String,
+    pub(crate) topic: String,
+    pub(crate) message_id: String,
+    pub(crate) time: String,
+    pub(crate) content_type: String,
+    pub(crate) connection_id: String,
+    pub(crate) data: String,
+}
+
+impl DownstreamEnvelope {
+    /// `SYSTEM`, `EVENT` or `CALLBACK`
+    pub fn frame_type(&self) -> &str {
+        &self.frame_type
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    /// id of the frame, the one to answer, not the id of a robot message
+    pub fn message_id(&self) -> &str {
+        &self.message_id
+    }
+
+    /// when the server sent the frame, milliseconds since the epoch
+    pub fn time_millis(&self) -> Option<i64> {
+        self.time.parse().ok()
+    }
+
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// stream connection as named by the server
+    pub fn connection_id(&self) -> &str {
+        &self.connection_id
+    }
+
+    /// payload as received, usually JSON
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    /// payload parsed as `T`
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_str(&self.data)
+    }
+
+    /// field of the payload at `path` as `T`, see [`EventData::extract`]
+    pub fn extract<T: DeserializeOwned>(&self, path: &str) -> Option<T> {
+        extract(&self.data, path)
+    }
+}
+
+impl From<&ClientDownStream> for DownstreamEnvelope {
+    fn from(frame: &ClientDownStream) -> Self {
+        Self {
+            frame_type: frame.r#type.clone(),
+            topic: frame.headers.topic.clone(),
+            message_id: frame.headers.message_id.clone(),
+            time: frame.headers.time.clone(),
+            content_type: frame.headers.content_type.clone(),
+            connection_id: frame.headers.connection_id.clone(),
+            data: frame.data.clone(),
+        }
+    }
+}
+
+/// Event type pushed by DingTalk server
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/org-event-overview) for the definition of each field
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventData {
+    #[serde(default)]
+    pub event_type: String,
+    #[serde(default)]
+    pub event_born_time: String,
+    #[serde(default)]
+    pub event_id: String,
+    #[serde(default)]
+    pub event_corp_id: String,
+    #[serde(default)]
+    pub event_unified_app_id: String,
+    /// payload of the event as received, its fields depend on `event_type`
+    #[serde(skip)]
+    pub data: String,
+}
+
+impl EventData {
+    /// Field of the payload at `path` as `T`, for event types without a type in this crate
+    ///
+    /// See [`json_path`] for the path syntax, e.g. `"UserId.0"` for the first changed user.
+    /// `None` when the payload is not JSON, the field is missing or it does not fit `T`.
+    pub fn extract<T: DeserializeOwned>(&self, path: &str) -> Option<T> {
+        extract(&self.data, path)
+    }
+}
+
+/// Value at a dot separated `path` in `value`
+///
+/// Segments are object keys, or indices into arrays, e.g. `"staff.0.name"`. The empty path is
+/// `value` itself. Keys containing dots are reached with [`Value::pointer`] instead.
+pub fn json_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    if path.is_empty() {
+        return Some(value);
+    }
+    path.split('.')
+        .try_fold(value, |value, segment| match value {
+            Value::Object(fields) => fields.get(segment),
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            _ => None,
+        })
+}
+
+fn extract<T: DeserializeOwned>(data: &str, path: &str) -> Option<T> {
+    let payload: Value = serde_json::from_str(data).ok()?;
+    T::deserialize(json_path(&payload, path)?).ok()
+}
+
+/// Message type pushed by DingTalk server
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/receive-message) for the definition of each field
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", remote = "Self")]
+#[cfg_attr(feature = "strict-protocol", serde(deny_unknown_fields))]
+pub struct RobotRecvMessage {
+    pub msg_id: String,
+    pub msgtype: String,
+    /// `content`, or `text` for text messages, decoded according to `msgtype`
+    #[serde(skip, default = "no_content")]
+    pub content: MsgContent,
+
+    pub conversation_id: String,
+    /// 1 - single chat
+    /// 2 - group chat
+    pub conversation_type: String,
+    #[serde(default)]
+    pub conversation_title: String,
+
+    #[serde(default)]
+    pub at_users: Vec<User>,
+    #[serde(default)]
+    pub is_in_at_list: bool,
+
+    #[serde(default)]
+    pub chatbot_corp_id: String,
+    pub chatbot_user_id: String,
+
+    pub sender_id: String,
+    pub sender_nick: String,
+    #[serde(default)]
+    pub sender_corp_id: String,
+    #[serde(default)]
+    pub sender_staff_id: String,
+
+    pub session_webhook_expired_time: u64,
+    pub session_webhook: String,
+
+    #[serde(default)]
+    pub is_admin: bool,
+    pub create_at: u64,
+}
+
+impl RobotRecvMessage {
+    /// 1:1 chat between the sender and the robot
+    pub fn is_direct(&self) -> bool {
+        self.conversation_type == "1"
+    }
+
+    /// group chat message
+    pub fn is_group(&self) -> bool {
+        self.conversation_type == "2"
+    }
+
+    /// Parse a robot message from its frame data
+    ///
+    /// Lenient about content: a msgtype none of the [`MsgContent`] variants fits becomes
+    /// [`MsgContent::UnknownMsgType`] with the content object kept in `raw`.
+    pub fn from_json(data: &str) -> serde_json::Result<Self> {
+        Self::from_json_with(data, |_, _| None)
+    }
+
+    /// [`from_json`](Self::from_json) asking `decode` first for the content of the msgtype
+    pub fn from_json_with(
+        data: &str,
+        decode: impl FnOnce(&str, &Value) -> Option<MsgContent>,
+    ) -> serde_json::Result<Self> {
+        Self::from_value_with(serde_json::from_str(data)?, decode)
+    }
+
+    fn from_value_with(
+        value: Value,
+        decode: impl FnOnce(&str, &Value) -> Option<MsgContent>,
+    ) -> serde_json::Result<Self> {
+        let mut value = value;
+        // content is decoded below, by msgtype
+        let (content, text) = match value.as_object_mut() {
+            Some(fields) => (fields.remove("content"), fields.remove("text")),
+            None => (None, None),
+        };
+        let mut msg = RobotRecvMessage::deserialize(&value)?;
+        let raw = content.or(text).unwrap_or_default();
+
+        msg.content = match decode(&msg.msgtype, &raw) {
+            Some(content) => content,
+            None => match MsgContent::from_msgtype(&msg.msgtype, &raw) {
+                Some(Ok(content)) => content,
+                #[cfg(feature = "strict-protocol")]
+                Some(Err(e)) => return Err(e),
+                // unknown msgtype, or a known one whose content does not fit
+                _ => MsgContent::UnknownMsgType {
+                    unknown_msg_type: raw["unknownMsgType"]
+                        .as_str()
+                        .unwrap_or(&msg.msgtype)
+                        .to_owned(),
+                    raw,
+                },
+            },
+        };
+        Ok(msg)
+    }
+}
+
+impl<'de> Deserialize<'de> for RobotRecvMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Self::from_value_with(Value::deserialize(deserializer)?, |_, _| None)
+            .map_err(de::Error::custom)
+    }
+}
+
+fn no_content() -> MsgContent {
+    MsgContent::UnknownMsgType {
+        unknown_msg_type: String::new(),
+        raw: Value::Null,
+    }
+}
+
+/// At(@) User type
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/receive-message) for the definition of each field
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+#[cfg_attr(feature = "strict-protocol", serde(deny_unknown_fields))]
+pub struct User {
+    pub dingtalk_id: String,
+    #[serde(default)]
+    pub staff_id: String,
+}
+
+/// Enumeration types for all received messages
+///
+/// The variant is picked by `msgtype`, so it deserializes from `{"msgtype": .., "content": ..}`.
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/receive-message) for the definition of each field
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", tag = "msgtype", content = "content")]
+#[cfg_attr(feature = "strict-protocol", serde(deny_unknown_fields))]
+pub enum MsgContent {
+    #[serde(rename_all = "camelCase")]
+    Text { content: String },
+    #[serde(rename_all = "camelCase")]
+    File {
+        download_code: String,
+        file_name: String,
+        /// id of the file in the sender's drive space
+        #[serde(default)]
+        file_id: String,
+        #[serde(default)]
+        space_id: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Picture {
+        download_code: String,
+        #[serde(default)]
+        picture_download_code: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    RichText { rich_text: Vec<RichText> },
+    #[serde(rename_all = "camelCase")]
+    Audio {
+        duration: u32,
+        download_code: String,
+        recognition: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Video {
+        duration: u32,
+        download_code: String,
+        video_type: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Location {
+        latitude: f64,
+        longitude: f64,
+        #[serde(default)]
+        title: String,
+        #[serde(default)]
+        address: String,
+    },
+    /// msgtype without a variant, or content that does not fit its variant
+    #[serde(skip)]
+    UnknownMsgType {
+        unknown_msg_type: String,
+        /// the content object as received
+        raw: Value,
+    },
+    /// sticker from the emoticon panel, msgtype `sticker`
+    Sticker(StickerContent),
+    /// animated emotion, msgtype `emotion`
+    Emotion(EmotionContent),
+    /// content of a msgtype registered with [`Client::register_msg_type`](crate::client::Client::register_msg_type)
+    #[serde(skip)]
+    Custom {
+        msgtype: String,
+        content: CustomContent,
+    },
+}
+
+impl MsgContent {
+    /// msgtypes with a built-in variant
+    pub const MSG_TYPES: &'static [&'static str] = &[
+        "text", "file", "picture", "richText", "audio", "video", "location", "sticker", "emotion",
+    ];
+
+    /// decode the content of a built-in msgtype, `None` for any other msgtype
+    pub fn from_msgtype(msgtype: &str, raw: &Value) -> Option<serde_json::Result<Self>> {
+        Self::MSG_TYPES
+            .contains(&msgtype)
+            .then(|| MsgContent::deserialize(json!({ "msgtype": msgtype, "content": raw })))
+    }
+
+    /// short human readable form, used for quotes and logs
+    pub fn summary(&self) -> String {
+        match self {
+            MsgContent::Text { content } => content.trim().to_owned(),
+            MsgContent::File { file_name, .. } => format!("[file] {file_name}"),
+            MsgContent::Picture { .. } => "[picture]".to_owned(),
+            MsgContent::RichText { rich_text } => rich_text
+                .iter()
+                .map(|r| match r {
+                    RichText::Text { text } => text.as_str(),
+                    RichText::Picture { .. } => "[picture]",
+                })
+                .collect::<Vec<_>>()
+                .join(""),
+            MsgContent::Audio { recognition, .. } => format!("[audio] {recognition}"),
+            MsgContent::Video { .. } => "[video]".to_owned(),
+            MsgContent::UnknownMsgType {
+                unknown_msg_type, ..
+            } => format!("[{unknown_msg_type}]"),
+            MsgContent::Location { title, address, .. } => format!("[location] {title} {address}"),
+            MsgContent::Sticker(_) => "[sticker]".to_owned(),
+            MsgContent::Emotion(e) => format!("[{}]", e.emotion_name),
+            MsgContent::Custom { msgtype, .. } => format!("[{msgtype}]"),
+        }
+    }
+}
+
+/// Content of a [`MsgContent::Sticker`]
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+#[cfg_attr(feature = "strict-protocol", serde(deny_unknown_fields))]
+pub struct StickerContent {
+    /// use with [`Client::download`](crate::client::Client::download) to get the image
+    pub download_code: String,
+    pub sticker_id: String,
+}
+
+/// Content of a [`MsgContent::Emotion`]
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+#[cfg_attr(feature = "strict-protocol", serde(deny_unknown_fields))]
+pub struct EmotionContent {
+    pub emotion_id: String,
+    /// name shown in the emoticon panel, e.g. `微笑`
+    pub emotion_name: String,
+    /// use with [`Client::download`](crate::client::Client::download) to get the animation, empty for built-in emotions
+    pub download_code: String,
+}
+
+/// Enumeration types for rich text
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/receive-message) for the definition of each field
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", untagged)]
+#[cfg_attr(feature = "strict-protocol", serde(deny_unknown_fields))]
+pub enum RichText {
+    #[serde(rename_all = "camelCase")]
+    Text { text: String },
+    #[serde(rename_all = "camelCase")]
+    Picture {
+        download_code: String,
+        #[serde(default)]
+        picture_download_code: String,
+        r#type: String,
+    },
+}
+
+/// Message content decoded by a msgtype decoder registered with [`Client::register_msg_type`](crate::client::Client::register_msg_type)
+#[derive(Clone)]
+pub struct CustomContent(Arc<dyn Any + Send + Sync>);
+
+impl CustomContent {
+    pub fn new<T: Any + Send + Sync>(value: T) -> Self {
+        Self(Arc::new(value))
+    }
+
+    /// the decoded value, `None` when it is not a `T`
+    pub fn downcast_ref<T: 'static>(&self) -> Option<&T> {
+        self.0.downcast_ref()
+    }
+}
+
+/// holds `()`, what content is left as after a reflection round trip
+impl Default for CustomContent {
+    fn default() -> Self {
+        Self::new(())
+    }
+}
+
+impl std::fmt::Debug for CustomContent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CustomContent").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    fn fixture_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/robot_message")
+    }
+
+    fn read_fixture(name: &str) -> String {
+        std::fs::read_to_string(fixture_dir().join(name)).unwrap()
+    }
+
+    fn fixture(name: &str) -> RobotRecvMessage {
+        RobotRecvMessage::from_json(&read_fixture(name)).unwrap_or_else(|e| panic!("{name}: {e}"))
+    }
+
+    /// msgtype a content variant is decoded from
+    fn msgtype_of(content: &MsgContent) -> &str {
+        match content {
+            MsgContent::Text { .. } => "text",
+            MsgContent::File { .. } => "file",
+            MsgContent::Picture { .. } => "picture",
+            MsgContent::RichText { .. } => "richText",
+            MsgContent::Audio { .. } => "audio",
+            MsgContent::Video { .. } => "video",
+            MsgContent::Location { .. } => "location",
+            MsgContent::UnknownMsgType { .. } => "unknownMsgType",
+            MsgContent::Sticker(_) => "sticker",
+            MsgContent::Emotion(_) => "emotion",
+            MsgContent::Custom { msgtype, .. } => msgtype,
+        }
+    }
+
+    #[test]
+    fn every_fixture_decodes_to_its_msgtype() {
+        let mut count = 0;
+        for entry in std::fs::read_dir(fixture_dir()).unwrap() {
+            let name = entry.unwrap().file_name().into_string().unwrap();
+            let msg = fixture(&name);
+            assert_eq!(msgtype_of(&msg.content), msg.msgtype, "{name}");
+            assert_eq!(msg.conversation_id, "cidOZ3Xq8bLr2ZK0TqKfJc1Tw==", "{name}");
+            count += 1;
+        }
+        assert_eq!(count, 10);
+    }
+
+    #[test]
+    fn picture_file_and_audio_keep_their_fields() {
+        let MsgContent::Picture {
+            download_code,
+            picture_download_code,
+        } = fixture("picture.json").content
+        else {
+            panic!("picture fixture is not a picture");
+        };
+        assert!(download_code.starts_with("mIofN681YE3f"));
+        assert_ne!(download_code, picture_download_code);
+
+        let MsgContent::File {
+            file_name,
+            file_id,
+            space_id,
+            ..
+        } = fixture("file.json").content
+        else {
+            panic!("file fixture is not a file");
+        };
+        assert_eq!(file_name, "report.pdf");
+        assert_eq!(file_id, "147842569832");
+        assert_eq!(space_id, "25186582171");
+
+        let MsgContent::Audio {
+            duration,
+            recognition,
+            ..
+        } = fixture("audio.json").content
+        else {
+            panic!("audio fixture is not audio");
+        };
+        assert_eq!(duration, 4000);
+        assert_eq!(recognition, "hello bot");
+    }
+
+    #[test]
+    fn text_and_rich_text_content() {
+        let msg = fixture("text.json");
+        assert!(matches!(msg.content, MsgContent::Text { .. }));
+        assert!(msg.is_direct());
+
+        let MsgContent::RichText { rich_text } = fixture("rich_text.json").content else {
+            panic!("rich text fixture is not rich text");
+        };
+        assert!(matches!(&rich_text[0], RichText::Text { text } if text == "look at this"));
+        assert!(matches!(rich_text[1], RichText::Picture { .. }));
+    }
+
+    #[test]
+    fn unknown_msgtype_keeps_the_reported_type() {
+        let MsgContent::UnknownMsgType {
+            unknown_msg_type,
+            raw,
+        } = fixture("unknown.json").content
+        else {
+            panic!("unknown fixture decoded to a known type");
+        };
+        assert_eq!(unknown_msg_type, "interactiveCard");
+        assert_eq!(raw["unknownMsgType"], "interactiveCard");
+    }
+
+    #[test]
+    #[cfg(not(feature = "strict-protocol"))]
+    fn content_not_fitting_its_msgtype_is_unknown() {
+        let mut data: Value = serde_json::from_str(&read_fixture("picture.json")).unwrap();
+        data["content"] = json!({ "fileName": "a.txt" });
+        let msg = RobotRecvMessage::from_json(&data.to_string()).unwrap();
+        assert!(matches!(
+            msg.content,
+            MsgContent::UnknownMsgType { ref unknown_msg_type, .. } if unknown_msg_type == "picture"
+        ));
+    }
+}
@@ -0,0 +1,262 @@
+//! Frames and messages sent to DingTalk
+
+use serde::{de, ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use strum::VariantNames;
+
+/// Frame sent over the stream connection, the ACK of a received frame or a ping answer
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientUpStream {
+    pub code: u32,
+    pub headers: StreamUpHeader,
+    pub message: String,
+    pub data: String,
+}
+
+impl ClientUpStream {
+    pub fn new(data: impl Into<String>, message_id: impl Into<String>) -> Self {
+        let data = data.into();
+        let message_id = message_id.into();
+
+        Self {
+            code: 200,
+            headers: StreamUpHeader {
+                message_id,
+                content_type: "application/json".to_owned(),
+            },
+            message: "OK".to_owned(),
+            data,
+        }
+    }
+}
+
+/// Headers of a [`ClientUpStream`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamUpHeader {
+    pub content_type: String, // always application/json
+    pub message_id: String,   // same StreamDownHeaders::message_id
+}
+
+/// Answer to an EVENT frame
+///
+/// Serialized as `{"status": "SUCCESS", "message": "", "data": {...}}`, `data` only when set.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventAckData {
+    pub status: AckStatus,
+    #[serde(default)]
+    pub message: String,
+    /// response data of the event, for events that expect one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// Whether an event was handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum AckStatus {
+    #[default]
+    Success,
+    /// deliver the event again later
+    Later,
+}
+
+impl EventAckData {
+    pub fn success() -> Self {
+        Self::default()
+    }
+
+    /// let DingTalk deliver the event again, see
+    /// [`Client::redelivery_count`](crate::client::Client::redelivery_count)
+    pub fn later(message: impl Into<String>) -> Self {
+        Self {
+            status: AckStatus::Later,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// answer with response `data`
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+/// Message enum to be sent to DingTalk server
+///
+/// Please refer to the [official document](https://open.dingtalk.com/document/orgapp/types-of-messages-sent-by-robots) for the definition of each field
+///
+/// Serialized as `{"msgKey": "sampleText", "msgParam": {...}}`, the same names the send APIs use.
+/// Keys without a variant deserialize to [`MessageTemplate::Raw`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, strum::Display, strum::VariantNames)]
+#[serde(
+    rename_all = "camelCase",
+    tag = "msgKey",
+    content = "msgParam",
+    remote = "Self"
+)]
+#[strum(serialize_all = "camelCase")]
+pub enum MessageTemplate {
+    #[serde(rename_all = "camelCase")]
+    SampleText { content: String },
+    #[serde(rename_all = "camelCase")]
+    SampleMarkdown { title: String, text: String },
+    #[serde(rename_all = "camelCase")]
+    SampleImageMsg {
+        #[serde(rename = "photoURL")]
+        photo_url: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SampleLink {
+        text: String,
+        title: String,
+        pic_url: String,
+        message_url: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SampleActionCard {
+        title: String,
+        text: String,
+        single_title: String,
+        #[serde(rename = "singleURL")]
+        single_url: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SampleActionCard2 {
+        title: String,
+        text: String,
+        action_title_1: String,
+        #[serde(rename = "actionURL1")]
+        action_url_1: String,
+        action_title_2: String,
+        #[serde(rename = "actionURL2")]
+        action_url_2: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SampleActionCard3 {
+        title: String,
+        text: String,
+        action_title_1: String,
+        #[serde(rename = "actionURL1")]
+        action_url_1: String,
+        action_title_2: String,
+        #[serde(rename = "actionURL2")]
+        action_url_2: String,
+        action_title_3: String,
+        #[serde(rename = "actionURL3")]
+        action_url_3: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SampleActionCard4 {
+        title: String,
+        text: String,
+        action_title_1: String,
+        #[serde(rename = "actionURL1")]
+        action_url_1: String,
+        action_title_2: String,
+        #[serde(rename = "actionURL2")]
+        action_url_2: String,
+        action_title_3: String,
+        #[serde(rename = "actionURL3")]
+        action_url_3: String,
+        action_title_4: String,
+        #[serde(rename = "actionURL4")]
+        action_url_4: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SampleActionCard5 {
+        title: String,
+        text: String,
+        action_title_1: String,
+        #[serde(rename = "actionURL1")]
+        action_url_1: String,
+        action_title_2: String,
+        #[serde(rename = "actionURL2")]
+        action_url_2: String,
+        action_title_3: String,
+        #[serde(rename = "actionURL3")]
+        action_url_3: String,
+        action_title_4: String,
+        #[serde(rename = "actionURL4")]
+        action_url_4: String,
+        action_title_5: String,
+        #[serde(rename = "actionURL5")]
+        action_url_5: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SampleActionCard6 {
+        title: String,
+        text: String,
+        button_title_1: String,
+        button_url_1: String,
+        button_title_2: String,
+        button_url_2: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SampleAudio { media_id: String, duration: String },
+    #[serde(rename_all = "camelCase")]
+    SampleFile {
+        media_id: String,
+        file_name: String,
+        file_type: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SampleVideo {
+        duration: String,
+        video_media_id: String,
+        video_type: String,
+        pic_media_id: String,
+    },
+    /// Any other `msgKey`, `msg_param` is sent as is
+    ///
+    /// For message types added to the send APIs before this enum knows them.
+    #[serde(skip)]
+    #[strum(to_string = "{msg_key}")]
+    Raw {
+        msg_key: String,
+        msg_param: Value,
+    },
+}
+
+impl Serialize for MessageTemplate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            MessageTemplate::Raw { msg_key, msg_param } => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("msgKey", msg_key)?;
+                map.serialize_entry("msgParam", msg_param)?;
+                map.end()
+            }
+            _ => MessageTemplate::serialize(self, serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for MessageTemplate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        match value["msgKey"].as_str() {
+            Some(msg_key) if !Self::VARIANTS.contains(&msg_key) => Ok(MessageTemplate::Raw {
+                msg_key: msg_key.to_owned(),
+                msg_param: value["msgParam"].clone(),
+            }),
+            _ => MessageTemplate::deserialize(value).map_err(de::Error::custom),
+        }
+    }
+}
+
+impl TryInto<String> for MessageTemplate {
+    type Error = serde_json::Error;
+
+    /// the `msgParam` json string
+    fn try_into(self) -> std::result::Result<String, Self::Error> {
+        match serde_json::to_value(&self)? {
+            Value::Object(mut map) => serde_json::to_string(&map.remove("msgParam")),
+            _ => Err(serde::ser::Error::custom(
+                "message template is not an object",
+            )),
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Reflection of the wire types in [`protocol`](crate::protocol), which stays free of Bevy
+//!
+//! The definitions are repeated for [`impl_reflect!`], keep them in step with the wire types when
+//! fields are added.
+
+use bevy::reflect::impl_reflect;
+
+use crate::protocol::down::{
+    DownstreamEnvelope, EmotionContent, EventData, MsgContent, RichText, RobotRecvMessage,
+    StickerContent, User,
+};
+use crate::protocol::up::MessageTemplate;
+
+impl_reflect!(
+    #[type_path = "bevy_stream_dingtalk::protocol::down"]
+    struct DownstreamEnvelope {
+        frame_type: String,
+        topic: String,
+        message_id: String,
+        time: String,
+        content_type: String,
+        connection_id: String,
+        data: String,
+    }
+);
+
+impl_reflect!(
+    #[type_path = "bevy_stream_dingtalk::protocol::down"]
+    struct EventData {
+        event_type: String,
+        event_born_time: String,
+        event_id: String,
+        event_corp_id: String,
+        event_unified_app_id: String,
+        data: String,
+    }
+);
+
+impl_reflect!(
+    #[type_path = "bevy_stream_dingtalk::protocol::down"]
+    struct RobotRecvMessage {
+        msg_id: String,
+        msgtype: String,
+        content: MsgContent,
+        conversation_id: String,
+        conversation_type: String,
+        conversation_title: String,
+        at_users: Vec<User>,
+        is_in_at_list: bool,
+        chatbot_corp_id: String,
+        chatbot_user_id: String,
+        sender_id: String,
+        sender_nick: String,
+        sender_corp_id: String,
+        sender_staff_id: String,
+        session_webhook_expired_time: u64,
+        session_webhook: String,
+        is_admin: bool,
+        create_at: u64,
+    }
+);
+
+impl_reflect!(
+    #[type_path = "bevy_stream_dingtalk::protocol::down"]
+    struct User {
+        dingtalk_id: String,
+        staff_id: String,
+    }
+);
+
+impl_reflect!(
+    #[type_path = "bevy_stream_dingtalk::protocol::down"]
+    enum MsgContent {
+        Text {
+            content: String,
+        },
+        File {
+            download_code: String,
+            file_name: String,
+            file_id: String,
+            space_id: String,
+        },
+        Picture {
+            download_code: String,
+            picture_download_code: String,
+        },
+        RichText {
+            rich_text: Vec<RichText>,
+        },
+        Audio {
+            duration: u32,
+            download_code: String,
+            recognition: String,
+        },
+        Video {
+            duration: u32,
+            download_code: String,
+            video_type: String,
+        },
+        Location {
+            latitude: f64,
+            longitude: f64,
+            title: String,
+            address: String,
+        },
+        UnknownMsgType {
+            unknown_msg_type: String,
+            #[reflect(ignore)]
+            raw: serde_json::Value,
+        },
+        Sticker(StickerContent),
+        Emotion(EmotionContent),
+        Custom {
+            msgtype: String,
+            #[reflect(ignore)]
+            content: crate::protocol::down::CustomContent,
+        },
+    }
+);
+
+impl_reflect!(
+    #[type_path = "bevy_stream_dingtalk::protocol::down"]
+    struct StickerContent {
+        download_code: String,
+        sticker_id: String,
+    }
+);
+
+impl_reflect!(
+    #[type_path = "bevy_stream_dingtalk::protocol::down"]
+    struct EmotionContent {
+        emotion_id: String,
+        emotion_name: String,
+        download_code: String,
+    }
+);
+
+impl_reflect!(
+    #[type_path = "bevy_stream_dingtalk::protocol::down"]
+    enum RichText {
+        Text {
+            text: String,
+        },
+        Picture {
+            download_code: String,
+            picture_download_code: String,
+            r#type: String,
+        },
+    }
+);
+
+impl_reflect!(
+    #[type_path = "bevy_stream_dingtalk::protocol::up"]
+    enum MessageTemplate {
+        SampleText {
+            content: String,
+        },
+        SampleMarkdown {
+            title: String,
+            text: String,
+        },
+        SampleImageMsg {
+            photo_url: String,
+        },
+        SampleLink {
+            text: String,
+            title: String,
+            pic_url: String,
+            message_url: String,
+        },
+        SampleActionCard {
+            title: String,
+            text: String,
+            single_title: String,
+            single_url: String,
+        },
+        SampleActionCard2 {
+            title: String,
+            text: String,
+            action_title_1: String,
+            action_url_1: String,
+            action_title_2: String,
+            action_url_2: String,
+        },
+        SampleActionCard3 {
+            title: String,
+            text: String,
+            action_title_1: String,
+            action_url_1: String,
+            action_title_2: String,
+            action_url_2: String,
+            action_title_3: String,
+            action_url_3: String,
+        },
+        SampleActionCard4 {
+            title: String,
+            text: String,
+            action_title_1: String,
+            action_url_1: String,
+            action_title_2: String,
+            action_url_2: String,
+            action_title_3: String,
+            action_url_3: String,
+            action_title_4: String,
+            action_url_4: String,
+        },
+        SampleActionCard5 {
+            title: String,
+            text: String,
+            action_title_1: String,
+            action_url_1: String,
+            action_title_2: String,
+            action_url_2: String,
+            action_title_3: String,
+            action_url_3: String,
+            action_title_4: String,
+            action_url_4: String,
+            action_title_5: String,
+            action_url_5: String,
+        },
+        SampleActionCard6 {
+            title: String,
+            text: String,
+            button_title_1: String,
+            button_url_1: String,
+            button_title_2: String,
+            button_url_2: String,
+        },
+        SampleAudio {
+            media_id: String,
+            duration: String,
+        },
+        SampleFile {
+            media_id: String,
+            file_name: String,
+            file_type: String,
+        },
+        SampleVideo {
+            duration: String,
+            video_media_id: String,
+            video_type: String,
+            pic_media_id: String,
+        },
+        Raw {
+            msg_key: String,
+            #[reflect(ignore)]
+            msg_param: serde_json::Value,
+        },
+    }
+);
@@ -0,0 +1,264 @@
+//! Pluggable persistence shared by every feature that has to survive a restart
+//!
+//! Values are raw bytes grouped into namespaces. The client keeps its token cache (namespace
+//! [`TOKENS`]) and the duplicate frame filter ([`DEDUPE`]) here. [`MemoryStorage`] is the default,
+//! `sled` and `sqlite` features add on-disk stores, and games can implement [`Storage`] on top of
+//! their own save system.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// namespace of cached access tokens
+pub const TOKENS: &str = "tokens";
+/// namespace of frame ids already dispatched
+pub const DEDUPE: &str = "dedupe";
+/// namespace of idempotency keys of confirmed sends
+pub const SENT: &str = "sent";
+/// namespace of sends held back by [quiet hours](crate::client::Client::quiet_hours)
+pub const HELD: &str = "held";
+/// namespace of held sends given up after repeated failures, see
+/// [`Client::release_held`](crate::client::Client::release_held)
+pub const HELD_FAILED: &str = "held_failed";
+/// namespace of incident escalations waiting for their deadline, see
+/// [`Notifier`](crate::notify::Notifier)
+pub const ESCALATIONS: &str = "escalations";
+/// namespace of recorded messages, see [`MessageHistory`](crate::history::MessageHistory)
+pub const HISTORY: &str = "history";
+/// namespace of conversation tags, see [`Conversations`](crate::conversations::Conversations)
+pub const CONVERSATIONS: &str = "conversations";
+
+/// Namespaced key-value store
+///
+/// Calls happen on the async runtime and must return quickly, implementations backed by slow
+/// I/O should buffer writes.
+pub trait Storage: Send + Sync + 'static {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>>;
+
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()>;
+
+    fn remove(&self, namespace: &str, key: &str) -> Result<()>;
+
+    /// every entry of `namespace` whose key starts with `prefix`, ordered by key
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>>;
+}
+
+impl dyn Storage {
+    pub fn get_json<T: DeserializeOwned>(&self, namespace: &str, key: &str) -> Result<Option<T>> {
+        self.get(namespace, key)?
+            .map(|value| serde_json::from_slice(&value))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    pub fn put_json<T: Serialize>(&self, namespace: &str, key: &str, value: &T) -> Result<()> {
+        self.put(namespace, key, &serde_json::to_vec(value)?)
+    }
+
+    /// store `key` with the unix time of `now` as value, for
+    /// [`remove_expired`](crate::storage::Storage#method.remove_expired)
+    pub fn mark(&self, namespace: &str, key: &str, now: DateTime<Utc>) -> Result<()> {
+        self.put(namespace, key, now.timestamp().to_string().as_bytes())
+    }
+
+    /// drop entries stored by [`mark`](crate::storage::Storage#method.mark) more than
+    /// `max_age_secs` before `now`
+    pub fn remove_expired(
+        &self,
+        namespace: &str,
+        max_age_secs: i64,
+        now: DateTime<Utc>,
+    ) -> Result<usize> {
+        let now = now.timestamp();
+        let mut removed = 0;
+        for (key, at) in self.scan(namespace, "")? {
+            let expired = String::from_utf8_lossy(&at)
+                .parse::<i64>()
+                .map_or(true, |at| now - at > max_age_secs);
+            if expired {
+                self.remove(namespace, &key)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Storage kept in memory, lost on exit
+#[derive(Debug, Default)]
+pub struct MemoryStorage(Mutex<BTreeMap<(String, String), Vec<u8>>>);
+
+impl MemoryStorage {
+    pub fn new() -> Arc<Self> {
+        Arc::default()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        let map = self.0.lock().unwrap();
+        Ok(map.get(&(namespace.to_owned(), key.to_owned())).cloned())
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+        let mut map = self.0.lock().unwrap();
+        map.insert((namespace.to_owned(), key.to_owned()), value.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, namespace: &str, key: &str) -> Result<()> {
+        let mut map = self.0.lock().unwrap();
+        map.remove(&(namespace.to_owned(), key.to_owned()));
+        Ok(())
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let map = self.0.lock().unwrap();
+        Ok(map
+            .range((namespace.to_owned(), prefix.to_owned())..)
+            .take_while(|((ns, key), _)| ns == namespace && key.starts_with(prefix))
+            .map(|((_, key), value)| (key.clone(), value.clone()))
+            .collect())
+    }
+}
+
+/// Storage in a sled database, one tree per namespace
+#[cfg(feature = "sled")]
+#[derive(Debug, Clone)]
+pub struct SledStorage(sled::Db);
+
+#[cfg(feature = "sled")]
+impl SledStorage {
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Arc<Self>> {
+        Ok(Arc::new(Self(sled::open(path)?)))
+    }
+
+    pub fn from_db(db: sled::Db) -> Arc<Self> {
+        Arc::new(Self(db))
+    }
+}
+
+#[cfg(feature = "sled")]
+impl Storage for SledStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.open_tree(namespace)?.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+        self.0.open_tree(namespace)?.insert(key, value)?;
+        Ok(())
+    }
+
+    fn remove(&self, namespace: &str, key: &str) -> Result<()> {
+        self.0.open_tree(namespace)?.remove(key)?;
+        Ok(())
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        self.0
+            .open_tree(namespace)?
+            .scan_prefix(prefix)
+            .map(|entry| {
+                let (key, value) = entry?;
+                Ok((String::from_utf8(key.to_vec())?, value.to_vec()))
+            })
+            .collect()
+    }
+}
+
+/// Storage in a single sqlite table
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteStorage(Mutex<rusqlite::Connection>);
+
+#[cfg(feature = "sqlite")]
+impl SqliteStorage {
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Arc<Self>> {
+        Self::from_connection(rusqlite::Connection::open(path)?)
+    }
+
+    pub fn from_connection(connection: rusqlite::Connection) -> Result<Arc<Self>> {
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS dingtalk_storage (
+                namespace TEXT NOT NULL,
+                key TEXT NOT NULL,
+                value BLOB NOT NULL,
+                PRIMARY KEY (namespace, key)
+            )",
+            (),
+        )?;
+        Ok(Arc::new(Self(Mutex::new(connection))))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Storage for SqliteStorage {
+    fn get(&self, namespace: &str, key: &str) -> Result<Option<Vec<u8>>> {
+        use rusqlite::OptionalExtension;
+
+        Ok(self
+            .0
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT value FROM dingtalk_storage WHERE namespace = ?1 AND key = ?2",
+                (namespace, key),
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn put(&self, namespace: &str, key: &str, value: &[u8]) -> Result<()> {
+        self.0.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO dingtalk_storage (namespace, key, value) VALUES (?1, ?2, ?3)",
+            (namespace, key, value),
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, namespace: &str, key: &str) -> Result<()> {
+        self.0.lock().unwrap().execute(
+            "DELETE FROM dingtalk_storage WHERE namespace = ?1 AND key = ?2",
+            (namespace, key),
+        )?;
+        Ok(())
+    }
+
+    fn scan(&self, namespace: &str, prefix: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let connection = self.0.lock().unwrap();
+        let mut statement = connection.prepare(
+            "SELECT key, value FROM dingtalk_storage
+             WHERE namespace = ?1 AND substr(key, 1, length(?2)) = ?2 ORDER BY key",
+        )?;
+        let rows =
+            statement.query_map((namespace, prefix), |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    #[test]
+    fn marks_expire_relative_to_now() {
+        let storage: Arc<dyn Storage> = MemoryStorage::new();
+        let start = DateTime::from_timestamp(1_718_083_800, 0).unwrap();
+        storage.mark(DEDUPE, "old", start).unwrap();
+        storage
+            .mark(DEDUPE, "new", start + Duration::seconds(50))
+            .unwrap();
+
+        let now = start + Duration::seconds(100);
+        assert_eq!(storage.remove_expired(DEDUPE, 60, now).unwrap(), 1);
+        assert!(storage.get(DEDUPE, "old").unwrap().is_none());
+        assert!(storage.get(DEDUPE, "new").unwrap().is_some());
+    }
+}
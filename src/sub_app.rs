@@ -0,0 +1,185 @@
+//! Running the DingTalk pipeline in its own [`SubApp`]
+//!
+//! [`DingTalkSubAppPlugin`] builds the [`StreamDingTalkPlugin`] into a separate app with its own
+//! world and schedules. Frames are turned into events and handled there, the main world only
+//! sees what crosses the sync point, which runs once per main update before the sub app runs:
+//!
+//! - events registered with [`DingTalkSubAppPlugin::to_main`] are copied into the main world,
+//!   as the sync point follows the main schedule its systems read them two updates after the
+//!   sub app sent them
+//! - events registered with [`DingTalkSubAppPlugin::to_sub`] and [`AppExit`] are copied into the
+//!   sub app
+//! - [`KeepConnected`] and [`DingTalkSubscriptions`] of the main world replace those of the sub
+//!   app whenever they changed
+//!
+//! [`DingTalk`](crate::param::DingTalk), [`Notifier`], [`Conversations`] and [`UserDirectory`]
+//! share their state with the sub app and work from main world systems as usual.
+//!
+//! ```ignore
+//! app.add_plugins(
+//!     DingTalkSubAppPlugin::new(StreamDingTalkPlugin::new(CLIENT_ID, CLIENT_SECRET))
+//!         .setup(|sub| {
+//!             sub.add_plugins(CommandRouter::<GameCommand>::new("/"));
+//!         })
+//!         .to_main::<CommandReceived<GameCommand>>(),
+//! );
+//! ```
+//!
+//! Tests can step the pipeline without the main world with
+//! `app.sub_app_mut(DingTalkSubApp).update()`.
+
+use std::sync::Mutex;
+
+use bevy::app::{AppExit, AppLabel, SubApp};
+use bevy::ecs::event::ManualEventReader;
+use bevy::prelude::*;
+use bevy::time::TimePlugin;
+
+use crate::client::{DingTalkClient, KeepConnected};
+use crate::conversations::Conversations;
+use crate::directory::UserDirectory;
+use crate::notify::Notifier;
+use crate::outbound::OutboundQueue;
+use crate::plugin::StreamDingTalkPlugin;
+use crate::subscriptions::DingTalkSubscriptions;
+
+/// Label of the sub app added by [`DingTalkSubAppPlugin`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, AppLabel)]
+pub struct DingTalkSubApp;
+
+/// copies data between the main world and the sub app world, in that argument order
+type SyncFn = Box<dyn Fn(&mut World, &mut World) + Send + Sync>;
+type SetupFn = Box<dyn Fn(&mut App) + Send + Sync>;
+type SyncFactory = Box<dyn Fn(&mut App) -> SyncFn + Send + Sync>;
+
+/// Builds [`StreamDingTalkPlugin`] into the [`DingTalkSubApp`] sub app
+pub struct DingTalkSubAppPlugin {
+    /// taken on build
+    plugin: Mutex<Option<StreamDingTalkPlugin>>,
+    setups: Vec<SetupFn>,
+    /// registers the events of a sync on the main app and makes it
+    syncs: Vec<SyncFactory>,
+}
+
+impl DingTalkSubAppPlugin {
+    pub fn new(plugin: StreamDingTalkPlugin) -> Self {
+        Self {
+            plugin: Mutex::new(Some(plugin)),
+            setups: vec![],
+            syncs: vec![],
+        }
+        .to_sub::<AppExit>()
+        .resource_to_sub::<KeepConnected>()
+        .resource_to_sub::<DingTalkSubscriptions>()
+    }
+
+    /// add plugins or systems to the sub app, after the stream plugin
+    pub fn setup(mut self, setup: impl Fn(&mut App) + Send + Sync + 'static) -> Self {
+        self.setups.push(Box::new(setup));
+        self
+    }
+
+    /// copy events of type `E` sent in the sub app into the main world
+    pub fn to_main<E: Event + Clone>(mut self) -> Self {
+        self.syncs.push(Box::new(|main: &mut App| {
+            main.add_event::<E>();
+            let reader = Mutex::new(ManualEventReader::<E>::default());
+            Box::new(move |main: &mut World, sub: &mut World| {
+                copy_events(&reader, sub, main);
+            })
+        }));
+        self
+    }
+
+    /// copy events of type `E` sent in the main world into the sub app
+    pub fn to_sub<E: Event + Clone>(mut self) -> Self {
+        self.syncs.push(Box::new(|main: &mut App| {
+            main.add_event::<E>();
+            let reader = Mutex::new(ManualEventReader::<E>::default());
+            Box::new(move |main: &mut World, sub: &mut World| {
+                copy_events(&reader, main, sub);
+            })
+        }));
+        self
+    }
+
+    /// replace resource `R` of the sub app with the main world's whenever that changed
+    pub fn resource_to_sub<R: Resource + Clone>(mut self) -> Self {
+        self.syncs.push(Box::new(|_: &mut App| {
+            Box::new(|main: &mut World, sub: &mut World| {
+                if main.is_resource_changed::<R>() {
+                    sub.insert_resource(main.resource::<R>().clone());
+                }
+            })
+        }));
+        self
+    }
+}
+
+/// send the events of `from` not yet seen by `reader` in `to`
+fn copy_events<E: Event + Clone>(
+    reader: &Mutex<ManualEventReader<E>>,
+    from: &World,
+    to: &mut World,
+) {
+    let Some(events) = from.get_resource::<Events<E>>() else {
+        return;
+    };
+    let copied: Vec<E> = reader.lock().unwrap().read(events).cloned().collect();
+    if !copied.is_empty() {
+        to.send_event_batch(copied);
+    }
+}
+
+/// insert a clone of the sub app's `R` into the main world
+fn share<R: Resource + Clone>(sub: &App, main: &mut App) {
+    if let Some(resource) = sub.world.get_resource::<R>() {
+        main.insert_resource(resource.clone());
+    }
+}
+
+impl Plugin for DingTalkSubAppPlugin {
+    fn build(&self, app: &mut App) {
+        let Some(plugin) = self.plugin.lock().unwrap().take() else {
+            warn!("DingTalkSubAppPlugin built twice, the sub app exists already");
+            return;
+        };
+        let mut sub = App::new();
+        sub.add_plugins(TimePlugin).add_plugins(plugin);
+        for setup in &self.setups {
+            setup(&mut sub);
+        }
+
+        if let Some(client) = sub.world.get_resource::<DingTalkClient>() {
+            app.insert_resource(client.shared());
+        }
+        share::<OutboundQueue>(&sub, app);
+        share::<Conversations>(&sub, app);
+        share::<UserDirectory>(&sub, app);
+        share::<Notifier>(&sub, app);
+        share::<KeepConnected>(&sub, app);
+        share::<DingTalkSubscriptions>(&sub, app);
+
+        let syncs: Vec<SyncFn> = self.syncs.iter().map(|factory| factory(app)).collect();
+        app.insert_sub_app(
+            DingTalkSubApp,
+            SubApp::new(sub, move |main, sub| {
+                for sync in &syncs {
+                    sync(main, &mut sub.world);
+                }
+            }),
+        );
+    }
+
+    fn finish(&self, app: &mut App) {
+        if let Ok(sub) = app.get_sub_app_mut(DingTalkSubApp) {
+            sub.finish();
+        }
+    }
+
+    fn cleanup(&self, app: &mut App) {
+        if let Ok(sub) = app.get_sub_app_mut(DingTalkSubApp) {
+            sub.cleanup();
+        }
+    }
+}
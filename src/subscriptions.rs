@@ -0,0 +1,73 @@
+//! [`DingTalkSubscriptions`] resource controlling what the stream delivers
+
+use bevy::prelude::Resource;
+
+use crate::client::{ClientConfig, Subscription};
+use crate::topics::Topic;
+
+/// Subscriptions of the stream connection, editable from systems
+///
+/// Any change reconnects the stream with the new set, e.g. card callbacks can be enabled only
+/// while the game is in its lobby state. Listeners of unsubscribed topics simply stop receiving.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct DingTalkSubscriptions(pub Vec<Subscription>);
+
+impl Default for DingTalkSubscriptions {
+    fn default() -> Self {
+        let mut subscriptions = Self(ClientConfig::default().subscriptions);
+        subscriptions.subscribe_topic(Topic::ROBOT_MESSAGES);
+        subscriptions
+    }
+}
+
+impl DingTalkSubscriptions {
+    pub fn contains(&self, r#type: &str, topic: &str) -> bool {
+        self.0
+            .iter()
+            .any(|s| s.r#type == r#type && s.topic == topic)
+    }
+
+    /// add a subscription, does nothing when it already exists
+    pub fn subscribe(&mut self, r#type: impl Into<String>, topic: impl Into<String>) {
+        let subscription = Subscription {
+            r#type: r#type.into(),
+            topic: topic.into(),
+        };
+        if !self.0.contains(&subscription) {
+            self.0.push(subscription);
+        }
+    }
+
+    /// subscribe to `topic` with its [frame type](Topic::frame_type)
+    pub fn subscribe_topic(&mut self, topic: Topic) {
+        self.subscribe(topic.frame_type(), topic);
+    }
+
+    pub fn unsubscribe(&mut self, r#type: &str, topic: &str) {
+        self.0.retain(|s| !(s.r#type == r#type && s.topic == topic));
+    }
+
+    /// receive robot messages
+    pub fn set_robot_messages(&mut self, enabled: bool) {
+        self.set_callback(Topic::ROBOT_MESSAGES, enabled);
+    }
+
+    /// receive interactive card callbacks
+    pub fn set_card_callbacks(&mut self, enabled: bool) {
+        self.set_callback(Topic::CARD_CALLBACKS, enabled);
+    }
+
+    /// receive AI assistant skill invocations as
+    /// [`AssistantSkillInvoked`](crate::event::AssistantSkillInvoked) events
+    pub fn set_assistant_skills(&mut self, enabled: bool) {
+        self.set_callback(Topic::AI_ASSISTANT, enabled);
+    }
+
+    fn set_callback(&mut self, topic: Topic, enabled: bool) {
+        if enabled {
+            self.subscribe_topic(topic);
+        } else {
+            self.unsubscribe(topic.frame_type(), topic.as_str());
+        }
+    }
+}
@@ -2,40 +2,104 @@ use bevy::prelude::*;
 use bevy::tasks::TaskPool;
 
 
-use crate::client::{ConnectionState, Client, DingTalkClient, AsyncRuntime};
-use crate::client::down::RobotRecvMessage;
+use crate::bridge::BridgeReceiver;
+use crate::client::{ConnectionState, Client, DingTalkClient, AsyncRuntime, KeepConnected};
 use crate::client::up::EventAckData;
-use crate::constant::TOPIC_ROBOT;
+use crate::client::down::MsgContent;
+use crate::error::{DingTalkError, ErrorContext};
+use crate::event::{
+    CreateGroupRequest, EmotionReceived, HealthCheckResultEvent, RawFrameReceived, RobotMessageReceived,
+    StickerReceived,
+};
+use crate::outbound::{Outbound, OutboundQueue};
+use crate::plugin::DingTalkSettings;
+use crate::subscriptions::DingTalkSubscriptions;
+use crate::targets::STATS;
+use crate::topics::Topic;
 
+/// connect in the background, back to [`ConnectionState::Disconnected`] once the connection
+/// ends or [`ConnectionState::Stopped`] when it gave up
 pub(crate) fn connect_to_server(
     mut client: ResMut<DingTalkClient>,
     rt: Res<AsyncRuntime>,
     mut state: ResMut<NextState<ConnectionState>>,
+    settings: Res<DingTalkSettings>,
+    subscriptions: Res<DingTalkSubscriptions>,
+    mut registered: Local<bool>,
 ) {
+    let message_filter = settings.message_filter;
+    let raw_topics = settings.raw_topics.clone();
+    let subscriptions = subscriptions.0.clone();
+    // listeners outlive the connection, only add them on the first connect
+    let register = !std::mem::replace(&mut *registered, true);
 
     let client = client.clone();
-    rt.spawn(async {
-        client
-            .register_callback_listener(TOPIC_ROBOT, |client, msg| {
-                async move {
-                    let RobotRecvMessage {
-                        content,
-                        sender_staff_id,
-                        conversation_id,
-                        conversation_type,
-                        sender_nick,
-                        ..
-                    } = msg;
-                    println!("Message Received from {}: {:?}", sender_nick, content);
-
-                    Ok::<_, anyhow::Error>(())
-                }
-            })
-            .register_all_event_listener(|msg| {
-                println!("event: {:?}", msg);
-                EventAckData::default()
-            })
-            .connect().await.unwrap();
+    rt.spawn(async move {
+        if register {
+            client
+                .clone()
+                .register_enveloped_listener(Topic::ROBOT_MESSAGES, message_filter, |client, msg, envelope| {
+                    async move {
+                        debug!("Message Received from {}: {:?}", msg.sender_nick, msg.content);
+                        let tenant = msg.tenant();
+                        match &msg.content {
+                            MsgContent::Sticker(sticker) => {
+                                client.bridge.send_event(StickerReceived {
+                                    tenant: tenant.clone(),
+                                    message: msg.clone(),
+                                    sticker: sticker.clone(),
+                                })
+                            }
+                            MsgContent::Emotion(emotion) => {
+                                client.bridge.send_event(EmotionReceived {
+                                    tenant: tenant.clone(),
+                                    message: msg.clone(),
+                                    emotion: emotion.clone(),
+                                })
+                            }
+                            _ => {}
+                        }
+                        client.bridge.send_event(RobotMessageReceived {
+                            tenant,
+                            ack: client.ack_token(&msg),
+                            redelivery_count: client.redelivery_count(&msg.msg_id),
+                            message: msg,
+                            envelope,
+                        });
+
+                        Ok::<_, anyhow::Error>(())
+                    }
+                })
+                .register_all_event_listener(|msg| {
+                    println!("event: {:?}", msg);
+                    EventAckData::default()
+                });
+        }
+        if register {
+            for topic in raw_topics {
+                client.clone().register_raw_listener(topic, |client, envelope| async move {
+                    client.bridge.send_event(RawFrameReceived(envelope));
+                    Ok(())
+                });
+            }
+        }
+        client.config.lock().unwrap().subscriptions = subscriptions;
+        let next = match client.clone().connect().await {
+            Ok(()) => ConnectionState::Disconnected,
+            // already reported by the client
+            Err(e) if matches!(e.downcast_ref(), Some(DingTalkError::ReconnectStopped { .. })) => {
+                ConnectionState::Stopped
+            }
+            Err(e) => {
+                client.report_error(ErrorContext::Connection, "connect", &e);
+                ConnectionState::Disconnected
+            }
+        };
+        client.bridge.run(move |world| {
+            if let Some(mut state) = world.get_resource_mut::<NextState<ConnectionState>>() {
+                state.set(next);
+            }
+        });
     });
 
     state.set(ConnectionState::Connecting);
@@ -57,8 +121,99 @@ pub(crate) fn connect_to_server(
     // );
 }
 
-pub(crate) fn handle_network_events(
+/// drop the connection when [`KeepConnected`] turns off, [`connect_to_server`] reconnects once
+/// it is back on
+pub(crate) fn apply_keep_connected(
+    keep: Res<KeepConnected>,
+    client: Res<DingTalkClient>,
+    mut state: ResMut<NextState<ConnectionState>>,
+) {
+    if !keep.is_changed() || keep.is_added() || keep.0 {
+        return;
+    }
+
+    debug!("disconnect, no longer wanted");
+    client.exit();
+    state.set(ConnectionState::Disconnected);
+}
+
+/// keep the stream connected only while `S` is one of `states`
+pub(crate) fn keep_connected_in_states<S: States>(
+    states: Vec<S>,
+) -> impl FnMut(Res<State<S>>, ResMut<KeepConnected>) {
+    move |state, mut keep| {
+        keep.set_if_neq(KeepConnected(states.contains(state.get())));
+    }
+}
+
+/// run [`Client::health_check`] once when enabled in the plugin
+pub(crate) fn run_health_check(
+    client: Res<DingTalkClient>,
+    rt: Res<AsyncRuntime>,
+    settings: Res<DingTalkSettings>,
+) {
+    if !settings.health_check {
+        return;
+    }
+
+    let client = client.clone();
+    rt.spawn(async move {
+        let report = client.health_check().await;
+        client.bridge.send_event(HealthCheckResultEvent(report));
+    });
+}
+
+/// send messages held by quiet hours once they ended
+pub(crate) fn release_held_sends(client: Res<DingTalkClient>, rt: Res<AsyncRuntime>) {
+    let client = client.clone();
+    rt.spawn(async move {
+        client.release_held().await;
+    });
+}
+
+/// one INFO line with the connection counters and the outbound queue depth
+pub(crate) fn log_connection_stats(client: Res<DingTalkClient>, queue: Res<OutboundQueue>) {
+    info!(
+        target: STATS,
+        "{} queue={}",
+        client.connection_stats(),
+        queue.pending()
+    );
+}
 
+/// hand group creation requests to the outbound worker
+pub(crate) fn queue_group_creation(
+    mut requests: EventReader<CreateGroupRequest>,
+    queue: Res<OutboundQueue>,
 ) {
+    for request in requests.read() {
+        queue.push(Outbound::CreateGroup(request.clone()));
+    }
+}
+
+/// reconnect with the new subscription set whenever systems change it
+pub(crate) fn apply_subscriptions(
+    client: Res<DingTalkClient>,
+    subscriptions: Res<DingTalkSubscriptions>,
+) {
+    if !subscriptions.is_changed() || subscriptions.is_added() {
+        return;
+    }
 
+    let mut config = client.config.lock().unwrap();
+    if config.subscriptions == subscriptions.0 {
+        return;
+    }
+    debug!("subscriptions changed: {:?}", subscriptions.0);
+    config.subscriptions = subscriptions.0.clone();
+    drop(config);
+    client.restart();
+}
+
+pub(crate) fn handle_network_events(world: &mut World) {
+    world.resource_scope(|world, mut bridge: Mut<BridgeReceiver>| {
+        while let Some(command) = bridge.try_recv() {
+            command(world);
+        }
+    });
 }
\ No newline at end of file
@@ -0,0 +1,22 @@
+//! Log targets of the client subsystems
+//!
+//! Everything else logs under its module path. Targets are filtered like modules, e.g. the
+//! filter `bevy_stream_dingtalk=debug,bevy_stream_dingtalk::ws=info` keeps message logs while
+//! silencing ping/pong and raw frames.
+//!
+//! Raw frame logging can also be changed at runtime through
+//! [`ClientConfig::frame_logging`](crate::client::ClientConfig::frame_logging) on
+//! [`Client::config`](crate::client::Client::config), which the Bevy plugin exposes through the
+//! [`DingTalkClient`](crate::client::DingTalkClient) resource.
+
+/// websocket connections, frames, ping/pong and reconnects
+pub const WS: &str = "bevy_stream_dingtalk::ws";
+/// requests to the DingTalk http APIs
+pub const HTTP: &str = "bevy_stream_dingtalk::http";
+/// access token acquisition, caching and refresh
+pub const TOKEN: &str = "bevy_stream_dingtalk::token";
+/// events handed from the async runtime to the Bevy world
+pub const BRIDGE: &str = "bevy_stream_dingtalk::bridge";
+/// periodic connection summary, see
+/// [`StreamDingTalkPlugin::stats_log_interval`](crate::prelude::StreamDingTalkPlugin::stats_log_interval)
+pub const STATS: &str = "bevy_stream_dingtalk::stats";
@@ -0,0 +1,97 @@
+//! Stream topics known to this crate
+//!
+//! A subscription is a frame type and a topic. [`Topic::KNOWN`] lists the topics the crate has
+//! types for, anything else can still be received through [`Topic::custom`], e.g. with
+//! [`StreamDingTalkPlugin::raw_topic`](crate::prelude::StreamDingTalkPlugin::raw_topic).
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// A stream topic, compare it with the topic of a frame through [`Topic::as_str`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Topic(Cow<'static, str>);
+
+impl Topic {
+    /// messages sent to the robot, in groups or one-on-one
+    pub const ROBOT_MESSAGES: Topic = Topic(Cow::Borrowed("/v1.0/im/bot/messages/get"));
+    /// clicks and form submits of interactive cards
+    pub const CARD_CALLBACKS: Topic = Topic(Cow::Borrowed("/v1.0/card/instances/callback"));
+    /// skill invocations of an AI assistant
+    pub const AI_ASSISTANT: Topic = Topic(Cow::Borrowed("/v1.0/graph/api/invoke"));
+    /// every event of the app's event subscription, e.g. group or contact changes
+    pub const ALL_EVENTS: Topic = Topic(Cow::Borrowed("*"));
+
+    /// topics this crate has types for
+    pub const KNOWN: [Topic; 4] = [
+        Self::ROBOT_MESSAGES,
+        Self::CARD_CALLBACKS,
+        Self::AI_ASSISTANT,
+        Self::ALL_EVENTS,
+    ];
+
+    /// a topic this crate does not know
+    pub fn custom(topic: impl Into<String>) -> Self {
+        Self(Cow::Owned(topic.into()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// frame type of subscriptions to this topic, `EVENT` or `CALLBACK`
+    pub fn frame_type(&self) -> &'static str {
+        if *self == Self::ALL_EVENTS {
+            "EVENT"
+        } else {
+            "CALLBACK"
+        }
+    }
+
+    /// official document of the topic's payload, `None` for custom topics
+    pub fn docs(&self) -> Option<&'static str> {
+        match self.as_str() {
+            "/v1.0/im/bot/messages/get" => {
+                Some("https://open.dingtalk.com/document/orgapp/receive-message")
+            }
+            "/v1.0/card/instances/callback" => {
+                Some("https://open.dingtalk.com/document/orgapp/interactive-card-callback")
+            }
+            "*" => Some("https://open.dingtalk.com/document/orgapp/org-event-overview"),
+            _ => None,
+        }
+    }
+
+    pub fn is_known(&self) -> bool {
+        Self::KNOWN.contains(self)
+    }
+}
+
+impl AsRef<str> for Topic {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Display for Topic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<Topic> for String {
+    fn from(topic: Topic) -> Self {
+        topic.0.into_owned()
+    }
+}
+
+impl PartialEq<str> for Topic {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<String> for Topic {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
@@ -0,0 +1,110 @@
+use bevy_stream_dingtalk::command::{BotCommand, CommandParseError};
+
+#[derive(BotCommand, Debug, PartialEq)]
+enum GameCommand {
+    /// join a team
+    #[command(alias = "j")]
+    Join {
+        team: String,
+        #[arg(default = "1")]
+        count: u32,
+    },
+    /// announce something
+    #[command(name = "Say", alias = "SHOUT")]
+    Say {
+        #[arg(rest)]
+        text: String,
+    },
+    Kick(String, Option<String>),
+    #[command(hidden)]
+    ResetScores,
+    #[command(admin)]
+    Ban {
+        #[arg(name = "staff-id")]
+        user: String,
+    },
+}
+
+#[test]
+fn defaults_and_aliases() {
+    assert_eq!(
+        GameCommand::parse("join red").unwrap(),
+        GameCommand::Join {
+            team: "red".to_owned(),
+            count: 1
+        }
+    );
+    assert_eq!(
+        GameCommand::parse("J \"red team\" 3").unwrap(),
+        GameCommand::Join {
+            team: "red team".to_owned(),
+            count: 3
+        }
+    );
+}
+
+#[test]
+fn mixed_case_names_match() {
+    let say = GameCommand::Say {
+        text: "game starts in 5 minutes".to_owned(),
+    };
+    assert_eq!(GameCommand::parse("say game starts in 5 minutes"), Ok(say));
+    assert!(GameCommand::parse("SAY hi").is_ok());
+    assert!(GameCommand::parse("shout hi").is_ok());
+    let info = GameCommand::info("say").unwrap();
+    assert_eq!(info.aliases, &["shout"]);
+    assert_eq!(info.usage, "say <text...>");
+}
+
+#[test]
+fn optional_and_renamed_arguments() {
+    assert_eq!(
+        GameCommand::parse("kick zhangsan").unwrap(),
+        GameCommand::Kick("zhangsan".to_owned(), None)
+    );
+    assert_eq!(
+        GameCommand::parse("kick zhangsan afk").unwrap(),
+        GameCommand::Kick("zhangsan".to_owned(), Some("afk".to_owned()))
+    );
+    assert_eq!(
+        GameCommand::parse("ban").unwrap_err(),
+        CommandParseError::MissingArgument {
+            command: "ban".to_owned(),
+            argument: "staff-id".to_owned(),
+        }
+    );
+    assert_eq!(
+        GameCommand::parse("reset-scores"),
+        Ok(GameCommand::ResetScores)
+    );
+}
+
+#[test]
+fn unknown_commands_and_extra_arguments() {
+    assert_eq!(
+        GameCommand::parse("dance now").unwrap_err(),
+        CommandParseError::Unknown("dance".to_owned())
+    );
+    assert_eq!(
+        GameCommand::parse("join red 2 blue").unwrap_err(),
+        CommandParseError::TooManyArguments {
+            command: "join".to_owned(),
+            extra: "blue".to_owned(),
+        }
+    );
+    assert!(matches!(
+        GameCommand::parse("join red two"),
+        Err(CommandParseError::InvalidArgument { argument, .. }) if argument == "count"
+    ));
+}
+
+#[test]
+fn help_lists_public_commands() {
+    let help = GameCommand::help("/");
+    assert_eq!(
+        help,
+        "/join <team> [count=1] - join a team\n\
+         /say <text...> - announce something\n\
+         /kick <arg1> [arg2]"
+    );
+}
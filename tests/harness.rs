@@ -0,0 +1,478 @@
+#![cfg(feature = "harness")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy_stream_dingtalk::client::bundle::MessageBundle;
+use bevy_stream_dingtalk::client::mock::Outgoing;
+use bevy_stream_dingtalk::client::quiet::{QuietHours, MAX_RELEASE_ATTEMPTS};
+use bevy_stream_dingtalk::client::up::{MessageTemplate, RobotSendMessage};
+use bevy_stream_dingtalk::client::zone::Zone;
+use bevy_stream_dingtalk::client::{AsyncRuntime, Client, Endpoints};
+use bevy_stream_dingtalk::command::{
+    BotCommand, CommandReceived, CommandRouter, Cooldown, RateLimitedUserEvent,
+};
+use bevy_stream_dingtalk::event::{AlertAcknowledgedEvent, RobotMessageReceived};
+use bevy_stream_dingtalk::fixtures;
+use bevy_stream_dingtalk::harness::TestHarness;
+use bevy_stream_dingtalk::notify::{Notifications, Notifier, Severity};
+use bevy_stream_dingtalk::poll::{Poll, PollFinishedEvent, PollPlugin, Polls};
+use bevy_stream_dingtalk::prelude::StreamDingTalkPlugin;
+use bevy_stream_dingtalk::protocol::MsgContent;
+use bevy_stream_dingtalk::storage::{MemoryStorage, Storage};
+use bevy_stream_dingtalk::topics::Topic;
+use chrono::{DateTime, FixedOffset, NaiveTime, Utc};
+
+#[derive(BotCommand, Debug, PartialEq)]
+enum GameCommand {
+    Score,
+}
+
+#[test]
+fn robot_message_is_acked_and_received() {
+    let mut harness = TestHarness::new();
+    let mut frame = fixtures::callback_frame(Topic::ROBOT_MESSAGES, fixtures::robot_message::TEXT);
+    frame.headers.message_id = "frame-1".to_owned();
+    harness.inject(frame).unwrap();
+
+    let acks = harness.sent_acks();
+    assert_eq!(acks.len(), 1);
+    assert_eq!(acks[0].code, 200);
+    assert_eq!(acks[0].headers.message_id, "frame-1");
+
+    let received = harness.events::<RobotMessageReceived>();
+    assert_eq!(received.len(), 1);
+    let message = &received[0].message;
+    assert_eq!(message.sender_staff_id, fixtures::STAFF_ID);
+    assert!(
+        matches!(&message.content, MsgContent::Text { content } if content.trim() == "hello bot")
+    );
+}
+
+#[test]
+fn ping_answers_are_not_acks() {
+    let mut harness = TestHarness::new();
+    harness
+        .inject(serde_json::from_str(fixtures::frame::PING).unwrap())
+        .unwrap();
+    assert!(harness.sent_acks().is_empty());
+    assert!(matches!(&harness.outgoing()[..], [Outgoing::Frame(_)]));
+}
+
+#[test]
+fn group_reply_is_recorded_as_http() {
+    let mut harness = TestHarness::new();
+    harness.app.add_plugins(CommandRouter::<GameCommand>::new());
+    harness
+        .send_group_text(fixtures::CONVERSATION_ID, "/dance")
+        .unwrap();
+
+    let sends = harness.sent_http("groupMessages/send");
+    assert_eq!(sends.len(), 1);
+    assert!(sends[0].dry_run);
+    let body = sends[0].body.as_ref().unwrap();
+    assert_eq!(body["openConversationId"], fixtures::CONVERSATION_ID);
+    assert_eq!(body["msgKey"], "sampleText");
+    let outgoing = harness.outgoing();
+    assert!(
+        matches!(&outgoing[..], [Outgoing::Ack(_), Outgoing::Request(reply)] if reply == &sends[0])
+    );
+}
+
+#[test]
+fn cooldown_ends_when_the_clock_moves() {
+    let mut harness = TestHarness::new();
+    harness
+        .app
+        .add_plugins(CommandRouter::<GameCommand>::new().cooldown(Cooldown {
+            per_user: Duration::from_secs(10),
+            per_conversation: Duration::ZERO,
+            reply: None,
+        }));
+
+    harness.send_text("/score").unwrap();
+    harness.send_text("/score").unwrap();
+    assert_eq!(harness.events::<CommandReceived<GameCommand>>().len(), 1);
+    let limited = harness.events::<RateLimitedUserEvent>();
+    assert_eq!(limited.len(), 1);
+    assert_eq!(limited[0].retry_after, Duration::from_secs(10));
+
+    harness.advance(Duration::from_secs(9));
+    harness.send_text("/score").unwrap();
+    assert!(harness.events::<CommandReceived<GameCommand>>().is_empty());
+
+    harness.advance(Duration::from_secs(1));
+    harness.send_text("/score").unwrap();
+    assert_eq!(harness.events::<CommandReceived<GameCommand>>().len(), 1);
+}
+
+#[test]
+fn poll_closes_at_its_deadline() {
+    let mut harness = TestHarness::new();
+    harness.app.add_plugins(PollPlugin);
+    harness.update();
+    let poll = Poll::new(
+        fixtures::CONVERSATION_ID,
+        "poll_template",
+        "next map?",
+        ["desert", "forest"],
+        chrono::Duration::minutes(5),
+    );
+    let id = harness.app.world.resource_mut::<Polls>().start(poll);
+    harness.update();
+    let deadline = harness
+        .app
+        .world
+        .resource::<Polls>()
+        .get(&id)
+        .unwrap()
+        .deadline();
+    assert_eq!(
+        deadline,
+        Some(harness.client().now() + chrono::Duration::minutes(5))
+    );
+
+    harness.advance(Duration::from_secs(299));
+    assert!(harness.events::<PollFinishedEvent>().is_empty());
+    harness.advance(Duration::from_secs(1));
+    let finished = harness.events::<PollFinishedEvent>();
+    assert_eq!(finished.len(), 1);
+    assert_eq!(finished[0].poll_id, id);
+    assert!(harness.app.world.resource::<Polls>().get(&id).is_none());
+}
+
+#[test]
+fn webhook_expires_by_the_client_clock() {
+    let harness = TestHarness::new();
+    let client = harness.client();
+    let mut message = fixtures::text_message("hi");
+    message.session_webhook_expired_time =
+        (client.current_clock().now() + chrono::Duration::minutes(1)).timestamp_millis() as u64;
+
+    assert!(!message.webhook_expired(&client));
+    harness.clock().advance(Duration::from_secs(60));
+    assert!(message.webhook_expired(&client));
+}
+
+#[test]
+fn message_counts_roll_over_by_the_client_clock() {
+    let mut harness = TestHarness::new();
+    harness.send_text("hello").unwrap();
+    let client = harness.client();
+    assert_eq!(client.message_stats().today().received, 1);
+
+    harness.clock().advance(Duration::from_secs(24 * 3600));
+    assert_eq!(client.message_stats().today().received, 0);
+    harness.send_text("hello again").unwrap();
+    let stats = client.message_stats();
+    assert_eq!(stats.today().received, 1);
+    let yesterday = client.now().date_naive().pred_opt().unwrap();
+    assert_eq!(stats.day(yesterday).received, 1);
+}
+
+/// a harness whose conversation is quiet from 22:00 to 07:00 UTC, standing at 23:00
+fn quiet_plugin(storage: Arc<dyn Storage>) -> StreamDingTalkPlugin {
+    let hours = QuietHours::new(
+        NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+        NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+    )
+    .zone(Zone::Fixed(FixedOffset::east_opt(0).unwrap()));
+    StreamDingTalkPlugin::new("harness", "harness")
+        .storage(storage)
+        .quiet_hours(fixtures::CONVERSATION_ID, hours)
+}
+
+/// harness at 23:00, inside the quiet hours 22:00 to 07:00 of the fixture conversation
+fn quiet_harness_of(plugin: StreamDingTalkPlugin) -> TestHarness {
+    let mut harness = TestHarness::with_plugin(plugin);
+    harness.clock().set(at("2024-06-11T23:00:00Z"));
+    harness.update();
+    harness
+}
+
+fn quiet_harness(storage: Arc<dyn Storage>) -> TestHarness {
+    quiet_harness_of(quiet_plugin(storage))
+}
+
+fn at(time: &str) -> DateTime<Utc> {
+    time.parse().unwrap()
+}
+
+#[test]
+fn quiet_hours_hold_sends_across_restarts() {
+    let storage: Arc<dyn Storage> = MemoryStorage::new();
+    let harness = quiet_harness(storage.clone());
+    let client = harness.client();
+    let send = RobotSendMessage::group(
+        client.clone(),
+        fixtures::CONVERSATION_ID,
+        MessageTemplate::SampleText {
+            content: "server restarts at 8:00".to_owned(),
+        },
+    )
+    .unwrap();
+    let result = harness
+        .app
+        .world
+        .resource::<AsyncRuntime>()
+        .block_on(send.send())
+        .unwrap();
+    assert!(result.held);
+    assert_eq!(client.held_sends(), 1);
+    drop(harness);
+
+    let mut harness = quiet_harness(storage);
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.client().held_sends(), 1);
+    assert!(harness.sent_http("groupMessages/send").is_empty());
+
+    harness.clock().set(at("2024-06-12T07:00:00Z"));
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.client().held_sends(), 0);
+    let sends = harness.sent_http("groupMessages/send");
+    assert_eq!(sends.len(), 1);
+    assert_eq!(
+        sends[0].body.as_ref().unwrap()["openConversationId"],
+        fixtures::CONVERSATION_ID
+    );
+}
+
+/// hold a send in the quiet hours of `harness`, then let sends fail on a closed port
+fn hold_failing_send(harness: &TestHarness) -> Arc<Client> {
+    let client = harness.client();
+    let send = RobotSendMessage::group(
+        client.clone(),
+        fixtures::CONVERSATION_ID,
+        MessageTemplate::SampleText {
+            content: "server restarts at 8:00".to_owned(),
+        },
+    )
+    .unwrap();
+    let runtime = harness.app.world.resource::<AsyncRuntime>();
+    assert!(runtime.block_on(send.send()).unwrap().held);
+    fail_sends(&client);
+    client
+}
+
+/// really send, to a closed port
+fn fail_sends(client: &Arc<Client>) {
+    let closed = "http://127.0.0.1:1".to_owned();
+    client.clone().dry_run(false).endpoints(Endpoints {
+        token: format!("{closed}/gettoken"),
+        api: closed,
+        ..Default::default()
+    });
+}
+
+#[test]
+fn failed_releases_are_kept_and_retried() {
+    let harness = quiet_harness(MemoryStorage::new());
+    let client = hold_failing_send(&harness);
+    let runtime = harness.app.world.resource::<AsyncRuntime>();
+    harness.clock().set(at("2024-06-12T07:00:00Z"));
+    assert_eq!(runtime.block_on(client.release_held()), 0);
+    assert_eq!(client.held_sends(), 1);
+
+    client.clone().dry_run(true);
+    assert_eq!(runtime.block_on(client.release_held()), 0);
+    harness.clock().advance(Duration::from_secs(30));
+    assert_eq!(runtime.block_on(client.release_held()), 1);
+    assert_eq!(client.held_sends(), 0);
+    let sends = harness.sent_http("groupMessages/send");
+    assert_eq!(sends.iter().filter(|send| send.dry_run).count(), 1);
+}
+
+#[test]
+fn held_sends_failing_too_often_move_aside() {
+    let harness = quiet_harness(MemoryStorage::new());
+    let client = hold_failing_send(&harness);
+    let runtime = harness.app.world.resource::<AsyncRuntime>();
+    harness.clock().set(at("2024-06-12T07:00:00Z"));
+    for _ in 0..MAX_RELEASE_ATTEMPTS {
+        assert_eq!(runtime.block_on(client.release_held()), 0);
+        harness.clock().advance(Duration::from_secs(24 * 60 * 60));
+    }
+    assert_eq!(client.held_sends(), 0);
+    assert_eq!(client.failed_held_sends(), 1);
+}
+
+#[test]
+fn quiet_hours_hold_bundles_in_order() {
+    let mut harness = quiet_harness(MemoryStorage::new());
+    let client = harness.client();
+    let runtime = harness.app.world.resource::<AsyncRuntime>();
+    let bundle = MessageBundle::new().text("first").text("second");
+    let results = runtime
+        .block_on(client.send_bundle(fixtures::CONVERSATION_ID, bundle.clone()))
+        .unwrap();
+    assert!(results.iter().all(|result| result.held));
+    let results = runtime
+        .block_on(client.send_bundle(fixtures::CONVERSATION_ID, bundle.urgent(true)))
+        .unwrap();
+    assert!(results.iter().all(|result| !result.held));
+    assert_eq!(harness.sent_http("groupMessages/send").len(), 2);
+    assert_eq!(client.held_sends(), 1);
+
+    harness.clock().set(at("2024-06-12T07:00:00Z"));
+    harness.advance(Duration::from_secs(1));
+    let contents: Vec<_> = harness
+        .sent_http("groupMessages/send")
+        .iter()
+        .map(|send| {
+            send.body.as_ref().unwrap()["msgParam"]
+                .as_str()
+                .unwrap()
+                .to_owned()
+        })
+        .collect();
+    assert_eq!(contents.len(), 4);
+    assert!(contents[2].contains("first"));
+    assert!(contents[3].contains("second"));
+}
+
+#[test]
+fn quiet_hours_hold_ack_cards() {
+    let notifications = Notifications::new()
+        .route(Severity::Warn, fixtures::CONVERSATION_ID)
+        .ack_card("alert-card");
+    let plugin = quiet_plugin(MemoryStorage::new()).notifications(notifications);
+    let mut harness = quiet_harness_of(plugin);
+    let notifier = harness.app.world.resource::<Notifier>().clone();
+    assert_eq!(notifier.notify(Severity::Warn, "disk 90% full"), 1);
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.client().held_sends(), 1);
+    assert!(harness
+        .sent_http("card/instances/createAndDeliver")
+        .is_empty());
+
+    harness.clock().set(at("2024-06-12T07:00:00Z"));
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.client().held_sends(), 0);
+    let cards = harness.sent_http("card/instances/createAndDeliver");
+    assert_eq!(cards.len(), 1);
+    assert_eq!(
+        cards[0].body.as_ref().unwrap()["cardTemplateId"],
+        "alert-card"
+    );
+}
+
+fn notifying_harness(storage: Arc<dyn Storage>) -> TestHarness {
+    let notifications = Notifications::new()
+        .route(Severity::Critical, fixtures::CONVERSATION_ID)
+        .min_interval(Severity::Critical, Duration::ZERO)
+        .escalate(Severity::Critical, Duration::from_secs(300), ["oncall"])
+        .ack_keyword("roger");
+    let plugin = StreamDingTalkPlugin::new("harness", "harness")
+        .storage(storage)
+        .notifications(notifications);
+    let mut harness = TestHarness::with_plugin(plugin);
+    harness.clock().set(at("2024-06-11T12:00:00Z"));
+    harness.update();
+    harness
+}
+
+fn notifier(harness: &TestHarness) -> Notifier {
+    harness.app.world.resource::<Notifier>().clone()
+}
+
+#[test]
+fn repeats_collapse_into_one_incident() {
+    let mut harness = notifying_harness(MemoryStorage::new());
+    let notifier = notifier(&harness);
+    assert_eq!(notifier.notify(Severity::Critical, "db down"), 1);
+    assert_eq!(notifier.notify(Severity::Critical, "db down"), 0);
+    assert_eq!(notifier.notify(Severity::Critical, "db down"), 0);
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.sent_http("groupMessages/send").len(), 1);
+    let incident = notifier.incident("db down").unwrap();
+    assert_eq!(incident.count, 3);
+
+    harness.clock().advance(Duration::from_secs(600));
+    assert!(notifier.incident("db down").is_none());
+    assert_eq!(notifier.notify(Severity::Critical, "db down"), 1);
+    assert_eq!(notifier.incident("db down").unwrap().id, incident.id + 1);
+}
+
+#[test]
+fn unacknowledged_incident_escalates_by_the_clock() {
+    let mut harness = notifying_harness(MemoryStorage::new());
+    let notifier = notifier(&harness);
+    notifier.notify(Severity::Critical, "db down");
+    harness.advance(Duration::from_secs(1));
+    assert!(harness.sent_http("oToMessages/batchSend").is_empty());
+
+    harness.clock().advance(Duration::from_secs(300));
+    harness.advance(Duration::from_secs(1));
+    let pages = harness.sent_http("oToMessages/batchSend");
+    assert_eq!(pages.len(), 1);
+    let page = pages[0].body.as_ref().unwrap();
+    assert_eq!(page["userIds"], serde_json::json!(["oncall"]));
+    let id = notifier.incident("db down").unwrap().id;
+    assert!(page["msgParam"]
+        .as_str()
+        .unwrap()
+        .contains(&format!("send `roger {id}`")));
+
+    harness.send_text(&format!("roger {id}")).unwrap();
+    assert_eq!(harness.events::<AlertAcknowledgedEvent>().len(), 1);
+}
+
+#[test]
+fn acknowledged_incident_does_not_escalate() {
+    let mut harness = notifying_harness(MemoryStorage::new());
+    let notifier = notifier(&harness);
+    notifier.notify(Severity::Critical, "db down");
+    let id = notifier.incident("db down").unwrap().id;
+    assert!(notifier.acknowledge(id, "oncall"));
+    harness.update();
+    assert_eq!(harness.events::<AlertAcknowledgedEvent>().len(), 1);
+
+    harness.clock().advance(Duration::from_secs(300));
+    harness.advance(Duration::from_secs(1));
+    assert!(harness.sent_http("oToMessages/batchSend").is_empty());
+}
+
+#[test]
+fn escalation_survives_a_restart() {
+    let storage: Arc<dyn Storage> = MemoryStorage::new();
+    let harness = notifying_harness(storage.clone());
+    notifier(&harness).notify(Severity::Critical, "db down");
+    drop(harness);
+
+    let mut harness = notifying_harness(storage);
+    harness.clock().advance(Duration::from_secs(300));
+    harness.advance(Duration::from_secs(1));
+    assert_eq!(harness.sent_http("oToMessages/batchSend").len(), 1);
+    notifier(&harness).notify(Severity::Critical, "cache down");
+    assert_eq!(notifier(&harness).incident("cache down").unwrap().id, 2);
+}
+
+#[test]
+fn held_bundle_is_retried_as_a_whole() {
+    let harness = quiet_harness(MemoryStorage::new());
+    let client = harness.client();
+    let runtime = harness.app.world.resource::<AsyncRuntime>();
+    let bundle = MessageBundle::new().text("first").text("second");
+    runtime
+        .block_on(client.send_bundle(fixtures::CONVERSATION_ID, bundle))
+        .unwrap();
+    assert_eq!(client.held_sends(), 1);
+
+    fail_sends(&client);
+    harness.clock().set(at("2024-06-12T07:00:00Z"));
+    assert_eq!(runtime.block_on(client.release_held()), 0);
+    assert_eq!(client.held_sends(), 1);
+
+    client.clone().dry_run(true);
+    harness.clock().advance(Duration::from_secs(30));
+    assert_eq!(runtime.block_on(client.release_held()), 1);
+    let sent: Vec<_> = harness
+        .sent_http("groupMessages/send")
+        .into_iter()
+        .filter(|send| send.dry_run)
+        .map(|send| send.body.unwrap()["msgParam"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(sent.len(), 2);
+    assert!(sent[0].contains("first"));
+    assert!(sent[1].contains("second"));
+}
@@ -0,0 +1,422 @@
+//! Property tests: wire payloads decode to what was encoded and encode back unchanged
+
+use bevy_stream_dingtalk::protocol::{
+    ClientDownStream, MessageTemplate, MsgContent, RichText, RobotRecvMessage,
+};
+use proptest::prelude::*;
+use serde_json::{json, Map, Value};
+
+fn text() -> impl Strategy<Value = String> {
+    "\\PC{0,24}"
+}
+
+prop_compose! {
+    /// a stream frame as DingTalk sends it
+    fn down_stream()(
+        kind in prop_oneof![Just("SYSTEM"), Just("EVENT"), Just("CALLBACK")],
+        spec_version in text(),
+        ids in (text(), text(), text()),
+        time in "[0-9]{13}",
+        topic in text(),
+        event in (text(), "[0-9]{13}", text(), text(), text()),
+        data in text(),
+    ) -> Value {
+        let (app_id, connection_id, message_id) = ids;
+        let (event_type, event_born_time, event_id, event_corp_id, event_unified_app_id) = event;
+        json!({
+            "specVersion": spec_version,
+            "type": kind,
+            "headers": {
+                "appId": app_id,
+                "connectionId": connection_id,
+                "contentType": "application/json",
+                "messageId": message_id,
+                "time": time,
+                "topic": topic,
+                "eventType": event_type,
+                "eventBornTime": event_born_time,
+                "eventId": event_id,
+                "eventCorpId": event_corp_id,
+                "eventUnifiedAppId": event_unified_app_id,
+            },
+            "data": data,
+        })
+    }
+}
+
+fn encode_down_stream(frame: &ClientDownStream) -> Value {
+    let headers = &frame.headers;
+    json!({
+        "specVersion": frame.spec_version,
+        "type": frame.r#type,
+        "headers": {
+            "appId": headers.app_id,
+            "connectionId": headers.connection_id,
+            "contentType": headers.content_type,
+            "messageId": headers.message_id,
+            "time": headers.time,
+            "topic": headers.topic,
+            "eventType": headers.event.event_type,
+            "eventBornTime": headers.event.event_born_time,
+            "eventId": headers.event.event_id,
+            "eventCorpId": headers.event.event_corp_id,
+            "eventUnifiedAppId": headers.event.event_unified_app_id,
+        },
+        "data": frame.data,
+    })
+}
+
+/// msgtype and content of a robot message
+fn content() -> impl Strategy<Value = (String, Value)> {
+    prop_oneof![
+        text().prop_map(|content| ("text".to_owned(), json!({ "content": content }))),
+        (text(), text(), text(), text()).prop_map(|(code, name, file_id, space_id)| (
+            "file".to_owned(),
+            json!({
+                "downloadCode": code,
+                "fileName": name,
+                "fileId": file_id,
+                "spaceId": space_id,
+            })
+        )),
+        (text(), text()).prop_map(|(code, picture_code)| (
+            "picture".to_owned(),
+            json!({ "downloadCode": code, "pictureDownloadCode": picture_code })
+        )),
+        (any::<u32>(), text(), text()).prop_map(|(duration, code, recognition)| (
+            "audio".to_owned(),
+            json!({ "duration": duration, "downloadCode": code, "recognition": recognition })
+        )),
+        (any::<u32>(), text(), text()).prop_map(|(duration, code, video_type)| (
+            "video".to_owned(),
+            json!({ "duration": duration, "downloadCode": code, "videoType": video_type })
+        )),
+        // coordinates in microdegrees, as precise as DingTalk sends them
+        (
+            -90_000_000..90_000_000i32,
+            -180_000_000..180_000_000i32,
+            text(),
+            text()
+        )
+            .prop_map(|(latitude, longitude, title, address)| (
+                "location".to_owned(),
+                json!({
+                    "latitude": latitude as f64 / 1e6,
+                    "longitude": longitude as f64 / 1e6,
+                    "title": title,
+                    "address": address,
+                })
+            )),
+        prop::collection::vec(
+            prop_oneof![
+                text().prop_map(|text| json!({ "text": text })),
+                (text(), text()).prop_map(|(code, picture_code)| json!({
+                    "downloadCode": code,
+                    "pictureDownloadCode": picture_code,
+                    "type": "picture",
+                })),
+            ],
+            0..4
+        )
+        .prop_map(|items| ("richText".to_owned(), json!({ "richText": items }))),
+        (text(), text()).prop_map(|(code, sticker_id)| (
+            "sticker".to_owned(),
+            json!({ "downloadCode": code, "stickerId": sticker_id })
+        )),
+        (text(), text(), text()).prop_map(|(emotion_id, name, code)| (
+            "emotion".to_owned(),
+            json!({ "emotionId": emotion_id, "emotionName": name, "downloadCode": code })
+        )),
+        prop::collection::btree_map("[a-z]{1,8}", text(), 0..4).prop_map(|fields| (
+            "interactiveCard".to_owned(),
+            Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(k, v)| (k, Value::String(v)))
+                    .collect()
+            )
+        )),
+    ]
+}
+
+prop_compose! {
+    /// robot message data as DingTalk sends it
+    fn robot_message()(
+        (msgtype, content) in content(),
+        conversation in (text(), prop_oneof![Just("1"), Just("2")], text()),
+        at_users in prop::collection::vec((text(), text()), 0..3),
+        is_in_at_list in any::<bool>(),
+        robot in (text(), text()),
+        sender in (text(), text(), text(), text()),
+        webhook in (any::<u64>(), text()),
+        is_admin in any::<bool>(),
+        ids in (text(), any::<u64>()),
+    ) -> Value {
+        let (conversation_id, conversation_type, conversation_title) = conversation;
+        let (sender_id, sender_nick, sender_corp_id, sender_staff_id) = sender;
+        let (msg_id, create_at) = ids;
+        let mut data = json!({
+            "msgId": msg_id,
+            "msgtype": msgtype,
+            "conversationId": conversation_id,
+            "conversationType": conversation_type,
+            "conversationTitle": conversation_title,
+            "atUsers": at_users
+                .into_iter()
+                .map(|(dingtalk_id, staff_id)| json!({
+                    "dingtalkId": dingtalk_id,
+                    "staffId": staff_id,
+                }))
+                .collect::<Vec<_>>(),
+            "isInAtList": is_in_at_list,
+            "chatbotCorpId": robot.0,
+            "chatbotUserId": robot.1,
+            "senderId": sender_id,
+            "senderNick": sender_nick,
+            "senderCorpId": sender_corp_id,
+            "senderStaffId": sender_staff_id,
+            "sessionWebhookExpiredTime": webhook.0,
+            "sessionWebhook": webhook.1,
+            "isAdmin": is_admin,
+            "createAt": create_at,
+        });
+        // text messages carry their content under `text`
+        let key = if msgtype == "text" { "text" } else { "content" };
+        data[key] = content;
+        data
+    }
+}
+
+fn encode_content(content: &MsgContent) -> Value {
+    match content {
+        MsgContent::Text { content } => json!({ "content": content }),
+        MsgContent::File {
+            download_code,
+            file_name,
+            file_id,
+            space_id,
+        } => json!({
+            "downloadCode": download_code,
+            "fileName": file_name,
+            "fileId": file_id,
+            "spaceId": space_id,
+        }),
+        MsgContent::Picture {
+            download_code,
+            picture_download_code,
+        } => json!({
+            "downloadCode": download_code,
+            "pictureDownloadCode": picture_download_code,
+        }),
+        MsgContent::RichText { rich_text } => {
+            let items: Vec<Value> = rich_text
+                .iter()
+                .map(|item| match item {
+                    RichText::Text { text } => json!({ "text": text }),
+                    RichText::Picture {
+                        download_code,
+                        picture_download_code,
+                        r#type,
+                    } => json!({
+                        "downloadCode": download_code,
+                        "pictureDownloadCode": picture_download_code,
+                        "type": r#type,
+                    }),
+                })
+                .collect();
+            json!({ "richText": items })
+        }
+        MsgContent::Audio {
+            duration,
+            download_code,
+            recognition,
+        } => json!({
+            "duration": duration,
+            "downloadCode": download_code,
+            "recognition": recognition,
+        }),
+        MsgContent::Video {
+            duration,
+            download_code,
+            video_type,
+        } => json!({
+            "duration": duration,
+            "downloadCode": download_code,
+            "videoType": video_type,
+        }),
+        MsgContent::Location {
+            latitude,
+            longitude,
+            title,
+            address,
+        } => json!({
+            "latitude": latitude,
+            "longitude": longitude,
+            "title": title,
+            "address": address,
+        }),
+        MsgContent::Sticker(sticker) => json!({
+            "downloadCode": sticker.download_code,
+            "stickerId": sticker.sticker_id,
+        }),
+        MsgContent::Emotion(emotion) => json!({
+            "emotionId": emotion.emotion_id,
+            "emotionName": emotion.emotion_name,
+            "downloadCode": emotion.download_code,
+        }),
+        MsgContent::UnknownMsgType { raw, .. } => raw.clone(),
+        MsgContent::Custom { msgtype, .. } => panic!("no decoder registered for {msgtype}"),
+    }
+}
+
+fn encode_robot_message(msg: &RobotRecvMessage) -> Value {
+    let mut data = json!({
+        "msgId": msg.msg_id,
+        "msgtype": msg.msgtype,
+        "conversationId": msg.conversation_id,
+        "conversationType": msg.conversation_type,
+        "conversationTitle": msg.conversation_title,
+        "atUsers": msg
+            .at_users
+            .iter()
+            .map(|user| json!({ "dingtalkId": user.dingtalk_id, "staffId": user.staff_id }))
+            .collect::<Vec<_>>(),
+        "isInAtList": msg.is_in_at_list,
+        "chatbotCorpId": msg.chatbot_corp_id,
+        "chatbotUserId": msg.chatbot_user_id,
+        "senderId": msg.sender_id,
+        "senderNick": msg.sender_nick,
+        "senderCorpId": msg.sender_corp_id,
+        "senderStaffId": msg.sender_staff_id,
+        "sessionWebhookExpiredTime": msg.session_webhook_expired_time,
+        "sessionWebhook": msg.session_webhook,
+        "isAdmin": msg.is_admin,
+        "createAt": msg.create_at,
+    });
+    let key = if msg.msgtype == "text" {
+        "text"
+    } else {
+        "content"
+    };
+    data[key] = encode_content(&msg.content);
+    data
+}
+
+/// templates of every known msgKey and raw ones of unknown keys
+fn message_template() -> impl Strategy<Value = MessageTemplate> {
+    prop_oneof![
+        text().prop_map(|content| MessageTemplate::SampleText { content }),
+        (text(), text()).prop_map(|(title, text)| MessageTemplate::SampleMarkdown { title, text }),
+        text().prop_map(|photo_url| MessageTemplate::SampleImageMsg { photo_url }),
+        (text(), text(), text(), text()).prop_map(|(text, title, pic_url, message_url)| {
+            MessageTemplate::SampleLink {
+                text,
+                title,
+                pic_url,
+                message_url,
+            }
+        }),
+        (text(), text(), text(), text()).prop_map(|(title, text, single_title, single_url)| {
+            MessageTemplate::SampleActionCard {
+                title,
+                text,
+                single_title,
+                single_url,
+            }
+        }),
+        (text(), text(), [text(), text(), text(), text()]).prop_map(|(title, text, a)| {
+            let [action_title_1, action_url_1, action_title_2, action_url_2] = a;
+            MessageTemplate::SampleActionCard2 {
+                title,
+                text,
+                action_title_1,
+                action_url_1,
+                action_title_2,
+                action_url_2,
+            }
+        }),
+        (
+            text(),
+            text(),
+            [text(), text(), text(), text(), text(), text()]
+        )
+            .prop_map(|(title, text, a)| {
+                let [t1, u1, t2, u2, t3, u3] = a;
+                MessageTemplate::SampleActionCard3 {
+                    title,
+                    text,
+                    action_title_1: t1,
+                    action_url_1: u1,
+                    action_title_2: t2,
+                    action_url_2: u2,
+                    action_title_3: t3,
+                    action_url_3: u3,
+                }
+            }),
+        (text(), text(), [text(), text(), text(), text()]).prop_map(|(title, text, b)| {
+            let [button_title_1, button_url_1, button_title_2, button_url_2] = b;
+            MessageTemplate::SampleActionCard6 {
+                title,
+                text,
+                button_title_1,
+                button_url_1,
+                button_title_2,
+                button_url_2,
+            }
+        }),
+        (text(), text())
+            .prop_map(|(media_id, duration)| MessageTemplate::SampleAudio { media_id, duration }),
+        (text(), text(), text()).prop_map(|(media_id, file_name, file_type)| {
+            MessageTemplate::SampleFile {
+                media_id,
+                file_name,
+                file_type,
+            }
+        }),
+        (text(), text(), text(), text()).prop_map(
+            |(duration, video_media_id, video_type, pic_media_id)| MessageTemplate::SampleVideo {
+                duration,
+                video_media_id,
+                video_type,
+                pic_media_id,
+            }
+        ),
+        (
+            "[a-z]{1,12}".prop_filter("known msgKey", |key| {
+                !key.starts_with("sample") && key != "raw"
+            }),
+            prop::collection::btree_map("[a-zA-Z]{1,8}", text(), 0..4),
+        )
+            .prop_map(|(msg_key, params)| MessageTemplate::Raw {
+                msg_key,
+                msg_param: Value::Object(
+                    params
+                        .into_iter()
+                        .map(|(k, v)| (k, Value::String(v)))
+                        .collect::<Map<_, _>>(),
+                ),
+            }),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn down_stream_round_trip(wire in down_stream()) {
+        let frame: ClientDownStream = serde_json::from_value(wire.clone()).unwrap();
+        prop_assert_eq!(encode_down_stream(&frame), wire);
+    }
+
+    #[test]
+    fn robot_message_round_trip(wire in robot_message()) {
+        let msg = RobotRecvMessage::from_json(&wire.to_string()).unwrap();
+        prop_assert_eq!(encode_robot_message(&msg), wire);
+    }
+
+    #[test]
+    fn message_template_round_trip(template in message_template()) {
+        let wire = serde_json::to_string(&template).unwrap();
+        let again: MessageTemplate = serde_json::from_str(&wire).unwrap();
+        prop_assert_eq!(&again, &template);
+        prop_assert_eq!(again.to_string(), template.to_string());
+    }
+}
@@ -0,0 +1,151 @@
+#![cfg(feature = "fixtures")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_stream_dingtalk::client::{AsyncRuntime, DingTalkClient, KeepConnected};
+use bevy_stream_dingtalk::event::RobotMessageReceived;
+use bevy_stream_dingtalk::fixtures;
+use bevy_stream_dingtalk::prelude::StreamDingTalkPlugin;
+use bevy_stream_dingtalk::sub_app::{DingTalkSubApp, DingTalkSubAppPlugin};
+use bevy_stream_dingtalk::subscriptions::DingTalkSubscriptions;
+use bevy_stream_dingtalk::topics::Topic;
+
+/// sent in the sub app for every robot message
+#[derive(Event, Debug, Clone)]
+struct Heard(String);
+
+/// ids of the robot messages a world read, [`Heard`] sent in the sub app or read in main
+#[derive(Resource, Debug, Default)]
+struct Received(Vec<String>);
+
+fn hear(
+    mut messages: EventReader<RobotMessageReceived>,
+    mut heard: EventWriter<Heard>,
+    mut sent: ResMut<Received>,
+) {
+    for received in messages.read() {
+        sent.0.push(received.message.msg_id.clone());
+        heard.send(Heard(received.message.msg_id.clone()));
+    }
+}
+
+fn receive(mut heard: EventReader<Heard>, mut received: ResMut<Received>) {
+    received.0.extend(heard.read().map(|Heard(id)| id.clone()));
+}
+
+/// main app running the plugin in mock mode in its sub app, sending [`Heard`] to main
+fn app() -> App {
+    let plugin = StreamDingTalkPlugin::new("sub", "sub")
+        .mock(true)
+        .current_thread(true)
+        .health_check(false);
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .init_resource::<Received>()
+        .add_plugins(
+            DingTalkSubAppPlugin::new(plugin)
+                .setup(|sub| {
+                    sub.add_event::<Heard>()
+                        .init_resource::<Received>()
+                        .add_systems(Update, hear);
+                })
+                .to_main::<Heard>(),
+        )
+        .add_systems(Update, receive);
+    app.finish();
+    app.cleanup();
+    connect(&mut app);
+    app
+}
+
+/// let the runtime's tasks run until they wait on something
+fn drive(app: &App) {
+    let sub = app.sub_app(DingTalkSubApp);
+    sub.world.resource::<AsyncRuntime>().block_on(async {
+        for _ in 0..16 {
+            tokio::task::yield_now().await;
+        }
+    });
+}
+
+/// step the sub app past the first connect attempt, which registers the listeners
+fn connect(app: &mut App) {
+    let sub = app.sub_app_mut(DingTalkSubApp);
+    sub.world
+        .resource_mut::<Time<Virtual>>()
+        .set_max_delta(Duration::MAX);
+    sub.update();
+    sub.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs(1)));
+    sub.update();
+    sub.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::ZERO));
+    drive(app);
+}
+
+/// hand a robot message frame to the sub app's client and let the runtime handle it
+fn inject(app: &mut App) {
+    let client = Arc::clone(app.world.resource::<DingTalkClient>());
+    let frame = fixtures::callback_frame(Topic::ROBOT_MESSAGES, fixtures::robot_message::TEXT);
+    let sub = app.sub_app(DingTalkSubApp);
+    sub.world
+        .resource::<AsyncRuntime>()
+        .block_on(client.inject_frame(frame))
+        .unwrap();
+    drive(app);
+}
+
+/// ids of the [`Heard`] events sent in the sub app so far
+fn heard_in_sub(app: &App) -> Vec<String> {
+    app.sub_app(DingTalkSubApp)
+        .world
+        .resource::<Received>()
+        .0
+        .clone()
+}
+
+#[test]
+fn sub_app_steps_without_the_main_world() {
+    let mut app = app();
+    inject(&mut app);
+
+    // the first update takes in the frame's event, the second reads it
+    app.sub_app_mut(DingTalkSubApp).update();
+    app.sub_app_mut(DingTalkSubApp).update();
+    assert_eq!(heard_in_sub(&app).len(), 1);
+    assert!(app.world.resource::<Events<Heard>>().is_empty());
+    assert!(app.world.resource::<Received>().0.is_empty());
+}
+
+#[test]
+fn events_reach_main_two_updates_after_they_were_sent() {
+    let mut app = app();
+    inject(&mut app);
+
+    app.update();
+    app.update();
+    let heard = heard_in_sub(&app);
+    assert_eq!(heard.len(), 1);
+
+    app.update();
+    assert!(app.world.resource::<Received>().0.is_empty());
+    app.update();
+    assert_eq!(app.world.resource::<Received>().0, heard);
+}
+
+#[test]
+fn changed_resources_replace_those_of_the_sub_app() {
+    let mut app = app();
+    app.update();
+
+    app.insert_resource(KeepConnected(false));
+    let mut subscriptions = app.world.resource::<DingTalkSubscriptions>().clone();
+    subscriptions.set_card_callbacks(true);
+    app.insert_resource(subscriptions.clone());
+    app.update();
+
+    let sub = &app.sub_app(DingTalkSubApp).world;
+    assert_eq!(*sub.resource::<KeepConnected>(), KeepConnected(false));
+    assert_eq!(*sub.resource::<DingTalkSubscriptions>(), subscriptions);
+}